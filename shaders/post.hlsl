@@ -0,0 +1,81 @@
+// the GPU post passes, each drawn over the whole frame with the fullscreen triangle into a texture the frame's size
+
+Texture2D source : register(t0);
+Texture2D bloom : register(t1);
+
+cbuffer Post : register(b0) {
+    // a texel across or down, the way the blur goes
+    int2 direction;
+    float radius;
+    float threshold;
+    float intensity;
+    // the same look as the software CrtEffect
+    float curvature;
+    float chroma_offset;
+    float scanline_brightness;
+};
+
+struct Vertex {
+    float4 position : SV_Position;
+    float2 uv : TEXCOORD0;
+};
+
+int2 source_size() {
+    uint width, height;
+    source.GetDimensions(width, height);
+    return int2(width, height);
+}
+
+// edges repeat, so blurs don't darken towards them
+float4 clamped(int2 texel) {
+    return source.Load(int3(clamp(texel, 0, source_size() - 1), 0));
+}
+
+// past the edge of the picture is the black of the tube
+float4 or_black(int2 texel) {
+    return all(texel >= 0) && all(texel < source_size()) ? source.Load(int3(texel, 0)) : 0;
+}
+
+// one direction of a gaussian, the radius at about two standard deviations
+float4 blur_main(Vertex input) : SV_Target {
+    int2 texel = int2(input.position.xy);
+    float sigma = max(radius / 2, 0.5);
+    int reach = min((int)ceil(radius), 64);
+    float4 sum = 0;
+    float total = 0;
+    [loop]
+    for (int offset = -reach; offset <= reach; offset++) {
+        float weight = exp(-(offset * offset) / (2 * sigma * sigma));
+        sum += clamped(texel + direction * offset) * weight;
+        total += weight;
+    }
+    return sum / total;
+}
+
+// only what's brighter than threshold, fading in above it rather than cutting on
+float4 threshold_main(Vertex input) : SV_Target {
+    float4 color = source.Load(int3(input.position.xy, 0));
+    float brightness = max(color.r, max(color.g, color.b));
+    return color * saturate((brightness - threshold) / max(1 - threshold, 0.0001));
+}
+
+float4 composite_main(Vertex input) : SV_Target {
+    int3 texel = int3(input.position.xy, 0);
+    return saturate(source.Load(texel) + bloom.Load(texel) * intensity);
+}
+
+float4 crt_main(Vertex input) : SV_Target {
+    // -1..1 from the center, pushed outwards more the further out it is
+    float2 half_size = source_size() / 2.0;
+    float2 from = (input.position.xy - half_size) / half_size;
+    float2 bent = half_size + from * (1 + curvature * dot(from, from)) * half_size;
+    int y = (int)floor(bent.y);
+    float3 color = float3(
+        or_black(int2(floor(bent.x + chroma_offset), y)).r,
+        or_black(int2(floor(bent.x), y)).g,
+        or_black(int2(floor(bent.x - chroma_offset), y)).b);
+    if ((int)input.position.y % 2 == 1) {
+        color *= scanline_brightness;
+    }
+    return float4(color, 1);
+}
@@ -0,0 +1,64 @@
+Texture2D frame : register(t0);
+SamplerState frame_sampler : register(s0);
+
+cbuffer Output : register(b0) {
+    // 0 is plain SDR, 1 scRGB, 2 HDR10
+    uint output_mode;
+    // how bright white in the frame comes out and the brightest the display goes, in nits
+    float paper_white;
+    float max_nits;
+};
+
+struct Vertex {
+    float4 position : SV_Position;
+    float2 uv : TEXCOORD0;
+};
+
+// one triangle big enough to cover the viewport, what's outside it gets clipped
+Vertex vertex_main(uint id : SV_VertexID) {
+    Vertex output;
+    output.uv = float2((id << 1) & 2, id & 2);
+    output.position = float4(output.uv * float2(2, -2) + float2(-1, 1), 0, 1);
+    return output;
+}
+
+float3 srgb_to_linear(float3 color) {
+    return color <= 0.04045 ? color / 12.92 : pow((color + 0.055) / 1.055, 2.4);
+}
+
+// SMPTE ST 2084, nits over 10000 to the signal HDR10 sends
+float3 pq(float3 value) {
+    float3 power = pow(max(value, 0), 0.1593017578125);
+    return pow((0.8359375 + 18.8515625 * power) / (1 + 18.6875 * power), 78.84375);
+}
+
+// the frame is SDR, so there's only paper white's worth of brightness to fit under what the display can do.
+// past a knee it rolls off towards max_nits instead of clipping
+float3 tone_map(float3 nits) {
+    float knee = 0.8 * max_nits;
+    return nits <= knee ? nits : knee + (max_nits - knee) * (1 - exp(-(nits - knee) / (max_nits - knee)));
+}
+
+float4 pixel_main(Vertex input) : SV_Target {
+    float3 color = frame.Sample(frame_sampler, input.uv).rgb;
+    if (output_mode == 0) {
+        return float4(color, 1);
+    }
+    float3 nits = tone_map(srgb_to_linear(color) * paper_white);
+    if (output_mode == 1) {
+        // scRGB is linear with 1 at 80 nits
+        return float4(nits / 80, 1);
+    }
+    // rec.709 primaries to rec.2020
+    float3x3 to_2020 = {
+        0.6274, 0.3293, 0.0433,
+        0.0691, 0.9195, 0.0114,
+        0.0164, 0.0880, 0.8956,
+    };
+    return float4(pq(mul(to_2020, nits) / 10000), 1);
+}
+
+// the overlay keeps its alpha, it's blended over the frame
+float4 overlay_main(Vertex input) : SV_Target {
+    return frame.Sample(frame_sampler, input.uv);
+}
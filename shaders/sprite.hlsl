@@ -0,0 +1,34 @@
+cbuffer Sizes : register(b0) {
+    float2 frame_size;
+    float2 atlas_size;
+};
+
+Texture2D atlas : register(t0);
+SamplerState atlas_sampler : register(s0);
+
+struct Sprite {
+    float2 origin : ORIGIN;
+    float2 x_axis : XAXIS;
+    float2 y_axis : YAXIS;
+    // x, y, width, height in the atlas, in texels
+    float4 source : SOURCE;
+};
+
+struct Vertex {
+    float4 position : SV_Position;
+    float2 uv : TEXCOORD0;
+};
+
+// four vertices per sprite as a strip, the corner comes from which vertex it is
+Vertex vertex_main(Sprite sprite, uint id : SV_VertexID) {
+    float2 corner = float2(id & 1, id >> 1);
+    float2 position = sprite.origin + corner.x * sprite.x_axis + corner.y * sprite.y_axis;
+    Vertex output;
+    output.position = float4(position / frame_size * float2(2, -2) + float2(-1, 1), 0, 1);
+    output.uv = (sprite.source.xy + corner * sprite.source.zw) / atlas_size;
+    return output;
+}
+
+float4 pixel_main(Vertex input) : SV_Target {
+    return atlas.Sample(atlas_sampler, input.uv);
+}
@@ -0,0 +1,127 @@
+//! Frame sequences from animated GIFs and APNGs. Both formats store frames as changes to a canvas, here every frame is
+//! composited out to a full bitmap at load so playing one back is just picking a bitmap by time.
+
+use std::{fs, io, path::Path, sync::Arc, time::Duration};
+
+use crate::{bitmap::Bitmap, color::Pixel};
+
+#[derive(Clone, Debug)]
+pub struct AnimationFrame {
+    pub bitmap: Arc<Bitmap>,
+    /// How long the frame stays up before the next one
+    pub delay: Duration,
+}
+
+/// Frames all the same size, played in order and looped
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub frames: Vec<AnimationFrame>,
+}
+
+/// What happens to a frame's rectangle once the frame's delay is up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Dispose {
+    /// Left as it is for the next frame to draw over
+    Keep,
+    /// Cleared to transparent
+    Clear,
+    /// Put back how it was before the frame was drawn
+    Previous,
+}
+
+/// One frame as the file stores it, a rectangle of the canvas to draw
+pub(crate) struct RawFrame {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// premultiplied, width by height
+    pub pixels: Vec<Pixel>,
+    /// Drawn over the canvas when set, replacing it otherwise
+    pub blend: bool,
+    pub dispose: Dispose,
+    pub delay: Duration,
+}
+
+impl Animation {
+    /// A GIF or APNG, told apart by signature. Still PNGs and BMPs load as a single frame so anything a sprite can be
+    /// loaded from works here too
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(b"GIF8") {
+            Self::parse_gif(&bytes)
+        } else if bytes.starts_with(b"BM") {
+            Bitmap::parse_bmp(&bytes).map(Self::still)
+        } else {
+            Self::parse_apng(&bytes)
+        }
+    }
+
+    pub fn still(bitmap: Bitmap) -> Self {
+        Animation { frames: vec![AnimationFrame { bitmap: Arc::new(bitmap), delay: Duration::ZERO }] }
+    }
+
+    /// One loop through every frame
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|frame| frame.delay).sum()
+    }
+
+    /// The frame up at time, counting from the start of the first loop
+    pub fn frame_at(&self, time: Duration) -> &Arc<Bitmap> {
+        let duration = self.duration();
+        if duration.is_zero() {
+            return &self.frames[0].bitmap;
+        }
+        let mut into_loop = Duration::from_nanos((time.as_nanos() % duration.as_nanos()) as u64);
+        for frame in &self.frames {
+            if into_loop < frame.delay {
+                return &frame.bitmap;
+            }
+            into_loop -= frame.delay;
+        }
+        &self.frames[self.frames.len() - 1].bitmap
+    }
+
+    /// Plays raw frames onto a width by height canvas that starts out transparent, keeping a copy after each one
+    pub(crate) fn compose(width: i32, height: i32, raw_frames: Vec<RawFrame>) -> io::Result<Self> {
+        if raw_frames.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Bad animation: no frames"));
+        }
+        let mut canvas = vec![0 as Pixel; width as usize * height as usize];
+        let mut frames = Vec::with_capacity(raw_frames.len());
+        for raw in raw_frames {
+            let before = (raw.dispose == Dispose::Previous).then(|| canvas.clone());
+            // frames reaching past the canvas are cut off at its edges
+            let (left, top) = (raw.x.clamp(0, width), raw.y.clamp(0, height));
+            let (right, bottom) = ((raw.x + raw.width).clamp(left, width), (raw.y + raw.height).clamp(top, height));
+            for y in top..bottom {
+                let source = &raw.pixels[((y - raw.y) * raw.width + left - raw.x) as usize..][..(right - left) as usize];
+                let dest = &mut canvas[(y * width + left) as usize..(y * width + right) as usize];
+                for (dest, source) in dest.iter_mut().zip(source) {
+                    *dest = if raw.blend { over(*source, *dest) } else { *source };
+                }
+            }
+            frames.push(AnimationFrame { bitmap: Arc::new(Bitmap { width, height, pixels: canvas.clone() }), delay: raw.delay });
+            match (raw.dispose, before) {
+                (Dispose::Clear, _) => {
+                    for y in top..bottom {
+                        canvas[(y * width + left) as usize..(y * width + right) as usize].fill(0);
+                    }
+                }
+                (Dispose::Previous, Some(before)) => canvas = before,
+                _ => {}
+            }
+        }
+        Ok(Animation { frames })
+    }
+}
+
+/// Premultiplied source over destination, a channel at a time
+fn over(source: Pixel, dest: Pixel) -> Pixel {
+    let remaining = 255 - (source >> 24);
+    (0..4).fold(0, |pixel, channel| {
+        let shift = channel * 8;
+        let blended = ((source >> shift) & 0xFF) + (((dest >> shift) & 0xFF) * remaining + 127) / 255;
+        pixel | blended.min(255) << shift
+    })
+}
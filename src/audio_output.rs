@@ -0,0 +1,62 @@
+//! The seam between the samples the game makes and the device that plays them. The audio thread opens an AudioOutput
+//! at startup and tops it up every time it wakes, the game only ever makes 16 bit stereo and each backend turns that
+//! into what its device takes.
+
+use std::time::Duration;
+
+// what's assumed before any writes have been timed
+const INITIAL_FRAME_TIME: f64 = 1.0 / 30.0;
+// a stall longer than this (a breakpoint, the thread not being scheduled) isn't the usual gap, don't size the queue
+// for it
+const LONGEST_FRAME_TIME: f64 = 0.25;
+// how quickly the average follows the gap, and the worst gap is forgotten, per write
+const AVERAGE_WEIGHT: f64 = 0.05;
+const WORST_DECAY: f64 = 0.99;
+
+/// How long is going between writes, the audio thread's wakes, which is how long what's written each time has to last
+/// until the next write. Called frames here, they're the audio thread's frames and not the game's
+#[derive(Clone, Copy, Debug)]
+pub struct FrameTiming {
+    average: f64,
+    // the slowest recent frame, slowly coming back down to the average
+    worst: f64,
+}
+
+impl Default for FrameTiming {
+    fn default() -> Self {
+        FrameTiming { average: INITIAL_FRAME_TIME, worst: INITIAL_FRAME_TIME }
+    }
+}
+
+impl FrameTiming {
+    pub fn update(&mut self, frame_time: Duration) {
+        let seconds = frame_time.as_secs_f64().min(LONGEST_FRAME_TIME);
+        self.average += (seconds - self.average) * AVERAGE_WEIGHT;
+        self.worst = seconds.max(self.worst * WORST_DECAY).max(self.average);
+    }
+
+    /// Seconds usually between writes
+    pub fn frame_seconds(&self) -> f64 {
+        self.average
+    }
+
+    /// Seconds of extra to queue so a wake as late as the recent late ones doesn't run the device dry. At least a
+    /// third of the usual gap, for the jitter that never shows in the average
+    pub fn safety_seconds(&self) -> f64 {
+        (self.worst - self.average).max(self.average / 3.0)
+    }
+}
+
+pub trait AudioOutput {
+    fn name(&self) -> &'static str;
+
+    /// The rate the samples given to write play at, picked by the backend to suit the device
+    fn samples_per_second(&self) -> u32;
+
+    /// How many stereo samples write should get now to keep the device fed until the next write, as little
+    /// ahead of what's playing as the device allows
+    fn samples_to_write(&mut self, timing: &FrameTiming) -> usize;
+
+    /// Queues interleaved left, right samples to play after the ones written before
+    fn write(&mut self, samples: &[i16]);
+}
@@ -0,0 +1,282 @@
+//! Mixing and feeding the device on a thread of its own, so a slow frame can't starve the sound. The game side only
+//! pushes commands onto a lock-free queue and reads back which sounds finished off another, neither side ever waits
+//! on the other. What goes out is up to the game's get_sound_samples, this only keeps the device fed.
+
+use std::{
+    ops::Range,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audio_output::{AudioOutput, FrameTiming},
+    mixer::{Mixer, Sound},
+    music::MusicStream,
+    ring_queue::RingQueue,
+    sine_wave::SineWave,
+};
+
+// how often the thread wakes to top the device up. Short next to a frame, so what's queued can be too
+const WAKE_INTERVAL: Duration = Duration::from_millis(2);
+// a frame's worth of commands with plenty to spare, pushes past this are dropped
+const COMMAND_CAPACITY: usize = 256;
+
+/// What the game's sound is made from. Lives on the audio thread, where the game's commands change it and its
+/// get_sound_samples makes the device's samples from it
+pub struct GameSoundMemory {
+    pub mixer: Mixer,
+    pub tone: SineWave,
+}
+
+/// Samples for the game to fill, interleaved left, right, starting as silence
+pub struct GameSoundOutputBuffer<'a> {
+    pub samples_per_second: u32,
+    pub samples: &'a mut [i16],
+}
+
+/// The game's side of the sound, called whenever the device wants topping up
+pub type GetSoundSamples = fn(&mut GameSoundMemory, &mut GameSoundOutputBuffer);
+
+/// Names one play of a sound. Stays valid after the sound ends, it just stops being playing, and never names a
+/// later sound that reuses the slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SoundHandle {
+    index: usize,
+    generation: u32,
+}
+
+enum AudioCommand {
+    Play { handle: SoundHandle, sound: Arc<Sound>, volume: f32, pitch: f32, looping: Option<Range<usize>> },
+    Stop(SoundHandle),
+    StopAll,
+    SetVolume(SoundHandle, f32),
+    SetPan(SoundHandle, f32),
+    SetChannelVolumes(SoundHandle, [f32; 2]),
+    SetPitch(SoundHandle, f32),
+    PlayMusic(MusicStream, f32),
+    SetMasterVolume(f32),
+    SetMuted(bool),
+    SetTone { tone_hz: f32, volume: f32 },
+}
+
+struct Shared {
+    commands: RingQueue<AudioCommand>,
+    finished: RingQueue<SoundHandle>,
+    name: &'static str,
+    samples_per_second: u32,
+}
+
+/// The game's end of the audio thread. Without an output everything still works, it's just never heard and nothing
+/// is ever playing
+#[derive(Clone, Default)]
+pub struct Audio {
+    shared: Option<Arc<Shared>>,
+    // per slot: bumped whenever its sound finishes or is stopped, so old handles to it go stale, and whether a sound
+    // the game knows of is in it
+    generations: Vec<u32>,
+    playing: Vec<bool>,
+    muted: bool,
+}
+
+impl Audio {
+    /// Starts the audio thread, which opens its output with create so the output never has to leave it, and fills it
+    /// with get_sound_samples. create is also the place to raise the thread's priority
+    pub fn start(create: impl FnOnce() -> Option<Box<dyn AudioOutput>> + Send + 'static, get_sound_samples: GetSoundSamples) -> Self {
+        let (started, result) = mpsc::channel();
+        let spawned = thread::Builder::new().name("audio".into()).spawn(move || {
+            let Some(mut output) = create() else {
+                let _ = started.send(None);
+                return;
+            };
+            let shared = Arc::new(Shared {
+                commands: RingQueue::new(COMMAND_CAPACITY),
+                finished: RingQueue::new(COMMAND_CAPACITY),
+                name: output.name(),
+                samples_per_second: output.samples_per_second(),
+            });
+            if started.send(Some(shared.clone())).is_ok() {
+                run(&shared, output.as_mut(), get_sound_samples);
+            }
+        });
+        if let Err(error) = spawned {
+            println!("Failed to start the audio thread: {error}");
+            return Audio::default();
+        }
+        Audio { shared: result.recv().ok().flatten(), generations: Vec::new(), playing: Vec::new(), muted: false }
+    }
+
+    /// The output's name and rate, None when there's no sound
+    pub fn output(&self) -> Option<(&'static str, u32)> {
+        self.shared.as_ref().map(|shared| (shared.name, shared.samples_per_second))
+    }
+
+    /// Catches up on the sounds the audio thread finished, once a frame before the game asks after any
+    pub fn update(&mut self) {
+        let Some(shared) = self.shared.clone() else {
+            return;
+        };
+        while let Some(handle) = shared.finished.pop() {
+            // a handle the game already stopped, its slot may even be playing something else now
+            if self.is_playing(handle) {
+                self.free(handle.index);
+            }
+        }
+    }
+
+    /// Starts sound from the beginning. volume 1 is as recorded, pitch 1 is as recorded, 2 an octave up and twice as fast
+    pub fn play(&mut self, sound: &Arc<Sound>, volume: f32, pitch: f32) -> SoundHandle {
+        self.start_voice(sound, volume, pitch, None)
+    }
+
+    /// Plays sound round and round until it's stopped: from the beginning to the end of loop_points, then from their
+    /// start. Without loop_points it goes round the sound's own, from its file, or failing that the whole sound
+    pub fn play_looping(&mut self, sound: &Arc<Sound>, volume: f32, pitch: f32, loop_points: Option<Range<usize>>) -> SoundHandle {
+        let looping = loop_points.or_else(|| sound.loop_points.clone()).unwrap_or(0..sound.length());
+        self.start_voice(sound, volume, pitch, Some(looping))
+    }
+
+    fn start_voice(&mut self, sound: &Arc<Sound>, volume: f32, pitch: f32, looping: Option<Range<usize>>) -> SoundHandle {
+        let index = match self.playing.iter().position(|playing| !playing) {
+            Some(index) => index,
+            None => {
+                self.playing.push(false);
+                self.generations.push(0);
+                self.playing.len() - 1
+            }
+        };
+        let handle = SoundHandle { index, generation: self.generations[index] };
+        if self.send(AudioCommand::Play { handle, sound: sound.clone(), volume, pitch, looping }) {
+            self.playing[index] = true;
+        } else {
+            // never heard, but the handle mustn't name the slot's next sound either
+            self.generations[index] = self.generations[index].wrapping_add(1);
+        }
+        handle
+    }
+
+    pub fn stop(&mut self, handle: SoundHandle) {
+        if self.is_playing(handle) {
+            self.send(AudioCommand::Stop(handle));
+            self.free(handle.index);
+        }
+    }
+
+    /// Stops every sound, leaving the music and tone be. Every handle given out so far goes stale, the slots' next
+    /// sounds get new ones
+    pub fn stop_all(&mut self) {
+        self.send(AudioCommand::StopAll);
+        for index in 0..self.playing.len() {
+            self.free(index);
+        }
+    }
+
+    pub fn set_volume(&mut self, handle: SoundHandle, volume: f32) {
+        if self.is_playing(handle) {
+            self.send(AudioCommand::SetVolume(handle, volume));
+        }
+    }
+
+    /// Places the sound across the stereo field, -1 all the way left to 1 all the way right
+    pub fn set_pan(&mut self, handle: SoundHandle, pan: f32) {
+        if self.is_playing(handle) {
+            self.send(AudioCommand::SetPan(handle, pan));
+        }
+    }
+
+    /// Left and right volumes of their own, on top of the volume and pan
+    pub fn set_channel_volumes(&mut self, handle: SoundHandle, left: f32, right: f32) {
+        if self.is_playing(handle) {
+            self.send(AudioCommand::SetChannelVolumes(handle, [left, right]));
+        }
+    }
+
+    /// Changes how fast the sound plays from here on, and so its pitch, 2 an octave up. 0 holds it where it is
+    pub fn set_pitch(&mut self, handle: SoundHandle, pitch: f32) {
+        if self.is_playing(handle) {
+            self.send(AudioCommand::SetPitch(handle, pitch));
+        }
+    }
+
+    pub fn is_playing(&self, handle: SoundHandle) -> bool {
+        self.generations.get(handle.index) == Some(&handle.generation) && self.playing[handle.index]
+    }
+
+    /// Plays a music stream in place of any already playing
+    pub fn play_music(&mut self, stream: MusicStream, volume: f32) {
+        self.send(AudioCommand::PlayMusic(stream, volume));
+    }
+
+    /// Over everything that's heard, 1 as it's mixed. Volume changes slide rather than jump, this one and the voices'
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.send(AudioCommand::SetMasterVolume(volume));
+    }
+
+    /// Mutes everything, or unmutes back to the master volume. Returns whether it's now muted
+    pub fn toggle_mute(&mut self) -> bool {
+        if self.send(AudioCommand::SetMuted(!self.muted)) {
+            self.muted = !self.muted;
+        }
+        self.muted
+    }
+
+    /// The test tone underneath everything, volume out of i16::MAX, 0 for none
+    pub fn set_tone(&mut self, tone_hz: f32, volume: f32) {
+        self.send(AudioCommand::SetTone { tone_hz, volume });
+    }
+
+    // false when the command didn't make it, there's no audio or the queue's full
+    fn send(&self, command: AudioCommand) -> bool {
+        self.shared.as_ref().is_some_and(|shared| shared.commands.push(command).is_ok())
+    }
+
+    fn free(&mut self, index: usize) {
+        self.playing[index] = false;
+        self.generations[index] = self.generations[index].wrapping_add(1);
+    }
+}
+
+// mixes and writes until the game's end is gone
+fn run(shared: &Arc<Shared>, output: &mut dyn AudioOutput, get_sound_samples: GetSoundSamples) {
+    let mut memory = GameSoundMemory { mixer: Mixer::default(), tone: SineWave::new(256.0, 0.0) };
+    let mut timing = FrameTiming::default();
+    let mut last_wake = Instant::now();
+    let mut samples = Vec::new();
+    // finished handles that didn't fit in the queue yet
+    let mut unsent = Vec::new();
+    while Arc::strong_count(shared) > 1 {
+        while let Some(command) = shared.commands.pop() {
+            match command {
+                AudioCommand::Play { handle, sound, volume, pitch, looping } => memory.mixer.play(handle, sound, volume, pitch, looping),
+                AudioCommand::Stop(handle) => memory.mixer.stop(handle),
+                AudioCommand::StopAll => memory.mixer.stop_all(),
+                AudioCommand::SetVolume(handle, volume) => memory.mixer.set_volume(handle, volume),
+                AudioCommand::SetPan(handle, pan) => memory.mixer.set_pan(handle, pan),
+                AudioCommand::SetChannelVolumes(handle, [left, right]) => memory.mixer.set_channel_volumes(handle, left, right),
+                AudioCommand::SetPitch(handle, pitch) => memory.mixer.set_pitch(handle, pitch),
+                AudioCommand::PlayMusic(stream, volume) => memory.mixer.play_music(stream, volume),
+                AudioCommand::SetMasterVolume(volume) => memory.mixer.set_master_volume(volume),
+                AudioCommand::SetMuted(muted) => memory.mixer.set_muted(muted),
+                AudioCommand::SetTone { tone_hz, volume } => {
+                    memory.tone.tone_hz = tone_hz;
+                    memory.tone.volume = volume;
+                }
+            }
+        }
+
+        // the time between wakes is this thread's frame, what's written has to last until the next one
+        let now = Instant::now();
+        timing.update(now - last_wake);
+        last_wake = now;
+        samples.clear();
+        samples.resize(output.samples_to_write(&timing) * 2, 0);
+        if !samples.is_empty() {
+            get_sound_samples(&mut memory, &mut GameSoundOutputBuffer { samples_per_second: output.samples_per_second(), samples: &mut samples });
+            output.write(&samples);
+        }
+
+        unsent.extend(memory.mixer.take_finished());
+        unsent.retain(|handle| shared.finished.push(*handle).is_err());
+        thread::sleep(WAKE_INTERVAL);
+    }
+}
@@ -0,0 +1,111 @@
+use std::io;
+
+use crate::color::{pack_rgba8, premultiply, Pixel};
+
+/// Decoded image, top row first, pixels packed like the backbuffer with premultiplied alpha
+#[derive(Clone, Debug)]
+pub struct Bitmap {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<Pixel>,
+}
+
+impl Bitmap {
+    /// Uncompressed 24 and 32 bit BMPs, including BI_BITFIELDS / V4 / V5 headers with their channel masks
+    pub fn parse_bmp(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Bad BMP: {message}"));
+        let u16_at = |offset: usize| bytes.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let u32_at = |offset: usize| bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+        if bytes.get(0..2) != Some(b"BM") {
+            return Err(invalid("missing BM signature"));
+        }
+        let header = (|| {
+            Some((
+                u32_at(10)? as usize,
+                u32_at(14)?,
+                u32_at(18)? as i32,
+                u32_at(22)? as i32,
+                u16_at(28)?,
+                u32_at(30)?,
+            ))
+        })();
+        let Some((pixel_offset, header_size, width, height, bits_per_pixel, compression)) = header else {
+            return Err(invalid("truncated header"));
+        };
+        if width <= 0 || height == 0 {
+            return Err(invalid("empty image"));
+        }
+
+        const BI_RGB: u32 = 0;
+        const BI_BITFIELDS: u32 = 3;
+        const BI_ALPHABITFIELDS: u32 = 6;
+        // the masks sit right after the 40 byte info header, inside it for V4/V5 headers
+        let masks = match (bits_per_pixel, compression) {
+            (24, BI_RGB) => None,
+            (32, BI_RGB) => Some([0x00FF0000, 0x0000FF00, 0x000000FF, 0xFF000000]),
+            (32, BI_BITFIELDS | BI_ALPHABITFIELDS) => {
+                let alpha = if header_size >= 56 || compression == BI_ALPHABITFIELDS { u32_at(66) } else { Some(0) };
+                match (u32_at(54), u32_at(58), u32_at(62), alpha) {
+                    (Some(red), Some(green), Some(blue), Some(alpha)) => Some([red, green, blue, alpha]),
+                    _ => return Err(invalid("truncated channel masks")),
+                }
+            }
+            _ => return Err(invalid(&format!("unsupported format ({bits_per_pixel} bpp, compression {compression})"))),
+        };
+
+        // positive height means the rows are stored bottom-up
+        let bottom_up = height > 0;
+        let (width, height) = (width, height.abs());
+        let bytes_per_pixel = bits_per_pixel as usize / 8;
+        let row_size = (width as usize * bytes_per_pixel).div_ceil(4) * 4;
+        let pixel_data = bytes
+            .get(pixel_offset..)
+            .filter(|data| data.len() >= row_size * height as usize)
+            .ok_or_else(|| invalid("truncated pixel data"))?;
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as usize {
+            let source_row = if bottom_up { height as usize - 1 - y } else { y };
+            let row = &pixel_data[source_row * row_size..][..width as usize * bytes_per_pixel];
+            for source in row.chunks_exact(bytes_per_pixel) {
+                let pixel = match masks {
+                    None => pack_rgba8(source[2], source[1], source[0], 255),
+                    Some([red, green, blue, alpha]) => {
+                        let value = u32::from_le_bytes([source[0], source[1], source[2], source[3]]);
+                        let alpha = if alpha == 0 { 255 } else { extract_channel(value, alpha) };
+                        pack_rgba8(extract_channel(value, red), extract_channel(value, green), extract_channel(value, blue), alpha)
+                    }
+                };
+                pixels.push(pixel);
+            }
+        }
+
+        // plain 32 bit BMPs usually leave the fourth byte at zero, only trust it if something in there is set
+        if masks.is_some() && compression == BI_RGB && pixels.iter().all(|pixel| pixel >> 24 == 0) {
+            for pixel in pixels.iter_mut() {
+                *pixel |= 0xFF000000;
+            }
+        }
+        // files store straight alpha, the blitters all expect it premultiplied
+        for pixel in pixels.iter_mut() {
+            *pixel = premultiply(*pixel);
+        }
+
+        Ok(Bitmap { width, height, pixels })
+    }
+}
+
+/// Pulls the masked bits out and stretches them to 0..255
+fn extract_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = mask.count_ones();
+    let channel = (value & mask) >> mask.trailing_zeros();
+    if bits == 8 {
+        channel as u8
+    } else {
+        (channel as u64 * 255 / ((1u64 << bits) - 1)) as u8
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::OnceLock;
+
+/// One backbuffer pixel, 0xAARRGGBB in a register. Little endian stores it as B G R A, which is the 32 bit DIB layout.
+pub type Pixel = u32;
+
+pub const fn pack_rgba8(r: u8, g: u8, b: u8, a: u8) -> Pixel {
+    (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+/// Scales the color channels by the pixel's alpha, what bitmaps are converted to when they load
+pub fn premultiply(pixel: Pixel) -> Pixel {
+    let alpha = pixel >> 24;
+    let channel = |shift: u32| ((((pixel >> shift) & 0xFF) * alpha + 127) / 255) << shift;
+    alpha << 24 | channel(16) | channel(8) | channel(0)
+}
+
+/// Exact sRGB transfer curve, 0.0..1.0 in and out
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Lookup tables for blending in linear light without a powf per channel
+pub struct GammaTables {
+    /// sRGB byte to linear, scaled to 0..=65535
+    pub to_linear: [u16; 256],
+    /// 12 bit linear (the top bits of to_linear's range) back to the nearest sRGB byte
+    pub to_srgb: [u8; 4096],
+}
+
+/// Built on first use and shared by every thread after that
+pub fn gamma_tables() -> &'static GammaTables {
+    static TABLES: OnceLock<GammaTables> = OnceLock::new();
+    TABLES.get_or_init(|| GammaTables {
+        to_linear: std::array::from_fn(|index| (srgb_to_linear(index as f32 / 255.0) * 65535.0 + 0.5) as u16),
+        to_srgb: std::array::from_fn(|index| (linear_to_srgb(index as f32 / 4095.0) * 255.0 + 0.5) as u8),
+    })
+}
+
+/// Color with float channels in 0.0..1.0, alpha straight (not premultiplied).
+/// Drawing code takes these and packs them to a Pixel once per draw call, the per pixel loops stay on packed values.
+/// The default is transparent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb8(0, 0, 0);
+    pub const WHITE: Color = Color::rgb8(255, 255, 255);
+    pub const TRANSPARENT: Color = Color::rgba8(0, 0, 0, 0);
+
+    pub const fn from_rgba_f32(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color { r, g, b, a }
+    }
+
+    pub const fn rgba8(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color::from_rgba_f32(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0)
+    }
+
+    pub const fn rgb8(r: u8, g: u8, b: u8) -> Self {
+        Color::rgba8(r, g, b, 255)
+    }
+
+    /// Clamped and rounded to 8 bits per channel
+    pub fn to_pixel(self) -> Pixel {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+        pack_rgba8(channel(self.r), channel(self.g), channel(self.b), channel(self.a))
+    }
+
+    pub fn premultiplied(self) -> Self {
+        Color::from_rgba_f32(self.r * self.a, self.g * self.a, self.b * self.a, self.a)
+    }
+
+    pub fn lerp(self, other: Color, t: f32) -> Self {
+        let mix = |from: f32, to: f32| from + (to - from) * t;
+        Color::from_rgba_f32(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b), mix(self.a, other.a))
+    }
+
+    /// Alpha is linear already and passes through unchanged
+    pub fn to_linear(self) -> Self {
+        Color::from_rgba_f32(srgb_to_linear(self.r), srgb_to_linear(self.g), srgb_to_linear(self.b), self.a)
+    }
+
+    pub fn to_srgb(self) -> Self {
+        Color::from_rgba_f32(linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b), self.a)
+    }
+}
+
+/// How pixels are stored in the backbuffer the window is presented from. Drawing always happens in Pixel,
+/// the 16 bit format is converted to once a frame is finished
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelFormat {
+    #[default]
+    Bgra32,
+    /// 5 bits of red, 6 of green and 5 of blue, half the bytes to push to the screen each frame
+    Rgb565,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> i32 {
+        match self {
+            PixelFormat::Bgra32 => 4,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+
+    /// Bytes per row of a width pixels wide dib, windows wants rows to start on a multiple of 4
+    pub fn pitch(self, width: i32) -> i32 {
+        (width * self.bytes_per_pixel() + 3) & !3
+    }
+
+    /// Where red, green and blue are in one stored pixel
+    pub fn channel_masks(self) -> [u32; 3] {
+        match self {
+            PixelFormat::Bgra32 => [0x00FF0000, 0x0000FF00, 0x000000FF],
+            PixelFormat::Rgb565 => [0xF800, 0x07E0, 0x001F],
+        }
+    }
+}
+
+// 4x4 ordered dither thresholds, 0..16 spread so neighbours are far apart
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// pixel at x, y down to 5:6:5. The dither breaks the banding from dropping bits into a fine pattern, smooth
+/// gradients would show steps of 8 levels otherwise. Alpha is dropped, the backbuffer is opaque anyway
+pub fn pack_rgb565(pixel: Pixel, x: usize, y: usize) -> u16 {
+    let threshold = BAYER_4X4[y & 3][x & 3] as u32;
+    // a threshold from 0 up to just under one step of the channel's new precision
+    let reduce = |shift: u32, bits: u32| ((((pixel >> shift) & 0xFF) + (threshold >> (bits - 4))).min(255) >> (8 - bits)) as u16;
+    reduce(16, 5) << 11 | reduce(8, 6) << 5 | reduce(0, 5)
+}
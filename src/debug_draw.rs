@@ -0,0 +1,104 @@
+//! Immediate mode debug shapes in world units. Any code can call the debug_draw_* functions without a render group
+//! in reach: shapes collect here and the platform draws them all in an overlay pass over the frame, with the game's
+//! camera, until their duration runs out. A zero duration shape is drawn on the next frame only.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    color::Color,
+    font::Font,
+    math::V2,
+    render_group::{Camera, Depth, RenderGroup, SortKey},
+    text_layout::TextLayout,
+};
+
+enum Shape {
+    Line { from: V2, to: V2 },
+    Rect { min: V2, max: V2 },
+    Circle { center: V2, radius: f32 },
+    Text { position: V2, text: String },
+    Message { text: String },
+}
+
+struct DebugShape {
+    shape: Shape,
+    color: Color,
+    expires: Instant,
+}
+
+static DEBUG_SHAPES: Mutex<Vec<DebugShape>> = Mutex::new(Vec::new());
+
+fn add(shape: Shape, color: Color, duration: Duration) {
+    DEBUG_SHAPES.lock().unwrap().push(DebugShape { shape, color, expires: Instant::now() + duration });
+}
+
+pub fn debug_draw_line(from: V2, to: V2, color: Color, duration: Duration) {
+    add(Shape::Line { from, to }, color, duration);
+}
+
+/// Outline only, so it doesn't hide what it's pointing at
+pub fn debug_draw_rect(min: V2, max: V2, color: Color, duration: Duration) {
+    add(Shape::Rect { min, max }, color, duration);
+}
+
+pub fn debug_draw_circle(center: V2, radius: f32, color: Color, duration: Duration) {
+    add(Shape::Circle { center, radius }, color, duration);
+}
+
+/// Top left of the text at position, it stays the font's pixel size whatever the zoom
+pub fn debug_draw_text(position: V2, text: &str, color: Color, duration: Duration) {
+    add(Shape::Text { position, text: text.to_string() }, color, duration);
+}
+
+/// Screen text in the top left corner, under any other messages, for things going wrong that there's nowhere better
+/// to show
+pub fn debug_draw_message(text: &str, color: Color, duration: Duration) {
+    add(Shape::Message { text: text.to_string() }, color, duration);
+}
+
+/// The overlay pass: pushes every live shape on a layer above everything else, then forgets the ones that have run out.
+/// Text needs a font and is skipped without one. Leaves the group's camera and sort key how it found them.
+pub fn push_debug_shapes(render_group: &mut RenderGroup, camera: Option<Camera>, font: Option<&Arc<Font>>) {
+    let mut shapes = DEBUG_SHAPES.lock().unwrap();
+    if shapes.is_empty() {
+        return;
+    }
+    let (previous_camera, previous_sort_key) = (render_group.camera, render_group.sort_key);
+    render_group.camera = camera;
+    render_group.sort_key = SortKey { layer: i32::MAX, depth: Depth::PushOrder };
+    let mut message_y = 8.0;
+    for DebugShape { shape, color, .. } in shapes.iter() {
+        let color = *color;
+        match shape {
+            Shape::Line { from, to } => render_group.push_line(from.x, from.y, to.x, to.y, color),
+            Shape::Rect { min, max } => {
+                let corners = [*min, V2::new(max.x, min.y), *max, V2::new(min.x, max.y)];
+                for (from, to) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                    render_group.push_line(from.x, from.y, to.x, to.y, color);
+                }
+            }
+            Shape::Circle { center, radius } => render_group.push_circle_outline(center.x, center.y, *radius, color),
+            Shape::Text { position, text } => {
+                if let Some(font) = font {
+                    render_group.push_text_block(font, &TextLayout::default().layout(font, text), position.x, position.y, color);
+                }
+            }
+            Shape::Message { text } => {
+                if let Some(font) = font {
+                    let block = TextLayout::default().layout(font, text);
+                    render_group.camera = None;
+                    render_group.push_text_block(font, &block, 8.0, message_y, color);
+                    render_group.camera = camera;
+                    message_y += block.height + 4.0;
+                }
+            }
+        }
+    }
+    let now = Instant::now();
+    shapes.retain(|shape| shape.expires > now);
+    render_group.camera = previous_camera;
+    render_group.sort_key = previous_sort_key;
+}
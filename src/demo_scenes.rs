@@ -0,0 +1,382 @@
+//! The selectable demo scenes, ctrl+F1..F5 switches between them. Each one is a visual test for some part of the
+//! platform layer, a new platform feature gets its own scene by implementing Scene and going in SCENES.
+
+use std::{f32::consts::TAU, time::Duration};
+
+use windows::Win32::UI::Input::KeyboardAndMouse::{VK_B, VK_SPACE, VK_TAB};
+
+use crate::{
+    color::Color,
+    debug_draw::{debug_draw_line, debug_draw_rect},
+    game_input::{ButtonState, GameInput, MouseButton},
+    math::V2,
+    post_process::PostPass,
+    render::{BitmapFilter, Gradient, NineSlice, Particle, PointLight, SliceFill},
+    render_group::{Depth, RenderGroup, SortKey},
+    test_pattern::TestPattern,
+    GameState,
+};
+
+pub trait Scene {
+    fn name(&self) -> &'static str;
+    /// Runs after the shared input handling in game_update_and_render, so the gradient offsets and camera have
+    /// already moved for this frame
+    fn update_and_render(&self, state: &mut GameState, input: &GameInput, render_group: &mut RenderGroup);
+}
+
+pub const SCENES: [&dyn Scene; 5] = [&GradientScene, &BouncingSprites, &AudioVisualizer, &InputTester, &TestPatterns];
+
+/// The original showcase: the scrolling gradient with every drawing feature somewhere in the world over it
+struct GradientScene;
+
+impl Scene for GradientScene {
+    fn name(&self) -> &'static str {
+        "Gradient"
+    }
+
+    fn update_and_render(&self, state: &mut GameState, input: &GameInput, render_group: &mut RenderGroup) {
+        let GameInput { time, keyboard, mouse, touch, .. } = input;
+        let GameState { x_anim, y_anim, players, sprite, sprite_filter, sphere, sphere_normals, environment, panel, camera, .. } = state;
+        let gamepads = players.gamepad(0, &input.gamepads).map(std::slice::from_ref).unwrap_or(&[]);
+        let mouse_position = V2::new(mouse.x as f32, mouse.y as f32);
+
+        render_group.push_clear(Color::BLACK);
+        render_group.push_gradient(*x_anim, *y_anim);
+        // from here on positions are in world units, until the screen space overlay at the end
+        render_group.camera = Some(*camera);
+        // player 1's left stick as a vector from the world origin
+        if let Some(gamepad) = gamepads.first() {
+            render_group.push_line(0.0, 0.0, gamepad.sticks.left_x * 100.0, -gamepad.sticks.left_y * 100.0, Color::rgb8(0xFF, 0xFF, 0x00));
+        }
+        let triangle = [V2::new(-260.0, 60.0), V2::new(-200.0, -60.0), V2::new(-140.0, 60.0)];
+        render_group.push_triangle(triangle, [Color::rgb8(0xFF, 0, 0), Color::rgb8(0, 0xFF, 0), Color::rgb8(0, 0, 0xFF)]);
+        // and gradient fills on the other side of the origin to compare with
+        let corners = [Color::rgb8(0xFF, 0, 0), Color::rgb8(0, 0xFF, 0), Color::rgb8(0, 0, 0xFF), Color::WHITE];
+        render_group.push_gradient_rect(140.0, -60.0, 260.0, 60.0, Gradient::Corners(corners));
+        render_group.push_gradient_rect(140.0, 70.0, 260.0, 80.0, Gradient::Horizontal(Color::rgb8(0xFF, 0x40, 0x40), Color::rgb8(0x40, 0xFF, 0x40)));
+        // 20 pixels a second is well under one a frame, this should glide rather than step
+        let drift = (time.as_secs_f32() * 20.0) % 200.0 - 100.0;
+        render_group.push_rect(drift - 8.0, 120.0, drift + 8.0, 136.0, Color::rgb8(0x80, 0xFF, 0x80));
+        // the sprite spins at the world origin and breathes between 1x and 2x under the mouse,
+        // B flips between nearest and bilinear sampling
+        if keyboard.was_pressed(VK_B) {
+            *sprite_filter = match sprite_filter {
+                BitmapFilter::Nearest => BitmapFilter::Bilinear,
+                BitmapFilter::Bilinear => BitmapFilter::Nearest,
+            };
+        }
+        if let Some(sprite) = sprite {
+            let angle = time.as_secs_f32();
+            let x_axis = V2::new(angle.cos(), angle.sin()) * sprite.width as f32;
+            let y_axis = V2::new(angle.cos(), angle.sin()).perp() * sprite.height as f32;
+            render_group.push_bitmap_transformed(sprite, (x_axis + y_axis) * -0.5, x_axis, y_axis, *sprite_filter);
+
+            let scale = 1.5 + 0.5 * (time.as_secs_f32() * 2.0).sin();
+            let (width, height) = (sprite.width as f32 * scale, sprite.height as f32 * scale);
+            let under_mouse = render_group.unproject(mouse_position);
+            render_group.push_bitmap_scaled(sprite, under_mouse.x - width / 2.0, under_mouse.y - height / 2.0, width, height, *sprite_filter);
+        }
+
+        // the ball above the origin is lit by a light following the mouse and a slower orange one going round it
+        render_group.push_light(PointLight { position: render_group.unproject(mouse_position), height: 60.0, radius: 400.0, color: Color::WHITE });
+        let orbit = time.as_secs_f32() * 0.7;
+        let orbit_position = V2::new(0.0, -160.0) + V2::new(orbit.cos(), orbit.sin()) * 90.0;
+        render_group.push_light(PointLight { position: orbit_position, height: 30.0, radius: 200.0, color: Color::rgb8(0xFF, 0x80, 0x20) });
+        let half = sphere.width as f32 / 2.0;
+        render_group.push_bitmap_lit(sphere, sphere_normals, -half, -160.0 - half, Color::rgb8(0x18, 0x18, 0x20));
+
+        // and a shiny copy below the origin, rolling so its reflection has to follow the normals round
+        render_group.environment = Some(environment.clone());
+        let roll = time.as_secs_f32() * 0.5;
+        let x_axis = V2::new(roll.cos(), roll.sin()) * sphere.width as f32;
+        let y_axis = V2::new(roll.cos(), roll.sin()).perp() * sphere.height as f32;
+        render_group.push_bitmap_reflective(sphere, sphere_normals, V2::new(0.0, 200.0) - (x_axis + y_axis) * 0.5, x_axis, y_axis, 0.8);
+        // a fountain of sparks and some bubbles drifting up next to it, worked out from the time alone so there's no
+        // particle state to keep. Every particle relaunches when its two second life runs out
+        let sparks: Vec<Particle> = (0..1500)
+            .map(|index| {
+                let age = (time.as_secs_f32() + index as f32 * 2.0 / 1500.0) % 2.0;
+                // multiplying by a prime scatters neighbouring indices over -1..1
+                let spread = (index * 7919 % 1000) as f32 / 500.0 - 1.0;
+                let velocity = V2::new(spread * 60.0, -260.0 - (index % 7) as f32 * 10.0);
+                let position = V2::new(220.0, 240.0) + velocity * age + V2::new(0.0, 150.0) * (age * age);
+                let color = Color::rgb8(0xFF, 0xD0, 0x60).lerp(Color::rgba8(0xFF, 0x40, 0x00, 0), age / 2.0);
+                Particle { position, size: 3.0, color }
+            })
+            .collect();
+        render_group.push_particles(&sparks, None);
+        let bubbles: Vec<Particle> = (0..60)
+            .map(|index| {
+                let age = (time.as_secs_f32() + index as f32 * 4.0 / 60.0) % 4.0;
+                let wobble = (age * 3.0 + index as f32).sin() * 6.0;
+                let position = V2::new(300.0 + (index % 5) as f32 * 8.0 + wobble, 240.0 - age * 60.0);
+                Particle { position, size: 6.0 + (index % 3) as f32 * 4.0, color: Color::rgba8(0x80, 0xC0, 0xFF, 0xC0) }
+            })
+            .collect();
+        render_group.push_particles(&bubbles, Some(sphere));
+        // roughly how far the sparks and bubbles get, and which way the orbiting light is shining from
+        debug_draw_rect(V2::new(100.0, 0.0), V2::new(350.0, 320.0), Color::rgb8(0x00, 0xFF, 0xFF), Duration::ZERO);
+        debug_draw_line(orbit_position, V2::new(0.0, -160.0), Color::rgb8(0xFF, 0x80, 0x20), Duration::ZERO);
+        // three balls circling each other, pushed in the same order every frame but drawn nearest (lowest) last,
+        // on a layer of their own so the y sort doesn't mix them up with the push ordered world
+        render_group.sort_key = SortKey { layer: 1, depth: Depth::Y };
+        let colors = [Color::rgb8(0xFF, 0x60, 0x60), Color::rgb8(0x60, 0xFF, 0x60), Color::rgb8(0x60, 0x60, 0xFF)];
+        for (index, color) in colors.into_iter().enumerate() {
+            let angle = time.as_secs_f32() + index as f32 * std::f32::consts::TAU / 3.0;
+            render_group.push_circle(-200.0 + angle.cos() * 30.0, 200.0 + angle.sin() * 12.0, 20.0, color);
+        }
+        // their shadow is pushed after them but forced underneath
+        render_group.sort_key.depth = Depth::Z(f32::MIN);
+        render_group.push_circle(-200.0, 230.0, 40.0, Color::rgba8(0, 0, 0, 0x60));
+
+        render_group.camera = None;
+        // the overlay goes over everything in the world, whatever its keys
+        render_group.sort_key = SortKey { layer: 2, depth: Depth::PushOrder };
+        render_group.push_vignette(0.6);
+        // an unscaled copy of the sprite in the corner to compare against
+        if let Some(sprite) = sprite {
+            render_group.push_bitmap(sprite, 8.0, 8.0);
+        }
+        // a panel drawn into its own target: the ball bouncing in it is cut off at the panel's edges, and the whole
+        // panel shakes for a moment every couple of seconds without anything inside it having to know
+        let stretched = NineSlice { left: 4, top: 4, right: 4, bottom: 4, fill: SliceFill::Stretch };
+        let target = render_group.push_target(160, 100);
+        render_group.push_bitmap_nine_slice(panel, stretched, 0.0, 0.0, 160.0, 100.0);
+        let bounce = time.as_secs_f32() * 3.0;
+        render_group.push_circle(80.0 + bounce.sin() * 90.0, 50.0 + (bounce * 1.3).cos() * 60.0, 20.0, Color::rgb8(0xFF, 0xC0, 0x40));
+        render_group.pop_target();
+        let shake = if time.as_secs_f32() % 2.0 < 0.3 { (time.as_secs_f32() * 90.0).sin() * 4.0 } else { 0.0 };
+        render_group.push_target_output(target, render_group.width as f32 - 176.0 + shake, 16.0, 160.0, 100.0);
+        // and a tiled box under it that keeps changing width, the corners shouldn't budge
+        let width = 96.0 + 64.0 * (time.as_secs_f32() * 1.5).sin();
+        let box_x = render_group.width as f32 - 16.0 - width;
+        render_group.push_bitmap_nine_slice(panel, NineSlice { fill: SliceFill::Tile, ..stretched }, box_x, 124.0, width, 32.0);
+        // with a ticker of dots scrolling through it that mustn't spill past its border
+        render_group.push_clip_rect(box_x as i32 + 4, 128, width as i32 - 8, 24);
+        let scroll = (time.as_secs_f32() * 40.0) % 24.0;
+        for index in 0..20 {
+            render_group.push_circle(box_x + index as f32 * 24.0 - scroll, 140.0, 6.0, Color::rgb8(0x80, 0xFF, 0xC0));
+        }
+        render_group.pop_clip_rect();
+        // a dot under every finger and a ring around pen tips, bigger and brighter the harder it presses
+        for point in touch.points().filter(|point| !point.released) {
+            let radius = 8.0 + point.pressure * 24.0;
+            let (x, y) = (point.x as f32, point.y as f32);
+            let base = if point.pen { Color::rgb8(0x40, 0xC0, 0xFF) } else { Color::rgb8(0xFF, 0x80, 0x40) };
+            // mixed in linear light so half pressure looks half way to white
+            let color = base.to_linear().lerp(Color::WHITE, point.pressure * 0.5).to_srgb();
+            if point.pen {
+                render_group.push_circle_outline(x, y, radius, color);
+            } else {
+                render_group.push_circle(x, y, radius, color);
+            }
+        }
+        // middle drag shows a selection box, stand-in for what debug UI will do with drags
+        if let Some(((origin_x, origin_y), (delta_x, delta_y))) = mouse.drag(MouseButton::Middle) {
+            render_group.push_rect_outline(origin_x, origin_y, origin_x + delta_x, origin_y + delta_y, Color::WHITE);
+        }
+    }
+}
+
+/// A crowd of sprites bouncing round the window, placed from the time alone so there's nothing to keep between frames.
+/// Uses the --sprite bitmap when there is one and the ball otherwise
+struct BouncingSprites;
+
+impl Scene for BouncingSprites {
+    fn name(&self) -> &'static str {
+        "Bouncing sprites"
+    }
+
+    fn update_and_render(&self, state: &mut GameState, input: &GameInput, render_group: &mut RenderGroup) {
+        render_group.push_clear(Color::rgb8(0x10, 0x10, 0x18));
+        let bitmap = state.sprite.as_ref().unwrap_or(&state.sphere);
+        let room_x = (render_group.width - bitmap.width).max(1) as f32;
+        let room_y = (render_group.height - bitmap.height).max(1) as f32;
+        // the distance travelled folded back and forth across the room is the same as bouncing off its walls
+        let bounce = |distance: f32, room: f32| {
+            let folded = distance.rem_euclid(2.0 * room);
+            if folded > room { 2.0 * room - folded } else { folded }
+        };
+        let seconds = input.time.as_secs_f32();
+        for index in 0..64 {
+            let speed_x = 80.0 + (index * 37 % 100) as f32 * 2.0;
+            let speed_y = 60.0 + (index * 53 % 100) as f32 * 2.0;
+            let x = bounce(index as f32 * 97.0 + seconds * speed_x, room_x);
+            let y = bounce(index as f32 * 61.0 + seconds * speed_y, room_y);
+            render_group.push_bitmap(bitmap, x, y);
+        }
+    }
+}
+
+const VISUALIZER_SAMPLE_RATE: f64 = 48000.0;
+const VISUALIZER_WINDOW: usize = 512;
+const VISUALIZER_BARS: usize = 48;
+
+/// Oscilloscope over spectrum bars, of a synthesized chord with sliding notes rather than what's playing. The mix
+/// only exists on the audio thread and nothing of it comes back to the game, so the scope shows a stand-in signal on
+/// purpose, one that's the same for the same time whether or not there's a device
+struct AudioVisualizer;
+
+impl Scene for AudioVisualizer {
+    fn name(&self) -> &'static str {
+        "Audio visualizer"
+    }
+
+    fn update_and_render(&self, _state: &mut GameState, input: &GameInput, render_group: &mut RenderGroup) {
+        render_group.push_clear(Color::BLACK);
+        let seconds = input.time.as_secs_f64();
+        let notes = [220.0 * (1.0 + 0.5 * (seconds * 0.3).sin()), 330.0, 880.0 + 440.0 * (seconds * 0.7).sin()];
+        // phases in f64, f32 seconds times a frequency runs out of precision after a few minutes
+        let samples: Vec<f32> = (0..VISUALIZER_WINDOW)
+            .map(|index| {
+                let t = seconds + index as f64 / VISUALIZER_SAMPLE_RATE;
+                let voices = notes.iter().enumerate().map(|(voice, frequency)| ((t * frequency).fract() as f32 * TAU).sin() / (voice + 1) as f32);
+                voices.sum::<f32>() / 1.9
+            })
+            .collect();
+
+        let (width, height) = (render_group.width as f32, render_group.height as f32);
+        let scope_y = height / 4.0;
+        let step = width / (VISUALIZER_WINDOW - 1) as f32;
+        for (index, pair) in samples.windows(2).enumerate() {
+            let x = index as f32 * step;
+            render_group.push_line(x, scope_y - pair[0] * height / 5.0, x + step, scope_y - pair[1] * height / 5.0, Color::rgb8(0x40, 0xFF, 0x80));
+        }
+
+        // a plain DFT of the first few bins is cheap enough at this size, hann windowed so the bars don't smear
+        let bar_width = width / VISUALIZER_BARS as f32;
+        for bar in 0..VISUALIZER_BARS {
+            let bin = (bar + 1) as f32;
+            let (mut real, mut imaginary) = (0.0, 0.0);
+            for (index, sample) in samples.iter().enumerate() {
+                let window = 0.5 - 0.5 * (TAU * index as f32 / VISUALIZER_WINDOW as f32).cos();
+                let angle = TAU * bin * index as f32 / VISUALIZER_WINDOW as f32;
+                real += sample * window * angle.cos();
+                imaginary -= sample * window * angle.sin();
+            }
+            let level = ((real * real + imaginary * imaginary).sqrt() / (VISUALIZER_WINDOW as f32 / 4.0)).min(1.0);
+            let top = height - level * height / 2.0;
+            let color = Color::rgb8(0x40, 0xFF, 0x40).lerp(Color::rgb8(0xFF, 0x40, 0x40), level);
+            render_group.push_rect(bar as f32 * bar_width + 1.0, top, (bar + 1) as f32 * bar_width - 1.0, height, color);
+        }
+        // the loud bars glow, on backends that run post passes
+        render_group.post_passes.push(PostPass::Bloom { threshold: 0.6, radius: 8.0, intensity: 1.2 });
+    }
+}
+
+/// Everything the input snapshot holds, drawn raw: the 256 virtual keys, the mouse, every connected pad and
+/// the touch points. Every key pressed blips, higher and further right the further into the codes, and space hums
+/// while it's held, panned to follow the mouse across and louder the higher it is. Holding the left or right mouse
+/// button drops that side of the hum out, and the first pad's right stick bends it up or down an octave
+struct InputTester;
+
+impl Scene for InputTester {
+    fn name(&self) -> &'static str {
+        "Input tester"
+    }
+
+    fn update_and_render(&self, state: &mut GameState, input: &GameInput, render_group: &mut RenderGroup) {
+        let GameInput { keyboard, mouse, touch, gamepads, .. } = input;
+        for (code, key) in keyboard.keys.iter().enumerate() {
+            if key.was_pressed() {
+                let blip = state.audio.play(&state.blip, 1.0, 0.5 + code as f32 / 128.0);
+                state.audio.set_pan(blip, code as f32 / 128.0 - 1.0);
+            }
+        }
+        let hum_volume = (1.0 - mouse.y as f32 / render_group.height as f32).clamp(0.1, 1.0);
+        let hum_pan = (mouse.x as f32 / render_group.width as f32 * 2.0 - 1.0).clamp(-1.0, 1.0);
+        let hum_pitch = 2f32.powf(gamepads.iter().find(|gamepad| gamepad.connected).map_or(0.0, |gamepad| gamepad.sticks.right_y));
+        let hum_sides = [!mouse.is_down(MouseButton::Left), !mouse.is_down(MouseButton::Right)].map(|on| if on { 1.0 } else { 0.0 });
+        match state.hum_voice {
+            Some(voice) if !keyboard.is_down(VK_SPACE) => {
+                state.audio.stop(voice);
+                state.hum_voice = None;
+            }
+            Some(voice) if state.audio.is_playing(voice) => {
+                state.audio.set_volume(voice, hum_volume);
+                state.audio.set_pan(voice, hum_pan);
+                state.audio.set_channel_volumes(voice, hum_sides[0], hum_sides[1]);
+                state.audio.set_pitch(voice, hum_pitch);
+            }
+            _ if keyboard.is_down(VK_SPACE) => {
+                let voice = state.audio.play_looping(&state.hum, hum_volume, hum_pitch, None);
+                state.audio.set_pan(voice, hum_pan);
+                state.audio.set_channel_volumes(voice, hum_sides[0], hum_sides[1]);
+                state.hum_voice = Some(voice);
+            }
+            _ => {}
+        }
+        render_group.push_clear(Color::rgb8(0x18, 0x18, 0x18));
+        let up = Color::rgb8(0x40, 0x40, 0x40);
+        let down = Color::rgb8(0x40, 0xFF, 0x40);
+        let pressed = Color::WHITE;
+        let state_color = |button: &ButtonState| match button {
+            _ if button.was_pressed() => pressed,
+            _ if button.ended_down => down,
+            _ => up,
+        };
+
+        // virtual key codes 0..256 in rows of 16, so 0x41 'A' is row 4 column 1
+        for (code, key) in keyboard.keys.iter().enumerate() {
+            let (x, y) = (16.0 + (code % 16) as f32 * 16.0, 16.0 + (code / 16) as f32 * 16.0);
+            render_group.push_rect(x, y, x + 14.0, y + 14.0, state_color(key));
+        }
+
+        // buttons left to right in MouseButton order, the wheel as a bar up or down from the middle of the last box
+        for (index, button) in mouse.buttons.iter().enumerate() {
+            let x = 16.0 + index as f32 * 32.0;
+            render_group.push_rect(x, 288.0, x + 28.0, 328.0, state_color(button));
+        }
+        let wheel = (mouse.wheel_delta as f32 / 4.0).clamp(-20.0, 20.0);
+        render_group.push_rect(180.0, 308.0 - wheel.max(0.0), 196.0, 308.0 - wheel.min(0.0), Color::rgb8(0xFF, 0xC0, 0x40));
+        let (mouse_x, mouse_y) = (mouse.x as f32, mouse.y as f32);
+        let crosshair = if mouse.captured { Color::rgb8(0xFF, 0x40, 0x40) } else { Color::WHITE };
+        render_group.push_line(mouse_x - 12.0, mouse_y, mouse_x + 12.0, mouse_y, crosshair);
+        render_group.push_line(mouse_x, mouse_y - 12.0, mouse_x, mouse_y + 12.0, crosshair);
+
+        // one row per connected pad: both sticks, both triggers, then the 16 buttons in GamepadButtons::all order
+        for (row, gamepad) in gamepads.iter().filter(|gamepad| gamepad.connected).enumerate() {
+            let top = 16.0 + row as f32 * 64.0;
+            let sticks = [(gamepad.sticks.left_x, gamepad.sticks.left_y), (gamepad.sticks.right_x, gamepad.sticks.right_y)];
+            for (index, (stick_x, stick_y)) in sticks.into_iter().enumerate() {
+                let (center_x, center_y) = (320.0 + index as f32 * 64.0, top + 28.0);
+                render_group.push_circle_outline(center_x, center_y, 26.0, up);
+                render_group.push_circle(center_x + stick_x * 26.0, center_y - stick_y * 26.0, 5.0, down);
+            }
+            for (index, trigger) in [gamepad.triggers.left, gamepad.triggers.right].into_iter().enumerate() {
+                let x = 456.0 + index as f32 * 16.0;
+                render_group.push_rect(x, top + 2.0, x + 12.0, top + 54.0, up);
+                render_group.push_rect(x, top + 54.0 - trigger * 52.0, x + 12.0, top + 54.0, down);
+            }
+            for (index, button) in gamepad.buttons.all().iter().enumerate() {
+                let (x, y) = (500.0 + (index % 8) as f32 * 20.0, top + 8.0 + (index / 8) as f32 * 20.0);
+                render_group.push_rect(x, y, x + 16.0, y + 16.0, state_color(button));
+            }
+        }
+
+        for point in touch.points() {
+            let color = if point.released { up } else if point.pen { Color::rgb8(0x40, 0xC0, 0xFF) } else { Color::rgb8(0xFF, 0x80, 0x40) };
+            render_group.push_circle_outline(point.x as f32, point.y as f32, 8.0 + point.pressure * 24.0, color);
+        }
+    }
+}
+
+/// The fixed test patterns full screen, tab steps through them and holding B blurs them on backends that run post passes
+struct TestPatterns;
+
+impl Scene for TestPatterns {
+    fn name(&self) -> &'static str {
+        "Test patterns"
+    }
+
+    fn update_and_render(&self, state: &mut GameState, input: &GameInput, render_group: &mut RenderGroup) {
+        if input.keyboard.was_pressed(VK_TAB) {
+            state.test_pattern = (state.test_pattern + 1) % TestPattern::ALL.len();
+            println!("Test pattern: {:?}", TestPattern::ALL[state.test_pattern]);
+        }
+        render_group.push_test_pattern(TestPattern::ALL[state.test_pattern]);
+        if input.keyboard.is_down(VK_B) {
+            render_group.post_passes.push(PostPass::Blur { radius: 4.0 });
+        }
+    }
+}
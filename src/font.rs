@@ -0,0 +1,510 @@
+use std::{fs, io, path::Path};
+
+use crate::{bitmap::Bitmap, color::pack_rgba8};
+
+// printable ascii, everything else draws as '?'
+const FIRST_CHARACTER: char = ' ';
+const LAST_CHARACTER: char = '~';
+// composite glyphs can reference other composites, bail out of broken fonts that loop
+const MAX_COMPONENT_DEPTH: u32 = 8;
+
+/// One baked glyph: coverage in the alpha channel of a white bitmap, placed relative to the pen on the baseline (y down)
+#[derive(Clone, Debug)]
+pub struct Glyph {
+    pub bitmap: Bitmap,
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub advance: f32,
+}
+
+/// TrueType font rasterized once at a fixed pixel height, for debug text
+#[derive(Clone, Debug)]
+pub struct Font {
+    /// Baseline distance from the top of a line
+    pub ascent: f32,
+    pub line_height: f32,
+    glyphs: Vec<Glyph>,
+}
+
+impl Font {
+    pub fn load(path: impl AsRef<Path>, pixel_height: f32) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?, pixel_height)
+    }
+
+    /// Bakes the printable ascii range, pixel height is ascent to descent like stb_truetype's ScaleForPixelHeight
+    pub fn parse(data: &[u8], pixel_height: f32) -> io::Result<Self> {
+        let font = TrueType::parse(data).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Bad TrueType font"))?;
+        let scale = pixel_height / (font.ascent - font.descent) as f32;
+        let glyphs = (FIRST_CHARACTER..=LAST_CHARACTER)
+            .map(|character| {
+                let glyph_index = font.glyph_index(character);
+                let (bitmap, offset_x, offset_y) = font.rasterize(glyph_index, scale);
+                Glyph {
+                    bitmap,
+                    offset_x,
+                    offset_y,
+                    advance: font.advance(glyph_index) as f32 * scale,
+                }
+            })
+            .collect();
+        Ok(Font {
+            ascent: font.ascent as f32 * scale,
+            line_height: (font.ascent - font.descent + font.line_gap) as f32 * scale,
+            glyphs,
+        })
+    }
+
+    pub fn glyph(&self, character: char) -> &Glyph {
+        let character = if (FIRST_CHARACTER..=LAST_CHARACTER).contains(&character) { character } else { '?' };
+        &self.glyphs[character as usize - FIRST_CHARACTER as usize]
+    }
+}
+
+#[derive(Clone, Copy)]
+struct OutlinePoint {
+    x: f32,
+    y: f32,
+    on_curve: bool,
+}
+
+/// The handful of tables needed to get outlines and metrics out of a .ttf
+struct TrueType<'a> {
+    data: &'a [u8],
+    glyf: usize,
+    loca: usize,
+    hmtx: usize,
+    cmap_subtable: usize,
+    long_loca: bool,
+    glyph_count: u16,
+    horizontal_metric_count: u16,
+    ascent: i16,
+    descent: i16,
+    line_gap: i16,
+}
+
+impl<'a> TrueType<'a> {
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let mut font = TrueType {
+            data,
+            glyf: 0,
+            loca: 0,
+            hmtx: 0,
+            cmap_subtable: 0,
+            long_loca: false,
+            glyph_count: 0,
+            horizontal_metric_count: 0,
+            ascent: 0,
+            descent: 0,
+            line_gap: 0,
+        };
+        let table = |tag: &[u8; 4]| {
+            (0..font.u16(4)? as usize)
+                .map(|index| 12 + index * 16)
+                .find(|record| data.get(*record..record + 4) == Some(tag))
+                .and_then(|record| Some(font.u32(record + 8)? as usize))
+        };
+        let (head, maxp, hhea, cmap) = (table(b"head")?, table(b"maxp")?, table(b"hhea")?, table(b"cmap")?);
+        let (glyf, loca, hmtx) = (table(b"glyf")?, table(b"loca")?, table(b"hmtx")?);
+
+        // unicode bmp (windows 3/1, or any unicode platform 0 table), or full unicode (3/10)
+        let cmap_subtable = (0..font.u16(cmap + 2)? as usize).find_map(|index| {
+            let record = cmap + 4 + index * 8;
+            let (platform, encoding) = (font.u16(record)?, font.u16(record + 2)?);
+            let subtable = cmap + font.u32(record + 4)? as usize;
+            let format = font.u16(subtable)?;
+            (matches!((platform, encoding), (3, 1) | (3, 10) | (0, _)) && matches!(format, 4 | 12)).then_some(subtable)
+        })?;
+
+        font.glyf = glyf;
+        font.loca = loca;
+        font.hmtx = hmtx;
+        font.cmap_subtable = cmap_subtable;
+        font.long_loca = font.u16(head + 50)? != 0;
+        font.glyph_count = font.u16(maxp + 4)?;
+        font.ascent = font.i16(hhea + 4)?;
+        font.descent = font.i16(hhea + 6)?;
+        font.line_gap = font.i16(hhea + 8)?;
+        font.horizontal_metric_count = font.u16(hhea + 34)?;
+        Some(font)
+    }
+
+    fn u8(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        self.data.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i16(&self, offset: usize) -> Option<i16> {
+        self.u16(offset).map(|value| value as i16)
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        self.data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// 0 (the missing glyph box) when the font doesn't have the character
+    fn glyph_index(&self, character: char) -> u16 {
+        let code = character as u32;
+        let subtable = self.cmap_subtable;
+        let lookup = || -> Option<u16> {
+            match self.u16(subtable)? {
+                4 => {
+                    let segment_count = self.u16(subtable + 6)? as usize / 2;
+                    let end_codes = subtable + 14;
+                    let start_codes = end_codes + 2 + segment_count * 2;
+                    let deltas = start_codes + segment_count * 2;
+                    let range_offsets = deltas + segment_count * 2;
+                    let segment = (0..segment_count).find(|segment| self.u16(end_codes + segment * 2).is_some_and(|end| code <= end as u32))?;
+                    let start = self.u16(start_codes + segment * 2)? as u32;
+                    if code < start {
+                        return None;
+                    }
+                    let delta = self.u16(deltas + segment * 2)?;
+                    let range_offset = self.u16(range_offsets + segment * 2)? as usize;
+                    if range_offset == 0 {
+                        return Some((code as u16).wrapping_add(delta));
+                    }
+                    // the offset is relative to where it is stored, one of the odder bits of the format
+                    let glyph = self.u16(range_offsets + segment * 2 + range_offset + (code - start) as usize * 2)?;
+                    (glyph != 0).then(|| glyph.wrapping_add(delta))
+                }
+                12 => {
+                    let group_count = self.u32(subtable + 12)? as usize;
+                    (0..group_count).find_map(|group| {
+                        let group = subtable + 16 + group * 12;
+                        let (start, end) = (self.u32(group)?, self.u32(group + 4)?);
+                        (start..=end).contains(&code).then(|| Some((self.u32(group + 8)? + code - start) as u16))?
+                    })
+                }
+                _ => None,
+            }
+        };
+        lookup().filter(|glyph| *glyph < self.glyph_count).unwrap_or(0)
+    }
+
+    fn advance(&self, glyph_index: u16) -> u16 {
+        let metric = glyph_index.min(self.horizontal_metric_count.saturating_sub(1)) as usize;
+        self.u16(self.hmtx + metric * 4).unwrap_or(0)
+    }
+
+    /// Byte range of the glyph in the glyf table, None for empty glyphs like space
+    fn glyph_range(&self, glyph_index: u16) -> Option<(usize, usize)> {
+        let index = glyph_index as usize;
+        let (start, end) = if self.long_loca {
+            (self.u32(self.loca + index * 4)? as usize, self.u32(self.loca + index * 4 + 4)? as usize)
+        } else {
+            (self.u16(self.loca + index * 2)? as usize * 2, self.u16(self.loca + index * 2 + 2)? as usize * 2)
+        };
+        (end > start).then_some((self.glyf + start, self.glyf + end))
+    }
+
+    /// Contours in font units, y up. Composite glyphs are flattened with their component transforms applied.
+    fn outline(&self, glyph_index: u16, depth: u32, contours: &mut Vec<Vec<OutlinePoint>>) -> Option<()> {
+        let (glyph, _) = self.glyph_range(glyph_index)?;
+        let contour_count = self.i16(glyph)?;
+        if contour_count >= 0 {
+            return self.simple_outline(glyph, contour_count as usize, contours);
+        }
+        if depth >= MAX_COMPONENT_DEPTH {
+            return None;
+        }
+
+        const ARGS_ARE_WORDS: u16 = 0x0001;
+        const ARGS_ARE_OFFSETS: u16 = 0x0002;
+        const HAS_SCALE: u16 = 0x0008;
+        const MORE_COMPONENTS: u16 = 0x0020;
+        const HAS_XY_SCALE: u16 = 0x0040;
+        const HAS_TWO_BY_TWO: u16 = 0x0080;
+        let f2dot14 = |offset| Some(self.i16(offset)? as f32 / 16384.0);
+
+        let mut offset = glyph + 10;
+        loop {
+            let flags = self.u16(offset)?;
+            let component = self.u16(offset + 2)?;
+            offset += 4;
+            let (dx, dy) = if flags & ARGS_ARE_WORDS != 0 {
+                offset += 4;
+                (self.i16(offset - 4)? as f32, self.i16(offset - 2)? as f32)
+            } else {
+                offset += 2;
+                (self.u8(offset - 2)? as i8 as f32, self.u8(offset - 1)? as i8 as f32)
+            };
+            // [xx, xy, yx, yy]
+            let matrix = if flags & HAS_SCALE != 0 {
+                offset += 2;
+                let scale = f2dot14(offset - 2)?;
+                [scale, 0.0, 0.0, scale]
+            } else if flags & HAS_XY_SCALE != 0 {
+                offset += 4;
+                [f2dot14(offset - 4)?, 0.0, 0.0, f2dot14(offset - 2)?]
+            } else if flags & HAS_TWO_BY_TWO != 0 {
+                offset += 8;
+                [f2dot14(offset - 8)?, f2dot14(offset - 6)?, f2dot14(offset - 4)?, f2dot14(offset - 2)?]
+            } else {
+                [1.0, 0.0, 0.0, 1.0]
+            };
+            // point matched components are rare in latin fonts, they are placed without an offset
+            let (dx, dy) = if flags & ARGS_ARE_OFFSETS != 0 { (dx, dy) } else { (0.0, 0.0) };
+
+            let first_new = contours.len();
+            self.outline(component, depth + 1, contours);
+            for point in contours[first_new..].iter_mut().flatten() {
+                let (x, y) = (point.x, point.y);
+                point.x = x * matrix[0] + y * matrix[2] + dx;
+                point.y = x * matrix[1] + y * matrix[3] + dy;
+            }
+
+            if flags & MORE_COMPONENTS == 0 {
+                return Some(());
+            }
+        }
+    }
+
+    fn simple_outline(&self, glyph: usize, contour_count: usize, contours: &mut Vec<Vec<OutlinePoint>>) -> Option<()> {
+        const ON_CURVE: u8 = 0x01;
+        const X_SHORT: u8 = 0x02;
+        const Y_SHORT: u8 = 0x04;
+        const REPEAT: u8 = 0x08;
+        const X_SAME_OR_POSITIVE: u8 = 0x10;
+        const Y_SAME_OR_POSITIVE: u8 = 0x20;
+
+        let contour_ends: Vec<usize> = (0..contour_count).map(|index| Some(self.u16(glyph + 10 + index * 2)? as usize)).collect::<Option<_>>()?;
+        let point_count = contour_ends.last().map_or(0, |end| end + 1);
+        let instructions_length = self.u16(glyph + 10 + contour_count * 2)? as usize;
+        let mut offset = glyph + 12 + contour_count * 2 + instructions_length;
+
+        let mut flags = Vec::with_capacity(point_count);
+        while flags.len() < point_count {
+            let flag = self.u8(offset)?;
+            offset += 1;
+            let repeat = if flag & REPEAT != 0 {
+                offset += 1;
+                self.u8(offset - 1)? as usize
+            } else {
+                0
+            };
+            flags.extend(std::iter::repeat_n(flag, repeat + 1));
+        }
+        flags.truncate(point_count);
+
+        // coordinates are deltas, short ones carry their sign in the SAME_OR_POSITIVE bit
+        let mut read_coordinates = |short: u8, same_or_positive: u8| -> Option<Vec<f32>> {
+            let mut value = 0i32;
+            flags
+                .iter()
+                .map(|flag| {
+                    if flag & short != 0 {
+                        let delta = self.u8(offset)? as i32;
+                        offset += 1;
+                        value += if flag & same_or_positive != 0 { delta } else { -delta };
+                    } else if flag & same_or_positive == 0 {
+                        value += self.i16(offset)? as i32;
+                        offset += 2;
+                    }
+                    Some(value as f32)
+                })
+                .collect()
+        };
+        let xs = read_coordinates(X_SHORT, X_SAME_OR_POSITIVE)?;
+        let ys = read_coordinates(Y_SHORT, Y_SAME_OR_POSITIVE)?;
+
+        let mut start = 0;
+        for end in contour_ends {
+            if end < start || end >= point_count {
+                return None;
+            }
+            contours.push(
+                (start..=end)
+                    .map(|index| OutlinePoint {
+                        x: xs[index],
+                        y: ys[index],
+                        on_curve: flags[index] & ON_CURVE != 0,
+                    })
+                    .collect(),
+            );
+            start = end + 1;
+        }
+        Some(())
+    }
+
+    /// Coverage bitmap of the glyph and where its top left sits relative to the pen position on the baseline
+    fn rasterize(&self, glyph_index: u16, scale: f32) -> (Bitmap, i32, i32) {
+        let empty = (Bitmap { width: 0, height: 0, pixels: Vec::new() }, 0, 0);
+        let mut contours = Vec::new();
+        if self.outline(glyph_index, 0, &mut contours).is_none() || contours.iter().all(Vec::is_empty) {
+            return empty;
+        }
+
+        // to pixels, y down
+        for point in contours.iter_mut().flatten() {
+            point.x *= scale;
+            point.y *= -scale;
+        }
+        let points = || contours.iter().flatten();
+        let min_x = points().map(|point| point.x).fold(f32::MAX, f32::min).floor();
+        let min_y = points().map(|point| point.y).fold(f32::MAX, f32::min).floor();
+        let max_x = points().map(|point| point.x).fold(f32::MIN, f32::max).ceil();
+        let max_y = points().map(|point| point.y).fold(f32::MIN, f32::max).ceil();
+        let (width, height) = ((max_x - min_x) as usize, (max_y - min_y) as usize);
+        if width == 0 || height == 0 {
+            return empty;
+        }
+
+        let mut rasterizer = Rasterizer::new(width, height);
+        for contour in contours.iter().filter(|contour| !contour.is_empty()) {
+            let to_local = |point: &OutlinePoint| (point.x - min_x, point.y - min_y, point.on_curve);
+            let points: Vec<(f32, f32, bool)> = contour.iter().map(to_local).collect();
+            rasterizer.draw_contour(&points);
+        }
+
+        let pixels = rasterizer.coverage().map(|coverage| {
+            // premultiplied white, so every channel is the coverage
+            let coverage = (coverage * 255.0 + 0.5) as u8;
+            pack_rgba8(coverage, coverage, coverage, coverage)
+        }).collect();
+        (Bitmap { width: width as i32, height: height as i32, pixels }, min_x as i32, min_y as i32)
+    }
+}
+
+/// Signed area accumulation rasterizer (the font-rs approach): every edge adds its coverage delta to the cells it crosses,
+/// a running sum along each row then gives exact antialiased coverage without sorting edges or tracking spans
+struct Rasterizer {
+    width: usize,
+    height: usize,
+    accumulation: Vec<f32>,
+}
+
+impl Rasterizer {
+    fn new(width: usize, height: usize) -> Self {
+        Rasterizer {
+            width,
+            height,
+            // one spare cell past the end, the last column's edges spill into it
+            accumulation: vec![0.0; width * height + 4],
+        }
+    }
+
+    /// Closed quadratic b-spline contour, two off-curve points in a row have an implied on-curve point between them
+    fn draw_contour(&mut self, points: &[(f32, f32, bool)]) {
+        let midpoint = |a: (f32, f32, bool), b: (f32, f32, bool)| ((a.0 + b.0) * 0.5, (a.1 + b.1) * 0.5, true);
+        let (first, last) = (points[0], points[points.len() - 1]);
+        let (start, rest) = if first.2 {
+            (first, &points[1..])
+        } else if last.2 {
+            (last, &points[..points.len() - 1])
+        } else {
+            (midpoint(first, last), points)
+        };
+
+        let mut current = start;
+        let mut control: Option<(f32, f32, bool)> = None;
+        for &point in rest {
+            match (point.2, control) {
+                (true, Some(control_point)) => self.draw_quad(current, control_point, point),
+                (true, None) => self.draw_line(current, point),
+                (false, Some(control_point)) => {
+                    let implied = midpoint(control_point, point);
+                    self.draw_quad(current, control_point, implied);
+                    current = implied;
+                    control = Some(point);
+                    continue;
+                }
+                (false, None) => {
+                    control = Some(point);
+                    continue;
+                }
+            }
+            current = point;
+            control = None;
+        }
+        match control {
+            Some(control_point) => self.draw_quad(current, control_point, start),
+            None => self.draw_line(current, start),
+        }
+    }
+
+    fn draw_quad(&mut self, p0: (f32, f32, bool), p1: (f32, f32, bool), p2: (f32, f32, bool)) {
+        // more segments the further the control point pulls the curve away from the chord
+        let (deviation_x, deviation_y) = (p0.0 - 2.0 * p1.0 + p2.0, p0.1 - 2.0 * p1.1 + p2.1);
+        let deviation = deviation_x * deviation_x + deviation_y * deviation_y;
+        if deviation < 0.333 {
+            self.draw_line(p0, p2);
+            return;
+        }
+        let segments = 1 + (3.0 * deviation).sqrt().sqrt().floor() as usize;
+        let mut previous = p0;
+        for index in 1..=segments {
+            let t = index as f32 / segments as f32;
+            let u = 1.0 - t;
+            let point = (
+                u * u * p0.0 + 2.0 * u * t * p1.0 + t * t * p2.0,
+                u * u * p0.1 + 2.0 * u * t * p1.1 + t * t * p2.1,
+                true,
+            );
+            self.draw_line(previous, point);
+            previous = point;
+        }
+    }
+
+    fn draw_line(&mut self, p0: (f32, f32, bool), p1: (f32, f32, bool)) {
+        if (p0.1 - p1.1).abs() <= f32::EPSILON {
+            return;
+        }
+        // walk top to bottom, the direction only decides the sign of the coverage
+        let (direction, p0, p1) = if p0.1 < p1.1 { (1.0, p0, p1) } else { (-1.0, p1, p0) };
+        let dxdy = (p1.0 - p0.0) / (p1.1 - p0.1);
+        let mut x = p0.0;
+        if p0.1 < 0.0 {
+            x -= p0.1 * dxdy;
+        }
+        let first_row = p0.1.max(0.0) as usize;
+        let end_row = self.height.min(p1.1.ceil() as usize);
+        for y in first_row..end_row {
+            let row_start = y * self.width;
+            let dy = ((y + 1) as f32).min(p1.1) - (y as f32).max(p0.1);
+            let x_next = x + dxdy * dy;
+            let delta = dy * direction;
+            let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+            let x0_floor = x0.floor();
+            let x0_cell = x0_floor as usize;
+            let x1_ceil = x1.ceil();
+            let x1_cell = x1_ceil as usize;
+            if x1_cell <= x0_cell + 1 {
+                // the edge stays inside one column on this row
+                let x_middle = 0.5 * (x + x_next) - x0_floor;
+                self.accumulation[row_start + x0_cell] += delta - delta * x_middle;
+                self.accumulation[row_start + x0_cell + 1] += delta * x_middle;
+            } else {
+                let inverse_width = (x1 - x0).recip();
+                let x0_fraction = x0 - x0_floor;
+                let first_area = 0.5 * inverse_width * (1.0 - x0_fraction) * (1.0 - x0_fraction);
+                let x1_fraction = x1 - x1_ceil + 1.0;
+                let last_area = 0.5 * inverse_width * x1_fraction * x1_fraction;
+                self.accumulation[row_start + x0_cell] += delta * first_area;
+                if x1_cell == x0_cell + 2 {
+                    self.accumulation[row_start + x0_cell + 1] += delta * (1.0 - first_area - last_area);
+                } else {
+                    let second_area = inverse_width * (1.5 - x0_fraction);
+                    self.accumulation[row_start + x0_cell + 1] += delta * (second_area - first_area);
+                    for cell in x0_cell + 2..x1_cell - 1 {
+                        self.accumulation[row_start + cell] += delta * inverse_width;
+                    }
+                    let covered = second_area + (x1_cell - x0_cell - 3) as f32 * inverse_width;
+                    self.accumulation[row_start + x1_cell - 1] += delta * (1.0 - covered - last_area);
+                }
+                self.accumulation[row_start + x1_cell] += delta * last_area;
+            }
+            x = x_next;
+        }
+    }
+
+    /// Running sum of the deltas, 0.0..1.0 per pixel. Nonzero winding, so overlapping contours don't cancel out.
+    fn coverage(&self) -> impl Iterator<Item = f32> + '_ {
+        self.accumulation[..self.width * self.height].iter().scan(0.0, |sum, delta| {
+            *sum += delta;
+            Some(sum.abs().min(1.0))
+        })
+    }
+}
@@ -0,0 +1,168 @@
+//! The pixels the renderer draws into. The platform makes one of these over the backbuffer each frame and render
+//! targets make them over their own bitmaps, after that drawing code only sees rows as slices. The pointer math for
+//! views into a bigger buffer stays in here.
+
+use std::{marker::PhantomData, ops::Range};
+
+use crate::{color::Pixel, render_group::DirtyRect};
+
+/// A whole buffer or a rectangle of one. Rows are stride pixels apart, which is more than width for views.
+/// It stands in for a &mut [Pixel] with gaps in it, so it is as exclusive as one: views borrow the buffer they're
+/// cut from, and disjoint views for other threads come from split_mut.
+pub struct FrameBuffer<'a> {
+    pixels: *mut Pixel,
+    width: i32,
+    height: i32,
+    stride: usize,
+    _borrow: PhantomData<&'a mut [Pixel]>,
+}
+
+// sending one is sending the exclusive borrow it holds, same as sending the &mut [Pixel]
+unsafe impl Send for FrameBuffer<'_> {}
+
+impl<'a> FrameBuffer<'a> {
+    /// Rows of width pixels one after another, like a Bitmap's
+    pub fn new(pixels: &'a mut [Pixel], width: i32, height: i32) -> Self {
+        let (width, height) = (width.max(0), height.max(0));
+        assert!(pixels.len() >= width as usize * height as usize, "{} pixels for a {width}x{height} buffer", pixels.len());
+        FrameBuffer { pixels: pixels.as_mut_ptr(), width, height, stride: width as usize, _borrow: PhantomData }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Bytes from the start of one row to the next
+    pub fn pitch(&self) -> i32 {
+        (self.stride * size_of::<Pixel>()) as i32
+    }
+
+    /// Nothing to draw into, what a backbuffer that hasn't been allocated yet turns into
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Row y, panics outside the buffer
+    pub fn row_mut(&mut self, y: i32) -> &mut [Pixel] {
+        assert!((0..self.height).contains(&y), "row {y} of a buffer {} high", self.height);
+        unsafe { self.row_unchecked_mut(y) }
+    }
+
+    /// length pixels of row y starting at x, panics if any of them are outside the buffer
+    pub fn span_mut(&mut self, x: i32, y: i32, length: usize) -> &mut [Pixel] {
+        let start = usize::try_from(x).expect("span starting left of the buffer");
+        &mut self.row_mut(y)[start..start + length]
+    }
+
+    /// Row y without the check, for loops that have clipped already
+    ///
+    /// # Safety
+    /// y has to be in 0..height
+    pub unsafe fn row_unchecked(&self, y: i32) -> &[Pixel] {
+        unsafe { std::slice::from_raw_parts(self.pixels.add(y as usize * self.stride), self.width as usize) }
+    }
+
+    /// # Safety
+    /// y has to be in 0..height
+    pub unsafe fn row_unchecked_mut(&mut self, y: i32) -> &mut [Pixel] {
+        unsafe { std::slice::from_raw_parts_mut(self.pixels.add(y as usize * self.stride), self.width as usize) }
+    }
+
+    /// Every row top to bottom, each one width pixels whatever the pitch
+    pub fn rows(&self) -> impl DoubleEndedIterator<Item = &[Pixel]> + ExactSizeIterator {
+        (0..self.height).map(|y| unsafe { self.row_unchecked(y) })
+    }
+
+    /// The rows as disjoint mutable slices. They can be collected and handed out to different threads
+    pub fn rows_mut(&mut self) -> RowsMut<'_> {
+        RowsMut { pixels: self.pixels, width: self.width as usize, stride: self.stride, rows: 0..self.height as usize, _borrow: PhantomData }
+    }
+
+    /// Every pixel left to right, top to bottom, skipping the gaps between the rows of a view
+    pub fn pixels(&self) -> impl Iterator<Item = &Pixel> {
+        self.rows().flatten()
+    }
+
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = &mut Pixel> {
+        self.rows_mut().flatten()
+    }
+
+    /// A rectangle of this buffer as a buffer of its own, for as long as it borrows this one. Panics if it doesn't fit
+    pub fn view_mut(&mut self, x: i32, y: i32, width: i32, height: i32) -> FrameBuffer<'_> {
+        unsafe { self.view_unchecked(&DirtyRect { x, y, width, height }) }
+    }
+
+    /// Disjoint rectangles of this buffer as buffers of their own, so each can go to a different thread.
+    /// Panics if any of them overlap or don't fit
+    pub fn split_mut(&mut self, rects: &[DirtyRect]) -> Vec<FrameBuffer<'_>> {
+        for (index, a) in rects.iter().enumerate() {
+            for b in &rects[index + 1..] {
+                let overlap = a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height;
+                assert!(!overlap || a.width <= 0 || a.height <= 0 || b.width <= 0 || b.height <= 0, "{a:?} overlaps {b:?}");
+            }
+        }
+        rects.iter().map(|rect| unsafe { self.view_unchecked(rect) }).collect()
+    }
+
+    /// Checks rect fits, the caller makes sure nothing else can reach the same pixels while the view lives
+    unsafe fn view_unchecked(&self, rect: &DirtyRect) -> FrameBuffer<'a> {
+        let DirtyRect { x, y, width, height } = *rect;
+        assert!(
+            x >= 0 && y >= 0 && width >= 0 && height >= 0 && x + width <= self.width && y + height <= self.height,
+            "{rect:?} outside a {}x{} buffer",
+            self.width,
+            self.height
+        );
+        // an empty view at the far edge would point one past the end, which add still allows
+        let pixels = unsafe { self.pixels.add(y as usize * self.stride + x as usize) };
+        FrameBuffer { pixels, width, height, stride: self.stride, _borrow: PhantomData }
+    }
+
+    /// Cheap fingerprint of the pixels (fxhash style), equal frames give equal hashes
+    pub fn content_hash(&self) -> u64 {
+        self.pixels().fold(0u64, |hash, pixel| (hash.rotate_left(5) ^ *pixel as u64).wrapping_mul(0x517CC1B727220A95))
+    }
+}
+
+/// Iterator behind FrameBuffer::rows_mut
+pub struct RowsMut<'a> {
+    pixels: *mut Pixel,
+    width: usize,
+    stride: usize,
+    rows: Range<usize>,
+    _borrow: PhantomData<&'a mut [Pixel]>,
+}
+
+// the rows it hands out never overlap, so they can end up on any thread
+unsafe impl Send for RowsMut<'_> {}
+
+impl<'a> RowsMut<'a> {
+    fn row(&self, y: usize) -> &'a mut [Pixel] {
+        // each y comes out of the range once, so no row is handed out twice
+        unsafe { std::slice::from_raw_parts_mut(self.pixels.add(y * self.stride), self.width) }
+    }
+}
+
+impl<'a> Iterator for RowsMut<'a> {
+    type Item = &'a mut [Pixel];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next().map(|y| self.row(y))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for RowsMut<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.rows.next_back().map(|y| self.row(y))
+    }
+}
+
+impl ExactSizeIterator for RowsMut<'_> {}
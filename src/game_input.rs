@@ -0,0 +1,436 @@
+//! Input types the game reads, shared by every platform layer.
+//! Nothing in here may depend on the windows crate, backends translate their native state into these.
+
+use std::time::Duration;
+
+/// Button state for one frame: where it ended up, and how many times it flipped during the frame.
+/// Counting transitions keeps presses that go down and up again inside a single frame.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ButtonState {
+    pub ended_down: bool,
+    pub half_transition_count: u32,
+}
+
+impl ButtonState {
+    pub fn update(&mut self, is_down: bool) {
+        if self.ended_down != is_down {
+            self.ended_down = is_down;
+            self.half_transition_count += 1;
+        }
+    }
+
+    pub fn was_pressed(&self) -> bool {
+        self.half_transition_count > 1 || (self.half_transition_count == 1 && self.ended_down)
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct GamepadButtons {
+    pub dpad_up: ButtonState,
+    pub dpad_down: ButtonState,
+    pub dpad_left: ButtonState,
+    pub dpad_right: ButtonState,
+    pub start: ButtonState,
+    pub back: ButtonState,
+    pub left_thumb: ButtonState,
+    pub right_thumb: ButtonState,
+    pub left_shoulder: ButtonState,
+    pub right_shoulder: ButtonState,
+    pub a: ButtonState,
+    pub b: ButtonState,
+    pub x: ButtonState,
+    pub y: ButtonState,
+    // digital versions of the analog triggers, down once past the trigger threshold
+    pub left_trigger: ButtonState,
+    pub right_trigger: ButtonState,
+}
+
+impl GamepadButtons {
+    pub fn all(&self) -> [ButtonState; 16] {
+        [
+            self.dpad_up, self.dpad_down, self.dpad_left, self.dpad_right,
+            self.start, self.back, self.left_thumb, self.right_thumb,
+            self.left_shoulder, self.right_shoulder, self.a, self.b, self.x, self.y,
+            self.left_trigger, self.right_trigger,
+        ]
+    }
+
+    pub fn all_mut(&mut self) -> [&mut ButtonState; 16] {
+        [
+            &mut self.dpad_up, &mut self.dpad_down, &mut self.dpad_left, &mut self.dpad_right,
+            &mut self.start, &mut self.back, &mut self.left_thumb, &mut self.right_thumb,
+            &mut self.left_shoulder, &mut self.right_shoulder, &mut self.a, &mut self.b, &mut self.x, &mut self.y,
+            &mut self.left_trigger, &mut self.right_trigger,
+        ]
+    }
+}
+
+/// Trigger pull in 0.0..1.0 with the threshold already removed
+#[derive(Default, Clone, Copy, Debug)]
+pub struct GamepadTriggers {
+    pub left: f32,
+    pub right: f32,
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct GamepadSticks {
+    pub left_x: f32,
+    pub left_y: f32,
+    pub right_x: f32,
+    pub right_y: f32,
+}
+
+/// Motor speeds in 0.0..1.0, set by the game loop each frame and sent to the pad by the platform
+#[derive(Default, Clone, Copy, Debug)]
+pub struct GamepadVibration {
+    pub low_frequency: f32,
+    pub high_frequency: f32,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatteryType {
+    #[default]
+    Unknown,
+    Wired,
+    Alkaline,
+    NickelMetalHydride,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BatteryLevel {
+    Empty,
+    Low,
+    Medium,
+    #[default]
+    Full,
+}
+
+/// Refreshed at a low rate, wired pads always report Full
+#[derive(Default, Clone, Copy, Debug)]
+pub struct GamepadBattery {
+    pub battery_type: BatteryType,
+    pub level: BatteryLevel,
+}
+
+impl GamepadBattery {
+    pub fn is_wireless(&self) -> bool {
+        matches!(self.battery_type, BatteryType::Alkaline | BatteryType::NickelMetalHydride)
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct GamepadState {
+    pub connected: bool,
+    pub battery: GamepadBattery,
+    pub buttons: GamepadButtons,
+    pub triggers: GamepadTriggers,
+    pub sticks: GamepadSticks,
+    pub vibration: GamepadVibration,
+    // dpad directions (up, down, left, right) the left stick is currently holding, see GamepadSettings::stick_to_dpad
+    pub stick_dpad: [bool; 4],
+}
+
+/// How stick deflection maps to output once the deadzone is removed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StickCurve {
+    Linear,
+    // gentle near the center for fine aiming, still reaches full speed at the edge
+    Squared,
+    Exponent(f32),
+}
+
+impl StickCurve {
+    pub fn apply(self, magnitude: f32) -> f32 {
+        match self {
+            StickCurve::Linear => magnitude,
+            StickCurve::Squared => magnitude * magnitude,
+            StickCurve::Exponent(exponent) => magnitude.powf(exponent),
+        }
+    }
+}
+
+/// Physical key position: the set 1 scan code, with bit 8 set for extended (0xE0 prefixed) keys.
+/// Unlike key codes it doesn't move with the layout, W is the key above S on AZERTY too (where it's labeled Z).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scancode(pub u16);
+
+pub const SCANCODE_COUNT: usize = 512;
+
+impl Scancode {
+    pub const W: Scancode = Scancode(0x11);
+    pub const A: Scancode = Scancode(0x1E);
+    pub const S: Scancode = Scancode(0x1F);
+    pub const D: Scancode = Scancode(0x20);
+}
+
+/// Layout dependent key, numbered like the windows VK_* codes, other platforms translate their key symbols into these
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyCode(pub u16);
+
+/// Per key state, indexed both by the key code (layout dependent) and by the physical scancode
+#[derive(Clone, Copy, Debug)]
+pub struct KeyboardState {
+    pub keys: [ButtonState; 256],
+    pub scancodes: [ButtonState; SCANCODE_COUNT],
+}
+
+impl Default for KeyboardState {
+    fn default() -> Self {
+        KeyboardState {
+            keys: [ButtonState::default(); 256],
+            scancodes: [ButtonState::default(); SCANCODE_COUNT],
+        }
+    }
+}
+
+impl KeyboardState {
+    pub fn is_down(&self, key: impl Into<KeyCode>) -> bool {
+        self.keys[key.into().0 as usize & 0xFF].ended_down
+    }
+
+    pub fn was_pressed(&self, key: impl Into<KeyCode>) -> bool {
+        self.keys[key.into().0 as usize & 0xFF].was_pressed()
+    }
+
+    pub fn is_scancode_down(&self, scancode: Scancode) -> bool {
+        self.scancodes[scancode.0 as usize % SCANCODE_COUNT].ended_down
+    }
+
+    /// Called once the game loop has taken its snapshot, so transitions are counted per frame
+    pub fn begin_frame(&mut self) {
+        for key in self.keys.iter_mut().chain(self.scancodes.iter_mut()) {
+            key.half_transition_count = 0;
+        }
+    }
+}
+
+/// Text typed during the frame as UTF-8.
+/// Backspace and enter stay in the text, in order, as '\u{8}' and '\n' so a text field can replay edits exactly.
+/// Every other control character is dropped.
+#[derive(Default, Clone, Debug)]
+pub struct TextInputState {
+    pub text: String,
+    // first half of a surrogate pair, WM_CHAR delivers the two halves as separate messages
+    pub pending_high_surrogate: Option<u16>,
+}
+
+impl TextInputState {
+    pub fn push_utf16(&mut self, code_unit: u16) {
+        match code_unit {
+            0xD800..=0xDBFF => self.pending_high_surrogate = Some(code_unit),
+            0xDC00..=0xDFFF => {
+                if let Some(high_surrogate) = self.pending_high_surrogate.take() {
+                    let decoded = char::decode_utf16([high_surrogate, code_unit]).next();
+                    if let Some(Ok(character)) = decoded {
+                        self.push_char(character);
+                    }
+                }
+            }
+            _ => {
+                self.pending_high_surrogate = None;
+                if let Some(character) = char::from_u32(code_unit as u32) {
+                    self.push_char(character);
+                }
+            }
+        }
+    }
+
+    pub fn push_char(&mut self, character: char) {
+        match character {
+            '\u{8}' => self.text.push('\u{8}'),
+            '\r' | '\n' => self.text.push('\n'),
+            '\t' => self.text.push('\t'),
+            _ if character.is_control() => {}
+            _ => self.text.push(character),
+        }
+    }
+
+    /// Applies the frame's text to a single line text field, returns any lines submitted with enter
+    pub fn apply_to_line(&self, line: &mut String) -> Vec<String> {
+        let mut submitted = Vec::new();
+        for character in self.text.chars() {
+            match character {
+                '\u{8}' => {
+                    line.pop();
+                }
+                '\n' => submitted.push(std::mem::take(line)),
+                _ => line.push(character),
+            }
+        }
+        submitted
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    X1,
+    X2,
+}
+
+pub const MOUSE_BUTTON_COUNT: usize = 5;
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct MouseState {
+    // backbuffer pixels, top left is 0,0, mapped back through any scaling and letterboxing. Not clamped, over the
+    // bars it's negative above and left of the picture, and past the buffer's width or height below and right of it
+    pub x: i32,
+    pub y: i32,
+    pub buttons: [ButtonState; MOUSE_BUTTON_COUNT],
+    // accumulated wheel movement since the game loop last consumed it, in WHEEL_DELTA units
+    pub wheel_delta: i32,
+    // unaccelerated relative motion from raw input, accumulated until the game loop consumes it
+    pub raw_dx: i32,
+    pub raw_dy: i32,
+    // captured (relative) mode: cursor hidden and pinned to the window, only raw deltas are meaningful
+    pub captured: bool,
+    // set for the frame with the second click of a double click, windows decides the timing and distance
+    pub double_clicks: [bool; MOUSE_BUTTON_COUNT],
+    // where each held button went down, cleared on release
+    pub drag_origins: [Option<(i32, i32)>; MOUSE_BUTTON_COUNT],
+    // held button has moved past the system drag threshold since it went down
+    pub dragging: [bool; MOUSE_BUTTON_COUNT],
+}
+
+impl MouseState {
+    pub fn is_down(&self, button: MouseButton) -> bool {
+        self.buttons[button as usize].ended_down
+    }
+
+    pub fn was_double_clicked(&self, button: MouseButton) -> bool {
+        self.double_clicks[button as usize]
+    }
+
+    /// Drag start and how far the cursor is from it, only once the button is actually dragging
+    pub fn drag(&self, button: MouseButton) -> Option<((i32, i32), (i32, i32))> {
+        let (origin_x, origin_y) = self.drag_origins[button as usize].filter(|_| self.dragging[button as usize])?;
+        Some(((origin_x, origin_y), (self.x - origin_x, self.y - origin_y)))
+    }
+
+    pub fn press(&mut self, button: MouseButton, is_down: bool) {
+        let index = button as usize;
+        self.buttons[index].update(is_down);
+        self.drag_origins[index] = is_down.then_some((self.x, self.y));
+        self.dragging[index] = false;
+    }
+
+    pub fn update_drags(&mut self, threshold_x: i32, threshold_y: i32) {
+        for (origin, dragging) in self.drag_origins.iter().zip(self.dragging.iter_mut()) {
+            if let Some((origin_x, origin_y)) = *origin {
+                *dragging |= (self.x - origin_x).abs() > threshold_x || (self.y - origin_y).abs() > threshold_y;
+            }
+        }
+    }
+
+    /// Clears the per-frame accumulators once the game loop has taken its snapshot
+    pub fn begin_frame(&mut self) {
+        for button in self.buttons.iter_mut() {
+            button.half_transition_count = 0;
+        }
+        self.wheel_delta = 0;
+        self.raw_dx = 0;
+        self.raw_dy = 0;
+        self.double_clicks = [false; MOUSE_BUTTON_COUNT];
+    }
+}
+
+/// One finger or pen tip on the screen
+#[derive(Default, Clone, Copy, Debug)]
+pub struct TouchPoint {
+    pub id: u32,
+    // backbuffer pixels like the mouse, out of the buffer's bounds the same way over the letterbox bars
+    pub x: i32,
+    pub y: i32,
+    // 0.0..1.0, fingers on screens that can't measure it report 1.0
+    pub pressure: f32,
+    pub pen: bool,
+    // lifted during the frame, the point is still reported once so quick taps aren't lost
+    pub released: bool,
+}
+
+pub const MAX_TOUCH_POINTS: usize = 10;
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct TouchState {
+    pub points: [Option<TouchPoint>; MAX_TOUCH_POINTS],
+}
+
+impl TouchState {
+    pub fn points(&self) -> impl Iterator<Item = &TouchPoint> {
+        self.points.iter().flatten()
+    }
+
+    pub fn update(&mut self, point: TouchPoint) {
+        let slot = self
+            .points
+            .iter()
+            .position(|existing| existing.is_some_and(|existing| existing.id == point.id))
+            .or_else(|| self.points.iter().position(Option::is_none));
+        // more contacts than slots, the extra ones are dropped
+        if let Some(slot) = slot {
+            self.points[slot] = Some(point);
+        }
+    }
+
+    /// Drops the points that were lifted, once the game loop has seen them
+    pub fn begin_frame(&mut self) {
+        for point in self.points.iter_mut() {
+            if point.is_some_and(|point| point.released) {
+                *point = None;
+            }
+        }
+    }
+}
+// enough for every xinput slot plus a few pads only Windows.Gaming.Input (or another platform's api) can see
+pub const MAX_GAMEPADS: usize = 8;
+
+/// Everything the game reads from the input devices in one frame, built fresh by the platform loop
+/// and handed to the game by reference so nothing on the game side touches the device globals
+pub struct GameInput {
+    // time since the platform layer started, taken once per frame
+    pub time: Duration,
+    pub keyboard: KeyboardState,
+    pub mouse: MouseState,
+    pub touch: TouchState,
+    pub gamepads: [GamepadState; MAX_GAMEPADS],
+    pub text_input: TextInputState,
+}
+
+/// Applies a radial deadzone to raw stick values and maps them to -1.0..1.0,
+/// rescaling what is left past the deadzone so motion starts smoothly from 0, then shapes it with the curve.
+pub fn normalize_stick(x: i16, y: i16, deadzone: i16, curve: StickCurve) -> (f32, f32) {
+    let max_magnitude = i16::MAX as f32;
+    let deadzone = deadzone as f32;
+    let x = x as f32;
+    let y = y as f32;
+
+    let magnitude = (x * x + y * y).sqrt();
+    if magnitude <= deadzone {
+        return (0.0, 0.0);
+    }
+
+    let clamped_magnitude = magnitude.min(max_magnitude);
+    // curve goes on the magnitude so the stick direction is kept
+    let normalized_magnitude = curve.apply((clamped_magnitude - deadzone) / (max_magnitude - deadzone));
+    let scale = normalized_magnitude / magnitude;
+
+    ((x * scale).clamp(-1.0, 1.0), (y * scale).clamp(-1.0, 1.0))
+}
+
+pub fn process_polled_button(old_state: ButtonState, ended_down: bool) -> ButtonState {
+    // pads are polled once per frame so at most one transition can be seen per poll
+    ButtonState {
+        ended_down,
+        half_transition_count: (old_state.ended_down != ended_down) as u32,
+    }
+}
+
+/// Maps a raw trigger value to 0.0..1.0, with everything at or below the threshold reading as 0
+pub fn normalize_trigger(value: u8, threshold: u8) -> f32 {
+    if value <= threshold {
+        return 0.0;
+    }
+    (value - threshold) as f32 / (u8::MAX - threshold) as f32
+}
@@ -0,0 +1,433 @@
+//! GIF decoding into Animations, GIF87a and GIF89a. A GIF with one image comes out as a single frame.
+//! Color tables, transparency, interlacing and the three disposal methods are handled, plain text and application
+//! extensions (the loop count among them) are skipped, animations always loop.
+
+use std::{io, time::Duration};
+
+use crate::{
+    animation::{Animation, Dispose, RawFrame},
+    color::{pack_rgba8, Pixel},
+};
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Bad GIF: {message}"))
+}
+
+/// Reads through the file front to back
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let taken = self.bytes.get(self.position..self.position + count).ok_or_else(|| invalid("unexpected end of file"))?;
+        self.position += count;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// A color table of 2 << (packed & 7) entries, if the flag in bit 7 of packed says there is one
+    fn color_table(&mut self, packed: u8) -> io::Result<Option<Vec<Pixel>>> {
+        if packed & 0x80 == 0 {
+            return Ok(None);
+        }
+        let rgb = self.take(3 * (2 << (packed & 7)))?;
+        Ok(Some(rgb.chunks_exact(3).map(|rgb| pack_rgba8(rgb[0], rgb[1], rgb[2], 255)).collect()))
+    }
+
+    /// Data sub-blocks up to the empty one that ends them, joined together
+    fn sub_blocks(&mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        loop {
+            let length = self.u8()? as usize;
+            if length == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.take(length)?);
+        }
+    }
+}
+
+/// The last graphic control extension, it applies to the next image only
+#[derive(Default)]
+struct GraphicControl {
+    dispose: u8,
+    transparent: Option<u8>,
+    // hundredths of a second
+    delay: u16,
+}
+
+impl Animation {
+    pub fn parse_gif(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+        if !matches!(reader.take(6)?, b"GIF87a" | b"GIF89a") {
+            return Err(invalid("missing GIF signature"));
+        }
+        let (width, height) = (reader.u16()? as i32, reader.u16()? as i32);
+        let packed = reader.u8()?;
+        // background color and aspect ratio, the canvas starts out transparent like it does in browsers
+        reader.take(2)?;
+        if width == 0 || height == 0 {
+            return Err(invalid("empty image"));
+        }
+        let global_colors = reader.color_table(packed)?;
+
+        let mut control = GraphicControl::default();
+        let mut raw_frames = Vec::new();
+        loop {
+            match reader.u8()? {
+                // extension
+                0x21 => {
+                    let label = reader.u8()?;
+                    let data = reader.sub_blocks()?;
+                    if label == 0xF9 && data.len() >= 4 {
+                        control = GraphicControl {
+                            dispose: (data[0] >> 2) & 7,
+                            transparent: (data[0] & 1 == 1).then_some(data[3]),
+                            delay: u16::from_le_bytes([data[1], data[2]]),
+                        };
+                    }
+                }
+                // image
+                0x2C => {
+                    let (x, y) = (reader.u16()? as i32, reader.u16()? as i32);
+                    let (frame_width, frame_height) = (reader.u16()? as i32, reader.u16()? as i32);
+                    let packed = reader.u8()?;
+                    let local_colors = reader.color_table(packed)?;
+                    let colors = local_colors.as_ref().or(global_colors.as_ref()).ok_or_else(|| invalid("image without a color table"))?;
+                    let min_code_size = reader.u8()?;
+                    let indices = lzw_decode(&reader.sub_blocks()?, min_code_size, frame_width as usize * frame_height as usize)?;
+
+                    let mut pixels = vec![0; frame_width as usize * frame_height as usize];
+                    let interlaced = packed & 0x40 != 0;
+                    for (row, indices) in indices.chunks_exact(frame_width.max(1) as usize).take(frame_height as usize).enumerate() {
+                        let y = if interlaced { interlaced_row(row, frame_height as usize) } else { row };
+                        for (pixel, index) in pixels[y * frame_width as usize..].iter_mut().zip(indices) {
+                            // the transparent index and ones past the end of the table leave the canvas showing
+                            if control.transparent != Some(*index) {
+                                *pixel = colors.get(*index as usize).copied().unwrap_or(0);
+                            }
+                        }
+                    }
+                    let dispose = match control.dispose {
+                        2 => Dispose::Clear,
+                        3 => Dispose::Previous,
+                        _ => Dispose::Keep,
+                    };
+                    // 0 and 1 hundredths mean "as fast as possible", which browsers slow down to a tenth of a second
+                    let delay = Duration::from_millis(if control.delay <= 1 { 100 } else { control.delay as u64 * 10 });
+                    raw_frames.push(RawFrame { x, y, width: frame_width, height: frame_height, pixels, blend: true, dispose, delay });
+                    control = GraphicControl::default();
+                }
+                0x3B => break,
+                _ => return Err(invalid("unknown block")),
+            }
+        }
+        // a lone image isn't an animation, its delay means nothing
+        if let [frame] = raw_frames.as_mut_slice() {
+            frame.delay = Duration::ZERO;
+        }
+        Animation::compose(width, height, raw_frames)
+    }
+}
+
+/// Which row of the image the row'th row stored ends up on. Interlaced images store every 8th row from 0, then every
+/// 8th from 4, every 4th from 2 and every 2nd from 1
+fn interlaced_row(row: usize, height: usize) -> usize {
+    let mut row = row;
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        let in_pass = height.saturating_sub(start).div_ceil(step);
+        if row < in_pass {
+            return start + row * step;
+        }
+        row -= in_pass;
+    }
+    0
+}
+
+/// GIF's variable width LZW, codes packed least significant bit first. Stops at the end code, at the end of the data
+/// or once there are pixel_count indices, whichever comes first, plenty of encoders get the end slightly wrong
+fn lzw_decode(data: &[u8], min_code_size: u8, pixel_count: usize) -> io::Result<Vec<u8>> {
+    if !(1..=11).contains(&min_code_size) {
+        return Err(invalid("bad lzw code size"));
+    }
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    // every code past the roots is an earlier code plus one more index
+    let mut prefix = [0u16; 4096];
+    let mut suffix = [0u8; 4096];
+    let mut first = [0u8; 4096];
+    let mut length = [0u16; 4096];
+    for code in 0..clear {
+        (suffix[code as usize], first[code as usize], length[code as usize]) = (code as u8, code as u8, 1);
+    }
+
+    let mut output = Vec::with_capacity(pixel_count);
+    let (mut next, mut code_size, mut previous) = (end + 1, min_code_size as u32 + 1, None::<u16>);
+    let (mut buffer, mut count, mut position) = (0u32, 0u32, 0usize);
+    while output.len() < pixel_count {
+        while count < code_size && position < data.len() {
+            buffer |= (data[position] as u32) << count;
+            position += 1;
+            count += 8;
+        }
+        if count < code_size {
+            break;
+        }
+        let code = (buffer & ((1 << code_size) - 1)) as u16;
+        buffer >>= code_size;
+        count -= code_size;
+
+        if code == clear {
+            (next, code_size, previous) = (end + 1, min_code_size as u32 + 1, None);
+            continue;
+        }
+        if code == end {
+            break;
+        }
+        if let Some(previous) = previous {
+            // a code that isn't in the table yet can only be the one about to be added, previous plus its own first
+            let added_first = match code {
+                code if code < next => first[code as usize],
+                code if code == next => first[previous as usize],
+                _ => return Err(invalid("bad lzw code")),
+            };
+            if next < 4096 {
+                let index = next as usize;
+                (prefix[index], suffix[index], first[index], length[index]) = (previous, added_first, first[previous as usize], length[previous as usize] + 1);
+                next += 1;
+                if next == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+        } else if code >= clear {
+            return Err(invalid("bad lzw code"));
+        }
+        // the indices come out last first walking back through the prefixes
+        let start = output.len();
+        output.resize(start + length[code as usize] as usize, 0);
+        let mut walk = code;
+        for index in output[start..].iter_mut().rev() {
+            *index = suffix[walk as usize];
+            walk = prefix[walk as usize];
+        }
+        previous = Some(code);
+    }
+    // short images are padded out with index 0 rather than thrown away
+    output.resize(pixel_count, 0);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // the 10 by 10 sample from "What's in a GIF": red and blue squares with a white one in the middle
+    const SAMPLE_DATA: [u8; 22] = [0x8C, 0x2D, 0x99, 0x87, 0x2A, 0x1C, 0xDC, 0x33, 0xA0, 0x02, 0x75, 0xEC, 0x95, 0xFA, 0xA8, 0xDE, 0x60, 0x8C, 0x04, 0x91, 0x4C, 0x01];
+    const SAMPLE_INDICES: [&str; 10] =
+        ["1111122222", "1111122222", "1111122222", "1110000222", "1110000222", "2220000111", "2220000111", "2222211111", "2222211111", "2222211111"];
+    const COLORS: [u8; 12] = [255, 255, 255, 255, 0, 0, 0, 0, 255, 0, 0, 0];
+
+    // a plain GIF encoder: the codes grow with the table and a clear goes out whenever it fills up
+    fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1u32 << min_code_size;
+        let mut bytes = Vec::new();
+        let (mut buffer, mut count) = (0u64, 0u32);
+        let mut emit = |code: u32, size: u32| {
+            buffer |= (code as u64) << count;
+            count += size;
+            while count >= 8 {
+                bytes.push(buffer as u8);
+                buffer >>= 8;
+                count -= 8;
+            }
+        };
+        let mut table = HashMap::new();
+        let (mut next, mut size) = (clear + 2, min_code_size as u32 + 1);
+        emit(clear, size);
+        let mut current = None;
+        for &index in indices {
+            let Some(code) = current else {
+                current = Some(index as u32);
+                continue;
+            };
+            if let Some(&longer) = table.get(&(code, index)) {
+                current = Some(longer);
+                continue;
+            }
+            emit(code, size);
+            if next < 4096 {
+                table.insert((code, index), next);
+                next += 1;
+                // the decoder adds its entry a code later, so it gets to the next size one later too
+                if next > 1 << size && size < 12 {
+                    size += 1;
+                }
+            } else {
+                emit(clear, size);
+                table.clear();
+                (next, size) = (clear + 2, min_code_size as u32 + 1);
+            }
+            current = Some(index as u32);
+        }
+        if let Some(code) = current {
+            emit(code, size);
+        }
+        emit(clear + 1, size);
+        emit(0, 7);
+        bytes
+    }
+
+    fn sub_blocks(data: &[u8]) -> Vec<u8> {
+        let mut blocks: Vec<u8> = data.chunks(255).flat_map(|block| [&[block.len() as u8][..], block].concat()).collect();
+        blocks.push(0);
+        blocks
+    }
+
+    // one image at x, y, width, height, with a graphic control extension first when given (packed, delay, transparent index)
+    fn image(rect: [u16; 4], packed: u8, control: Option<(u8, u16, u8)>, min_code_size: u8, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if let Some((control_packed, delay, transparent)) = control {
+            bytes.extend([0x21, 0xF9, 4, control_packed]);
+            bytes.extend(delay.to_le_bytes());
+            bytes.extend([transparent, 0]);
+        }
+        bytes.push(0x2C);
+        for value in rect {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend([packed, min_code_size]);
+        bytes.extend(sub_blocks(data));
+        bytes
+    }
+
+    // a canvas with the four COLORS as its global table, around the images
+    fn gif(width: u16, height: u16, images: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        bytes.extend([0x81, 0, 0]);
+        bytes.extend(COLORS);
+        bytes.extend(images.concat());
+        bytes.push(0x3B);
+        bytes
+    }
+
+    fn color(index: u8) -> Pixel {
+        let rgb = &COLORS[index as usize * 3..][..3];
+        pack_rgba8(rgb[0], rgb[1], rgb[2], 255)
+    }
+
+    fn sample_indices() -> Vec<u8> {
+        SAMPLE_INDICES.iter().flat_map(|row| row.bytes().map(|digit| digit - b'0')).collect()
+    }
+
+    #[test]
+    fn decodes_the_sample_image() {
+        assert_eq!(lzw_decode(&SAMPLE_DATA, 2, 100).unwrap(), sample_indices());
+
+        let animation = Animation::parse_gif(&gif(10, 10, &[image([0, 0, 10, 10], 0, None, 2, &SAMPLE_DATA)])).unwrap();
+        assert_eq!(animation.frames.len(), 1);
+        assert_eq!(animation.frames[0].delay, Duration::ZERO);
+        let bitmap = &animation.frames[0].bitmap;
+        assert_eq!((bitmap.width, bitmap.height), (10, 10));
+        assert_eq!(bitmap.pixels, sample_indices().into_iter().map(color).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn lzw_codes_grow_and_the_table_starts_over() {
+        // enough varied indices to fill the table to 4096 codes several times over
+        let mut state = 1u32;
+        let indices: Vec<u8> = (0..60_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((state >> 16) % 13) as u8
+            })
+            .collect();
+        assert_eq!(lzw_decode(&lzw_encode(&indices, 4), 4, indices.len()).unwrap(), indices);
+        // runs make the codes that name themselves before they're in the table
+        let runs: Vec<u8> = (0..5000).map(|index| (index / 700 % 2) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&runs, 2), 2, runs.len()).unwrap(), runs);
+    }
+
+    #[test]
+    fn lzw_stops_at_the_pixel_count_and_pads_short_data() {
+        assert_eq!(lzw_decode(&SAMPLE_DATA, 2, 7).unwrap(), &sample_indices()[..7]);
+        let padded = lzw_decode(&SAMPLE_DATA[..4], 2, 100).unwrap();
+        assert_eq!(padded.len(), 100);
+        assert!(padded[20..].iter().all(|index| *index == 0));
+    }
+
+    #[test]
+    fn bad_lzw_is_an_error() {
+        // after a clear, 7 at 3 bits isn't a root, and after a root it's past the next code the table would make
+        assert!(lzw_decode(&[0b111_100], 2, 4).is_err());
+        assert!(lzw_decode(&(4u16 | 1 << 3 | 7 << 6).to_le_bytes(), 2, 4).is_err());
+        assert!(lzw_decode(&SAMPLE_DATA, 0, 100).is_err());
+        assert!(lzw_decode(&SAMPLE_DATA, 12, 100).is_err());
+    }
+
+    #[test]
+    fn interlaced_rows_come_in_four_passes() {
+        let order: Vec<usize> = (0..10).map(|row| interlaced_row(row, 10)).collect();
+        assert_eq!(order, [0, 8, 4, 2, 6, 1, 3, 5, 7, 9]);
+
+        let rows: Vec<u8> = order.iter().flat_map(|row| [*row as u8 % 4; 2]).collect();
+        let animation = Animation::parse_gif(&gif(2, 10, &[image([0, 0, 2, 10], 0x40, None, 2, &lzw_encode(&rows, 2))])).unwrap();
+        let expected: Vec<Pixel> = (0..10).flat_map(|row| [color(row % 4); 2]).collect();
+        assert_eq!(animation.frames[0].bitmap.pixels, expected);
+    }
+
+    #[test]
+    fn frames_dispose_and_show_through() {
+        // a 2 by 2 canvas: all white for 50ms kept, a red pixel over it cleared after, then a blue one with everything
+        // else transparent put back as it was before it
+        let frames = [
+            image([0, 0, 2, 2], 0, Some((1 << 2, 5, 0)), 2, &lzw_encode(&[0; 4], 2)),
+            image([1, 0, 1, 1], 0, Some((2 << 2, 0, 0)), 2, &lzw_encode(&[1], 2)),
+            image([0, 0, 2, 2], 0, Some((3 << 2 | 1, 20, 3)), 2, &lzw_encode(&[3, 3, 3, 2], 2)),
+            image([0, 0, 1, 1], 0, None, 2, &lzw_encode(&[3], 2)),
+        ];
+        let animation = Animation::parse_gif(&gif(2, 2, &frames)).unwrap();
+        let pixels: Vec<&[Pixel]> = animation.frames.iter().map(|frame| &frame.bitmap.pixels[..]).collect();
+        let [white, red, blue, black] = [0, 1, 2, 3].map(color);
+        assert_eq!(pixels, [&[white; 4][..], &[white, red, white, white], &[white, 0, white, blue], &[black, 0, white, white]]);
+        let delays: Vec<u64> = animation.frames.iter().map(|frame| frame.delay.as_millis() as u64).collect();
+        assert_eq!(delays, [50, 100, 200, 100]);
+    }
+
+    #[test]
+    fn bad_files_are_errors() {
+        let good = gif(10, 10, &[image([0, 0, 10, 10], 0, None, 2, &SAMPLE_DATA)]);
+        assert!(Animation::parse_gif(&good).is_ok());
+        for length in 0..good.len() {
+            assert!(Animation::parse_gif(&good[..length]).is_err(), "cut to {length} bytes");
+        }
+
+        let mut no_table = good.clone();
+        no_table[10] = 0;
+        no_table.drain(13..25);
+        let mut unknown_block = good.clone();
+        unknown_block.insert(25, 0x99);
+        for (what, bytes) in [
+            ("signature", [b"GIF90a", &good[6..]].concat()),
+            ("empty", gif(0, 10, &[])),
+            ("no color table", no_table),
+            ("unknown block", unknown_block),
+            ("no frames", gif(10, 10, &[])),
+        ] {
+            assert_eq!(Animation::parse_gif(&bytes).map(|_| ()).unwrap_err().kind(), io::ErrorKind::InvalidData, "{what}");
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! 8 bit palette mode: pixels are indices into a 256 color palette and only become BGRA when the frame is expanded
+//! for presenting. A quarter of the memory traffic while drawing, and recoloring the palette recolors the whole
+//! frame for free, which is where cycling and fades come from.
+
+use std::ops::RangeInclusive;
+
+use crate::{
+    color::{pack_rgba8, Color, Pixel},
+    frame_buffer::FrameBuffer,
+};
+
+#[derive(Clone)]
+pub struct Palette {
+    pub colors: [Pixel; 256],
+}
+
+impl Palette {
+    /// Evenly spaced stops blended into all 256 entries, wrapping from the last stop back to the first so it cycles seamlessly
+    pub fn looping_gradient(stops: &[Color]) -> Self {
+        let colors = std::array::from_fn(|index| {
+            let position = index as f32 / 256.0 * stops.len() as f32;
+            let (from, t) = (position as usize, position.fract());
+            stops[from].lerp(stops[(from + 1) % stops.len()], t).to_pixel()
+        });
+        Palette { colors }
+    }
+
+    /// Rotates the entries in range by steps, positive moves every color to the next index up
+    pub fn cycle(&mut self, range: RangeInclusive<u8>, steps: i32) {
+        let entries = &mut self.colors[*range.start() as usize..=*range.end() as usize];
+        let steps = steps.rem_euclid(entries.len() as i32) as usize;
+        entries.rotate_right(steps);
+    }
+
+    /// Every entry blended towards one color, amount 0 leaves it alone and 1 is all that color
+    pub fn faded(&self, toward: Color, amount: f32) -> Palette {
+        let [target_blue, target_green, target_red, _] = toward.to_pixel().to_le_bytes();
+        let weight = (amount.clamp(0.0, 1.0) * 256.0) as i32;
+        let mix = |from: u8, to: u8| (from as i32 + (((to as i32 - from as i32) * weight) >> 8)) as u8;
+        let colors = self.colors.map(|color| {
+            let [blue, green, red, alpha] = color.to_le_bytes();
+            pack_rgba8(mix(red, target_red), mix(green, target_green), mix(blue, target_blue), alpha)
+        });
+        Palette { colors }
+    }
+}
+
+/// One byte per pixel, rows packed with no padding
+pub struct IndexedBuffer {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<u8>,
+}
+
+impl IndexedBuffer {
+    pub fn new(width: i32, height: i32) -> Self {
+        IndexedBuffer { width, height, pixels: vec![0; (width.max(0) * height.max(0)) as usize] }
+    }
+
+    /// Looks every pixel up in the palette and writes the result into the backbuffer, clipped to whichever is smaller
+    pub fn expand_into(&self, palette: &Palette, buffer: &mut FrameBuffer) {
+        if buffer.is_empty() || self.width <= 0 || self.height <= 0 {
+            return;
+        }
+        // zip stops at the shorter of each, rows and pixels alike
+        for (dest, source) in buffer.rows_mut().zip(self.pixels.chunks_exact(self.width as usize)) {
+            for (dest, index) in dest.iter_mut().zip(source) {
+                *dest = palette.colors[*index as usize];
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! zlib / DEFLATE decompression (RFC 1950 and 1951), which is what PNG image data is stored in.
+//! Canonical huffman codes are decoded a bit at a time from counts per length, slow next to a table driven
+//! decoder but small, and images only get decoded once at load.
+
+use std::io;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Bad deflate stream: {message}"))
+}
+
+// base values and extra bits for length codes 257..285 and distance codes 0..29
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] =
+    [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// the order code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Bits come out least significant first, huffman codes most significant first (see Huffman::decode)
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.count < count {
+            let byte = *self.bytes.get(self.position).ok_or_else(|| invalid("ran out of data"))?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << count) - 1) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Drops the rest of the current byte, stored blocks start on a byte boundary
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical huffman code, as how many codes there are of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate().filter(|(_, length)| **length != 0) {
+            symbols[offsets[*length as usize] as usize] = symbol as u16;
+            offsets[*length as usize] += 1;
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        // code is read so far, first is the first code of the current length, index where its symbols start
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad huffman code"))
+    }
+}
+
+/// Unwraps a zlib stream and inflates what's inside, checking the adler32 at the end
+pub fn zlib_decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let [method, flags, ..] = *bytes else {
+        return Err(invalid("missing zlib header"));
+    };
+    if method & 0x0F != 8 || !(method as u16 * 256 + flags as u16).is_multiple_of(31) {
+        return Err(invalid("not a deflate zlib stream"));
+    }
+    if flags & 0x20 != 0 {
+        return Err(invalid("preset dictionaries aren't supported"));
+    }
+    let mut reader = BitReader { bytes: &bytes[2..], position: 0, buffer: 0, count: 0 };
+    let output = inflate(&mut reader)?;
+    let checksum = reader.bytes.get(reader.position..reader.position + 4).ok_or_else(|| invalid("missing adler32"))?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&output) {
+        return Err(invalid("adler32 mismatch"));
+    }
+    Ok(output)
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b could overflow
+    for chunk in bytes.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+fn inflate(reader: &mut BitReader) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader.bytes.get(reader.position..reader.position + 4).ok_or_else(|| invalid("truncated stored block"))?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(invalid("stored block length check"));
+                }
+                let start = reader.position + 4;
+                let block = reader.bytes.get(start..start + length as usize).ok_or_else(|| invalid("truncated stored block"))?;
+                output.extend_from_slice(block);
+                reader.position = start + length as usize;
+            }
+            1 => {
+                let mut lengths = [0u8; 288 + 30];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..288].fill(8);
+                lengths[288..].fill(5);
+                inflate_block(reader, &mut output, &Huffman::new(&lengths[..288]), &Huffman::new(&lengths[288..]))?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(reader)?;
+                inflate_block(reader, &mut output, &literals, &distances)?;
+            }
+            _ => return Err(invalid("reserved block type")),
+        }
+        if last {
+            // the checksum after the stream starts on the next whole byte
+            reader.align();
+            return Ok(output);
+        }
+    }
+}
+
+fn read_dynamic_codes(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[*index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    // both codes' lengths come as one run, repeats can cross from one to the other
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid("repeat with nothing before it"))?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count || lengths[256] == 0 {
+        return Err(invalid("bad code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn inflate_block(reader: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                let (Some(base), Some(extra)) = (LENGTH_BASE.get(index), LENGTH_EXTRA.get(index)) else {
+                    return Err(invalid("bad length code"));
+                };
+                let length = *base as usize + reader.bits(*extra as u32)? as usize;
+                let index = distances.decode(reader)? as usize;
+                let (Some(base), Some(extra)) = (DISTANCE_BASE.get(index), DISTANCE_EXTRA.get(index)) else {
+                    return Err(invalid("bad distance code"));
+                };
+                let distance = *base as usize + reader.bits(*extra as u32)? as usize;
+                if distance > output.len() {
+                    return Err(invalid("distance before the start"));
+                }
+                // byte by byte, the copy can overlap what it's writing
+                let start = output.len() - distance;
+                for offset in 0..length {
+                    output.push(output[start + offset]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // made with python's zlib.compress, each checked to be the one block type
+    const STORED: [u8; 23] = [120, 1, 1, 12, 0, 243, 255, 115, 116, 111, 114, 101, 100, 32, 98, 108, 111, 99, 107, 31, 128, 4, 189];
+    const FIXED: [u8; 27] = [120, 218, 75, 203, 172, 72, 77, 81, 200, 40, 77, 75, 203, 77, 204, 211, 81, 72, 195, 195, 85, 4, 0, 108, 17, 15, 249];
+    const DYNAMIC: [u8; 86] = [
+        120, 218, 173, 202, 201, 1, 64, 48, 16, 0, 192, 86, 182, 0, 15, 247, 81, 78, 46, 18, 57, 22, 27, 34, 170, 215, 130, 135, 121, 79, 212,
+        10, 100, 14, 204, 27, 1, 220, 161, 176, 160, 25, 129, 137, 4, 152, 2, 8, 148, 138, 10, 136, 191, 37, 198, 133, 84, 243, 162, 205, 106,
+        157, 15, 184, 237, 7, 197, 243, 74, 119, 126, 202, 170, 110, 218, 174, 31, 198, 233, 203, 121, 1, 201, 154, 65, 218,
+    ];
+
+    fn dynamic_text() -> Vec<u8> {
+        [b"the dynamic block has its own codes, ".repeat(3), b"abcdefghijklmnopqrstuvwxyz0123456789".repeat(2)].concat()
+    }
+
+    #[test]
+    fn inflates_each_block_type() {
+        assert_eq!(zlib_decompress(&STORED).unwrap(), b"stored block");
+        // the repeats are a match longer than its distance, copied over itself
+        assert_eq!(zlib_decompress(&FIXED).unwrap(), b"fixed huffman, fixed huffman, fixed huffman!");
+        assert_eq!(zlib_decompress(&DYNAMIC).unwrap(), dynamic_text());
+    }
+
+    #[test]
+    fn adler32_matches_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        // long enough for the sums to be taken modulo on the way
+        let long = vec![0xFF; 100_000];
+        let (a, b) = long.iter().fold((1u64, 0u64), |(a, b), byte| ((a + *byte as u64) % 65521, (b + a + *byte as u64) % 65521));
+        assert_eq!(adler32(&long), (b << 16 | a) as u32);
+    }
+
+    #[test]
+    fn checksum_mismatch_is_an_error() {
+        let mut stream = FIXED;
+        stream[26] ^= 1;
+        assert_eq!(zlib_decompress(&stream).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_streams_are_errors() {
+        for stream in [&STORED[..], &FIXED, &DYNAMIC] {
+            for length in 0..stream.len() {
+                assert!(zlib_decompress(&stream[..length]).is_err(), "{length} of {} bytes", stream.len());
+            }
+        }
+    }
+
+    #[test]
+    fn bad_headers_are_errors() {
+        // not deflate, a failing header check and a preset dictionary
+        for header in [[0x79, 0x9C], [0x78, 0x9D], [0x78, 0xBB]] {
+            assert!(zlib_decompress(&[&header[..], &STORED[2..]].concat()).is_err());
+        }
+    }
+}
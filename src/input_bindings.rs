@@ -0,0 +1,200 @@
+use windows::Win32::UI::Input::KeyboardAndMouse::*;
+
+use crate::game_input::{GamepadState, KeyCode, KeyboardState, MouseButton, MouseState, Scancode};
+
+/// Things the game wants to do, independent of which physical input triggers them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Boost,
+    Reset,
+}
+
+// full set of pad inputs so any of them can be bound, the defaults only use some and the rest are allowed to go
+// unused. Same order as GamepadButtons::all, so `button as usize` indexes it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GamepadButton {
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+    #[allow(dead_code)]
+    Start,
+    #[allow(dead_code)]
+    Back,
+    #[allow(dead_code)]
+    LeftThumb,
+    #[allow(dead_code)]
+    RightThumb,
+    LeftShoulder,
+    RightShoulder,
+    A,
+    B,
+    #[allow(dead_code)]
+    X,
+    Y,
+    #[allow(dead_code)]
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    #[allow(dead_code)]
+    RightStickX,
+    #[allow(dead_code)]
+    RightStickY,
+    #[allow(dead_code)]
+    LeftTrigger,
+    #[allow(dead_code)]
+    RightTrigger,
+}
+
+/// A physical input an action can be bound to.
+/// Kept as plain data (vk codes instead of windows types) so bindings can be saved to disk later.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    // virtual key, follows the layout (for keys picked by their label)
+    Key(u16),
+    // physical position, for keys picked by where they are like WASD
+    Scancode(u16),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+    // axis counts when pushed past threshold in the direction of sign (1.0 or -1.0)
+    Axis { axis: GamepadAxis, sign: f32, threshold: f32 },
+}
+
+impl Binding {
+    fn value(&self, keyboard: &KeyboardState, mouse: &MouseState, gamepads: &[GamepadState]) -> f32 {
+        let connected = || gamepads.iter().filter(|gamepad| gamepad.connected);
+        match *self {
+            Binding::Key(vk_code) => keyboard.is_down(KeyCode(vk_code)) as u8 as f32,
+            Binding::Scancode(scancode) => keyboard.is_scancode_down(Scancode(scancode)) as u8 as f32,
+            Binding::Mouse(button) => mouse.is_down(button) as u8 as f32,
+            Binding::Gamepad(button) => connected().any(|gamepad| gamepad_button_down(gamepad, button)) as u8 as f32,
+            Binding::Axis { axis, sign, threshold } => connected()
+                .map(|gamepad| gamepad_axis_value(gamepad, axis) * sign)
+                .filter(|value| *value > threshold)
+                .fold(0.0, f32::max),
+        }
+    }
+}
+
+fn gamepad_button_down(gamepad: &GamepadState, button: GamepadButton) -> bool {
+    let buttons = &gamepad.buttons;
+    let button = match button {
+        GamepadButton::DpadUp => buttons.dpad_up,
+        GamepadButton::DpadDown => buttons.dpad_down,
+        GamepadButton::DpadLeft => buttons.dpad_left,
+        GamepadButton::DpadRight => buttons.dpad_right,
+        GamepadButton::Start => buttons.start,
+        GamepadButton::Back => buttons.back,
+        GamepadButton::LeftThumb => buttons.left_thumb,
+        GamepadButton::RightThumb => buttons.right_thumb,
+        GamepadButton::LeftShoulder => buttons.left_shoulder,
+        GamepadButton::RightShoulder => buttons.right_shoulder,
+        GamepadButton::A => buttons.a,
+        GamepadButton::B => buttons.b,
+        GamepadButton::X => buttons.x,
+        GamepadButton::Y => buttons.y,
+        GamepadButton::LeftTrigger => buttons.left_trigger,
+        GamepadButton::RightTrigger => buttons.right_trigger,
+    };
+    button.ended_down
+}
+
+fn gamepad_axis_value(gamepad: &GamepadState, axis: GamepadAxis) -> f32 {
+    match axis {
+        GamepadAxis::LeftStickX => gamepad.sticks.left_x,
+        GamepadAxis::LeftStickY => gamepad.sticks.left_y,
+        GamepadAxis::RightStickX => gamepad.sticks.right_x,
+        GamepadAxis::RightStickY => gamepad.sticks.right_y,
+        GamepadAxis::LeftTrigger => gamepad.triggers.left,
+        GamepadAxis::RightTrigger => gamepad.triggers.right,
+    }
+}
+
+/// Action -> physical input table, an action can have any number of bindings
+#[derive(Clone)]
+pub struct InputBindings {
+    bindings: Vec<(Action, Binding)>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let stick = |axis, sign| Binding::Axis { axis, sign, threshold: 0.0 };
+        let mut bindings = InputBindings { bindings: Vec::new() };
+
+        bindings.bind(Action::MoveLeft, Binding::Key(VK_LEFT.0));
+        bindings.bind(Action::MoveLeft, Binding::Scancode(Scancode::A.0));
+        bindings.bind(Action::MoveLeft, Binding::Gamepad(GamepadButton::DpadLeft));
+        bindings.bind(Action::MoveLeft, stick(GamepadAxis::LeftStickX, -1.0));
+
+        bindings.bind(Action::MoveRight, Binding::Key(VK_RIGHT.0));
+        bindings.bind(Action::MoveRight, Binding::Scancode(Scancode::D.0));
+        bindings.bind(Action::MoveRight, Binding::Gamepad(GamepadButton::DpadRight));
+        bindings.bind(Action::MoveRight, stick(GamepadAxis::LeftStickX, 1.0));
+
+        bindings.bind(Action::MoveUp, Binding::Key(VK_UP.0));
+        bindings.bind(Action::MoveUp, Binding::Scancode(Scancode::W.0));
+        bindings.bind(Action::MoveUp, Binding::Gamepad(GamepadButton::DpadUp));
+        bindings.bind(Action::MoveUp, stick(GamepadAxis::LeftStickY, 1.0));
+
+        bindings.bind(Action::MoveDown, Binding::Key(VK_DOWN.0));
+        bindings.bind(Action::MoveDown, Binding::Scancode(Scancode::S.0));
+        bindings.bind(Action::MoveDown, Binding::Gamepad(GamepadButton::DpadDown));
+        bindings.bind(Action::MoveDown, stick(GamepadAxis::LeftStickY, -1.0));
+
+        bindings.bind(Action::Boost, Binding::Key(VK_SHIFT.0));
+        bindings.bind(Action::Boost, Binding::Gamepad(GamepadButton::A));
+        bindings.bind(Action::Boost, Binding::Gamepad(GamepadButton::RightTrigger));
+
+        bindings.bind(Action::Reset, Binding::Key(VK_SPACE.0));
+        bindings.bind(Action::Reset, Binding::Mouse(MouseButton::Middle));
+        bindings.bind(Action::Reset, Binding::Gamepad(GamepadButton::Y));
+
+        bindings
+    }
+}
+
+impl InputBindings {
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        if !self.bindings.contains(&(action, binding)) {
+            self.bindings.push((action, binding));
+        }
+    }
+
+    pub fn bindings_for(&self, action: Action) -> impl Iterator<Item = Binding> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(bound_action, _)| *bound_action == action)
+            .map(|(_, binding)| *binding)
+    }
+
+    /// Strongest of all inputs bound to the action, 0.0..1.0 (digital inputs are 0.0 or 1.0)
+    pub fn action_value(&self, action: Action, keyboard: &KeyboardState, mouse: &MouseState, gamepads: &[GamepadState]) -> f32 {
+        self.bindings_for(action)
+            .map(|binding| binding.value(keyboard, mouse, gamepads))
+            .fold(0.0, f32::max)
+    }
+
+    pub fn is_action_down(&self, action: Action, keyboard: &KeyboardState, mouse: &MouseState, gamepads: &[GamepadState]) -> bool {
+        self.action_value(action, keyboard, mouse, gamepads) > 0.0
+    }
+
+    /// Swaps everything bound to two actions, e.g. to invert an axis at runtime
+    pub fn swap(&mut self, first: Action, second: Action) {
+        for (action, _) in self.bindings.iter_mut() {
+            if *action == first {
+                *action = second;
+            } else if *action == second {
+                *action = first;
+            }
+        }
+    }
+}
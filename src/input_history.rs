@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use crate::{game_input::GamepadState, input_bindings::GamepadButton};
+
+const BUTTON_COUNT: usize = 16;
+
+/// When each button of one controller was last pressed, for timing based input on top of the per-frame button states.
+/// Times come from GameInput::time so recorded input replays with the same taps.
+#[derive(Clone, Default)]
+pub struct GamepadHistory {
+    now: Duration,
+    // two most recent presses per button, newest first
+    presses: [[Option<Duration>; 2]; BUTTON_COUNT],
+}
+
+impl GamepadHistory {
+    /// Call once per frame before asking about taps or chords
+    pub fn update(&mut self, gamepad: &GamepadState, now: Duration) {
+        self.now = now;
+        for (presses, button) in self.presses.iter_mut().zip(gamepad.buttons.all()) {
+            // a fast tap can fit a whole press (or two) inside one frame
+            let press_count = (button.half_transition_count + button.ended_down as u32) / 2;
+            for _ in 0..press_count.min(2) {
+                *presses = [Some(now), presses[0]];
+            }
+        }
+    }
+
+    /// Pressed this frame, and also pressed once before within the window
+    pub fn was_double_tapped(&self, button: GamepadButton, window_ms: u64) -> bool {
+        match self.presses[button as usize] {
+            [Some(last), Some(previous)] => last == self.now && last - previous <= Duration::from_millis(window_ms),
+            _ => false,
+        }
+    }
+
+    /// All the buttons are held right now
+    pub fn is_chord_down(gamepad: &GamepadState, buttons: &[GamepadButton]) -> bool {
+        let states = gamepad.buttons.all();
+        buttons.iter().all(|button| states[*button as usize].ended_down)
+    }
+
+    /// The chord was completed this frame, with every button pressed within the window of each other
+    pub fn was_chord_pressed(&self, gamepad: &GamepadState, buttons: &[GamepadButton], window_ms: u64) -> bool {
+        if !Self::is_chord_down(gamepad, buttons) {
+            return false;
+        }
+        let press_times: Vec<Duration> = buttons.iter().filter_map(|button| self.presses[*button as usize][0]).collect();
+        let (Some(first), Some(last)) = (press_times.iter().min(), press_times.iter().max()) else {
+            return false;
+        };
+        press_times.len() == buttons.len() && *last == self.now && *last - *first <= Duration::from_millis(window_ms)
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::{
+    game_input::{ButtonState, GameInput, GamepadState, KeyboardState, MouseState, TextInputState, TouchPoint, TouchState, MAX_GAMEPADS, MOUSE_BUTTON_COUNT},
+    GameState,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingMode {
+    Idle,
+    Recording,
+    PlayingBack,
+}
+
+/// Handmade style looped live editing: record the input of a stretch of frames to a file,
+/// then replay it forever, restoring the game state snapshot every time the loop wraps.
+pub struct InputRecorder {
+    path: PathBuf,
+    mode: RecordingMode,
+    snapshot: Option<GameState>,
+    writer: Option<BufWriter<File>>,
+    reader: Option<BufReader<File>>,
+}
+
+impl InputRecorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        InputRecorder {
+            path: path.into(),
+            mode: RecordingMode::Idle,
+            snapshot: None,
+            writer: None,
+            reader: None,
+        }
+    }
+
+    pub fn mode(&self) -> RecordingMode {
+        self.mode
+    }
+
+    /// Idle -> Recording -> PlayingBack -> Idle
+    pub fn toggle(&mut self, state: &mut GameState) -> io::Result<()> {
+        match self.mode {
+            RecordingMode::Idle => {
+                self.snapshot = Some(state.clone());
+                self.writer = Some(BufWriter::new(File::create(&self.path)?));
+                self.mode = RecordingMode::Recording;
+            }
+            RecordingMode::Recording => {
+                if let Some(mut writer) = self.writer.take() {
+                    writer.flush()?;
+                }
+                self.reader = Some(BufReader::new(File::open(&self.path)?));
+                self.restore(state);
+                self.mode = RecordingMode::PlayingBack;
+            }
+            RecordingMode::PlayingBack => {
+                self.reader = None;
+                self.snapshot = None;
+                self.mode = RecordingMode::Idle;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, input: &GameInput) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        let frame = encode_frame(input);
+        writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+        writer.write_all(&frame)
+    }
+
+    /// Replaces the live input with the next recorded frame, looping back to the start (and the snapshot) at the end
+    pub fn playback(&mut self, input: &mut GameInput, state: &mut GameState) -> io::Result<()> {
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(());
+        };
+
+        let frame = match read_frame(reader)? {
+            Some(frame) => frame,
+            None => {
+                reader.seek(SeekFrom::Start(0))?;
+                self.restore(state);
+                match read_frame(self.reader.as_mut().expect("Playback reader missing"))? {
+                    Some(frame) => frame,
+                    // nothing was recorded, keep the live input
+                    None => return Ok(()),
+                }
+            }
+        };
+        *input = decode_frame(&mut frame.as_slice())?;
+        Ok(())
+    }
+
+    // the audio thread's voices can't be rolled back with the rest, so the live end of it is kept and everything
+    // it's playing stopped, leaving no handle from before the snapshot or after it naming a sound that's still on
+    fn restore(&self, state: &mut GameState) {
+        if let Some(snapshot) = &self.snapshot {
+            let audio = std::mem::take(&mut state.audio);
+            *state = snapshot.clone();
+            state.audio = audio;
+            state.audio.stop_all();
+        }
+    }
+}
+
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let mut frame = vec![0u8; u32::from_le_bytes(length) as usize];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+// frames are written field by field rather than as raw struct bytes so padding never ends up in the file
+
+fn write_button(out: &mut Vec<u8>, button: &ButtonState) {
+    out.push(button.ended_down as u8);
+    out.extend_from_slice(&button.half_transition_count.to_le_bytes());
+}
+
+fn write_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_f32(out: &mut Vec<u8>, value: f32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn encode_frame(input: &GameInput) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    write_u64(&mut out, input.time.as_nanos() as u64);
+
+    for key in input.keyboard.keys.iter().chain(input.keyboard.scancodes.iter()) {
+        write_button(&mut out, key);
+    }
+
+    let mouse = &input.mouse;
+    write_i32(&mut out, mouse.x);
+    write_i32(&mut out, mouse.y);
+    for button in mouse.buttons.iter() {
+        write_button(&mut out, button);
+    }
+    write_i32(&mut out, mouse.wheel_delta);
+    write_i32(&mut out, mouse.raw_dx);
+    write_i32(&mut out, mouse.raw_dy);
+    out.push(mouse.captured as u8);
+    for ((double_click, origin), dragging) in mouse.double_clicks.iter().zip(mouse.drag_origins.iter()).zip(mouse.dragging.iter()) {
+        out.push(*double_click as u8);
+        out.push(origin.is_some() as u8);
+        let (origin_x, origin_y) = origin.unwrap_or_default();
+        write_i32(&mut out, origin_x);
+        write_i32(&mut out, origin_y);
+        out.push(*dragging as u8);
+    }
+
+    for point in input.touch.points.iter() {
+        out.push(point.is_some() as u8);
+        let point = point.unwrap_or_default();
+        out.extend_from_slice(&point.id.to_le_bytes());
+        write_i32(&mut out, point.x);
+        write_i32(&mut out, point.y);
+        write_f32(&mut out, point.pressure);
+        out.push(point.pen as u8);
+        out.push(point.released as u8);
+    }
+
+    for gamepad in input.gamepads.iter() {
+        out.push(gamepad.connected as u8);
+        for button in gamepad.buttons.all() {
+            write_button(&mut out, &button);
+        }
+        write_f32(&mut out, gamepad.triggers.left);
+        write_f32(&mut out, gamepad.triggers.right);
+        write_f32(&mut out, gamepad.sticks.left_x);
+        write_f32(&mut out, gamepad.sticks.left_y);
+        write_f32(&mut out, gamepad.sticks.right_x);
+        write_f32(&mut out, gamepad.sticks.right_y);
+    }
+
+    let text = input.text_input.text.as_bytes();
+    out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    out.extend_from_slice(text);
+
+    out
+}
+
+fn read_bytes<const N: usize>(data: &mut &[u8]) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    data.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_button(data: &mut &[u8]) -> io::Result<ButtonState> {
+    let [ended_down] = read_bytes::<1>(data)?;
+    Ok(ButtonState {
+        ended_down: ended_down != 0,
+        half_transition_count: u32::from_le_bytes(read_bytes(data)?),
+    })
+}
+
+fn read_i32(data: &mut &[u8]) -> io::Result<i32> {
+    Ok(i32::from_le_bytes(read_bytes(data)?))
+}
+
+fn read_u64(data: &mut &[u8]) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(data)?))
+}
+
+fn read_f32(data: &mut &[u8]) -> io::Result<f32> {
+    Ok(f32::from_le_bytes(read_bytes(data)?))
+}
+
+fn decode_frame(data: &mut &[u8]) -> io::Result<GameInput> {
+    let time = Duration::from_nanos(read_u64(data)?);
+
+    let mut keyboard = KeyboardState::default();
+    for key in keyboard.keys.iter_mut().chain(keyboard.scancodes.iter_mut()) {
+        *key = read_button(data)?;
+    }
+
+    let mut mouse = MouseState {
+        x: read_i32(data)?,
+        y: read_i32(data)?,
+        ..Default::default()
+    };
+    for button in mouse.buttons.iter_mut() {
+        *button = read_button(data)?;
+    }
+    mouse.wheel_delta = read_i32(data)?;
+    mouse.raw_dx = read_i32(data)?;
+    mouse.raw_dy = read_i32(data)?;
+    let [captured] = read_bytes::<1>(data)?;
+    mouse.captured = captured != 0;
+    for index in 0..MOUSE_BUTTON_COUNT {
+        let [double_click, has_origin] = read_bytes::<2>(data)?;
+        let origin = (read_i32(data)?, read_i32(data)?);
+        let [dragging] = read_bytes::<1>(data)?;
+        mouse.double_clicks[index] = double_click != 0;
+        mouse.drag_origins[index] = (has_origin != 0).then_some(origin);
+        mouse.dragging[index] = dragging != 0;
+    }
+
+    let mut touch = TouchState::default();
+    for point in touch.points.iter_mut() {
+        let [present] = read_bytes::<1>(data)?;
+        let id = u32::from_le_bytes(read_bytes(data)?);
+        let (x, y, pressure) = (read_i32(data)?, read_i32(data)?, read_f32(data)?);
+        let [pen, released] = read_bytes::<2>(data)?;
+        *point = (present != 0).then_some(TouchPoint {
+            id,
+            x,
+            y,
+            pressure,
+            pen: pen != 0,
+            released: released != 0,
+        });
+    }
+
+    let mut gamepads = [GamepadState::default(); MAX_GAMEPADS];
+    for gamepad in gamepads.iter_mut() {
+        let [connected] = read_bytes::<1>(data)?;
+        gamepad.connected = connected != 0;
+        for button in gamepad.buttons.all_mut() {
+            *button = read_button(data)?;
+        }
+        gamepad.triggers.left = read_f32(data)?;
+        gamepad.triggers.right = read_f32(data)?;
+        gamepad.sticks.left_x = read_f32(data)?;
+        gamepad.sticks.left_y = read_f32(data)?;
+        gamepad.sticks.right_x = read_f32(data)?;
+        gamepad.sticks.right_y = read_f32(data)?;
+    }
+
+    let text_length = u32::from_le_bytes(read_bytes(data)?) as usize;
+    let mut text = vec![0u8; text_length];
+    data.read_exact(&mut text)?;
+    let text = String::from_utf8(text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    Ok(GameInput {
+        time,
+        keyboard,
+        mouse,
+        touch,
+        gamepads,
+        text_input: TextInputState {
+            text,
+            pending_high_surrogate: None,
+        },
+    })
+}
@@ -0,0 +1,289 @@
+use std::{fs, io, path::Path};
+
+use windows::Win32::UI::Input::KeyboardAndMouse::*;
+
+use crate::game_input::{ButtonState, GameInput, GamepadButtons, GamepadState, KeyboardState, Scancode, MAX_GAMEPADS, SCANCODE_COUNT};
+
+/// One scripted change, applied at the start of its frame
+#[derive(Clone, Copy, Debug)]
+pub enum ScriptEvent {
+    Key { vk_code: u16, down: bool },
+    PadConnected { pad: usize, connected: bool },
+    PadButton { pad: usize, button: &'static str, down: bool },
+    PadAxis { pad: usize, axis: &'static str, value: f32 },
+    Quit,
+}
+
+/// Fake keyboard and gamepads driven by a list of timed events, in place of the real devices in the frame input.
+/// Lets the loop run without hardware (or a person) attached, e.g. in CI with `--input-script`. Keys the script never
+/// mentions stay live, so Escape and the hotkeys still work while it runs.
+///
+/// Script files have one event per line, `frame device input value`, `#` starts a comment:
+/// ```text
+/// 0   key  right    down
+/// 30  key  right    up
+/// 30  pad0 a        down
+/// 30  pad0 left_x   -0.5
+/// 90  pad0 unplug
+/// 120 quit
+/// ```
+#[derive(Default)]
+pub struct InputScript {
+    events: Vec<(u64, ScriptEvent)>,
+    frame: u64,
+    keyboard: KeyboardState,
+    // the keys and scancodes the script has driven so far, the rest of the keyboard is left live
+    scripted_keys: Vec<usize>,
+    scripted_scancodes: Vec<usize>,
+    gamepads: [GamepadState; MAX_GAMEPADS],
+}
+
+impl InputScript {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(source: &str) -> io::Result<Self> {
+        let mut script = InputScript::default();
+        for (line_index, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (frame, event) = parse_line(line)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Bad input script line {}: {line}", line_index + 1)))?;
+            script.push(frame, event);
+        }
+        Ok(script)
+    }
+
+    /// For harnesses that build the sequence in code instead of a file
+    pub fn push(&mut self, frame: u64, event: ScriptEvent) {
+        let index = self.events.partition_point(|(event_frame, _)| *event_frame <= frame);
+        self.events.insert(index, (frame, event));
+    }
+
+    /// Advances one frame, overwriting the gamepads and the scripted keys in the input. Returns false once a quit event
+    /// is hit.
+    pub fn apply(&mut self, input: &mut GameInput) -> bool {
+        self.keyboard.begin_frame();
+        for gamepad in self.gamepads.iter_mut() {
+            for button in gamepad.buttons.all_mut() {
+                button.half_transition_count = 0;
+            }
+        }
+
+        let mut running = true;
+        let due = self.events.partition_point(|(frame, _)| *frame <= self.frame);
+        for (_, event) in self.events.drain(..due) {
+            match event {
+                ScriptEvent::Key { vk_code, down } => {
+                    let key = vk_code as usize & 0xFF;
+                    let scancode = Scancode::from_virtual_key(VIRTUAL_KEY(vk_code)).0 as usize % SCANCODE_COUNT;
+                    self.keyboard.keys[key].update(down);
+                    self.keyboard.scancodes[scancode].update(down);
+                    if !self.scripted_keys.contains(&key) {
+                        self.scripted_keys.push(key);
+                    }
+                    if !self.scripted_scancodes.contains(&scancode) {
+                        self.scripted_scancodes.push(scancode);
+                    }
+                }
+                ScriptEvent::PadConnected { pad, connected } => {
+                    self.gamepads[pad] = GamepadState { connected, ..Default::default() };
+                }
+                ScriptEvent::PadButton { pad, button, down } => {
+                    self.gamepads[pad].connected = true;
+                    if let Some(button) = pad_button_mut(&mut self.gamepads[pad].buttons, button) {
+                        button.update(down);
+                    }
+                }
+                ScriptEvent::PadAxis { pad, axis, value } => {
+                    let gamepad = &mut self.gamepads[pad];
+                    gamepad.connected = true;
+                    match axis {
+                        "left_x" => gamepad.sticks.left_x = value,
+                        "left_y" => gamepad.sticks.left_y = value,
+                        "right_x" => gamepad.sticks.right_x = value,
+                        "right_y" => gamepad.sticks.right_y = value,
+                        "left_trigger" => gamepad.triggers.left = value,
+                        _ => gamepad.triggers.right = value,
+                    }
+                }
+                ScriptEvent::Quit => running = false,
+            }
+        }
+        self.frame += 1;
+
+        for &key in &self.scripted_keys {
+            input.keyboard.keys[key] = self.keyboard.keys[key];
+        }
+        for &scancode in &self.scripted_scancodes {
+            input.keyboard.scancodes[scancode] = self.keyboard.scancodes[scancode];
+        }
+        input.gamepads = self.gamepads;
+        running
+    }
+}
+
+const PAD_BUTTON_NAMES: [&str; 16] = [
+    "dpad_up", "dpad_down", "dpad_left", "dpad_right", "start", "back", "left_thumb", "right_thumb",
+    "left_shoulder", "right_shoulder", "a", "b", "x", "y", "left_trigger", "right_trigger",
+];
+const PAD_AXIS_NAMES: [&str; 6] = ["left_x", "left_y", "right_x", "right_y", "left_trigger", "right_trigger"];
+
+// same order as GamepadButtons::all_mut
+fn pad_button_mut<'a>(buttons: &'a mut GamepadButtons, name: &str) -> Option<&'a mut ButtonState> {
+    let index = PAD_BUTTON_NAMES.iter().position(|button_name| *button_name == name)?;
+    buttons.all_mut().into_iter().nth(index)
+}
+
+fn parse_line(line: &str) -> Option<(u64, ScriptEvent)> {
+    let mut words = line.split_whitespace();
+    let frame = words.next()?.parse().ok()?;
+    let device = words.next()?;
+    let input = words.next();
+    let value = words.next();
+
+    let event = match (device, input, value) {
+        ("quit", None, None) => ScriptEvent::Quit,
+        ("key", Some(key), Some(state)) => ScriptEvent::Key {
+            vk_code: parse_key(key)?,
+            down: parse_down(state)?,
+        },
+        (pad, Some(input), value) if pad.starts_with("pad") => {
+            let pad = pad.strip_prefix("pad")?.parse().ok().filter(|pad| *pad < MAX_GAMEPADS)?;
+            match (input, value) {
+                ("plug", None) => ScriptEvent::PadConnected { pad, connected: true },
+                ("unplug", None) => ScriptEvent::PadConnected { pad, connected: false },
+                (input, Some(value)) => {
+                    // trigger names are both a button and an axis, a number means the axis
+                    if let (Some(axis), Ok(value)) = (PAD_AXIS_NAMES.iter().copied().find(|axis| *axis == input), value.parse::<f32>()) {
+                        ScriptEvent::PadAxis { pad, axis, value }
+                    } else {
+                        let button = PAD_BUTTON_NAMES.iter().copied().find(|button| *button == input)?;
+                        ScriptEvent::PadButton { pad, button, down: parse_down(value)? }
+                    }
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some((frame, event))
+}
+
+fn parse_down(state: &str) -> Option<bool> {
+    match state {
+        "down" => Some(true),
+        "up" => Some(false),
+        _ => None,
+    }
+}
+
+/// Letters and digits by themselves, a few named keys, or any vk code as hex (`0x41`)
+fn parse_key(key: &str) -> Option<u16> {
+    if let Some(hex) = key.strip_prefix("0x") {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    if let [character] = key.as_bytes()
+        && character.is_ascii_alphanumeric()
+    {
+        return Some(character.to_ascii_uppercase() as u16);
+    }
+    if let Some(number) = key.strip_prefix('f').and_then(|number| number.parse::<u16>().ok()) {
+        return (1..=24).contains(&number).then(|| VK_F1.0 + number - 1);
+    }
+    let vk_code = match key {
+        "left" => VK_LEFT,
+        "right" => VK_RIGHT,
+        "up" => VK_UP,
+        "down" => VK_DOWN,
+        "space" => VK_SPACE,
+        "enter" => VK_RETURN,
+        "escape" => VK_ESCAPE,
+        "shift" => VK_SHIFT,
+        "control" => VK_CONTROL,
+        "alt" => VK_MENU,
+        "tab" => VK_TAB,
+        "backspace" => VK_BACK,
+        _ => return None,
+    };
+    Some(vk_code.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_input::{MouseState, TextInputState, TouchState};
+
+    fn empty_input() -> GameInput {
+        GameInput {
+            time: Default::default(),
+            keyboard: KeyboardState::default(),
+            mouse: MouseState::default(),
+            touch: TouchState::default(),
+            gamepads: Default::default(),
+            text_input: TextInputState::default(),
+        }
+    }
+
+    #[test]
+    fn plays_a_script_frame_by_frame() {
+        let mut script = InputScript::parse(
+            "# a comment line
+             0 key right down
+             1 key right up   # and one after an event
+             1 pad1 a down
+             1 pad1 left_x -0.5
+             2 pad1 left_trigger 0.25
+             2 pad1 unplug
+             3 quit",
+        )
+        .unwrap();
+
+        // the live keyboard, escape held the whole time
+        let mut live = KeyboardState::default();
+        live.keys[VK_ESCAPE.0 as usize].update(true);
+        let mut frame = |script: &mut InputScript| {
+            let mut input = empty_input();
+            input.keyboard = live;
+            live.begin_frame();
+            (script.apply(&mut input), input)
+        };
+
+        let (running, input) = frame(&mut script);
+        assert!(running);
+        assert!(input.keyboard.was_pressed(VK_RIGHT));
+        assert!(input.keyboard.was_pressed(VK_ESCAPE));
+        assert!(!input.gamepads[1].connected);
+
+        let (running, input) = frame(&mut script);
+        assert!(running);
+        assert!(!input.keyboard.is_down(VK_RIGHT));
+        assert_eq!(input.keyboard.keys[VK_RIGHT.0 as usize].half_transition_count, 1);
+        assert!(input.keyboard.is_down(VK_ESCAPE) && !input.keyboard.was_pressed(VK_ESCAPE));
+        let pad = input.gamepads[1];
+        assert!(pad.connected && pad.buttons.a.was_pressed());
+        assert_eq!(pad.sticks.left_x, -0.5);
+        assert!(!input.gamepads[0].connected);
+
+        let (running, input) = frame(&mut script);
+        assert!(running);
+        assert_eq!(input.keyboard.keys[VK_RIGHT.0 as usize].half_transition_count, 0);
+        // the trigger's value came in the same frame as the unplug, which clears it
+        assert!(!input.gamepads[1].connected);
+        assert_eq!(input.gamepads[1].triggers.left, 0.0);
+
+        let (running, input) = frame(&mut script);
+        assert!(!running);
+        assert!(input.keyboard.is_down(VK_ESCAPE));
+    }
+
+    #[test]
+    fn bad_lines_are_an_error() {
+        for source in ["x key a down", "0 key a sideways", "0 pad8 a down", "0 pad0 wheel down", "0 quit now"] {
+            assert!(InputScript::parse(source).is_err(), "{source}");
+        }
+    }
+}
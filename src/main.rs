@@ -1,8 +1,88 @@
-use std::{os::raw::c_void, ptr::null_mut};
+mod animation;
+mod audio_output;
+mod audio_thread;
+mod font;
+mod frame_buffer;
+mod game_input;
+mod gif;
+mod indexed;
+mod inflate;
+mod bitmap;
+mod color;
+mod debug_draw;
+mod demo_scenes;
+mod input_bindings;
+mod input_history;
+mod input_recording;
+mod input_script;
+mod math;
+mod mixer;
+mod music;
+mod player_slots;
+mod ogg;
+mod png;
+mod post_process;
+mod render;
+mod render_group;
+mod render_simd;
+mod renderer;
+mod ring_queue;
+mod rumble;
+mod shader_files;
+mod sine_wave;
+mod sprite_atlas;
+mod test_pattern;
+mod text_layout;
+mod vorbis;
+mod wav;
+mod win32_d3d11;
+mod win32_dsound;
+mod win32_gaming_input;
+mod win32_opengl;
+mod win32_wasapi;
+mod win32_xinput;
+mod work_queue;
 
+use std::{f32::consts::TAU, os::raw::c_void, path::PathBuf, ptr::null_mut, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, OnceLock}, time::{Duration, Instant}};
+
+use animation::Animation;
+use audio_output::AudioOutput;
+use audio_thread::{Audio, GameSoundMemory, GameSoundOutputBuffer, SoundHandle};
+use bitmap::Bitmap;
+use color::{pack_rgb565, pack_rgba8, Color, Pixel, PixelFormat};
+use debug_draw::{debug_draw_circle, debug_draw_text, push_debug_shapes};
+use demo_scenes::SCENES;
+use font::Font;
+use frame_buffer::FrameBuffer;
+use game_input::{
+    process_polled_button, BatteryLevel, ButtonState, GameInput, GamepadState, GamepadVibration, KeyCode, KeyboardState, MouseButton, MouseState,
+    Scancode, StickCurve, TextInputState, TouchPoint, TouchState, MAX_GAMEPADS, SCANCODE_COUNT,
+};
+use indexed::{IndexedBuffer, Palette};
+use input_bindings::{Action, Binding, GamepadButton, InputBindings};
+use input_history::GamepadHistory;
+use input_recording::{InputRecorder, RecordingMode};
+use input_script::InputScript;
+use math::V2;
+use mixer::Sound;
+use music::MusicStream;
+use player_slots::PlayerSlots;
+use post_process::{ColorGrade, CrtEffect, Lut3d, PostPass};
+use render::{BitmapFilter, EnvironmentMaps, Gradient};
+use render_group::{Camera, DirtyRect, RenderGroup, TileHashes};
+use renderer::{OutputMode, Renderer, VSync};
+use rumble::{RumbleEffect, RumbleMixer};
+use text_layout::{Align, TextLayout};
+use win32_d3d11::Win32D3D11;
+use win32_dsound::win32_init_dsound;
+use win32_gaming_input::GamingInputBackend;
+use win32_opengl::Win32OpenGl;
+use win32_wasapi::Win32Wasapi;
+use win32_xinput::XInputBackend;
+use work_queue::WorkQueue;
 use windows::{
     core::*,
-    Win32::{Foundation::*, Graphics::Gdi::*, System::{LibraryLoader::*, Memory::{VirtualAlloc, VirtualFree, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE}}, UI::WindowsAndMessaging::*},
+    Win32::{Foundation::*, Graphics::Gdi::*, Media::timeBeginPeriod, System::{DataExchange::*, LibraryLoader::*, Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, VirtualAlloc, VirtualFree, GMEM_MOVEABLE, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE}, Ole::CF_UNICODETEXT, Threading::{GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_TIME_CRITICAL}}, UI::{Input::{KeyboardAndMouse::*, Pointer::*, XboxController::*, *}, WindowsAndMessaging::*}},
 };
 
 struct Win32WindowDimension {
@@ -10,17 +90,281 @@ struct Win32WindowDimension {
     height: i32,
 }
 
+/// A BITMAPINFO with room for the three channel masks BI_BITFIELDS reads after the header,
+/// the windows-rs one only has space for one color
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Win32BitmapInfo {
+    header: BITMAPINFOHEADER,
+    masks: [u32; 3],
+}
+
 #[derive(Default)]
 struct Win32OffscreenBuffer {
-    bitmap_info: BITMAPINFO,
+    bitmap_info: Win32BitmapInfo,
+    format: PixelFormat,
     width: i32,
     height: i32,
+    // bytes per row of the dib at memory
     pitch: i32,
-    memory: *mut c_void
+    memory: *mut c_void,
+    // what gets drawn into when the dib isn't 32 bit, converted into memory when the frame is done
+    staging: Vec<Pixel>,
+}
+
+impl Win32OffscreenBuffer {
+    fn pixels(&self) -> &[Pixel] {
+        if self.memory.is_null() {
+            return &[];
+        }
+        match self.format {
+            PixelFormat::Bgra32 => unsafe { std::slice::from_raw_parts(self.memory as *const Pixel, (self.pitch / 4 * self.height) as usize) },
+            PixelFormat::Rgb565 => &self.staging,
+        }
+    }
+
+    /// The pixels for the renderer, made once a frame. Empty before the first resize allocates anything
+    fn frame(&mut self) -> FrameBuffer<'_> {
+        if self.memory.is_null() {
+            return FrameBuffer::new(&mut [], 0, 0);
+        }
+        let pixels = match self.format {
+            PixelFormat::Bgra32 => unsafe { std::slice::from_raw_parts_mut(self.memory as *mut Pixel, (self.pitch / 4 * self.height) as usize) },
+            PixelFormat::Rgb565 => &mut self.staging,
+        };
+        FrameBuffer::new(pixels, self.width, self.height)
+    }
+
+    /// Converts the finished frame into the dib's format, nothing to do when it was drawn in place
+    fn encode(&mut self) {
+        if self.format != PixelFormat::Rgb565 || self.memory.is_null() {
+            return;
+        }
+        let dib = unsafe { std::slice::from_raw_parts_mut(self.memory as *mut u16, (self.pitch / 2 * self.height) as usize) };
+        for (y, (dest, row)) in dib.chunks_exact_mut((self.pitch / 2) as usize).zip(self.staging.chunks_exact(self.width as usize)).enumerate() {
+            for (x, (dest, pixel)) in dest.iter_mut().zip(row).enumerate() {
+                *dest = pack_rgb565(*pixel, x, y);
+            }
+        }
+    }
+
+    /// Start of row y of the dib, for presenting part of it
+    fn dib_row(&self, y: i32) -> *const c_void {
+        unsafe { (self.memory as *const u8).add((y * self.pitch) as usize) as *const c_void }
+    }
+}
+
+// two is enough for one frame being drawn while the other is shown
+const BACKBUFFER_COUNT: usize = 2;
+
+/// The backbuffers frames are rendered into and presented from. The game draws into the back one while the front one
+/// holds the last finished frame, which is also what WM_PAINT repaints from. Never drawing into the buffer on screen is
+/// what lets a render thread work on frame N+1 while frame N is being presented.
+#[derive(Default)]
+struct Win32SwapChain {
+    buffers: [Win32OffscreenBuffer; BACKBUFFER_COUNT],
+    front: usize,
+}
+
+impl Win32SwapChain {
+    fn front(&self) -> &Win32OffscreenBuffer {
+        &self.buffers[self.front]
+    }
+
+    /// The back buffer's frame is finished and goes on screen
+    fn swap(&mut self) {
+        self.buffers[(self.front + 1) % BACKBUFFER_COUNT].encode();
+        self.front = (self.front + 1) % BACKBUFFER_COUNT;
+    }
+
+    /// The back buffer to draw this frame into, and the pixels of the one on screen for frames that only draw over
+    /// part of the last one
+    fn frames(&mut self) -> (FrameBuffer<'_>, &[Pixel]) {
+        let back = (self.front + 1) % BACKBUFFER_COUNT;
+        let [front, back] = self.buffers.get_disjoint_mut([self.front, back]).expect("front and back are different buffers");
+        (back.frame(), front.pixels())
+    }
+}
+
+struct GamepadSettings {
+    left_stick_deadzone: i16,
+    right_stick_deadzone: i16,
+    left_stick_curve: StickCurve,
+    right_stick_curve: StickCurve,
+    trigger_threshold: u8,
+    /// Left stick also presses the dpad buttons, so menus only have to look at one set of directions
+    stick_to_dpad: bool,
+    // stick has to go past press to push a direction, and back under release to let go of it,
+    // the gap keeps a stick resting near the edge from flickering the button
+    stick_dpad_press: f32,
+    stick_dpad_release: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        GamepadSettings {
+            left_stick_deadzone: XINPUT_GAMEPAD_LEFT_THUMB_DEADZONE.0 as i16,
+            right_stick_deadzone: XINPUT_GAMEPAD_RIGHT_THUMB_DEADZONE.0 as i16,
+            left_stick_curve: StickCurve::Linear,
+            right_stick_curve: StickCurve::Linear,
+            trigger_threshold: XINPUT_GAMEPAD_TRIGGER_THRESHOLD.0 as u8,
+            stick_to_dpad: false,
+            stick_dpad_press: 0.5,
+            stick_dpad_release: 0.3,
+        }
+    }
+}
+
+impl From<VIRTUAL_KEY> for KeyCode {
+    fn from(key: VIRTUAL_KEY) -> Self {
+        KeyCode(key.0)
+    }
+}
+
+impl Scancode {
+    /// Bits 16..24 of a WM_KEYDOWN/WM_KEYUP lparam are the scan code, bit 24 the extended flag
+    fn from_key_lparam(lparam: LPARAM) -> Self {
+        let scancode = ((lparam.0 >> 16) & 0xFF) as u16;
+        let extended = ((lparam.0 >> 24) & 1) as u16;
+        Scancode(scancode | extended << 8)
+    }
+
+    /// Where the virtual key sits with the current layout
+    fn from_virtual_key(key: VIRTUAL_KEY) -> Self {
+        let scancode = unsafe { MapVirtualKeyW(key.0 as u32, MAPVK_VK_TO_VSC_EX) };
+        // the _EX mapping puts the 0xE0/0xE1 prefix in the high byte
+        let extended = matches!(scancode >> 8, 0xE0 | 0xE1) as u16;
+        Scancode((scancode & 0xFF) as u16 | extended << 8)
+    }
+}
+
+/// Name of the key at a physical position in the current layout, for showing bindings in the UI
+fn platform_key_label(scancode: Scancode) -> String {
+    // GetKeyNameText wants the key message lparam layout
+    let lparam = ((scancode.0 as i32 & 0xFF) << 16) | ((scancode.0 as i32 >> 8) << 24);
+    let mut name = [0u16; 64];
+    let length = unsafe { GetKeyNameTextW(lparam, &mut name) };
+    if length <= 0 {
+        return format!("Scancode {:#x}", scancode.0);
+    }
+    String::from_utf16_lossy(&name[..length as usize])
+}
+
+#[derive(Clone, Copy, Debug)]
+enum GamepadEvent {
+    Connected(usize),
+    Disconnected(usize),
+    BatteryLow(usize, BatteryLevel),
+    // the pad that was driving the game went away, raised on top of Disconnected
+    ActiveDisconnected(usize),
+}
+
+/// Whatever the player last touched, so losing the pad they're actually using can pause the game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ActiveDevice {
+    KeyboardMouse,
+    Gamepad(usize),
+}
+
+impl ActiveDevice {
+    /// Keyboard/mouse wins when both are used in the same frame, it can't disconnect on its own
+    fn from_input(input: &GameInput) -> Option<Self> {
+        let key_pressed = input.keyboard.keys.iter().any(ButtonState::was_pressed);
+        let mouse_pressed = input.mouse.buttons.iter().any(ButtonState::was_pressed);
+        if key_pressed || mouse_pressed {
+            return Some(ActiveDevice::KeyboardMouse);
+        }
+        input
+            .gamepads
+            .iter()
+            .position(|gamepad| gamepad.connected && gamepad.buttons.all().iter().any(ButtonState::was_pressed))
+            .map(ActiveDevice::Gamepad)
+    }
+}
+
+// stop updating the game while the active pad is gone, instead of letting it run with nobody at the controls
+const PAUSE_ON_ACTIVE_GAMEPAD_LOSS: bool = true;
+
+// xinput slots first, then whatever Windows.Gaming.Input finds that xinput can't see
+const GAMING_INPUT_SLOTS: usize = MAX_GAMEPADS - XUSER_MAX_COUNT as usize;
+
+/// State the game loop mutates, snapshotted and restored by the input recorder
+#[derive(Clone)]
+struct GameState {
+    x_anim: i32,
+    y_anim: i32,
+    bindings: InputBindings,
+    players: PlayerSlots,
+    player_history: GamepadHistory,
+    // shared so the recorder's snapshots don't copy the pixels
+    sprite: Option<Arc<Bitmap>>,
+    // where sprite comes from, it's switched to whichever frame is up at the start of every frame
+    sprite_animation: Option<Animation>,
+    sprite_filter: BitmapFilter,
+    // a ball and its normals for the lighting demo, made once at startup
+    sphere: Arc<Bitmap>,
+    sphere_normals: Arc<Bitmap>,
+    environment: EnvironmentMaps,
+    // nine sliced for the UI boxes
+    panel: Arc<Bitmap>,
+    // when X was last pressed, the frame flashes red for a moment after
+    flashed_at: Option<Duration>,
+    // world origin starts in the middle of the screen
+    camera: Camera,
+    // index into SCENES
+    scene: usize,
+    // index into TestPattern::ALL, for the test pattern scene
+    test_pattern: usize,
+    // the game's end of the audio thread
+    audio: Audio,
+    // the input tester blips on every key and hums while space is held
+    blip: Arc<Sound>,
+    hum: Arc<Sound>,
+    hum_voice: Option<SoundHandle>,
+}
+// XInputGetState on an empty slot is slow, so empty slots are only polled this often
+const GAMEPAD_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+// loudness of the test tone out of i16::MAX, quiet enough to leave running
+const TONE_VOLUME: f32 = 3000.0;
+// streamed music is already mastered, half leaves room for everything else
+const MUSIC_VOLUME: f32 = 0.5;
+
+/// Standard system cursors the game can pick from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SystemCursor {
+    Arrow,
+    Crosshair,
+    Hand,
+    TextBeam,
+}
+
+/// Cursor shown over the client area, applied on WM_SETCURSOR
+struct Win32Cursor {
+    handle: HCURSOR,
+    // created from pixels, has to be destroyed when replaced
+    custom: bool,
+    visible: bool,
 }
 
 static mut GLOBAL_RUNNING: bool = false;
-static mut GLOBAL_BUFFER: *mut Win32OffscreenBuffer = null_mut();
+static mut GLOBAL_CURSOR: Win32Cursor = Win32Cursor {
+    handle: HCURSOR(null_mut()),
+    custom: false,
+    visible: true,
+};
+// windowed placement saved when going fullscreen, restored when coming back
+static GLOBAL_WINDOW_PLACEMENT: Mutex<Option<WINDOWPLACEMENT>> = Mutex::new(None);
+static mut GLOBAL_SWAP_CHAIN: *mut Win32SwapChain = null_mut();
+// with --letterbox the backbuffer keeps its shape in the window and the leftover space is black bars
+static GLOBAL_LETTERBOX: AtomicBool = AtomicBool::new(false);
+// F7 switches the blit to the window from pixel replication to gdi's smoothing filter
+static GLOBAL_SMOOTH_PRESENT: AtomicBool = AtomicBool::new(false);
+// set when windows reports a device change so empty slots get polled right away
+static GLOBAL_GAMEPAD_RECHECK: AtomicBool = AtomicBool::new(true);
+static GLOBAL_KEYBOARD: OnceLock<Mutex<KeyboardState>> = OnceLock::new();
+static GLOBAL_MOUSE: OnceLock<Mutex<MouseState>> = OnceLock::new();
+static GLOBAL_TEXT_INPUT: OnceLock<Mutex<TextInputState>> = OnceLock::new();
+static GLOBAL_TOUCH: OnceLock<Mutex<TouchState>> = OnceLock::new();
 
 fn win32_get_window_dimension(window: HWND) ->  Result<Win32WindowDimension> {
     unsafe {
@@ -36,10 +380,10 @@ fn win32_get_window_dimension(window: HWND) ->  Result<Win32WindowDimension> {
     }
 }
 
-fn win32_resize_dib_section(buffer: &mut Win32OffscreenBuffer, width: i32, height: i32) -> Win32OffscreenBuffer {
-    let bytes_per_pixel = 4;
-    let buffer_size = (width * height * bytes_per_pixel) as usize;
-    let pitch = width * bytes_per_pixel;
+/// Reallocates the buffer at the new size, in whatever format it's set to
+fn win32_resize_dib_section(buffer: &mut Win32OffscreenBuffer, width: i32, height: i32) {
+    let pitch = buffer.format.pitch(width);
+    let buffer_size = (pitch * height) as usize;
     
     let allocated_memory: *mut c_void;
     unsafe {
@@ -61,55 +405,554 @@ fn win32_resize_dib_section(buffer: &mut Win32OffscreenBuffer, width: i32, heigh
         }
     }
 
-    let bitmap_info = BITMAPINFO {
-        bmiHeader: BITMAPINFOHEADER {
+    let bitmap_info = Win32BitmapInfo {
+        header: BITMAPINFOHEADER {
             biSize: size_of::<BITMAPINFOHEADER>() as u32,
             biWidth: width,
             biHeight: -height, // negative height makes the bitmap buffer starr from top left when drawing on screen
             biPlanes: 1,
-            biBitCount: 32,
-            //biCompression: BI_RGB,
+            biBitCount: (buffer.format.bytes_per_pixel() * 8) as u16,
+            // 16 bit BI_RGB would be 5:5:5, the masks after the header say where the channels are instead
+            biCompression: match buffer.format {
+                PixelFormat::Bgra32 => BI_RGB.0,
+                PixelFormat::Rgb565 => BI_BITFIELDS.0,
+            },
             ..Default::default()
         },
-        ..Default::default()
+        masks: buffer.format.channel_masks(),
     };
 
-    let back_buffer = Win32OffscreenBuffer {
-        bitmap_info,
-        width,
-        height,
-        pitch,
-        memory: allocated_memory,
-    };
     buffer.bitmap_info = bitmap_info;
     buffer.pitch = pitch;
     buffer.memory = allocated_memory;
     buffer.width = width;
     buffer.height = height;
+    buffer.staging = match buffer.format {
+        PixelFormat::Bgra32 => Vec::new(),
+        PixelFormat::Rgb565 => vec![0; (width * height) as usize],
+    };
+}
 
-    return back_buffer
+fn win32_update_mouse_position(window: HWND, mouse: &mut MouseState, lparam: LPARAM) {
+    // the cursor is being recentered every frame while captured, the absolute position means nothing
+    if mouse.captured {
+        return;
+    }
+
+    // sign extend the low/high words, positions can go negative while the mouse is captured
+    let x = (lparam.0 & 0xFFFF) as i16 as i32;
+    let y = ((lparam.0 >> 16) & 0xFFFF) as i16 as i32;
+    (mouse.x, mouse.y) = win32_client_to_buffer(window, x, y);
+}
+
+/// Client area position to backbuffer pixel, undoing whatever scaling and bars the presentation added
+fn win32_client_to_buffer(window: HWND, x: i32, y: i32) -> (i32, i32) {
+    let Ok(dimension) = win32_get_window_dimension(window) else {
+        return (x, y);
+    };
+    let (buffer_width, buffer_height) = unsafe {
+        if GLOBAL_SWAP_CHAIN.is_null() {
+            return (x, y);
+        }
+        ((*GLOBAL_SWAP_CHAIN).front().width, (*GLOBAL_SWAP_CHAIN).front().height)
+    };
+    let rect = win32_presentation_rect(buffer_width, buffer_height, dimension.width, dimension.height);
+    let (rect_width, rect_height) = (rect.right - rect.left, rect.bottom - rect.top);
+    if rect_width <= 0 || rect_height <= 0 {
+        return (x, y);
+    }
+    (
+        ((x - rect.left) as i64 * buffer_width as i64 / rect_width as i64) as i32,
+        ((y - rect.top) as i64 * buffer_height as i64 / rect_height as i64) as i32,
+    )
+}
+
+/// Touch or pen contact for a WM_POINTER* message, None for mouse pointers
+fn win32_read_touch_point(window: HWND, pointer_id: u32, released: bool) -> Option<TouchPoint> {
+    let mut pointer_type = POINTER_INPUT_TYPE::default();
+    unsafe { GetPointerType(pointer_id, &mut pointer_type) }.ok()?;
+
+    // pressure is 0..1024 for both, touch only has it when the digitizer can measure it
+    let (info, pressure) = match pointer_type {
+        PT_TOUCH => {
+            let mut touch_info = POINTER_TOUCH_INFO::default();
+            unsafe { GetPointerTouchInfo(pointer_id, &mut touch_info) }.ok()?;
+            let pressure = if touch_info.touchMask & TOUCH_MASK_PRESSURE != 0 { touch_info.pressure as f32 / 1024.0 } else { 1.0 };
+            (touch_info.pointerInfo, pressure)
+        }
+        PT_PEN => {
+            let mut pen_info = POINTER_PEN_INFO::default();
+            unsafe { GetPointerPenInfo(pointer_id, &mut pen_info) }.ok()?;
+            let pressure = if pen_info.penMask & PEN_MASK_PRESSURE != 0 { pen_info.pressure as f32 / 1024.0 } else { 1.0 };
+            (pen_info.pointerInfo, pressure)
+        }
+        _ => return None,
+    };
+
+    // pointer positions are in screen coordinates
+    let mut position = info.ptPixelLocation;
+    let _ = unsafe { ScreenToClient(window, &mut position) };
+    let (x, y) = win32_client_to_buffer(window, position.x, position.y);
+    Some(TouchPoint {
+        id: pointer_id,
+        x,
+        y,
+        pressure,
+        pen: pointer_type == PT_PEN,
+        released,
+    })
+}
+
+fn win32_register_raw_mouse(window: HWND) -> Result<()> {
+    // generic desktop page (0x01), mouse usage (0x02)
+    let devices = [RAWINPUTDEVICE {
+        usUsagePage: 0x01,
+        usUsage: 0x02,
+        dwFlags: RAWINPUTDEVICE_FLAGS(0),
+        hwndTarget: window,
+    }];
+    unsafe { RegisterRawInputDevices(&devices, size_of::<RAWINPUTDEVICE>() as u32) }
+}
+
+fn win32_client_rect_on_screen(window: HWND) -> Result<RECT> {
+    let mut rect = RECT::default();
+    unsafe {
+        GetClientRect(window, &mut rect)?;
+        let mut top_left = POINT { x: rect.left, y: rect.top };
+        let mut bottom_right = POINT { x: rect.right, y: rect.bottom };
+        let _ = ClientToScreen(window, &mut top_left);
+        let _ = ClientToScreen(window, &mut bottom_right);
+        Ok(RECT {
+            left: top_left.x,
+            top: top_left.y,
+            right: bottom_right.x,
+            bottom: bottom_right.y,
+        })
+    }
+}
+
+/// Enters or leaves captured mouse mode (hidden cursor clipped to the client area, relative motion only)
+fn win32_set_mouse_captured(window: HWND, captured: bool) {
+    let was_captured = {
+        let mut mouse = GLOBAL_MOUSE
+            .get_or_init(|| Mutex::new(MouseState::default()))
+            .lock()
+            .expect("Mouse state lock poisoned");
+        std::mem::replace(&mut mouse.captured, captured)
+    };
+    if was_captured == captured {
+        return;
+    }
+
+    unsafe {
+        // ShowCursor keeps a display counter, so it is only called on actual transitions
+        ShowCursor(!captured);
+        if captured {
+            win32_update_mouse_capture(window);
+        } else {
+            let _ = ClipCursor(None);
+        }
+    }
+}
+
+/// Re-clips and recenters the cursor, called every frame while captured since windows drops the clip on focus and size changes
+fn win32_update_mouse_capture(window: HWND) {
+    let Ok(client_rect) = win32_client_rect_on_screen(window) else {
+        return;
+    };
+    unsafe {
+        let _ = ClipCursor(Some(&client_rect));
+        let _ = SetCursorPos((client_rect.left + client_rect.right) / 2, (client_rect.top + client_rect.bottom) / 2);
+    }
+}
+
+fn win32_apply_cursor(window: HWND) {
+    // only touch the cursor right away when it is over our client area, otherwise WM_SETCURSOR will pick it up
+    let mut cursor_position = POINT::default();
+    let Ok(client_rect) = win32_client_rect_on_screen(window) else {
+        return;
+    };
+    unsafe {
+        if GetCursorPos(&mut cursor_position).is_err() {
+            return;
+        }
+        let inside = cursor_position.x >= client_rect.left
+            && cursor_position.x < client_rect.right
+            && cursor_position.y >= client_rect.top
+            && cursor_position.y < client_rect.bottom;
+        if inside {
+            SetCursor(GLOBAL_CURSOR.visible.then_some(GLOBAL_CURSOR.handle));
+        }
+    }
+}
+
+fn win32_replace_cursor(window: HWND, handle: HCURSOR, custom: bool) {
+    unsafe {
+        if GLOBAL_CURSOR.custom {
+            let _ = DestroyCursor(GLOBAL_CURSOR.handle);
+        }
+        GLOBAL_CURSOR.handle = handle;
+        GLOBAL_CURSOR.custom = custom;
+    }
+    win32_apply_cursor(window);
+}
+
+fn platform_show_cursor(window: HWND, visible: bool) {
+    unsafe {
+        GLOBAL_CURSOR.visible = visible;
+    }
+    win32_apply_cursor(window);
+}
+
+fn platform_set_system_cursor(window: HWND, cursor: SystemCursor) -> Result<()> {
+    let cursor_name = match cursor {
+        SystemCursor::Arrow => IDC_ARROW,
+        SystemCursor::Crosshair => IDC_CROSS,
+        SystemCursor::Hand => IDC_HAND,
+        SystemCursor::TextBeam => IDC_IBEAM,
+    };
+    let handle = unsafe { LoadCursorW(None, cursor_name)? };
+    win32_replace_cursor(window, handle, false);
+    Ok(())
+}
+
+/// Builds a cursor from 0xAARRGGBB pixels, top row first, with the click point at hotspot
+fn platform_set_cursor_from_bitmap(window: HWND, pixels: &[u32], width: i32, height: i32, hotspot_x: u32, hotspot_y: u32) -> Result<()> {
+    assert_eq!(pixels.len(), (width * height) as usize, "Cursor pixel count doesn't match its size");
+    unsafe {
+        // with a 32 bit color bitmap the alpha channel does the masking, the mask just has to exist
+        let color = CreateBitmap(width, height, 1, 32, Some(pixels.as_ptr() as *const c_void));
+        let mask = CreateBitmap(width, height, 1, 1, None);
+        let icon_info = ICONINFO {
+            fIcon: false.into(),
+            xHotspot: hotspot_x,
+            yHotspot: hotspot_y,
+            hbmMask: mask,
+            hbmColor: color,
+        };
+        let icon = CreateIconIndirect(&icon_info);
+        // CreateIconIndirect copies the bitmaps
+        let _ = DeleteObject(color.into());
+        let _ = DeleteObject(mask.into());
+        win32_replace_cursor(window, HCURSOR(icon?.0), true);
+    }
+    Ok(())
+}
+
+/// Text on the clipboard with windows line endings turned into \n, None when there's no text or the clipboard is busy
+fn platform_get_clipboard_text(window: HWND) -> Option<String> {
+    unsafe {
+        IsClipboardFormatAvailable(CF_UNICODETEXT.0 as u32).ok()?;
+        OpenClipboard(Some(window)).ok()?;
+        let text = GetClipboardData(CF_UNICODETEXT.0 as u32).ok().and_then(|handle| {
+            let memory = HGLOBAL(handle.0);
+            let data = GlobalLock(memory) as *const u16;
+            if data.is_null() {
+                return None;
+            }
+            let length = (0..).take_while(|&index| *data.add(index) != 0).count();
+            let text = String::from_utf16_lossy(std::slice::from_raw_parts(data, length));
+            let _ = GlobalUnlock(memory);
+            Some(text.replace("\r\n", "\n"))
+        });
+        let _ = CloseClipboard();
+        text
+    }
+}
+
+fn platform_set_clipboard_text(window: HWND, text: &str) -> Result<()> {
+    let utf16: Vec<u16> = text.replace("\r\n", "\n").replace('\n', "\r\n").encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        OpenClipboard(Some(window))?;
+        let result = EmptyClipboard().and_then(|()| {
+            let memory = GlobalAlloc(GMEM_MOVEABLE, utf16.len() * size_of::<u16>())?;
+            let data = GlobalLock(memory) as *mut u16;
+            if data.is_null() {
+                let _ = GlobalFree(Some(memory));
+                return Err(Error::from_win32());
+            }
+            std::ptr::copy_nonoverlapping(utf16.as_ptr(), data, utf16.len());
+            let _ = GlobalUnlock(memory);
+            // the clipboard owns the memory once SetClipboardData succeeds, until then it's still ours to free
+            if let Err(error) = SetClipboardData(CF_UNICODETEXT.0 as u32, Some(HANDLE(memory.0))) {
+                let _ = GlobalFree(Some(memory));
+                return Err(error);
+            }
+            Ok(())
+        });
+        let _ = CloseClipboard();
+        result
+    }
+}
+
+fn win32_process_raw_input(lparam: LPARAM) {
+    let mut raw_input = RAWINPUT::default();
+    let mut size = size_of::<RAWINPUT>() as u32;
+    let bytes_read = unsafe {
+        GetRawInputData(
+            HRAWINPUT(lparam.0 as *mut c_void),
+            RID_INPUT,
+            Some(&mut raw_input as *mut RAWINPUT as *mut c_void),
+            &mut size,
+            size_of::<RAWINPUTHEADER>() as u32,
+        )
+    };
+    if bytes_read == u32::MAX || raw_input.header.dwType != RIM_TYPEMOUSE.0 {
+        return;
+    }
+
+    let raw_mouse = unsafe { raw_input.data.mouse };
+    // tablets and remote desktop report absolute positions, only relative motion is useful as a delta
+    if raw_mouse.usFlags.0 & MOUSE_MOVE_ABSOLUTE.0 != 0 {
+        return;
+    }
+
+    let mut mouse = GLOBAL_MOUSE
+        .get_or_init(|| Mutex::new(MouseState::default()))
+        .lock()
+        .expect("Mouse state lock poisoned");
+    mouse.raw_dx += raw_mouse.lLastX;
+    mouse.raw_dy += raw_mouse.lLastY;
+}
+
+/// Borderless fullscreen toggle, see Raymond Chen's "How do I switch a window between normal and fullscreen?"
+fn win32_toggle_fullscreen(window: HWND) {
+    let mut saved_placement = GLOBAL_WINDOW_PLACEMENT.lock().expect("Window placement lock poisoned");
+    unsafe {
+        let style = GetWindowLongW(window, GWL_STYLE) as u32;
+        if style & WS_OVERLAPPEDWINDOW.0 != 0 {
+            let mut placement = WINDOWPLACEMENT {
+                length: size_of::<WINDOWPLACEMENT>() as u32,
+                ..Default::default()
+            };
+            let mut monitor_info = MONITORINFO {
+                cbSize: size_of::<MONITORINFO>() as u32,
+                ..Default::default()
+            };
+            let monitor = MonitorFromWindow(window, MONITOR_DEFAULTTOPRIMARY);
+            if GetWindowPlacement(window, &mut placement).is_ok() && GetMonitorInfoW(monitor, &mut monitor_info).as_bool() {
+                *saved_placement = Some(placement);
+                let monitor_rect = monitor_info.rcMonitor;
+                SetWindowLongW(window, GWL_STYLE, (style & !WS_OVERLAPPEDWINDOW.0) as i32);
+                let _ = SetWindowPos(
+                    window,
+                    Some(HWND_TOP),
+                    monitor_rect.left,
+                    monitor_rect.top,
+                    monitor_rect.right - monitor_rect.left,
+                    monitor_rect.bottom - monitor_rect.top,
+                    SWP_NOOWNERZORDER | SWP_FRAMECHANGED,
+                );
+            }
+        } else {
+            SetWindowLongW(window, GWL_STYLE, (style | WS_OVERLAPPEDWINDOW.0) as i32);
+            if let Some(placement) = saved_placement.take() {
+                let _ = SetWindowPlacement(window, &placement);
+            }
+            let _ = SetWindowPos(
+                window,
+                None,
+                0,
+                0,
+                0,
+                0,
+                SWP_NOMOVE | SWP_NOSIZE | SWP_NOZORDER | SWP_NOOWNERZORDER | SWP_FRAMECHANGED,
+            );
+        }
+    }
+}
+
+/// Debug text uses the monospace font every windows install ships, falling back to Arial
+fn win32_load_debug_font(pixel_height: f32) -> Option<Font> {
+    let fonts_directory = PathBuf::from(std::env::var_os("WINDIR")?).join("Fonts");
+    ["consola.ttf", "cour.ttf", "arial.ttf"]
+        .iter()
+        .find_map(|name| Font::load(fonts_directory.join(name), pixel_height).ok())
+}
+
+/// Where the backbuffer goes in the client area. Stretching fills the whole window, letterboxing
+/// takes the biggest rect with the buffer's aspect ratio and centers it.
+fn win32_presentation_rect(buffer_width: i32, buffer_height: i32, window_width: i32, window_height: i32) -> RECT {
+    if !GLOBAL_LETTERBOX.load(Ordering::Relaxed) || buffer_width <= 0 || buffer_height <= 0 {
+        return RECT { left: 0, top: 0, right: window_width, bottom: window_height };
+    }
+
+    // compare the aspect ratios by cross multiplying, wider window means bars on the sides
+    let (buffer_width, buffer_height) = (buffer_width as i64, buffer_height as i64);
+    let (width, height) = if window_width as i64 * buffer_height > window_height as i64 * buffer_width {
+        ((window_height as i64 * buffer_width / buffer_height) as i32, window_height)
+    } else {
+        (window_width, (window_width as i64 * buffer_height / buffer_width) as i32)
+    };
+    let left = (window_width - width) / 2;
+    let top = (window_height - height) / 2;
+    RECT { left, top, right: left + width, bottom: top + height }
+}
+
+fn win32_set_stretch_mode(device_context: HDC) {
+    unsafe {
+        // halftone averages the source pixels under each destination pixel, it needs the brush origin reset after
+        if GLOBAL_SMOOTH_PRESENT.load(Ordering::Relaxed) {
+            SetStretchBltMode(device_context, HALFTONE);
+            let _ = SetBrushOrgEx(device_context, 0, 0, None);
+        } else {
+            SetStretchBltMode(device_context, COLORONCOLOR);
+        }
+    }
 }
 
 fn win32_display_buffer_in_window(device_context: HDC, buffer: &Win32OffscreenBuffer, window_width: i32, window_height: i32) {
+    let rect = win32_presentation_rect(buffer.width, buffer.height, window_width, window_height);
+    win32_set_stretch_mode(device_context);
     unsafe {
+        // only the bars get cleared, blacking out the whole window before the blit would flicker
+        if GLOBAL_LETTERBOX.load(Ordering::Relaxed) {
+            let _ = PatBlt(device_context, 0, 0, window_width, rect.top, BLACKNESS);
+            let _ = PatBlt(device_context, 0, rect.bottom, window_width, window_height - rect.bottom, BLACKNESS);
+            let _ = PatBlt(device_context, 0, rect.top, rect.left, rect.bottom - rect.top, BLACKNESS);
+            let _ = PatBlt(device_context, rect.right, rect.top, window_width - rect.right, rect.bottom - rect.top, BLACKNESS);
+        }
+
         StretchDIBits(
             device_context,
-            0,
-            0,
-            window_width,
-            window_height,
+            rect.left,
+            rect.top,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
             0,
             0,
             buffer.width,
             buffer.height,
             Some(buffer.memory),
-            &buffer.bitmap_info,
+            &buffer.bitmap_info as *const Win32BitmapInfo as *const BITMAPINFO,
             DIB_RGB_COLORS,
             SRCCOPY,
         );
     }
 }
 
+/// Presents only the given parts of the buffer, each mapped into the window the same way a full present would.
+/// Bars and anything else outside the buffer are left alone, they only change when the window does.
+fn win32_display_dirty_rects(device_context: HDC, buffer: &Win32OffscreenBuffer, window_width: i32, window_height: i32, dirty_rects: &[DirtyRect]) {
+    let rect = win32_presentation_rect(buffer.width, buffer.height, window_width, window_height);
+    let to_window_x = |x: i32| rect.left + (x as i64 * (rect.right - rect.left) as i64 / buffer.width as i64) as i32;
+    let to_window_y = |y: i32| rect.top + (y as i64 * (rect.bottom - rect.top) as i64 / buffer.height as i64) as i32;
+    win32_set_stretch_mode(device_context);
+    for dirty in dirty_rects {
+        // a header for just these rows, with the source rect covering all of them whether StretchDIBits
+        // counts the source y from the top or the bottom of a top-down dib can't matter
+        let mut bitmap_info = buffer.bitmap_info;
+        bitmap_info.header.biHeight = -dirty.height;
+        let (left, top) = (to_window_x(dirty.x), to_window_y(dirty.y));
+        let (right, bottom) = (to_window_x(dirty.x + dirty.width), to_window_y(dirty.y + dirty.height));
+        unsafe {
+            StretchDIBits(
+                device_context,
+                left,
+                top,
+                right - left,
+                bottom - top,
+                dirty.x,
+                0,
+                dirty.width,
+                dirty.height,
+                Some(buffer.dib_row(dirty.y)),
+                &bitmap_info as *const Win32BitmapInfo as *const BITMAPINFO,
+                DIB_RGB_COLORS,
+                SRCCOPY,
+            );
+        }
+    }
+}
+
+/// The software path: render_tiled into the back buffer and StretchDIBits to the window. Only the tiles that changed
+/// since the last frame get blitted, unless the window, the scaling or the post passes changed everything
+struct Win32GdiRenderer {
+    dc: HDC,
+    // the hashes are of the last frame, not of the older one in the back buffer, so the dirty tiles are what changed on screen
+    tile_hashes: TileHashes,
+    dirty_rects: Vec<DirtyRect>,
+    // the window size and present mode the last full present went out with, a change means presenting everything again
+    presented_as: Option<(i32, i32, bool)>,
+}
+
+impl Renderer for Win32GdiRenderer {
+    fn name(&self) -> &'static str {
+        "GDI"
+    }
+
+    fn execute(&mut self, render_group: &mut RenderGroup, frame: &mut FrameBuffer, queue: &WorkQueue, _software_only: bool) {
+        self.dirty_rects = render_group.render_tiled(frame, queue, &mut self.tile_hashes);
+    }
+
+    fn present(&mut self, buffer: &Win32OffscreenBuffer, window_width: i32, window_height: i32, everything_changed: bool) {
+        let presentation = (window_width, window_height, GLOBAL_SMOOTH_PRESENT.load(Ordering::Relaxed));
+        if self.presented_as != Some(presentation) || everything_changed {
+            win32_display_buffer_in_window(self.dc, buffer, window_width, window_height);
+            self.presented_as = Some(presentation);
+        } else {
+            win32_display_dirty_rects(self.dc, buffer, window_width, window_height, &self.dirty_rects);
+        }
+    }
+}
+
+/// Time critical so the audio thread is never kept waiting behind the game's, and a 1ms timer resolution so its
+/// sleeps between top ups are as short as asked for instead of the default 15ms
+fn win32_raise_audio_thread_priority() {
+    unsafe {
+        if let Err(error) = SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL) {
+            println!("Failed to raise the audio thread's priority: {error}");
+        }
+        timeBeginPeriod(1);
+    }
+}
+
+/// WASAPI unless `--dsound` asks for DirectSound or WASAPI doesn't start, which is also what happens before Vista.
+/// `--sample-rate <hz>` and `--sound-buffer-ms <ms>` set up the DirectSound buffer, WASAPI goes by the device
+fn win32_create_audio_output(window: HWND) -> Option<Box<dyn AudioOutput>> {
+    let args: Vec<String> = std::env::args().collect();
+    if !args.iter().any(|arg| arg == "--dsound") {
+        match Win32Wasapi::new() {
+            Ok(output) => return Some(Box::new(output)),
+            Err(error) => println!("Failed to start WASAPI, trying DirectSound: {error}"),
+        }
+    }
+    let samples_per_second = args.iter().skip_while(|arg| *arg != "--sample-rate").nth(1).and_then(|rate| rate.parse().ok()).unwrap_or(48000u32);
+    let buffer_ms = args.iter().skip_while(|arg| *arg != "--sound-buffer-ms").nth(1).and_then(|ms| ms.parse().ok()).unwrap_or(1000u32);
+    let sound = match win32_init_dsound(window, samples_per_second, samples_per_second * buffer_ms / 1000 * 4) {
+        Ok(sound) => sound,
+        Err(error) => {
+            println!("Failed to start DirectSound: {error}");
+            return None;
+        }
+    };
+    match sound.play() {
+        Ok(()) => Some(Box::new(sound)),
+        Err(error) => {
+            println!("Failed to start the sound buffer playing: {error}");
+            None
+        }
+    }
+}
+
+/// The renderer the command line asks for, `--d3d11` or `--opengl`, falling back to GDI when it can't start
+fn win32_create_renderer(window: HWND, dc: HDC) -> Box<dyn Renderer> {
+    let args: Vec<String> = std::env::args().collect();
+    let gpu: Option<Result<Box<dyn Renderer>>> = if args.iter().any(|arg| arg == "--d3d11") {
+        Some(Win32D3D11::new(window).map(|renderer| Box::new(renderer) as Box<dyn Renderer>))
+    } else if args.iter().any(|arg| arg == "--opengl") {
+        Some(Win32OpenGl::new(window).map(|renderer| Box::new(renderer) as Box<dyn Renderer>))
+    } else {
+        None
+    };
+    match gpu {
+        Some(Ok(renderer)) => return renderer,
+        Some(Err(error)) => println!("Failed to start the GPU renderer, using GDI: {error}"),
+        None => {}
+    }
+    Box::new(Win32GdiRenderer { dc, tile_hashes: TileHashes::default(), dirty_rects: Vec::new(), presented_as: None })
+}
+
 unsafe extern "system" fn wnd_proc(
     hwnd: HWND,
     msg: u32,
@@ -135,17 +978,169 @@ unsafe extern "system" fn wnd_proc(
             }
             LRESULT(0)
         }
-        WM_KEYDOWN => {
-            println!("Key down: {:?}", wparam.0);
+        WM_KEYDOWN | WM_KEYUP | WM_SYSKEYDOWN | WM_SYSKEYUP => {
+            // bit 29 of lparam is set while alt is held, bit 30 is the key state before this message,
+            // bit 31 is the transition state (set on release)
+            let alt_down = (lparam.0 & (1 << 29)) != 0;
+            let was_down = (lparam.0 & (1 << 30)) != 0;
+            let is_down = (lparam.0 & (1 << 31)) == 0;
+            let vk_code = wparam.0 & 0xFF;
+            let mut scancode = Scancode::from_key_lparam(lparam);
+            if scancode.0 == 0 {
+                // injected keys (SendInput with only a vk) come without a scan code
+                scancode = Scancode::from_virtual_key(VIRTUAL_KEY(vk_code as u16));
+            }
+
+            // holding a key floods WM_KEYDOWN with auto-repeats, only real transitions count as input
+            if was_down != is_down {
+                let mut keyboard = GLOBAL_KEYBOARD
+                    .get_or_init(|| Mutex::new(KeyboardState::default()))
+                    .lock()
+                    .expect("Keyboard state lock poisoned");
+                keyboard.keys[vk_code].update(is_down);
+                keyboard.scancodes[scancode.0 as usize % SCANCODE_COUNT].update(is_down);
+            }
+
+            if is_down && alt_down {
+                if vk_code == VK_F4.0 as usize {
+                    unsafe {
+                        GLOBAL_RUNNING = false;
+                    }
+                    return LRESULT(0);
+                }
+                if vk_code == VK_RETURN.0 as usize {
+                    // swallow repeats too, DefWindowProc would beep on them
+                    if !was_down {
+                        win32_toggle_fullscreen(hwnd);
+                    }
+                    return LRESULT(0);
+                }
+            }
+
+            // system keys still go to windows for alt+space, alt+tab menu handling and friends
+            match msg {
+                WM_SYSKEYDOWN | WM_SYSKEYUP => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
+                _ => LRESULT(0),
+            }
+        }
+
+        WM_CHAR => {
+            let mut text_input = GLOBAL_TEXT_INPUT
+                .get_or_init(|| Mutex::new(TextInputState::default()))
+                .lock()
+                .expect("Text input lock poisoned");
+            text_input.push_utf16(wparam.0 as u16);
             LRESULT(0)
         }
-        WM_LBUTTONDOWN => {
-            println!("Mouse click!");
+        WM_UNICHAR => {
+            // returning TRUE for UNICODE_NOCHAR tells the sender we take UTF-32 directly
+            if wparam.0 as u32 == UNICODE_NOCHAR {
+                return LRESULT(1);
+            }
+            let mut text_input = GLOBAL_TEXT_INPUT
+                .get_or_init(|| Mutex::new(TextInputState::default()))
+                .lock()
+                .expect("Text input lock poisoned");
+            if let Some(character) = char::from_u32(wparam.0 as u32) {
+                text_input.push_char(character);
+            }
+            LRESULT(0)
+        }
+        WM_MOUSEMOVE | WM_LBUTTONDOWN | WM_LBUTTONUP | WM_MBUTTONDOWN | WM_MBUTTONUP | WM_RBUTTONDOWN | WM_RBUTTONUP | WM_XBUTTONDOWN | WM_XBUTTONUP
+        | WM_LBUTTONDBLCLK | WM_MBUTTONDBLCLK | WM_RBUTTONDBLCLK | WM_XBUTTONDBLCLK => {
+            let mut mouse = GLOBAL_MOUSE
+                .get_or_init(|| Mutex::new(MouseState::default()))
+                .lock()
+                .expect("Mouse state lock poisoned");
+            win32_update_mouse_position(hwnd, &mut mouse, lparam);
+
+            let x_button = if ((wparam.0 >> 16) & 0xFFFF) as u16 == XBUTTON1 { MouseButton::X1 } else { MouseButton::X2 };
+            // with CS_DBLCLKS the second press of a double click comes as *DBLCLK instead of *DOWN
+            let (button, is_down, double_click) = match msg {
+                WM_LBUTTONDOWN => (MouseButton::Left, true, false),
+                WM_LBUTTONUP => (MouseButton::Left, false, false),
+                WM_LBUTTONDBLCLK => (MouseButton::Left, true, true),
+                WM_MBUTTONDOWN => (MouseButton::Middle, true, false),
+                WM_MBUTTONUP => (MouseButton::Middle, false, false),
+                WM_MBUTTONDBLCLK => (MouseButton::Middle, true, true),
+                WM_RBUTTONDOWN => (MouseButton::Right, true, false),
+                WM_RBUTTONUP => (MouseButton::Right, false, false),
+                WM_RBUTTONDBLCLK => (MouseButton::Right, true, true),
+                WM_XBUTTONDOWN => (x_button, true, false),
+                WM_XBUTTONUP => (x_button, false, false),
+                WM_XBUTTONDBLCLK => (x_button, true, true),
+                _ => {
+                    let (threshold_x, threshold_y) = unsafe { (GetSystemMetrics(SM_CXDRAG), GetSystemMetrics(SM_CYDRAG)) };
+                    mouse.update_drags(threshold_x, threshold_y);
+                    return LRESULT(0);
+                }
+            };
+            mouse.press(button, is_down);
+            mouse.double_clicks[button as usize] |= double_click;
+
+            // WM_XBUTTON* expects TRUE back, the rest expect 0
+            match msg {
+                WM_XBUTTONDOWN | WM_XBUTTONUP | WM_XBUTTONDBLCLK => LRESULT(1),
+                _ => LRESULT(0),
+            }
+        }
+        WM_POINTERDOWN | WM_POINTERUPDATE | WM_POINTERUP | WM_POINTERCAPTURECHANGED => {
+            let pointer_id = (wparam.0 & 0xFFFF) as u32;
+            match win32_read_touch_point(hwnd, pointer_id, msg != WM_POINTERDOWN && msg != WM_POINTERUPDATE) {
+                Some(point) => {
+                    let mut touch = GLOBAL_TOUCH
+                        .get_or_init(|| Mutex::new(TouchState::default()))
+                        .lock()
+                        .expect("Touch state lock poisoned");
+                    touch.update(point);
+                    LRESULT(0)
+                }
+                // mouse pointers (and failures) go on to the default handling so the regular mouse messages still come
+                None => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
+            }
+        }
+        WM_MOUSEWHEEL => {
+            let mut mouse = GLOBAL_MOUSE
+                .get_or_init(|| Mutex::new(MouseState::default()))
+                .lock()
+                .expect("Mouse state lock poisoned");
+            // lparam holds screen coordinates here so only the wheel delta is used
+            mouse.wheel_delta += ((wparam.0 >> 16) & 0xFFFF) as i16 as i32;
             LRESULT(0)
         }
+        WM_SETCURSOR => {
+            // low word of lparam is the hit test result, only the client area cursor is ours
+            if (lparam.0 & 0xFFFF) as u32 == HTCLIENT {
+                unsafe {
+                    SetCursor(GLOBAL_CURSOR.visible.then_some(GLOBAL_CURSOR.handle));
+                }
+                return LRESULT(1);
+            }
+            unsafe {
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+        }
+        WM_ACTIVATEAPP => {
+            // never keep the cursor trapped once the user switches away
+            if wparam.0 == 0 {
+                win32_set_mouse_captured(hwnd, false);
+            }
+            LRESULT(0)
+        }
+        WM_DEVICECHANGE => {
+            GLOBAL_GAMEPAD_RECHECK.store(true, Ordering::Relaxed);
+            LRESULT(1)
+        }
+        WM_INPUT => {
+            win32_process_raw_input(lparam);
+            // DefWindowProc has to see WM_INPUT so windows can clean up the raw input data
+            unsafe {
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+        }
         WM_PAINT => {
             unsafe {
-                if GLOBAL_BUFFER.is_null() {
+                if GLOBAL_SWAP_CHAIN.is_null() {
                     return DefWindowProcW(hwnd, msg, wparam, lparam);
                 }
 
@@ -154,7 +1149,7 @@ unsafe extern "system" fn wnd_proc(
                 // You can draw using hdc here
 
                 let dimension = win32_get_window_dimension(hwnd).expect("Failed GetRect from windows");
-                win32_display_buffer_in_window(hdc, &*GLOBAL_BUFFER, dimension.width, dimension.height);
+                win32_display_buffer_in_window(hdc, (*GLOBAL_SWAP_CHAIN).front(), dimension.width, dimension.height);
 
                 let _ = EndPaint(hwnd, &ps);
             }
@@ -166,40 +1161,299 @@ unsafe extern "system" fn wnd_proc(
     }
 }
 
-fn render_gradient(buffer: &mut Win32OffscreenBuffer, x_offset: i32, y_offset: i32) {
-    let pixel_ptr = buffer.memory as *mut u32;
-    unsafe {
-        // Fill with gradient
-        for y in 0..buffer.height {
-            for x in 0..buffer.width {
-                let offset = (y * buffer.width + x) as usize;
-                /*
-                    offset          : +0 +1 +2 +3
-                    Pixel in memory : 00 00 00 00
-                    Channel         : BB GG RR xx (reversed little endian because windows reverses it to look like 0x xxRRGGBB)
+/// A source of gamepads. Each backend owns a fixed range of slots in the gamepad array,
+/// XInput comes first so xbox pads always land in the same slots they have in XInput.
+trait GamepadBackend: Send {
+    fn slot_count(&self) -> usize;
+
+    /// Updates the backend's slots, `recheck` means empty slots should be probed for new pads.
+    /// Slot numbers in events are global, i.e. offset by `first_slot`.
+    fn poll(&mut self, settings: &GamepadSettings, recheck: bool, first_slot: usize, gamepads: &mut [GamepadState], events: &mut Vec<GamepadEvent>);
+
+    /// Motor speeds in 0.0..1.0, slot is local to the backend. Returns false when the pad can't rumble.
+    fn set_vibration(&mut self, slot: usize, low_frequency: f32, high_frequency: f32) -> bool;
+}
+
+fn win32_init_gamepad_backends() -> Vec<Box<dyn GamepadBackend>> {
+    let backends: Vec<Box<dyn GamepadBackend>> = vec![
+        Box::new(XInputBackend::new()),
+        Box::new(GamingInputBackend::new(GAMING_INPUT_SLOTS)),
+    ];
+    debug_assert_eq!(backends.iter().map(|backend| backend.slot_count()).sum::<usize>(), MAX_GAMEPADS);
+    backends
+}
+
+fn read_controller_state(
+    backends: &mut [Box<dyn GamepadBackend>],
+    gamepads: &mut [GamepadState; MAX_GAMEPADS],
+    settings: &GamepadSettings,
+    last_recheck: &mut Instant,
+) -> Vec<GamepadEvent> {
+    let mut events = Vec::new();
+
+    let recheck = GLOBAL_GAMEPAD_RECHECK.swap(false, Ordering::Relaxed) || last_recheck.elapsed() >= GAMEPAD_RECHECK_INTERVAL;
+    if recheck {
+        *last_recheck = Instant::now();
+    }
+
+    let old_gamepads = *gamepads;
+    let mut first_slot = 0;
+    for backend in backends.iter_mut() {
+        let slot_count = backend.slot_count();
+        backend.poll(settings, recheck, first_slot, &mut gamepads[first_slot..first_slot + slot_count], &mut events);
+        first_slot += slot_count;
+    }
 
-                    in 32bit Register     : xx RR GG BB
-                    this is why void pointer is cast to u32 to fill it and move to next pixel
-                */
-                let b = (x + x_offset) as u8;
-                let g = (y + y_offset) as u8;
-                let r = 0u8;
-                let a = 255u8;
-                let pixel = (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | (b as u32);
-                *pixel_ptr.add(offset) = pixel;
+    if settings.stick_to_dpad {
+        for (gamepad, old_gamepad) in gamepads.iter_mut().zip(old_gamepads.iter()).filter(|(gamepad, _)| gamepad.connected) {
+            emulate_dpad_with_stick(gamepad, old_gamepad, settings);
+        }
+    }
+
+    events
+}
+
+/// Ors the left stick into the dpad buttons, on top of whatever the backend read from the real dpad
+fn emulate_dpad_with_stick(gamepad: &mut GamepadState, old_gamepad: &GamepadState, settings: &GamepadSettings) {
+    let sticks = gamepad.sticks;
+    let deflections = [sticks.left_y, -sticks.left_y, -sticks.left_x, sticks.left_x];
+    for (held, deflection) in gamepad.stick_dpad.iter_mut().zip(deflections) {
+        let threshold = if *held { settings.stick_dpad_release } else { settings.stick_dpad_press };
+        *held = deflection > threshold;
+    }
+
+    // redo the transitions from last frame's buttons so a dpad press handed over to the stick doesn't count twice
+    let old_buttons = old_gamepad.buttons;
+    let buttons = &mut gamepad.buttons;
+    let [up, down, left, right] = gamepad.stick_dpad;
+    buttons.dpad_up = process_polled_button(old_buttons.dpad_up, buttons.dpad_up.ended_down || up);
+    buttons.dpad_down = process_polled_button(old_buttons.dpad_down, buttons.dpad_down.ended_down || down);
+    buttons.dpad_left = process_polled_button(old_buttons.dpad_left, buttons.dpad_left.ended_down || left);
+    buttons.dpad_right = process_polled_button(old_buttons.dpad_right, buttons.dpad_right.ended_down || right);
+}
+
+fn set_gamepad_vibration(backends: &mut [Box<dyn GamepadBackend>], controller_index: usize, low_frequency: f32, high_frequency: f32) -> bool {
+    let mut first_slot = 0;
+    for backend in backends.iter_mut() {
+        let slot_count = backend.slot_count();
+        if controller_index < first_slot + slot_count {
+            return backend.set_vibration(controller_index - first_slot, low_frequency, high_frequency);
+        }
+        first_slot += slot_count;
+    }
+    false
+}
+
+fn write_controller_vibration(backends: &mut [Box<dyn GamepadBackend>], gamepads: &[GamepadState]) {
+    for (controller_index, gamepad) in gamepads.iter().enumerate().filter(|(_, gamepad)| gamepad.connected) {
+        set_gamepad_vibration(backends, controller_index, gamepad.vibration.low_frequency, gamepad.vibration.high_frequency);
+    }
+}
+
+/// Plasma for `--indexed`, drawn once. Everything that moves afterwards is the palette cycling through it
+fn palette_demo(width: i32, height: i32) -> (IndexedBuffer, Palette) {
+    let mut indexed = IndexedBuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let (x_f, y_f) = (x as f32, y as f32);
+            let value = (x_f / 37.0).sin() + (y_f / 23.0).sin() + ((x_f + y_f) / 41.0).sin() + ((x_f * x_f + y_f * y_f).sqrt() / 29.0).sin();
+            indexed.pixels[(y * width + x) as usize] = ((value + 4.0) / 8.0 * 255.0) as u8;
+        }
+    }
+    let stops = [Color::rgb8(0x10, 0x00, 0x40), Color::rgb8(0xFF, 0x40, 0x80), Color::rgb8(0xFF, 0xE0, 0x40), Color::rgb8(0x20, 0xC0, 0xFF)];
+    (indexed, Palette::looping_gradient(&stops))
+}
+
+/// A flat grey disk and the normal map that makes it read as a ball once it's lit
+fn sphere_demo(diameter: i32) -> (Bitmap, Bitmap) {
+    let mut albedo = Bitmap { width: diameter, height: diameter, pixels: vec![0; (diameter * diameter) as usize] };
+    let mut normals = Bitmap { width: diameter, height: diameter, pixels: vec![0; (diameter * diameter) as usize] };
+    let radius = diameter as f32 / 2.0;
+    for y in 0..diameter {
+        for x in 0..diameter {
+            let (dx, dy) = ((x as f32 + 0.5 - radius) / radius, (radius - y as f32 - 0.5) / radius);
+            let z_squared = 1.0 - dx * dx - dy * dy;
+            if z_squared <= 0.0 {
+                continue;
             }
+            let encode = |value: f32| ((value + 1.0) * 127.5) as u8;
+            let index = (y * diameter + x) as usize;
+            albedo.pixels[index] = pack_rgba8(0xC0, 0xC0, 0xC0, 0xFF);
+            normals.pixels[index] = pack_rgba8(encode(dx), encode(dy), encode(z_squared.sqrt()), 0xFF);
         }
     }
+    (albedo, normals)
+}
+
+/// Sky above, a striped wall behind and grass below, for the shiny ball to reflect.
+/// Low resolution on purpose, they get stretched over the whole frame and reflections are blurry anyway
+fn environment_demo() -> EnvironmentMaps {
+    let (width, height) = (64, 36);
+    let map = |pixel: &dyn Fn(i32, i32) -> Color| {
+        let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| pixel(x, y).to_pixel()).collect();
+        Arc::new(Bitmap { width, height, pixels })
+    };
+    EnvironmentMaps {
+        top: map(&|_, y| Color::rgb8(0x40, 0x80, 0xFF).lerp(Color::rgb8(0xD0, 0xE8, 0xFF), y as f32 / height as f32)),
+        middle: map(&|x, _| if x / 4 % 2 == 0 { Color::rgb8(0xE0, 0x40, 0x40) } else { Color::rgb8(0xF0, 0xF0, 0xE0) }),
+        bottom: map(&|x, y| if (x + y) % 5 == 0 { Color::rgb8(0x20, 0x60, 0x10) } else { Color::rgb8(0x40, 0xA0, 0x20) }),
+    }
+}
+
+/// 16x16 box skin: rounded corners, a light rim over a dark bevel and a faintly dotted translucent middle,
+/// so whether the middle was stretched or tiled shows
+fn panel_demo() -> Bitmap {
+    let size = 16;
+    let pixels = (0..size * size)
+        .map(|index| {
+            let (x, y) = (index % size, index / size);
+            let edge = x.min(y).min(size - 1 - x).min(size - 1 - y);
+            let corner = (x.min(size - 1 - x) + y.min(size - 1 - y)) < 2;
+            let color = match edge {
+                _ if corner => Color::TRANSPARENT,
+                0 => Color::rgb8(0xC0, 0xD0, 0xFF),
+                1..=3 => Color::rgb8(0x20, 0x28, 0x50),
+                _ if (x + y) % 4 == 0 => Color::rgba8(0x30, 0x40, 0x80, 0xD0),
+                _ => Color::rgba8(0x10, 0x10, 0x30, 0xC0),
+            };
+            color.premultiplied().to_pixel()
+        })
+        .collect();
+    Bitmap { width: size, height: size, pixels }
+}
+
+/// Two bars in the middle of the screen
+fn render_pause_sign(render_group: &mut RenderGroup) {
+    let (center_x, center_y) = (render_group.width as f32 / 2.0, render_group.height as f32 / 2.0);
+    render_group.push_rect(center_x - 40.0, center_y - 50.0, center_x - 10.0, center_y + 50.0, Color::WHITE);
+    render_group.push_rect(center_x + 10.0, center_y - 50.0, center_x + 40.0, center_y + 50.0, Color::WHITE);
+}
+
+/// One game frame: reads the input snapshot, updates the state and pushes what it wants drawn into the render group
+fn game_update_and_render(game_state: &mut GameState, input: &GameInput, render_group: &mut RenderGroup) {
+    let GameInput { time, keyboard, mouse, gamepads, .. } = input;
+    let GameState { x_anim, y_anim, bindings, players, player_history, sprite, sprite_animation, flashed_at, camera, .. } = game_state;
+    if let Some(animation) = sprite_animation {
+        *sprite = Some(animation.frame_at(*time).clone());
+    }
+
+    for (player, slot) in players.update(gamepads) {
+        println!("Gamepad {slot} is now player {}", player + 1);
+    }
+    // only player 1 drives the gradient, pads that haven't pressed start are ignored
+    let gamepads = players.gamepad(0, gamepads).map(std::slice::from_ref).unwrap_or(&[]);
+    if let Some(gamepad) = gamepads.first() {
+        player_history.update(gamepad, *time);
+        // double tapping B snaps back to the origin, shoulders together swap up and down like F1
+        if player_history.was_double_tapped(GamepadButton::B, 250) {
+            *x_anim = 0;
+            *y_anim = 0;
+        }
+        if player_history.was_chord_pressed(gamepad, &[GamepadButton::LeftShoulder, GamepadButton::RightShoulder], 100) {
+            bindings.swap(Action::MoveUp, Action::MoveDown);
+        }
+    }
+
+    // F1 inverts vertical movement by swapping the bindings at runtime
+    if keyboard.was_pressed(VK_F1) && !keyboard.is_down(VK_CONTROL) {
+        bindings.swap(Action::MoveUp, Action::MoveDown);
+    }
+
+    let action = |action| bindings.action_value(action, keyboard, mouse, gamepads);
+    let speed = if bindings.is_action_down(Action::Boost, keyboard, mouse, gamepads) { 8.0 } else { 4.0 };
+    *x_anim += ((action(Action::MoveRight) - action(Action::MoveLeft)) * speed) as i32;
+    *y_anim += ((action(Action::MoveDown) - action(Action::MoveUp)) * speed) as i32;
+    if bindings.is_action_down(Action::Reset, keyboard, mouse, gamepads) {
+        *x_anim = 0;
+        *y_anim = 0;
+    }
+
+    // holding left button pins the gradient origin under the cursor, wheel scrolls it vertically
+    if mouse.is_down(MouseButton::Left) {
+        *x_anim = -mouse.x;
+        *y_anim = -mouse.y;
+    }
+    let mouse_position = V2::new(mouse.x as f32, mouse.y as f32);
+    if mouse.was_double_clicked(MouseButton::Left) {
+        *x_anim = 0;
+        *y_anim = 0;
+        // marks where in the world it happened for a couple of seconds
+        let world = camera.screen_to_world(mouse_position, render_group.width, render_group.height);
+        debug_draw_circle(world, 12.0, Color::rgb8(0xFF, 0x40, 0xFF), Duration::from_secs(2));
+        debug_draw_text(world + V2::new(16.0, -8.0), &format!("{:.0}, {:.0}", world.x, world.y), Color::rgb8(0xFF, 0x40, 0xFF), Duration::from_secs(2));
+    }
+    if keyboard.is_down(VK_CONTROL) {
+        // ctrl+wheel zooms around the cursor: whatever was under it is moved back under it after the zoom
+        let before = camera.screen_to_world(mouse_position, render_group.width, render_group.height);
+        camera.zoom = (camera.zoom * 1.25f32.powf(mouse.wheel_delta as f32 / WHEEL_DELTA as f32)).clamp(0.125, 16.0);
+        let after = camera.screen_to_world(mouse_position, render_group.width, render_group.height);
+        camera.position = camera.position + (before - after);
+    } else {
+        *y_anim -= mouse.wheel_delta * 16 / WHEEL_DELTA as i32;
+    }
+    // Q and E turn the camera, Home puts it back
+    if keyboard.is_down(VK_Q) {
+        camera.rotation -= 0.02;
+    }
+    if keyboard.is_down(VK_E) {
+        camera.rotation += 0.02;
+    }
+    if keyboard.was_pressed(VK_HOME) {
+        *camera = Camera::default();
+    }
+    // right button (or captured mode) drags the gradient with raw mouse motion, camera style
+    if mouse.is_down(MouseButton::Right) || mouse.captured {
+        *x_anim -= mouse.raw_dx;
+        *y_anim -= mouse.raw_dy;
+    }
+
+    // stand in for taking damage
+    if keyboard.was_pressed(VK_X) {
+        *flashed_at = Some(*time);
+    }
+    if let Some(flashed_at) = flashed_at {
+        let fade = 1.0 - (*time - *flashed_at).as_secs_f32() / 0.4;
+        render_group.flash = Color::from_rgba_f32(1.0, 0.0, 0.0, 0.6 * fade.max(0.0));
+    }
+
+    // test animation to make sure render buffer update and main loop is working
+    *x_anim += 1;
+    *y_anim += 2;
+
+    // ctrl+F1..F5 pick the demo scene
+    if keyboard.is_down(VK_CONTROL)
+        && let Some(index) = [VK_F1, VK_F2, VK_F3, VK_F4, VK_F5].iter().position(|key| keyboard.was_pressed(*key))
+        && index < SCENES.len()
+    {
+        game_state.scene = index;
+        println!("Scene: {}", SCENES[index].name());
+    }
+    SCENES[game_state.scene].update_and_render(game_state, input, render_group);
+}
+
+/// The next stretch of sound: the test tone with everything the mixer's playing on top. Runs on the audio thread
+/// whenever the device wants more, the memory only changes with the commands game_update_and_render sends
+fn game_get_sound_samples(memory: &mut GameSoundMemory, sound_output_buffer: &mut GameSoundOutputBuffer) {
+    let GameSoundOutputBuffer { samples_per_second, samples } = sound_output_buffer;
+    memory.tone.fill(samples, *samples_per_second);
+    memory.mixer.mix(samples, *samples_per_second);
 }
 
 fn main() -> Result<()> {
     unsafe {
-        let default_width = 1280;
-        let default_height = 720;
+        // `--letterbox` renders at a fixed 960x540 and scales it into the window without distorting it
+        let letterbox = std::env::args().any(|arg| arg == "--letterbox");
+        GLOBAL_LETTERBOX.store(letterbox, Ordering::Relaxed);
+        let (default_width, default_height) = if letterbox { (960, 540) } else { (1280, 720) };
 
-        let buffer_state = Box::new(Win32OffscreenBuffer::default());
-        GLOBAL_BUFFER = Box::into_raw(buffer_state);
-        win32_resize_dib_section(&mut *GLOBAL_BUFFER, default_width, default_height);
+        // `--rgb565` keeps the backbuffers at 16 bits a pixel, drawing still happens at 32 and is converted at the end
+        let format = if std::env::args().any(|arg| arg == "--rgb565") { PixelFormat::Rgb565 } else { PixelFormat::Bgra32 };
+
+        GLOBAL_SWAP_CHAIN = Box::into_raw(Box::new(Win32SwapChain::default()));
+        for buffer in (*GLOBAL_SWAP_CHAIN).buffers.iter_mut() {
+            buffer.format = format;
+            win32_resize_dib_section(buffer, default_width, default_height);
+        }
 
         let h_instance = GetModuleHandleW(None)?;
         let class_name = w!("RustmadeWindowClass");
@@ -208,7 +1462,8 @@ fn main() -> Result<()> {
             hInstance: h_instance.into(),
             lpszClassName: class_name,
             lpfnWndProc: Some(wnd_proc),
-            style: CS_VREDRAW|CS_HREDRAW,
+            // own dc so the one the GL context is made on stays the window's
+            style: CS_VREDRAW|CS_HREDRAW|CS_DBLCLKS|CS_OWNDC,
             ..Default::default()
         };
 
@@ -232,9 +1487,146 @@ fn main() -> Result<()> {
         if let Ok(window) = hwnd {
             GLOBAL_RUNNING = true;
 
-            let mut x_anim = 0;
-            let mut y_anim = 0;
+            if let Err(error) = win32_register_raw_mouse(window) {
+                println!("Failed to register raw mouse input: {error}");
+            }
+            if let Err(error) = platform_set_system_cursor(window, SystemCursor::Arrow) {
+                println!("Failed to load arrow cursor: {error}");
+            }
+            let mut cursor_style = 0;
+
+            let sphere = sphere_demo(96);
+            let mut game_state = GameState {
+                x_anim: 0,
+                y_anim: 0,
+                bindings: InputBindings::default(),
+                players: PlayerSlots::default(),
+                player_history: GamepadHistory::default(),
+                sprite: None,
+                // `--sprite <path>` draws a bmp, png, animated gif or apng under the mouse cursor
+                sprite_animation: std::env::args().skip_while(|arg| arg != "--sprite").nth(1).and_then(|path| match Animation::load(&path) {
+                    Ok(animation) => Some(animation),
+                    Err(error) => {
+                        println!("Failed to load sprite {path}: {error}");
+                        None
+                    }
+                }),
+                sprite_filter: BitmapFilter::Bilinear,
+                sphere: Arc::new(sphere.0),
+                sphere_normals: Arc::new(sphere.1),
+                environment: environment_demo(),
+                panel: Arc::new(panel_demo()),
+                flashed_at: None,
+                camera: Camera::default(),
+                scene: 0,
+                test_pattern: 0,
+                audio: Audio::default(),
+                blip: Arc::new(Sound::from_fn(48000, 0.08, |t| (t * 880.0 * TAU).sin() * (1.0 - t / 0.08) * 0.3)),
+                hum: Arc::new(Sound::from_fn(48000, 4.0, |t| ((t * 110.0 * TAU).sin() + (t * 165.0 * TAU).sin()) * 0.15)),
+                hum_voice: None,
+            };
+            let mut recorder = InputRecorder::new("rustmade_loop.input");
+            let mut gamepad_backends = win32_init_gamepad_backends();
+            let mut gamepads = [GamepadState::default(); MAX_GAMEPADS];
+            let mut gamepad_settings = GamepadSettings::default();
+            let mut last_gamepad_recheck = Instant::now();
+            let mut console_line = String::new();
+            let start_time = Instant::now();
+            let mut active_device = None;
+            let mut paused_for_gamepad = None;
+            let mut rumble = RumbleMixer::default();
+            let mut measure_latency = false;
+            let mut last_frame = Instant::now();
+            // `--input-script <path>` swaps the keyboard and gamepads for a scripted sequence
+            let mut input_script = match std::env::args().skip_while(|arg| arg != "--input-script").nth(1) {
+                Some(path) => match InputScript::load(&path) {
+                    Ok(script) => Some(script),
+                    Err(error) => {
+                        println!("Failed to load input script {path}: {error}");
+                        None
+                    }
+                },
+                None => None,
+            };
+            let debug_font = win32_load_debug_font(20.0).map(Arc::new);
+            let mut render_group = RenderGroup::default();
+            let render_queue = WorkQueue::with_available_cores();
+            let mut crt = CrtEffect::default();
+            // F12 goes through these color grades and back to none, `--lut <path.cube>` adds one from a file
+            let mut grade = ColorGrade::default();
+            let mut looks = vec![Lut3d::from_curves(1.15, 1.25, 0.4), Lut3d::from_curves(1.0, 0.0, 0.0)];
+            if let Some(path) = std::env::args().skip_while(|arg| arg != "--lut").nth(1) {
+                match Lut3d::load_cube(&path) {
+                    Ok(lut) => looks.push(lut),
+                    Err(error) => println!("Failed to load color grade {path}: {error}"),
+                }
+            }
+            let mut look = 0;
+            // `--indexed` shows the 8 bit palette mode instead of the game
+            let mut palette_mode = std::env::args()
+                .any(|arg| arg == "--indexed")
+                .then(|| palette_demo((*GLOBAL_SWAP_CHAIN).front().width, (*GLOBAL_SWAP_CHAIN).front().height));
+            let mut palette_fade = 0.0f32;
+            if debug_font.is_none() {
+                println!("Failed to load a debug font, debug text is disabled");
+            }
             let dc = GetDC(Some(window));
+            let mut renderer = win32_create_renderer(window, dc);
+            println!("Rendering with {}", renderer.name());
+            // `--hdr scrgb|hdr10` on a display with HDR on, shift+F12 goes through the modes that work
+            let mut output_mode = OutputMode::Sdr;
+            if let Some(name) = std::env::args().skip_while(|arg| arg != "--hdr").nth(1) {
+                match OutputMode::parse(&name) {
+                    Some(mode) if renderer.set_output_mode(mode) => output_mode = mode,
+                    Some(_) => println!("{} can't output {name}, staying SDR", renderer.name()),
+                    None => println!("Unknown output mode {name}, it's sdr, scrgb or hdr10"),
+                }
+            }
+            // the window handle can't cross threads as it is, DirectSound only needs it to say whose sound it is
+            let sound_window = window.0 as isize;
+            game_state.audio = Audio::start(
+                move || {
+                    win32_raise_audio_thread_priority();
+                    win32_create_audio_output(HWND(sound_window as *mut c_void))
+                },
+                game_get_sound_samples,
+            );
+            match game_state.audio.output() {
+                Some((name, samples_per_second)) => println!("Playing sound with {name} at {samples_per_second}Hz"),
+                None => println!("No sound output, the game runs silent"),
+            }
+            // `--volume <0..1>` turns everything down, shift+F9 mutes and unmutes
+            if let Some(volume) = std::env::args().skip_while(|arg| arg != "--volume").nth(1) {
+                match volume.parse::<f32>() {
+                    Ok(volume) => game_state.audio.set_master_volume(volume.clamp(0.0, 1.0)),
+                    Err(_) => println!("Bad volume {volume}, it's 0 to 1"),
+                }
+            }
+            // `--music <path.ogg>` streams a song on loop underneath everything
+            if let Some(path) = std::env::args().skip_while(|arg| arg != "--music").nth(1) {
+                match MusicStream::open(&path, true) {
+                    Ok(stream) => game_state.audio.play_music(stream, MUSIC_VOLUME),
+                    Err(error) => println!("Failed to play music {path}: {error}"),
+                }
+            }
+            // `--ambience <path.wav>` loops a sound underneath everything, round the loop points in the file if it has any
+            if let Some(path) = std::env::args().skip_while(|arg| arg != "--ambience").nth(1) {
+                match Sound::load(&path) {
+                    Ok(sound) => {
+                        game_state.audio.play_looping(&Arc::new(sound), 1.0, 1.0, None);
+                    }
+                    Err(error) => println!("Failed to load ambience {path}: {error}"),
+                }
+            }
+            // `--vsync off|on|adaptive`, shift+F7 goes through them. The GPU backends wait for the vertical blank by default
+            let mut vsync = VSync::default();
+            if let Some(name) = std::env::args().skip_while(|arg| arg != "--vsync").nth(1) {
+                match VSync::parse(&name) {
+                    Some(mode) if renderer.set_vsync(mode) => vsync = mode,
+                    Some(_) => println!("VSync needs --d3d11 or --opengl, {} presents as soon as a frame is done", renderer.name()),
+                    None => println!("Unknown vsync mode {name}, it's off, on or adaptive"),
+                }
+            }
 
             let mut msg = MSG::default();
             while GLOBAL_RUNNING {
@@ -242,13 +1634,336 @@ fn main() -> Result<()> {
                     let _ = TranslateMessage(&msg);
                     let _ = DispatchMessageW(&msg);
                 }
-                render_gradient(&mut *GLOBAL_BUFFER, x_anim, y_anim);
-                let dimension = win32_get_window_dimension(window).expect("Failed GetRect from windows");
-                win32_display_buffer_in_window(dc, &*GLOBAL_BUFFER, dimension.width, dimension.height);
 
-                // test animation to make sure render buffer update and main loop is working
-                x_anim += 1;
-                y_anim += 2;
+                // latency debug mode measures from here, messages are pumped and the pads are about to be read
+                let polled_at = Instant::now();
+                let mut gamepad_events = read_controller_state(&mut gamepad_backends, &mut gamepads, &gamepad_settings, &mut last_gamepad_recheck);
+                if let Some(ActiveDevice::Gamepad(active_index)) = active_device {
+                    let active_lost = gamepad_events
+                        .iter()
+                        .any(|event| matches!(event, GamepadEvent::Disconnected(controller_index) if *controller_index == active_index));
+                    if active_lost {
+                        gamepad_events.push(GamepadEvent::ActiveDisconnected(active_index));
+                    }
+                }
+                for event in gamepad_events {
+                    match event {
+                        GamepadEvent::Connected(controller_index) => {
+                            println!("Gamepad {controller_index} connected");
+                            if paused_for_gamepad == Some(controller_index) {
+                                paused_for_gamepad = None;
+                                println!("Gamepad {controller_index} is back, resuming");
+                            }
+                        }
+                        GamepadEvent::Disconnected(controller_index) => println!("Gamepad {controller_index} disconnected"),
+                        GamepadEvent::BatteryLow(controller_index, level) => println!("Gamepad {controller_index} battery is {level:?}, plug it in soon"),
+                        GamepadEvent::ActiveDisconnected(controller_index) => {
+                            if PAUSE_ON_ACTIVE_GAMEPAD_LOSS {
+                                paused_for_gamepad = Some(controller_index);
+                                println!("Paused until gamepad {controller_index} is reconnected or the keyboard takes over");
+                            }
+                        }
+                    }
+                }
+                for (controller_index, gamepad) in gamepads.iter_mut().enumerate().filter(|(_, gamepad)| gamepad.connected) {
+                    // test rumble, triggers drive the motors directly, X and the right stick click play effects on top
+                    gamepad.vibration = GamepadVibration {
+                        low_frequency: gamepad.triggers.left,
+                        high_frequency: gamepad.triggers.right,
+                    };
+                    if gamepad.buttons.x.was_pressed() {
+                        rumble.play(controller_index, RumbleEffect::impact());
+                    }
+                    if gamepad.buttons.right_thumb.was_pressed() {
+                        rumble.play(controller_index, RumbleEffect::pulse(3, 0.25));
+                    }
+                }
+                let now = Instant::now();
+                rumble.tick((now - last_frame).as_secs_f32(), &mut gamepads);
+                last_frame = now;
+                write_controller_vibration(&mut gamepad_backends, &gamepads);
+
+                let keyboard = {
+                    let mut keyboard = GLOBAL_KEYBOARD.get_or_init(|| Mutex::new(KeyboardState::default())).lock().expect("Keyboard state lock poisoned");
+                    let snapshot = *keyboard;
+                    keyboard.begin_frame();
+                    snapshot
+                };
+                let mouse = {
+                    let mut mouse = GLOBAL_MOUSE.get_or_init(|| Mutex::new(MouseState::default())).lock().expect("Mouse state lock poisoned");
+                    let snapshot = *mouse;
+                    mouse.begin_frame();
+                    snapshot
+                };
+                let touch = {
+                    let mut touch = GLOBAL_TOUCH.get_or_init(|| Mutex::new(TouchState::default())).lock().expect("Touch state lock poisoned");
+                    let snapshot = *touch;
+                    touch.begin_frame();
+                    snapshot
+                };
+                let text_input = {
+                    let mut text_input = GLOBAL_TEXT_INPUT.get_or_init(|| Mutex::new(TextInputState::default())).lock().expect("Text input lock poisoned");
+                    TextInputState {
+                        text: std::mem::take(&mut text_input.text),
+                        pending_high_surrogate: None,
+                    }
+                };
+                let mut input = GameInput {
+                    time: start_time.elapsed(),
+                    keyboard,
+                    mouse,
+                    touch,
+                    gamepads,
+                    text_input,
+                };
+                if let Some(script) = input_script.as_mut()
+                    && !script.apply(&mut input)
+                {
+                    GLOBAL_RUNNING = false;
+                }
+
+                let input_pressed = ActiveDevice::from_input(&input);
+
+                if let Some(device) = input_pressed {
+                    active_device = Some(device);
+                    if device == ActiveDevice::KeyboardMouse && paused_for_gamepad.take().is_some() {
+                        println!("Keyboard took over, resuming");
+                    }
+                }
+
+                // platform hotkeys always come from the live keyboard, even during playback
+                if input.keyboard.was_pressed(VK_ESCAPE) {
+                    // escape gives the cursor back first, and only quits when it isn't captured
+                    if input.mouse.captured {
+                        win32_set_mouse_captured(window, false);
+                    } else {
+                        GLOBAL_RUNNING = false;
+                    }
+                }
+                // ctrl+F1..F5 belong to the game's scene switching
+                let ctrl = input.keyboard.is_down(VK_CONTROL);
+                if input.keyboard.was_pressed(VK_F2) && !ctrl {
+                    win32_set_mouse_captured(window, true);
+                }
+                if input.mouse.captured {
+                    win32_update_mouse_capture(window);
+                }
+                if input.keyboard.was_pressed(VK_F5) && !ctrl {
+                    // shows what the movement keys are called on the current layout
+                    for action in [Action::MoveUp, Action::MoveLeft, Action::MoveDown, Action::MoveRight] {
+                        let labels: Vec<String> = game_state
+                            .bindings
+                            .bindings_for(action)
+                            .filter_map(|binding| match binding {
+                                Binding::Key(vk_code) => Some(platform_key_label(Scancode::from_virtual_key(VIRTUAL_KEY(vk_code)))),
+                                Binding::Scancode(scancode) => Some(platform_key_label(Scancode(scancode))),
+                                _ => None,
+                            })
+                            .collect();
+                        println!("{action:?}: {}", labels.join(", "));
+                    }
+                }
+                if input.keyboard.was_pressed(VK_F6) {
+                    gamepad_settings.left_stick_curve = match gamepad_settings.left_stick_curve {
+                        StickCurve::Linear => StickCurve::Squared,
+                        StickCurve::Squared => StickCurve::Exponent(3.0),
+                        StickCurve::Exponent(_) => StickCurve::Linear,
+                    };
+                    println!("Left stick curve: {:?}", gamepad_settings.left_stick_curve);
+                }
+                if input.keyboard.was_pressed(VK_F7) && input.keyboard.is_down(VK_SHIFT) {
+                    if renderer.set_vsync(vsync.next()) {
+                        vsync = vsync.next();
+                        println!("VSync: {vsync:?}");
+                    } else {
+                        println!("VSync needs --d3d11 or --opengl");
+                    }
+                } else if input.keyboard.was_pressed(VK_F7) {
+                    let smooth = !GLOBAL_SMOOTH_PRESENT.fetch_xor(true, Ordering::Relaxed);
+                    println!("Smooth present: {smooth}");
+                }
+                if input.keyboard.was_pressed(VK_F9) && input.keyboard.is_down(VK_SHIFT) {
+                    println!("Sound muted: {}", game_state.audio.toggle_mute());
+                } else if input.keyboard.was_pressed(VK_F9) {
+                    render_simd::set_gamma_correct(!render_simd::gamma_correct());
+                    println!("Gamma correct blending: {}", render_simd::gamma_correct());
+                }
+                if input.keyboard.was_pressed(VK_F11) {
+                    crt.enabled = !crt.enabled;
+                    println!("CRT effect: {}", crt.enabled);
+                }
+                if input.keyboard.was_pressed(VK_F12) && input.keyboard.is_down(VK_SHIFT) {
+                    // SDR always works, so going round ends up back there at worst
+                    let mut mode = output_mode.next();
+                    while mode != OutputMode::Sdr && !renderer.set_output_mode(mode) {
+                        mode = mode.next();
+                    }
+                    if mode == OutputMode::Sdr {
+                        renderer.set_output_mode(mode);
+                    }
+                    output_mode = mode;
+                    println!("Output: {output_mode:?}");
+                } else if input.keyboard.was_pressed(VK_F12) {
+                    look = (look + 1) % (looks.len() + 1);
+                    grade.set_lut(look.checked_sub(1).map(|index| looks[index].clone()));
+                    println!("Color grade: {}", if look == 0 { "none".to_string() } else { look.to_string() });
+                }
+                if input.keyboard.was_pressed(VK_F8) {
+                    measure_latency = !measure_latency;
+                    println!("Latency measurement: {measure_latency}");
+                }
+                if input.keyboard.was_pressed(VK_F4) && !ctrl {
+                    gamepad_settings.stick_to_dpad = !gamepad_settings.stick_to_dpad;
+                    println!("Left stick as dpad: {}", gamepad_settings.stick_to_dpad);
+                }
+                if input.keyboard.was_pressed(VK_F3) && !ctrl {
+                    // cycle through the cursor api: system cursors, a generated custom one, then hidden
+                    cursor_style = (cursor_style + 1) % 6;
+                    let result = match cursor_style {
+                        0 => platform_set_system_cursor(window, SystemCursor::Arrow),
+                        1 => platform_set_system_cursor(window, SystemCursor::Crosshair),
+                        2 => platform_set_system_cursor(window, SystemCursor::Hand),
+                        3 => platform_set_system_cursor(window, SystemCursor::TextBeam),
+                        4 => {
+                            // 16x16 ring with a transparent background
+                            let pixels: Vec<u32> = (0..16 * 16)
+                                .map(|index| {
+                                    let (x, y) = ((index % 16) as f32 - 7.5, (index / 16) as f32 - 7.5);
+                                    let distance = (x * x + y * y).sqrt();
+                                    if (5.0..7.5).contains(&distance) { pack_rgba8(0xFF, 0x80, 0x00, 0xFF) } else { 0 }
+                                })
+                                .collect();
+                            platform_set_cursor_from_bitmap(window, &pixels, 16, 16, 8, 8)
+                        }
+                        _ => Ok(()),
+                    };
+                    platform_show_cursor(window, cursor_style != 5);
+                    if let Err(error) = result {
+                        println!("Failed to set cursor: {error}");
+                    }
+                }
+                if input.keyboard.was_pressed(VK_L) {
+                    match recorder.toggle(&mut game_state) {
+                        Ok(()) => println!("Input recording: {:?}", recorder.mode()),
+                        Err(error) => println!("Input recording failed: {error}"),
+                    }
+                }
+                let recording_result = match recorder.mode() {
+                    RecordingMode::Idle => Ok(()),
+                    RecordingMode::Recording => recorder.record(&input),
+                    RecordingMode::PlayingBack => recorder.playback(&mut input, &mut game_state),
+                };
+                if let Err(error) = recording_result {
+                    println!("Input recording failed: {error}");
+                }
+                // stand-in for the debug console, typed lines are echoed on enter
+                for line in input.text_input.apply_to_line(&mut console_line) {
+                    println!("> {line}");
+                }
+                // ctrl+c copies the line being typed, ctrl+v types the clipboard into it
+                let ctrl_down = input.keyboard.is_down(VK_CONTROL);
+                if ctrl_down
+                    && input.keyboard.was_pressed(VK_C)
+                    && let Err(error) = platform_set_clipboard_text(window, &console_line)
+                {
+                    println!("Failed to copy to clipboard: {error}");
+                }
+                if ctrl_down
+                    && input.keyboard.was_pressed(VK_V)
+                    && let Some(text) = platform_get_clipboard_text(window)
+                {
+                    let pasted = TextInputState { text, pending_high_surrogate: None };
+                    for line in pasted.apply_to_line(&mut console_line) {
+                        println!("> {line}");
+                    }
+                }
+
+                let swap_chain = &mut *GLOBAL_SWAP_CHAIN;
+                let (mut frame, previous_frame) = swap_chain.frames();
+                renderer.begin_frame(&mut render_group, frame.width(), frame.height());
+                // a backend with post passes runs the crt as it presents, off the frame, which then stays clean
+                let cpu_crt = crt.enabled && !renderer.runs_post_passes();
+                if let Some((indexed, palette)) = &mut palette_mode {
+                    // the frame is the indexed buffer expanded, the render group only draws the overlay over it.
+                    // pausing eases the palette halfway to black instead of putting up the sign
+                    palette.cycle(0..=255, 1);
+                    let fade_target = if paused_for_gamepad.is_some() { 0.5 } else { 0.0 };
+                    palette_fade += (fade_target - palette_fade).clamp(-0.05, 0.05);
+                    indexed.expand_into(&palette.faded(Color::BLACK, palette_fade), &mut frame);
+                } else if paused_for_gamepad.is_some() {
+                    // the last frame stays up with a pause sign over it, messages and input keep flowing.
+                    // the back buffer holds an older frame, so the one on screen is copied in to draw over,
+                    // from before the crt pass if it ran so the effect doesn't stack up frame after frame
+                    let restored = (cpu_crt && crt.restore(&mut frame)) || renderer.read_back(&mut frame);
+                    let (width, row_length) = (frame.width() as usize, frame.pitch() as usize / size_of::<Pixel>());
+                    if !restored && !frame.is_empty() && previous_frame.len() == row_length * frame.height() as usize {
+                        for (dest, row) in frame.rows_mut().zip(previous_frame.chunks_exact(row_length)) {
+                            dest.copy_from_slice(&row[..width]);
+                        }
+                    }
+                    render_pause_sign(&mut render_group);
+                    if let Some(font) = &debug_font {
+                        let caption = TextLayout { align: Align::Center, ..TextLayout::default() }.layout(font, "Paused\nreconnect the controller to carry on");
+                        let x = (render_group.width as f32 - caption.width) / 2.0;
+                        render_group.push_text_block(font, &caption, x, render_group.height as f32 / 2.0 + 64.0, Color::WHITE);
+                    }
+                } else {
+                    game_state.audio.update();
+                    game_update_and_render(&mut game_state, &input, &mut render_group);
+                }
+                push_debug_shapes(&mut render_group, Some(game_state.camera), debug_font.as_ref());
+                if let Some(font) = &debug_font
+                    && !console_line.is_empty()
+                {
+                    // long lines wrap and the panel grows upwards to fit them
+                    let layout = TextLayout { max_width: Some(render_group.width as f32 - 16.0), ..TextLayout::default() };
+                    let block = layout.layout(font, &format!("> {console_line}"));
+                    let y = render_group.height as f32 - block.height - 8.0;
+                    // a panel fading up from the bottom keeps the line readable over anything
+                    let panel = Gradient::Vertical(Color::rgba8(0, 0, 0, 0x40), Color::rgba8(0, 0, 0, 0xC0));
+                    render_group.push_gradient_rect(0.0, y - 8.0, render_group.width as f32, render_group.height as f32, panel);
+                    render_group.push_text_block(font, &block, 8.0, y, Color::WHITE);
+                }
+                // which of the render toggles are on, up in the top right corner
+                let toggles = [(render_simd::gamma_correct(), "F9 gamma correct"), (crt.enabled, "F11 crt")];
+                if let Some(font) = &debug_font
+                    && toggles.iter().any(|(on, _)| *on)
+                {
+                    let status: Vec<&str> = toggles.iter().filter(|(on, _)| *on).map(|(_, name)| *name).collect();
+                    let block = TextLayout { align: Align::Right, line_spacing: 1.2, ..TextLayout::default() }.layout(font, &status.join("\n"));
+                    render_group.push_text_block(font, &block, render_group.width as f32 - block.width - 8.0, 8.0, Color::rgb8(0xFF, 0xFF, 0x80));
+                }
+                if measure_latency {
+                    // the corner goes white on the frame that responds to a press,
+                    // filming it next to the pad with a high speed camera gives input to photon time
+                    let color = if input_pressed.is_some() { Color::WHITE } else { Color::BLACK };
+                    render_group.push_rect(0.0, 0.0, 64.0, 64.0, color);
+                }
+                if crt.enabled && !cpu_crt {
+                    render_group.post_passes.push(PostPass::Crt);
+                }
+                // all tiles are done by the time this returns, so the present below sees the whole frame
+                // the gpu blends in gamma space and the cpu post passes need the whole picture
+                let software_only = cpu_crt || grade.is_active(render_group.flash) || render_simd::gamma_correct();
+                renderer.execute(&mut render_group, &mut frame, &render_queue, software_only);
+                // a paused frame is the last one again, which was graded already
+                let regraded = paused_for_gamepad.is_none() && grade.apply(&mut frame, &render_queue, render_group.flash);
+                if !crt.enabled || cpu_crt {
+                    crt.apply(&mut frame, &render_queue);
+                }
+                swap_chain.swap();
+
+                let dimension = win32_get_window_dimension(window).expect("Failed GetRect from windows");
+                // the crt curve moves pixels across tiles, a changed tile can show up anywhere, and so can a change of grade
+                renderer.present(swap_chain.front(), dimension.width, dimension.height, cpu_crt || regraded);
+                // a test tone for now, 128 to 384Hz on the first pad's left stick. The audio thread mixes and plays it
+                let stick_y = input.gamepads.iter().find(|gamepad| gamepad.connected).map_or(0.0, |gamepad| gamepad.sticks.left_y);
+                game_state.audio.set_tone(256.0 + 128.0 * stick_y, TONE_VOLUME);
+                if measure_latency {
+                    let latency = polled_at.elapsed();
+                    let marker = if input_pressed.is_some() { " (press)" } else { "" };
+                    println!("Input to present: {:.2}ms{marker}", latency.as_secs_f64() * 1000.0);
+                }
             }
         }
     }
@@ -0,0 +1,50 @@
+use std::ops::{Add, Mul, Sub};
+
+/// 2d vector for positions and basis axes in pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct V2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl V2 {
+    pub const fn new(x: f32, y: f32) -> Self {
+        V2 { x, y }
+    }
+
+    pub fn dot(self, other: V2) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+    /// Rotated by angle radians, clockwise on screen since y points down
+    pub fn rotate(self, angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        V2::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+    }
+
+    /// Rotated a quarter turn, clockwise on screen since y points down
+    pub fn perp(self) -> Self {
+        V2::new(-self.y, self.x)
+    }
+}
+
+impl Add for V2 {
+    type Output = V2;
+    fn add(self, other: V2) -> V2 {
+        V2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for V2 {
+    type Output = V2;
+    fn sub(self, other: V2) -> V2 {
+        V2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Mul<f32> for V2 {
+    type Output = V2;
+    fn mul(self, scale: f32) -> V2 {
+        V2::new(self.x * scale, self.y * scale)
+    }
+}
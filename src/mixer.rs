@@ -0,0 +1,296 @@
+//! Plays any number of sounds at once. Lives on the audio thread, which starts and stops voices as the game's commands
+//! come in and mixes whatever's playing into the samples it sends out.
+
+use std::{f32::consts::FRAC_PI_4, fs, io, ops::Range, path::Path, sync::Arc};
+
+use crate::{audio_thread::SoundHandle, music::MusicStream};
+
+// how long a change of volume takes to slide over, long enough not to click and short enough to feel instant
+const RAMP_SECONDS: f32 = 0.02;
+
+/// Sound data the mixer plays from, 16 bit stereo interleaved left then right
+#[derive(Debug)]
+pub struct Sound {
+    pub samples: Vec<i16>,
+    pub samples_per_second: u32,
+    /// Where a looping play goes round, in stereo samples, the end not included. None loops the whole sound
+    pub loop_points: Option<Range<usize>>,
+}
+
+impl Sound {
+    /// seconds of f(time in seconds), -1..1, in both channels
+    pub fn from_fn(samples_per_second: u32, seconds: f32, f: impl Fn(f32) -> f32) -> Self {
+        let count = (seconds * samples_per_second as f32) as usize;
+        let samples = (0..count)
+            .flat_map(|index| {
+                let value = (f(index as f32 / samples_per_second as f32).clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                [value, value]
+            })
+            .collect();
+        Sound { samples, samples_per_second, loop_points: None }
+    }
+
+    /// A WAV file, the loop points with it
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse_wav(&fs::read(path)?)
+    }
+
+    /// How many stereo samples long
+    pub fn length(&self) -> usize {
+        self.samples.len() / 2
+    }
+
+    // left and right part way between two stereo samples, in a straight line from one to the other. Silence past the
+    // end, and the loop's start after the loop's end so going round is as smooth as anywhere else
+    fn sample_at(&self, position: f64, looping: Option<&Range<usize>>) -> [f32; 2] {
+        let index = position as usize;
+        let fraction = (position - index as f64) as f32;
+        let frame = |index: usize| self.samples.get(index * 2..index * 2 + 2).map_or([0.0; 2], |frame| [frame[0] as f32, frame[1] as f32]);
+        let next = match looping {
+            Some(looping) if index + 1 == looping.end => looping.start,
+            _ => index + 1,
+        };
+        let (from, to) = (frame(index), frame(next));
+        [from[0] + (to[0] - from[0]) * fraction, from[1] + (to[1] - from[1]) * fraction]
+    }
+}
+
+#[derive(Debug)]
+struct Voice {
+    handle: SoundHandle,
+    sound: Arc<Sound>,
+    // in the sound's stereo samples, fractional when the pitch or rates don't line up
+    position: f64,
+    volume: Ramp,
+    // -1 all the way left to 1 all the way right
+    pan: Ramp,
+    // left and right on top of the volume and pan
+    channel_volumes: [Ramp; 2],
+    pitch: f32,
+    // goes round these instead of ending
+    looping: Option<Range<usize>>,
+}
+
+/// Constant power: left and right on a quarter circle, so a sound keeps the same loudness wherever it's panned
+/// instead of dipping in the middle. Scaled so the middle leaves both channels as they are
+fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    [angle.cos() * std::f32::consts::SQRT_2, angle.sin() * std::f32::consts::SQRT_2]
+}
+
+/// A volume that slides to a new target in a straight line over RAMP_SECONDS, instead of jumping there and clicking
+#[derive(Clone, Copy, Debug)]
+struct Ramp {
+    current: f32,
+    // where the slide started from and how far along it is, 0..1
+    start: f32,
+    target: f32,
+    progress: f32,
+}
+
+impl Ramp {
+    fn new(volume: f32) -> Self {
+        Ramp { current: volume, start: volume, target: volume, progress: 1.0 }
+    }
+
+    // straight there when nothing's been heard yet, there's no click to avoid
+    fn set(&mut self, target: f32, slide: bool) {
+        *self = if slide { Ramp { current: self.current, start: self.current, target, progress: 0.0 } } else { Ramp::new(target) };
+    }
+
+    // the volume for one sample, step being a sample's share of the ramp
+    fn next(&mut self, step: f32) -> f32 {
+        if self.progress < 1.0 {
+            self.progress = (self.progress + step).min(1.0);
+            self.current = self.start + (self.target - self.start) * self.progress;
+        }
+        self.current
+    }
+}
+
+#[derive(Debug)]
+pub struct Mixer {
+    voices: Vec<Voice>,
+    // the mix before it's clipped down to 16 bits
+    scratch: Vec<f32>,
+    // streamed music plays on a voice of its own
+    music: Option<MusicVoice>,
+    // voices that got to their end since the game was last told
+    finished: Vec<SoundHandle>,
+    // over the whole mix, 0 while muted, with the volume to go back to when unmuted
+    master: Ramp,
+    master_volume: f32,
+    muted: bool,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Mixer {
+            voices: Vec::new(),
+            scratch: Vec::new(),
+            music: None,
+            finished: Vec::new(),
+            master: Ramp::new(1.0),
+            master_volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MusicVoice {
+    stream: MusicStream,
+    volume: Ramp,
+    // fraction of the way from the stream's last sample to its next, when its rate isn't the output's
+    position: f64,
+    samples: [[i16; 2]; 2],
+}
+
+impl Mixer {
+    /// Starts sound from the beginning as handle. volume 1 is as recorded, pitch 1 is as recorded, 2 an octave up and
+    /// twice as fast, anything in between blends neighbouring samples. With looping it never ends, going back to the
+    /// loop's start from its end until it's stopped
+    pub fn play(&mut self, handle: SoundHandle, sound: Arc<Sound>, volume: f32, pitch: f32, looping: Option<Range<usize>>) {
+        let looping = looping.map(|looping| looping.start..looping.end.min(sound.length())).filter(|looping| looping.start < looping.end);
+        self.voices.push(Voice {
+            handle,
+            sound,
+            position: 0.0,
+            volume: Ramp::new(volume),
+            pan: Ramp::new(0.0),
+            channel_volumes: [Ramp::new(1.0); 2],
+            pitch: pitch.max(0.0),
+            looping,
+        });
+    }
+
+    /// Plays a music stream in place of any already playing
+    pub fn play_music(&mut self, stream: MusicStream, volume: f32) {
+        self.music = Some(MusicVoice { stream, volume: Ramp::new(volume), position: 1.0, samples: [[0; 2]; 2] });
+    }
+
+    /// Nothing when handle's voice already finished
+    pub fn stop(&mut self, handle: SoundHandle) {
+        self.voices.retain(|voice| voice.handle != handle);
+    }
+
+    pub fn stop_all(&mut self) {
+        self.voices.clear();
+    }
+
+    pub fn set_volume(&mut self, handle: SoundHandle, volume: f32) {
+        if let Some(voice) = self.voices.iter_mut().find(|voice| voice.handle == handle) {
+            voice.volume.set(volume, voice.position > 0.0);
+        }
+    }
+
+    /// -1 all the way left to 1 all the way right, 0 in the middle
+    pub fn set_pan(&mut self, handle: SoundHandle, pan: f32) {
+        if let Some(voice) = self.voices.iter_mut().find(|voice| voice.handle == handle) {
+            voice.pan.set(pan, voice.position > 0.0);
+        }
+    }
+
+    /// Left and right volumes of their own, on top of the volume and pan
+    pub fn set_channel_volumes(&mut self, handle: SoundHandle, left: f32, right: f32) {
+        if let Some(voice) = self.voices.iter_mut().find(|voice| voice.handle == handle) {
+            voice.channel_volumes[0].set(left, voice.position > 0.0);
+            voice.channel_volumes[1].set(right, voice.position > 0.0);
+        }
+    }
+
+    /// Changes how fast the sound plays from here on, and so its pitch. 0 holds it where it is
+    pub fn set_pitch(&mut self, handle: SoundHandle, pitch: f32) {
+        if let Some(voice) = self.voices.iter_mut().find(|voice| voice.handle == handle) {
+            voice.pitch = pitch.max(0.0);
+        }
+    }
+
+    /// Over everything, the samples mix is given included. 1 leaves the mix as it is
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume;
+        if !self.muted {
+            self.master.set(volume, true);
+        }
+    }
+
+    /// Fades everything out, and back in to the master volume
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.master.set(if muted { 0.0 } else { self.master_volume }, true);
+    }
+
+    /// The voices that got to their end in the mixes since the last call
+    pub fn take_finished(&mut self) -> std::vec::Drain<'_, SoundHandle> {
+        self.finished.drain(..)
+    }
+
+    /// Adds everything playing onto interleaved left, right samples at samples_per_second, clipping what goes past
+    /// the 16 bit range, and moves the voices on. Voices that reach their end are done, and handed to take_finished
+    pub fn mix(&mut self, samples: &mut [i16], samples_per_second: u32) {
+        self.scratch.clear();
+        self.scratch.extend(samples.iter().map(|&sample| sample as f32));
+        let ramp_step = 1.0 / (RAMP_SECONDS * samples_per_second as f32);
+        for voice in &mut self.voices {
+            let step = voice.pitch as f64 * voice.sound.samples_per_second as f64 / samples_per_second as f64;
+            let length = voice.sound.length();
+            for frame in self.scratch.chunks_exact_mut(2) {
+                if voice.position as usize >= length {
+                    break;
+                }
+                let volume = voice.volume.next(ramp_step);
+                let pan = pan_gains(voice.pan.next(ramp_step));
+                let [left, right] = voice.sound.sample_at(voice.position, voice.looping.as_ref());
+                frame[0] += left * volume * pan[0] * voice.channel_volumes[0].next(ramp_step);
+                frame[1] += right * volume * pan[1] * voice.channel_volumes[1].next(ramp_step);
+                voice.position += step;
+                if let Some(looping) = &voice.looping
+                    && voice.position >= looping.end as f64
+                {
+                    // back by whole loops, keeping the fraction, so the wrap lands between samples like any other step
+                    let (start, loop_length) = (looping.start as f64, (looping.end - looping.start) as f64);
+                    voice.position = start + (voice.position - start) % loop_length;
+                }
+            }
+        }
+        self.voices.retain(|voice| {
+            let playing = (voice.position as usize) < voice.sound.length();
+            if !playing {
+                self.finished.push(voice.handle);
+            }
+            playing
+        });
+        if let Some(music) = &mut self.music {
+            let stream = &mut music.stream;
+            let step = stream.samples_per_second as f64 / samples_per_second as f64;
+            let mut finished = false;
+            for frame in self.scratch.chunks_exact_mut(2) {
+                while music.position >= 1.0 {
+                    music.position -= 1.0;
+                    match stream.next_sample() {
+                        Some(sample) => music.samples = [music.samples[1], sample],
+                        None => finished = true,
+                    }
+                }
+                if finished {
+                    break;
+                }
+                let volume = music.volume.next(ramp_step);
+                let [from, to] = music.samples;
+                for ((mixed, from), to) in frame.iter_mut().zip(from).zip(to) {
+                    *mixed += (from as f32 + (to as f32 - from as f32) * music.position as f32) * volume;
+                }
+                music.position += step;
+            }
+            if finished {
+                self.music = None;
+            }
+        }
+        for (frame, mixed) in samples.chunks_exact_mut(2).zip(self.scratch.chunks_exact(2)) {
+            let master = self.master.next(ramp_step);
+            for (sample, mixed) in frame.iter_mut().zip(mixed) {
+                *sample = (mixed * master).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+    }
+}
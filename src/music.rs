@@ -0,0 +1,106 @@
+//! Music streamed off disk. A background thread decodes an Ogg Vorbis file a little ahead of where it's playing and
+//! hands the mixer chunks of 16 bit stereo, so a song never has to be in memory whole.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError},
+    thread,
+};
+
+use crate::vorbis::VorbisDecoder;
+
+// stereo samples per chunk, and how many chunks the thread decodes ahead. 4 of 4096 is about a third of a second at
+// 48kHz, plenty to ride out a slow frame without holding much
+const CHUNK_LENGTH: usize = 4096;
+const CHUNKS_AHEAD: usize = 4;
+
+#[derive(Debug)]
+pub struct MusicStream {
+    pub samples_per_second: u32,
+    chunks: Receiver<Vec<i16>>,
+    // the chunk being played from, interleaved left, right
+    chunk: Vec<i16>,
+    // into chunk, in stereo samples
+    position: usize,
+    finished: bool,
+}
+
+impl MusicStream {
+    /// Reads the file's headers here so a bad file fails straight away, then decodes the rest on its own thread,
+    /// starting over from the top at the end when looping
+    pub fn open(path: &str, looping: bool) -> io::Result<Self> {
+        let decoder = VorbisDecoder::new(BufReader::new(File::open(path)?))?;
+        let samples_per_second = decoder.samples_per_second;
+        let (sender, chunks) = sync_channel(CHUNKS_AHEAD);
+        let path = path.to_string();
+        thread::Builder::new().name("music".into()).spawn(move || {
+            let mut decoder = decoder;
+            loop {
+                match decode(&mut decoder, &sender) {
+                    // the stream was dropped
+                    Ok(false) => return,
+                    Ok(true) if looping => {}
+                    Ok(true) => return,
+                    Err(error) => {
+                        println!("Music {path} stopped: {error}");
+                        return;
+                    }
+                }
+                decoder = match File::open(&path).and_then(|file| VorbisDecoder::new(BufReader::new(file))) {
+                    Ok(decoder) => decoder,
+                    Err(error) => {
+                        println!("Music {path} stopped: {error}");
+                        return;
+                    }
+                };
+            }
+        })?;
+        Ok(MusicStream { samples_per_second, chunks, chunk: Vec::new(), position: 0, finished: false })
+    }
+
+    /// The next stereo sample, silence when the thread hasn't kept up. None once the song is over for good
+    pub fn next_sample(&mut self) -> Option<[i16; 2]> {
+        while self.position * 2 >= self.chunk.len() {
+            if self.finished {
+                return None;
+            }
+            match self.chunks.try_recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(TryRecvError::Empty) => return Some([0, 0]),
+                Err(TryRecvError::Disconnected) => self.finished = true,
+            }
+        }
+        let sample = [self.chunk[self.position * 2], self.chunk[self.position * 2 + 1]];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+// decodes the whole stream into chunks, true when it got to the end and false when nobody's listening any more
+fn decode(decoder: &mut VorbisDecoder<BufReader<File>>, sender: &SyncSender<Vec<i16>>) -> io::Result<bool> {
+    // the usual channel orders put right second, except 3, 5 and 6 channels, which have centre there
+    let right = match decoder.channels {
+        1 => 0,
+        3 | 5 | 6 => 2,
+        _ => 1,
+    };
+    let mut chunk = Vec::with_capacity(CHUNK_LENGTH * 2);
+    while let Some(samples) = decoder.next_samples()? {
+        for (left, right) in samples[0].iter().zip(&samples[right]) {
+            chunk.extend([left, right].map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16));
+            if chunk.len() == CHUNK_LENGTH * 2
+                && sender.send(std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_LENGTH * 2))).is_err()
+            {
+                return Ok(false);
+            }
+        }
+    }
+    if !chunk.is_empty() && sender.send(chunk).is_err() {
+        return Ok(false);
+    }
+    Ok(true)
+}
@@ -0,0 +1,189 @@
+//! Ogg container reading (RFC 3533), just enough to pull the packets of the first logical stream out of a file a
+//! page at a time, so a long stream never has to be in memory all at once. Page checksums aren't checked.
+
+use std::io::{self, Read};
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Bad ogg stream: {message}"))
+}
+
+// header_type bits
+const CONTINUED: u8 = 1;
+const END_OF_STREAM: u8 = 4;
+
+/// One packet, with the granule position of the page it finished on when it was the last to finish there
+pub struct OggPacket {
+    pub data: Vec<u8>,
+    pub granule: Option<u64>,
+    /// Set on the stream's last packet
+    pub last: bool,
+}
+
+pub struct OggReader<R: Read> {
+    reader: R,
+    serial: Option<u32>,
+    // the page being read: its lacing values not used up yet, its body and how far into it packets have been taken
+    lacing: Vec<u8>,
+    lacing_used: usize,
+    body: Vec<u8>,
+    body_used: usize,
+    granule: u64,
+    end_of_stream: bool,
+    // a packet carried over from an earlier page
+    partial: Vec<u8>,
+}
+
+impl<R: Read> OggReader<R> {
+    pub fn new(reader: R) -> Self {
+        OggReader {
+            reader,
+            serial: None,
+            lacing: Vec::new(),
+            lacing_used: 0,
+            body: Vec::new(),
+            body_used: 0,
+            granule: 0,
+            end_of_stream: false,
+            partial: Vec::new(),
+        }
+    }
+
+    /// The next whole packet, None once the stream has ended
+    pub fn next_packet(&mut self) -> io::Result<Option<OggPacket>> {
+        loop {
+            if self.lacing_used == self.lacing.len() {
+                if self.end_of_stream || !self.read_page()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+            // a packet is a run of 255 byte segments ended by a shorter one, which can be on a later page
+            let mut size = 0;
+            let mut finished = false;
+            while self.lacing_used < self.lacing.len() {
+                let lacing = self.lacing[self.lacing_used];
+                self.lacing_used += 1;
+                size += lacing as usize;
+                if lacing < 255 {
+                    finished = true;
+                    break;
+                }
+            }
+            let end = self.body_used + size;
+            let segment = self.body.get(self.body_used..end).ok_or_else(|| invalid("page body is shorter than its lacing"))?;
+            self.partial.extend_from_slice(segment);
+            self.body_used = end;
+            if finished {
+                let last_on_page = !self.lacing[self.lacing_used..].iter().any(|lacing| *lacing < 255);
+                return Ok(Some(OggPacket {
+                    data: std::mem::take(&mut self.partial),
+                    granule: last_on_page.then_some(self.granule),
+                    last: last_on_page && self.end_of_stream,
+                }));
+            }
+        }
+    }
+
+    // false at the end of the file. Pages of other logical streams are skipped over
+    fn read_page(&mut self) -> io::Result<bool> {
+        loop {
+            let mut header = [0u8; 27];
+            match self.reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(error) => return Err(error),
+            }
+            if &header[..4] != b"OggS" || header[4] != 0 {
+                return Err(invalid("missing page header"));
+            }
+            let header_type = header[5];
+            let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+            let serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+            let mut lacing = vec![0u8; header[26] as usize];
+            self.reader.read_exact(&mut lacing)?;
+            let mut body = vec![0u8; lacing.iter().map(|lacing| *lacing as usize).sum()];
+            self.reader.read_exact(&mut body)?;
+            if *self.serial.get_or_insert(serial) != serial {
+                continue;
+            }
+            if header_type & CONTINUED == 0 {
+                // whatever was carried over never got finished, it can't be part of this page's first packet
+                self.partial.clear();
+            }
+            self.lacing = lacing;
+            self.lacing_used = 0;
+            self.body = body;
+            self.body_used = 0;
+            self.granule = granule;
+            self.end_of_stream = header_type & END_OF_STREAM != 0;
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(serial: u32, sequence: u32, header_type: u8, granule: u64, lacing: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend([0, header_type]);
+        page.extend(granule.to_le_bytes());
+        page.extend(serial.to_le_bytes());
+        page.extend(sequence.to_le_bytes());
+        page.extend([0; 4]);
+        page.push(lacing.len() as u8);
+        page.extend(lacing);
+        page.extend(body);
+        page
+    }
+
+    fn packets(stream: &[u8]) -> io::Result<Vec<OggPacket>> {
+        let mut reader = OggReader::new(stream);
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.next_packet()? {
+            packets.push(packet);
+        }
+        Ok(packets)
+    }
+
+    #[test]
+    fn joins_packets_across_pages_and_skips_other_streams() {
+        let long: Vec<u8> = (0..300).map(|index| index as u8).collect();
+        // the first page starts the stream, a page of another stream comes in between
+        let mut stream = page(7, 0, 2, u64::MAX, &[255], &long[..255]);
+        stream.extend(page(9, 0, 2, 0, &[3], &[1, 2, 3]));
+        let mut body = long[255..].to_vec();
+        body.extend([9; 10]);
+        stream.extend(page(7, 1, CONTINUED, 100, &[45, 10], &body));
+        stream.extend(page(7, 2, END_OF_STREAM, 150, &[5], &[5; 5]));
+
+        let packets = packets(&stream).unwrap();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0].data, long);
+        assert_eq!((packets[0].granule, packets[0].last), (None, false));
+        assert_eq!(packets[1].data, [9; 10]);
+        assert_eq!((packets[1].granule, packets[1].last), (Some(100), false));
+        assert_eq!(packets[2].data, [5; 5]);
+        assert_eq!((packets[2].granule, packets[2].last), (Some(150), true));
+    }
+
+    #[test]
+    fn drops_a_packet_the_next_page_doesnt_continue() {
+        let mut stream = page(7, 0, 2, 0, &[255], &[1; 255]);
+        stream.extend(page(7, 1, 0, 10, &[2], &[2, 2]));
+        let packets = packets(&stream).unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].data, [2, 2]);
+    }
+
+    #[test]
+    fn bad_and_truncated_pages_are_errors() {
+        let mut stream = page(7, 0, 2, 0, &[10], &[1; 10]);
+        stream[3] = b'T';
+        assert_eq!(packets(&stream).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        let stream = page(7, 0, 2, 0, &[10], &[1; 10]);
+        assert!(packets(&stream[..stream.len() - 3]).is_err());
+    }
+}
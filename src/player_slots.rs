@@ -0,0 +1,44 @@
+use crate::game_input::GamepadState;
+
+pub const MAX_PLAYERS: usize = 4;
+
+/// Logical player -> physical gamepad slot. Pads join by pressing start and players keep their number
+/// when a pad is unplugged, so re-plugging (even into a different slot) doesn't shuffle everyone around.
+#[derive(Clone, Default)]
+pub struct PlayerSlots {
+    players: [Option<usize>; MAX_PLAYERS],
+}
+
+impl PlayerSlots {
+    /// Hands out players to pads that pressed start this frame, returns the new (player, slot) pairs
+    pub fn update(&mut self, gamepads: &[GamepadState]) -> Vec<(usize, usize)> {
+        let mut claims = Vec::new();
+        for (slot, gamepad) in gamepads.iter().enumerate() {
+            if !gamepad.connected || !gamepad.buttons.start.was_pressed() || self.player_for_slot(slot).is_some() {
+                continue;
+            }
+
+            // a player whose pad went away first, that's most likely the same person re-plugging, otherwise an
+            // unclaimed player
+            let free_player = self
+                .players
+                .iter()
+                .position(|claimed| claimed.is_some_and(|claimed| !gamepads[claimed].connected))
+                .or_else(|| self.players.iter().position(Option::is_none));
+            if let Some(player) = free_player {
+                self.players[player] = Some(slot);
+                claims.push((player, slot));
+            }
+        }
+        claims
+    }
+
+    pub fn player_for_slot(&self, slot: usize) -> Option<usize> {
+        self.players.iter().position(|claimed| *claimed == Some(slot))
+    }
+
+    /// The pad a player claimed, still returned while unplugged (with connected false) so the player keeps its slot
+    pub fn gamepad<'a>(&self, player: usize, gamepads: &'a [GamepadState]) -> Option<&'a GamepadState> {
+        self.players.get(player).copied().flatten().and_then(|slot| gamepads.get(slot))
+    }
+}
@@ -0,0 +1,466 @@
+//! PNG decoding into Bitmaps. Every color type at 8 bits, 16 bit channels cut down to their high byte, and palette
+//! and greyscale images below 8 bits. Interlaced images are turned away, re-saving without interlacing fixes them.
+//! APNGs decode into Animations, each frame through the same path as a still image.
+
+use std::{io, time::Duration};
+
+use crate::{
+    animation::{Animation, Dispose, RawFrame},
+    bitmap::Bitmap,
+    color::{pack_rgba8, premultiply, Pixel},
+    inflate::zlib_decompress,
+};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Bad PNG: {message}"))
+}
+
+/// One chunk: its type and data, the crc is skipped since the zlib stream has its own check
+pub(crate) struct Chunk<'a> {
+    pub kind: [u8; 4],
+    pub data: &'a [u8],
+}
+
+pub(crate) fn chunks(bytes: &[u8]) -> io::Result<Vec<Chunk<'_>>> {
+    if bytes.get(..8) != Some(&SIGNATURE[..]) {
+        return Err(invalid("missing PNG signature"));
+    }
+    let mut chunks = Vec::new();
+    let mut rest = &bytes[8..];
+    while !rest.is_empty() {
+        let [l0, l1, l2, l3, k0, k1, k2, k3, ..] = *rest else {
+            return Err(invalid("truncated chunk header"));
+        };
+        let length = u32::from_be_bytes([l0, l1, l2, l3]) as usize;
+        let data = rest.get(8..8 + length).ok_or_else(|| invalid("truncated chunk"))?;
+        chunks.push(Chunk { kind: [k0, k1, k2, k3], data });
+        if &[k0, k1, k2, k3] == b"IEND" {
+            break;
+        }
+        rest = rest.get(12 + length..).unwrap_or(&[]);
+    }
+    Ok(chunks)
+}
+
+/// What IHDR, PLTE and tRNS say about how to turn the filtered bytes into pixels
+pub(crate) struct Header {
+    pub width: i32,
+    pub height: i32,
+    bit_depth: u8,
+    color_type: u8,
+    palette: Vec<Pixel>,
+    // greyscale or rgb value that means transparent, at the image's bit depth
+    transparent: Option<[u16; 3]>,
+}
+
+impl Header {
+    pub(crate) fn parse(chunks: &[Chunk]) -> io::Result<Self> {
+        let ihdr = chunks.first().filter(|chunk| &chunk.kind == b"IHDR" && chunk.data.len() >= 13).ok_or_else(|| invalid("IHDR isn't first"))?;
+        let data = ihdr.data;
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let (bit_depth, color_type, interlace) = (data[8], data[9], data[12]);
+        if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+            return Err(invalid("empty image"));
+        }
+        let supported = match color_type {
+            0 => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+            3 => matches!(bit_depth, 1 | 2 | 4 | 8),
+            2 | 4 | 6 => matches!(bit_depth, 8 | 16),
+            _ => false,
+        };
+        if !supported {
+            return Err(invalid(&format!("unsupported format (color type {color_type}, {bit_depth} bits)")));
+        }
+        if interlace != 0 {
+            return Err(invalid("interlaced images aren't supported"));
+        }
+
+        let mut palette = Vec::new();
+        let mut transparent = None;
+        for chunk in chunks {
+            match &chunk.kind {
+                b"PLTE" => palette = chunk.data.chunks_exact(3).map(|rgb| pack_rgba8(rgb[0], rgb[1], rgb[2], 255)).collect(),
+                // palette alphas in order, entries past the end stay opaque
+                b"tRNS" if color_type == 3 => {
+                    for (entry, alpha) in palette.iter_mut().zip(chunk.data) {
+                        *entry = (*entry & 0x00FFFFFF) | (*alpha as u32) << 24;
+                    }
+                }
+                b"tRNS" => {
+                    let sample = |index: usize| chunk.data.get(index * 2..index * 2 + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+                    transparent = match color_type {
+                        0 => sample(0).map(|grey| [grey; 3]),
+                        2 => sample(0).zip(sample(1)).zip(sample(2)).map(|((red, green), blue)| [red, green, blue]),
+                        _ => None,
+                    };
+                }
+                _ => {}
+            }
+        }
+        if color_type == 3 && palette.is_empty() {
+            return Err(invalid("palette image without a PLTE"));
+        }
+        Ok(Header { width: width as i32, height: height as i32, bit_depth, color_type, palette, transparent })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    /// Bytes in one row of a width pixels wide image, without the filter byte
+    fn row_bytes(&self, width: i32) -> usize {
+        (width as usize * self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+
+    /// Inflates and unfilters a width by height image's data and converts it to premultiplied pixels. APNG frames
+    /// come through here too, each with its own size
+    pub(crate) fn decode(&self, compressed: &[u8], width: i32, height: i32) -> io::Result<Vec<Pixel>> {
+        let data = zlib_decompress(compressed)?;
+        let row_bytes = self.row_bytes(width);
+        if data.len() < (row_bytes + 1) * height as usize {
+            return Err(invalid("not enough image data"));
+        }
+        // filters look at the byte one pixel back, which is the previous byte when pixels are under a byte
+        let pixel_bytes = (self.channels() * self.bit_depth as usize).div_ceil(8);
+        let mut previous = vec![0u8; row_bytes];
+        let mut row = vec![0u8; row_bytes];
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+        for filtered in data.chunks_exact(row_bytes + 1).take(height as usize) {
+            row.copy_from_slice(&filtered[1..]);
+            unfilter(filtered[0], &mut row, &previous, pixel_bytes)?;
+            pixels.extend((0..width as usize).map(|x| premultiply(self.pixel(&row, x))));
+            std::mem::swap(&mut row, &mut previous);
+        }
+        Ok(pixels)
+    }
+
+    fn pixel(&self, row: &[u8], x: usize) -> Pixel {
+        let depth = self.bit_depth as usize;
+        // channel i of pixel x at the image's depth
+        let sample = |channel: usize| -> u16 {
+            let index = x * self.channels() + channel;
+            match depth {
+                16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+                8 => row[index] as u16,
+                _ => {
+                    let bit = index * depth;
+                    ((row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1) as u8) as u16
+                }
+            }
+        };
+        // stretched to 8 bits: the high byte of 16 bit samples, low depths scaled up so the maximum is 255
+        let to_u8 = |value: u16| match depth {
+            16 => (value >> 8) as u8,
+            8 => value as u8,
+            _ => (value as u32 * 255 / ((1 << depth) - 1)) as u8,
+        };
+        let keyed = |samples: [u16; 3]| if self.transparent == Some(samples) { 0 } else { 255 };
+        match self.color_type {
+            0 => {
+                let grey = sample(0);
+                pack_rgba8(to_u8(grey), to_u8(grey), to_u8(grey), keyed([grey; 3]))
+            }
+            2 => {
+                let [red, green, blue] = [sample(0), sample(1), sample(2)];
+                pack_rgba8(to_u8(red), to_u8(green), to_u8(blue), keyed([red, green, blue]))
+            }
+            // out of range indices come out transparent black rather than failing the whole image
+            3 => self.palette.get(sample(0) as usize).copied().unwrap_or(0),
+            4 => {
+                let grey = to_u8(sample(0));
+                pack_rgba8(grey, grey, grey, to_u8(sample(1)))
+            }
+            _ => pack_rgba8(to_u8(sample(0)), to_u8(sample(1)), to_u8(sample(2)), to_u8(sample(3))),
+        }
+    }
+}
+
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], pixel_bytes: usize) -> io::Result<()> {
+    for index in 0..row.len() {
+        let left = if index >= pixel_bytes { row[index - pixel_bytes] } else { 0 };
+        let up = previous[index];
+        let up_left = if index >= pixel_bytes { previous[index - pixel_bytes] } else { 0 };
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return Err(invalid("unknown filter type")),
+        };
+        row[index] = row[index].wrapping_add(predicted);
+    }
+    Ok(())
+}
+
+/// Whichever of the three neighbours is closest to left + up - up_left
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) = ((estimate - left as i16).abs(), (estimate - up as i16).abs(), (estimate - up_left as i16).abs());
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+impl Bitmap {
+    pub fn parse_png(bytes: &[u8]) -> io::Result<Self> {
+        let chunks = chunks(bytes)?;
+        let header = Header::parse(&chunks)?;
+        let compressed: Vec<u8> = chunks.iter().filter(|chunk| &chunk.kind == b"IDAT").flat_map(|chunk| chunk.data.iter().copied()).collect();
+        let pixels = header.decode(&compressed, header.width, header.height)?;
+        Ok(Bitmap { width: header.width, height: header.height, pixels })
+    }
+}
+
+impl Animation {
+    /// An APNG's frames. A PNG without an acTL chunk is a still image and comes out as one frame
+    pub fn parse_apng(bytes: &[u8]) -> io::Result<Self> {
+        let chunks = chunks(bytes)?;
+        if !chunks.iter().any(|chunk| &chunk.kind == b"acTL") {
+            return Bitmap::parse_png(bytes).map(Animation::still);
+        }
+        let header = Header::parse(&chunks)?;
+        // every fcTL starts a frame, the IDATs are its data if they come after it (otherwise the default image isn't
+        // part of the animation) and fdATs are the same thing behind a sequence number
+        let mut controls: Vec<(&[u8], Vec<u8>)> = Vec::new();
+        for chunk in &chunks {
+            match (&chunk.kind, controls.last_mut()) {
+                (b"fcTL", _) => controls.push((chunk.data, Vec::new())),
+                (b"IDAT", Some((_, data))) => data.extend_from_slice(chunk.data),
+                (b"fdAT", Some((_, data))) => data.extend_from_slice(chunk.data.get(4..).unwrap_or(&[])),
+                _ => {}
+            }
+        }
+
+        let mut raw_frames = Vec::with_capacity(controls.len());
+        for (control, data) in controls {
+            if control.len() < 26 {
+                return Err(invalid("truncated fcTL"));
+            }
+            let [width, height, x, y] = [4, 8, 12, 16].map(|offset| u32::from_be_bytes([control[offset], control[offset + 1], control[offset + 2], control[offset + 3]]));
+            if width == 0 || height == 0 || x as u64 + width as u64 > header.width as u64 || y as u64 + height as u64 > header.height as u64 {
+                return Err(invalid("frame outside the canvas"));
+            }
+            let numerator = u16::from_be_bytes([control[20], control[21]]);
+            let denominator = u16::from_be_bytes([control[22], control[23]]);
+            // a denominator of 0 means hundredths of a second
+            let delay = Duration::from_secs_f64(numerator as f64 / if denominator == 0 { 100.0 } else { denominator as f64 });
+            let dispose = match control[24] {
+                1 => Dispose::Clear,
+                2 => Dispose::Previous,
+                _ => Dispose::Keep,
+            };
+            let (width, height) = (width as i32, height as i32);
+            let pixels = header.decode(&data, width, height)?;
+            raw_frames.push(RawFrame { x: x as i32, y: y as i32, width, height, pixels, blend: control[25] == 1, dispose, delay });
+        }
+        Animation::compose(header.width, header.height, raw_frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a zlib stream of one stored block, no compression to get wrong on this side
+    fn zlib_stored(data: &[u8]) -> Vec<u8> {
+        let (mut a, mut b) = (1u32, 0u32);
+        for byte in data {
+            a = (a + *byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        let length = data.len() as u16;
+        [&[0x78, 0x01, 1][..], &length.to_le_bytes(), &(!length).to_le_bytes(), data, &(b << 16 | a).to_be_bytes()].concat()
+    }
+
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        // the crc is never checked
+        [&(data.len() as u32).to_be_bytes()[..], kind, data, &[0; 4]].concat()
+    }
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+        [&width.to_be_bytes()[..], &height.to_be_bytes(), &[bit_depth, color_type, 0, 0, 0]].concat()
+    }
+
+    // rows already carry their filter bytes
+    fn png(ihdr: &[u8], extra: &[(&[u8; 4], &[u8])], rows: &[u8]) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(chunk(b"IHDR", ihdr));
+        for (kind, data) in extra {
+            bytes.extend(chunk(kind, data));
+        }
+        bytes.extend(chunk(b"IDAT", &zlib_stored(rows)));
+        bytes.extend(chunk(b"IEND", &[]));
+        bytes
+    }
+
+    // samples packed big endian at depth, behind an unfiltered filter byte
+    fn row(samples: &[u16], depth: u8) -> Vec<u8> {
+        let mut row = vec![0];
+        match depth {
+            16 => row.extend(samples.iter().flat_map(|sample| sample.to_be_bytes())),
+            8 => row.extend(samples.iter().map(|sample| *sample as u8)),
+            _ => {
+                let per_byte = 8 / depth as usize;
+                for byte_samples in samples.chunks(per_byte) {
+                    row.push(byte_samples.iter().enumerate().fold(0, |byte, (index, sample)| byte | (*sample as u8) << (8 - depth as usize * (index + 1))));
+                }
+            }
+        }
+        row
+    }
+
+    #[test]
+    fn decodes_every_color_type_and_depth() {
+        let (width, height) = (5, 3);
+        for (color_type, channels, depths) in [(0, 1, &[1, 2, 4, 8, 16][..]), (2, 3, &[8, 16]), (4, 2, &[8, 16]), (6, 4, &[8, 16])] {
+            for &depth in depths {
+                let maximum = (1u32 << depth) - 1;
+                let sample = |x: u32, y: u32, channel: u32| ((x * 3 + y * 5 + channel * 7) * 40503 % (maximum + 1)) as u16;
+                let to_u8 = |value: u16| if depth == 16 { (value >> 8) as u8 } else { (value as u32 * 255 / maximum) as u8 };
+                let rows: Vec<u8> = (0..height)
+                    .flat_map(|y| row(&(0..width).flat_map(|x| (0..channels).map(move |channel| sample(x, y, channel))).collect::<Vec<_>>(), depth))
+                    .collect();
+                let bitmap = Bitmap::parse_png(&png(&ihdr(width, height, depth, color_type), &[], &rows)).unwrap();
+                assert_eq!((bitmap.width, bitmap.height), (width as i32, height as i32));
+
+                for y in 0..height {
+                    for x in 0..width {
+                        let [first, second, third, fourth] = [0, 1, 2, 3].map(|channel| to_u8(sample(x, y, channel)));
+                        let expected = match color_type {
+                            0 => pack_rgba8(first, first, first, 255),
+                            2 => pack_rgba8(first, second, third, 255),
+                            4 => pack_rgba8(first, first, first, second),
+                            _ => pack_rgba8(first, second, third, fourth),
+                        };
+                        let pixel = bitmap.pixels[(y * width + x) as usize];
+                        assert_eq!(pixel, premultiply(expected), "color type {color_type}, {depth} bits, pixel {x},{y}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn low_depths_stretch_to_full_range() {
+        let bitmap = Bitmap::parse_png(&png(&ihdr(5, 1, 1, 0), &[], &row(&[1, 0, 1, 1, 0], 1))).unwrap();
+        let greys: Vec<u32> = bitmap.pixels.iter().map(|pixel| pixel & 0xFF).collect();
+        assert_eq!(greys, [255, 0, 255, 255, 0]);
+
+        let bitmap = Bitmap::parse_png(&png(&ihdr(4, 1, 2, 0), &[], &row(&[0, 1, 2, 3], 2))).unwrap();
+        let greys: Vec<u32> = bitmap.pixels.iter().map(|pixel| pixel & 0xFF).collect();
+        assert_eq!(greys, [0, 85, 170, 255]);
+    }
+
+    #[test]
+    fn decodes_palettes_with_transparency() {
+        let palette = [10, 20, 30, 40, 50, 60, 70, 80, 90];
+        // the first entry transparent and the second half, the third left opaque
+        let alphas = [0, 128];
+        for depth in [1, 2, 4, 8] {
+            let indices: Vec<u16> = if depth == 1 { vec![0, 1, 1, 0] } else { vec![0, 1, 2, 3] };
+            let bitmap = Bitmap::parse_png(&png(&ihdr(4, 1, depth, 3), &[(b"PLTE", &palette), (b"tRNS", &alphas)], &row(&indices, depth))).unwrap();
+            let expected: Vec<Pixel> = indices
+                .iter()
+                .map(|index| match index {
+                    0 => premultiply(pack_rgba8(10, 20, 30, 0)),
+                    1 => premultiply(pack_rgba8(40, 50, 60, 128)),
+                    2 => pack_rgba8(70, 80, 90, 255),
+                    // past the end of the palette
+                    _ => 0,
+                })
+                .collect();
+            assert_eq!(bitmap.pixels, expected, "{depth} bits");
+        }
+    }
+
+    #[test]
+    fn transparent_color_keys() {
+        let bitmap = Bitmap::parse_png(&png(&ihdr(3, 1, 8, 0), &[(b"tRNS", &[0, 7])], &row(&[6, 7, 8], 8))).unwrap();
+        assert_eq!(bitmap.pixels.iter().map(|pixel| pixel >> 24).collect::<Vec<_>>(), [255, 0, 255]);
+
+        let key = [0x1234u16, 0x5678, 0x9ABC];
+        let transparent: Vec<u8> = key.iter().flat_map(|sample| sample.to_be_bytes()).collect();
+        let samples = [key, [0x1234, 0x5678, 0x9ABD]].concat();
+        let bitmap = Bitmap::parse_png(&png(&ihdr(2, 1, 16, 2), &[(b"tRNS", &transparent)], &row(&samples, 16))).unwrap();
+        assert_eq!(bitmap.pixels, [0, pack_rgba8(0x12, 0x56, 0x9A, 255)]);
+    }
+
+    #[test]
+    fn undoes_every_filter() {
+        // 2 bytes a pixel grey and alpha, so left is two bytes back
+        let (width, height) = (3usize, 3usize);
+        let raw: Vec<Vec<u8>> = (0..height).map(|y| (0..width * 2).map(|index| (y * 71 + index * 37 + 200) as u8).collect()).collect();
+        for filter in 0..5u8 {
+            let mut rows = Vec::new();
+            for y in 0..height {
+                rows.push(filter);
+                for index in 0..width * 2 {
+                    let left = if index >= 2 { raw[y][index - 2] } else { 0 };
+                    let up = if y > 0 { raw[y - 1][index] } else { 0 };
+                    let up_left = if y > 0 && index >= 2 { raw[y - 1][index - 2] } else { 0 };
+                    let predicted = match filter {
+                        0 => 0,
+                        1 => left,
+                        2 => up,
+                        3 => ((left as u16 + up as u16) / 2) as u8,
+                        _ => paeth(left, up, up_left),
+                    };
+                    rows.push(raw[y][index].wrapping_sub(predicted));
+                }
+            }
+            let bitmap = Bitmap::parse_png(&png(&ihdr(width as u32, height as u32, 8, 4), &[], &rows)).unwrap();
+            let expected: Vec<Pixel> = raw.iter().flat_map(|row| row.chunks(2).map(|pixel| premultiply(pack_rgba8(pixel[0], pixel[0], pixel[0], pixel[1])))).collect();
+            assert_eq!(bitmap.pixels, expected, "filter {filter}");
+        }
+    }
+
+    #[test]
+    fn paeth_picks_the_closest_neighbour() {
+        // estimate 15 is up_left itself
+        assert_eq!(paeth(10, 20, 15), 15);
+        // ties go left, then up
+        assert_eq!(paeth(10, 10, 10), 10);
+        assert_eq!(paeth(1, 200, 1), 200);
+        assert_eq!(paeth(200, 1, 1), 200);
+    }
+
+    #[test]
+    fn bad_images_are_errors() {
+        let good = png(&ihdr(2, 2, 8, 0), &[], &[row(&[1, 2], 8), row(&[3, 4], 8)].concat());
+        assert!(Bitmap::parse_png(&good).is_ok());
+
+        let mut interlaced = ihdr(2, 2, 8, 0);
+        interlaced[12] = 1;
+        let short_data = row(&[1, 2], 8);
+        let bad_filter = [&[5, 1, 2][..], &row(&[3, 4], 8)].concat();
+        for (what, bytes) in [
+            ("interlaced", png(&interlaced, &[], &[row(&[1, 2], 8), row(&[3, 4], 8)].concat())),
+            ("a row short", png(&ihdr(2, 2, 8, 0), &[], &short_data)),
+            ("unknown filter", png(&ihdr(2, 2, 8, 0), &[], &bad_filter)),
+            ("no palette", png(&ihdr(2, 1, 8, 3), &[], &row(&[0, 0], 8))),
+            ("4 bit rgb", png(&ihdr(2, 1, 4, 2), &[], &row(&[0; 6], 4))),
+            ("empty", png(&ihdr(0, 1, 8, 0), &[], &[0])),
+            ("IDAT first", [&SIGNATURE[..], &chunk(b"IDAT", &[]), &chunk(b"IHDR", &ihdr(2, 2, 8, 0))].concat()),
+        ] {
+            assert_eq!(Bitmap::parse_png(&bytes).map(|_| ()).unwrap_err().kind(), io::ErrorKind::InvalidData, "{what}");
+        }
+
+        // cut short anywhere before the end of the image data, the IDAT's crc and the IEND can go since neither is read
+        for length in 0..good.len() - 16 {
+            assert!(Bitmap::parse_png(&good[..length]).is_err(), "cut to {length} bytes");
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Full frame effects run on the finished backbuffer right before it's presented. The ones here run on the CPU,
+//! PostPass is for the ones a GPU backend runs as it presents.
+
+use std::{fs, io, path::Path};
+
+use crate::{
+    color::{pack_rgba8, Color, Pixel},
+    frame_buffer::FrameBuffer,
+    render_group::DirtyRect,
+    work_queue::WorkQueue,
+};
+
+// horizontal bands, each one a job. Every output pixel reads from wherever the curve puts it, so this can't reuse the render tiles
+const BAND_COUNT: i32 = 8;
+// how far the corners get pushed out, as a fraction of the distance from the center
+pub(crate) const CURVATURE: f32 = 0.06;
+// red is read this many pixels to the right and blue to the left, the fringe a misconverged tube has
+pub(crate) const CHROMA_OFFSET: f32 = 1.0;
+// every other row is dimmed to this out of 256
+pub(crate) const SCANLINE_BRIGHTNESS: u32 = 180;
+
+/// A full frame effect for a GPU backend to run, pushed on RenderGroup::post_passes. Sizes are in pixels of the frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostPass {
+    /// Gaussian blur reaching radius pixels
+    Blur { radius: f32 },
+    /// What's brighter than threshold (0..1) blurred by radius and added back on, times intensity
+    Bloom { threshold: f32, radius: f32, intensity: f32 },
+    /// The CrtEffect look
+    Crt,
+}
+
+/// Old TV look for the low resolution modes: scanlines, the picture bulging out like the glass it's behind, and
+/// the red and blue channels slightly off from green. The curve is nearest sampled, an approximation but a cheap one.
+#[derive(Default)]
+pub struct CrtEffect {
+    pub enabled: bool,
+    // the frame as rendered, read from while the buffer is overwritten, and kept for restore
+    clean: Vec<Pixel>,
+    width: i32,
+    height: i32,
+}
+
+impl CrtEffect {
+    pub fn apply(&mut self, buffer: &mut FrameBuffer, queue: &WorkQueue) {
+        if !self.enabled || buffer.is_empty() {
+            // nothing kept from a frame the effect didn't run on, restore must not bring back an old one
+            self.clean.clear();
+            return;
+        }
+        let (width, height) = (buffer.width(), buffer.height());
+        self.width = width;
+        self.height = height;
+        self.clean.clear();
+        for row in buffer.rows() {
+            self.clean.extend_from_slice(row);
+        }
+
+        let clean = &self.clean;
+        for_each_row(buffer, queue, |y, row| {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = crt_pixel(clean, width, height, x as i32, y);
+            }
+        });
+    }
+
+    /// Puts the frame back the way it was before the effect, for frames that only draw over the last one.
+    /// False when there's nothing that fits the buffer to restore
+    pub fn restore(&self, buffer: &mut FrameBuffer) -> bool {
+        if buffer.is_empty() || buffer.width() != self.width || buffer.height() != self.height || self.clean.is_empty() {
+            return false;
+        }
+        for (pixel, clean) in buffer.pixels_mut().zip(&self.clean) {
+            *pixel = *clean;
+        }
+        true
+    }
+}
+
+/// Runs row_fn(y, row) over every row of the buffer, a band of rows per job
+fn for_each_row(buffer: &mut FrameBuffer, queue: &WorkQueue, row_fn: impl Fn(i32, &mut [Pixel]) + Sync) {
+    let (width, height) = (buffer.width(), buffer.height());
+    let row_fn = &row_fn;
+    let band_height = ((height + BAND_COUNT - 1) / BAND_COUNT).max(1);
+    let bands: Vec<DirtyRect> = (0..height)
+        .step_by(band_height as usize)
+        .map(|y| DirtyRect { x: 0, y, width, height: band_height.min(height - y) })
+        .collect();
+    let jobs = bands.iter().zip(buffer.split_mut(&bands)).map(|(rect, mut band)| {
+        let band_y = rect.y;
+        Box::new(move || {
+            for (row, pixels) in band.rows_mut().enumerate() {
+                row_fn(band_y + row as i32, pixels);
+            }
+        }) as Box<dyn FnOnce() + Send + '_>
+    });
+    queue.complete_all(jobs);
+}
+
+fn crt_pixel(clean: &[Pixel], width: i32, height: i32, x: i32, y: i32) -> Pixel {
+    // -1..1 from the center, pushed outwards more the further out it is
+    let (half_width, half_height) = (width as f32 / 2.0, height as f32 / 2.0);
+    let (from_x, from_y) = ((x as f32 + 0.5 - half_width) / half_width, (y as f32 + 0.5 - half_height) / half_height);
+    let bend = 1.0 + CURVATURE * (from_x * from_x + from_y * from_y);
+    let (source_x, source_y) = (half_width + from_x * bend * half_width, half_height + from_y * bend * half_height);
+
+    // past the edge of the picture is the black of the tube
+    let source_y = source_y.floor() as i32;
+    let fetch = |source_x: f32| {
+        let source_x = source_x.floor() as i32;
+        if (0..width).contains(&source_x) && (0..height).contains(&source_y) { clean[(source_y * width + source_x) as usize] } else { 0 }
+    };
+    let pixel = 0xFF000000 | fetch(source_x + CHROMA_OFFSET) & 0xFF0000 | fetch(source_x) & 0xFF00 | fetch(source_x - CHROMA_OFFSET) & 0xFF;
+    if y % 2 == 1 { dim(pixel, SCANLINE_BRIGHTNESS) } else { pixel }
+}
+
+// color channels times brightness / 256, alpha stays opaque
+fn dim(pixel: Pixel, brightness: u32) -> Pixel {
+    let red_blue = (((pixel & 0x00FF00FF) * brightness) >> 8) & 0x00FF00FF;
+    let green = (((pixel & 0x0000FF00) * brightness) >> 8) & 0x0000FF00;
+    0xFF000000 | red_blue | green
+}
+
+/// A color grade as a size x size x size grid of output colors spread evenly over the input color cube,
+/// red changing fastest then green then blue, the same order .cube files list them in. Colors in between are
+/// interpolated from the eight grid points around them, so 17 or so per side is plenty.
+#[derive(Clone, Debug)]
+pub struct Lut3d {
+    size: usize,
+    entries: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    pub fn from_fn(size: usize, grade: impl Fn([f32; 3]) -> [f32; 3]) -> Self {
+        let size = size.max(2);
+        let step = 1.0 / (size - 1) as f32;
+        let entries = (0..size * size * size).map(|index| grade([(index % size) as f32 * step, (index / size % size) as f32 * step, (index / (size * size)) as f32 * step])).collect();
+        Lut3d { size, entries }
+    }
+
+    /// Contrast pushes away from mid grey (1 is unchanged), saturation away from each color's grey (0 is black and white,
+    /// 1 unchanged), and temperature shifts towards orange when positive and blue when negative (0 unchanged, 1 is a lot)
+    pub fn from_curves(contrast: f32, saturation: f32, temperature: f32) -> Self {
+        Lut3d::from_fn(17, |[red, green, blue]| {
+            let (red, green, blue) = (red + temperature * 0.1, green, blue - temperature * 0.1);
+            let luma = 0.2126 * red + 0.7152 * green + 0.0722 * blue;
+            [red, green, blue].map(|channel| ((luma + (channel - luma) * saturation - 0.5) * contrast + 0.5).clamp(0.0, 1.0))
+        })
+    }
+
+    /// Reads an Adobe/Resolve .cube file: LUT_3D_SIZE then that many cubed "r g b" lines.
+    /// Titles, comments and the domain lines are skipped, the domain is taken to be 0..1.
+    pub fn load_cube(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let text = fs::read_to_string(path)?;
+        let mut size = None;
+        let mut entries = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if let Some(value) = line.strip_prefix("LUT_3D_SIZE") {
+                size = Some(value.trim().parse::<usize>().map_err(|_| invalid("Bad LUT_3D_SIZE"))?);
+            } else if line.starts_with(|character: char| character.is_ascii_digit() || character == '-' || character == '.') {
+                let values: Vec<f32> = line.split_whitespace().map(str::parse).collect::<Result<_, _>>().map_err(|_| invalid("Bad LUT entry"))?;
+                let [red, green, blue] = values[..] else {
+                    return Err(invalid("LUT entries need three values"));
+                };
+                entries.push([red, green, blue]);
+            }
+        }
+        let size = size.ok_or_else(|| invalid("No LUT_3D_SIZE, only 3D LUTs are supported"))?;
+        if size < 2 || entries.len() != size * size * size {
+            return Err(invalid("Wrong number of LUT entries for its size"));
+        }
+        Ok(Lut3d { size, entries })
+    }
+
+    /// Trilinear lookup of a 0..1 color
+    fn sample(&self, color: [f32; 3]) -> [f32; 3] {
+        let last = (self.size - 1) as f32;
+        let cell = color.map(|channel| {
+            let position = channel.clamp(0.0, 1.0) * last;
+            let low = (position as usize).min(self.size - 2);
+            (low, position - low as f32)
+        });
+        let mut result = [0.0; 3];
+        for corner in 0..8 {
+            let mut index = 0;
+            let mut weight = 1.0;
+            for (axis, (low, fraction)) in cell.iter().enumerate() {
+                let high = corner >> axis & 1;
+                index += (low + high) * self.size.pow(axis as u32);
+                weight *= if high == 1 { *fraction } else { 1.0 - fraction };
+            }
+            for (channel, value) in result.iter_mut().zip(self.entries[index]) {
+                *channel += value * weight;
+            }
+        }
+        result
+    }
+}
+
+/// Color grading of the finished frame: the lut, then a flash, a color the whole picture is pulled towards by the
+/// flash color's alpha (the game's damage flash). Does nothing while there's neither.
+#[derive(Default)]
+pub struct ColorGrade {
+    lut: Option<Lut3d>,
+    // bumped whenever the lut changes, so apply can tell a frame graded differently from the last
+    lut_version: u64,
+    applied: Option<(u64, Pixel)>,
+}
+
+impl ColorGrade {
+    pub fn set_lut(&mut self, lut: Option<Lut3d>) {
+        self.lut = lut;
+        self.lut_version += 1;
+    }
+
+    /// Whether apply changes anything with this flash
+    pub fn is_active(&self, flash: Color) -> bool {
+        self.lut.is_some() || flash.to_pixel() >> 24 != 0
+    }
+
+    /// Grades the buffer in place. True when it was graded differently from the frame before (or only one of them was),
+    /// in which case pixels can have changed anywhere, even where nothing was drawn differently
+    pub fn apply(&mut self, buffer: &mut FrameBuffer, queue: &WorkQueue, flash: Color) -> bool {
+        let flash_pixel = flash.to_pixel();
+        let active = self.is_active(flash);
+        let settings = active.then_some((self.lut_version, flash_pixel));
+        let changed = settings != self.applied;
+        self.applied = settings;
+        if !active || buffer.is_empty() {
+            return changed;
+        }
+
+        let lut = self.lut.as_ref();
+        let flash_amount = flash.a.clamp(0.0, 1.0);
+        for_each_row(buffer, queue, |_, row| {
+            for pixel in row.iter_mut() {
+                let [blue, green, red, alpha] = pixel.to_le_bytes();
+                let color = [red, green, blue].map(|channel| channel as f32 / 255.0);
+                let graded = lut.map_or(color, |lut| lut.sample(color));
+                let [red, green, blue] = [(graded[0], flash.r), (graded[1], flash.g), (graded[2], flash.b)]
+                    .map(|(channel, toward)| ((channel + (toward - channel) * flash_amount).clamp(0.0, 1.0) * 255.0 + 0.5) as u8);
+                *pixel = pack_rgba8(red, green, blue, alpha);
+            }
+        });
+        changed
+    }
+}
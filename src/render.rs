@@ -0,0 +1,944 @@
+use std::sync::Arc;
+
+use crate::{
+    bitmap::Bitmap,
+    color::{pack_rgba8, Color, Pixel},
+    font::Font,
+    frame_buffer::FrameBuffer,
+    math::V2,
+    render_simd,
+    text_layout::GlyphPlacement,
+};
+
+/// Fills the whole buffer with one color, replacing what was there rather than blending over it
+pub fn clear(buffer: &mut FrameBuffer, color: Color) {
+    if buffer.is_empty() {
+        return;
+    }
+    // only shows for translucent clears, which only make sense in render targets
+    let color = color.premultiplied().to_pixel();
+    for row in buffer.rows_mut() {
+        render_simd::fill(row, color);
+    }
+}
+
+/// Fills min..max, clipped to the buffer. Max is exclusive so rects that share an edge don't overdraw each other.
+/// Pixels the edges only partly cover are blended by how much of them is inside, so a rect can sit between pixels.
+/// Opaque rects on whole pixels are plain fills, anything else blends.
+pub fn draw_rect(buffer: &mut FrameBuffer, min_x: f32, min_y: f32, max_x: f32, max_y: f32, color: Color) {
+    if color.a >= 1.0 && [min_x, min_y, max_x, max_y].into_iter().all(is_whole_pixel) {
+        return fill_rect(buffer, min_x, min_y, max_x, max_y, color.to_pixel());
+    }
+    let left = (min_x.floor() as i32).max(0);
+    let top = (min_y.floor() as i32).max(0);
+    let right = (max_x.ceil() as i32).min(buffer.width());
+    let bottom = (max_y.ceil() as i32).min(buffer.height());
+    if left >= right || top >= bottom || buffer.is_empty() {
+        return;
+    }
+
+    // how much of a pixel's 0..1 span along one axis is inside min..max
+    let coverage = |pixel: i32, min: f32, max: f32| (max.min(pixel as f32 + 1.0) - min.max(pixel as f32)).clamp(0.0, 1.0);
+    let color = color.premultiplied().to_pixel();
+    let mut row = vec![0; (right - left) as usize];
+    for y in top..bottom {
+        let row_coverage = coverage(y, min_y, max_y);
+        for (x, pixel) in (left..right).zip(row.iter_mut()) {
+            let amount = row_coverage * coverage(x, min_x, max_x);
+            *pixel = lerp_pixel(0, color, (amount * 256.0 + 0.5) as u32);
+        }
+        render_simd::blend(buffer.span_mut(left, y, row.len()), &row);
+    }
+}
+
+fn fill_rect(buffer: &mut FrameBuffer, min_x: f32, min_y: f32, max_x: f32, max_y: f32, color: Pixel) {
+    let min_x = (min_x.round() as i32).max(0);
+    let min_y = (min_y.round() as i32).max(0);
+    let max_x = (max_x.round() as i32).min(buffer.width());
+    let max_y = (max_y.round() as i32).min(buffer.height());
+    if min_x >= max_x || min_y >= max_y || buffer.is_empty() {
+        return;
+    }
+
+    for y in min_y..max_y {
+        render_simd::fill(buffer.span_mut(min_x, y, (max_x - min_x) as usize), color);
+    }
+}
+
+/// How the color changes across a gradient filled rect
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gradient {
+    /// left edge to right edge
+    Horizontal(Color, Color),
+    /// top edge to bottom edge
+    Vertical(Color, Color),
+    /// top left, top right, bottom left, bottom right, blended bilinearly in between
+    Corners([Color; 4]),
+    /// inner until start (a fraction of the way from the center to the edges), then blending out to outer at the edges.
+    /// Stretched with the rect, so it's an ellipse in a rect that isn't square
+    Radial { inner: Color, outer: Color, start: f32 },
+}
+
+impl Gradient {
+    /// Color at u, v across the rect, 0..1 from the top left
+    fn sample(&self, u: f32, v: f32) -> Color {
+        match *self {
+            Gradient::Horizontal(left, right) => left.lerp(right, u),
+            Gradient::Vertical(top, bottom) => top.lerp(bottom, v),
+            Gradient::Corners([top_left, top_right, bottom_left, bottom_right]) => top_left.lerp(top_right, u).lerp(bottom_left.lerp(bottom_right, u), v),
+            Gradient::Radial { inner, outer, start } => {
+                let (x, y) = (u * 2.0 - 1.0, v * 2.0 - 1.0);
+                let distance = (x * x + y * y).sqrt();
+                inner.lerp(outer, ((distance - start) / (1.0 - start).max(1e-6)).clamp(0.0, 1.0))
+            }
+        }
+    }
+}
+
+/// Fills the pixels whose centers fall inside min..max (rounded like fill_rect) with a gradient, blended by its alpha
+pub fn draw_gradient_rect(buffer: &mut FrameBuffer, min_x: f32, min_y: f32, max_x: f32, max_y: f32, gradient: Gradient) {
+    let left = (min_x.round() as i32).max(0);
+    let top = (min_y.round() as i32).max(0);
+    let right = (max_x.round() as i32).min(buffer.width());
+    let bottom = (max_y.round() as i32).min(buffer.height());
+    if left >= right || top >= bottom || buffer.is_empty() {
+        return;
+    }
+
+    let (width, height) = (max_x - min_x, max_y - min_y);
+    let mut row = vec![0; (right - left) as usize];
+    for y in top..bottom {
+        let v = (y as f32 + 0.5 - min_y) / height;
+        for (x, pixel) in (left..right).zip(row.iter_mut()) {
+            *pixel = gradient.sample((x as f32 + 0.5 - min_x) / width, v).premultiplied().to_pixel();
+        }
+        render_simd::blend(buffer.span_mut(left, y, row.len()), &row);
+    }
+}
+
+/// Cohen-Sutherland region bits of a point against the buffer
+fn outcode(buffer: &FrameBuffer, x: f32, y: f32) -> u8 {
+    let mut code = 0;
+    if x < 0.0 {
+        code |= 1;
+    } else if x > (buffer.width() - 1) as f32 {
+        code |= 2;
+    }
+    if y < 0.0 {
+        code |= 4;
+    } else if y > (buffer.height() - 1) as f32 {
+        code |= 8;
+    }
+    code
+}
+
+/// Cuts the segment down to the part inside the buffer, None when all of it is outside
+fn clip_line(buffer: &FrameBuffer, mut x0: f32, mut y0: f32, mut x1: f32, mut y1: f32) -> Option<(f32, f32, f32, f32)> {
+    let (max_x, max_y) = ((buffer.width() - 1) as f32, (buffer.height() - 1) as f32);
+    let (mut code0, mut code1) = (outcode(buffer, x0, y0), outcode(buffer, x1, y1));
+    loop {
+        if code0 | code1 == 0 {
+            return Some((x0, y0, x1, y1));
+        }
+        if code0 & code1 != 0 {
+            return None;
+        }
+        // move whichever end is outside onto the edge it crosses
+        let code = if code0 != 0 { code0 } else { code1 };
+        let (x, y) = if code & 8 != 0 {
+            (x0 + (x1 - x0) * (max_y - y0) / (y1 - y0), max_y)
+        } else if code & 4 != 0 {
+            (x0 + (x1 - x0) * (0.0 - y0) / (y1 - y0), 0.0)
+        } else if code & 2 != 0 {
+            (max_x, y0 + (y1 - y0) * (max_x - x0) / (x1 - x0))
+        } else {
+            (0.0, y0 + (y1 - y0) * (0.0 - x0) / (x1 - x0))
+        };
+        if code == code0 {
+            (x0, y0) = (x, y);
+            code0 = outcode(buffer, x0, y0);
+        } else {
+            (x1, y1) = (x, y);
+            code1 = outcode(buffer, x1, y1);
+        }
+    }
+}
+
+/// One pixel wide line between the two points (both ends included), clipped to the buffer before stepping
+pub fn draw_line(buffer: &mut FrameBuffer, x0: f32, y0: f32, x1: f32, y1: f32, color: Color) {
+    if buffer.is_empty() {
+        return;
+    }
+    let Some((x0, y0, x1, y1)) = clip_line(buffer, x0, y0, x1, y1) else {
+        return;
+    };
+
+    // bresenham, clipped ends are inside so rounding can't step out of the buffer
+    let color = color.to_pixel();
+    let (mut x, mut y) = (x0.round() as i32, y0.round() as i32);
+    let (end_x, end_y) = (x1.round() as i32, y1.round() as i32);
+    let (dx, dy) = ((end_x - x).abs(), -(end_y - y).abs());
+    let (step_x, step_y) = (if x < end_x { 1 } else { -1 }, if y < end_y { 1 } else { -1 });
+    let mut error = dx + dy;
+    loop {
+        buffer.row_mut(y)[x as usize] = color;
+        if x == end_x && y == end_y {
+            break;
+        }
+        let error2 = 2 * error;
+        if error2 >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if error2 <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+fn plot_clipped(buffer: &mut FrameBuffer, x: i32, y: i32, color: Pixel) {
+    if x >= 0 && y >= 0 && x < buffer.width() && y < buffer.height() {
+        buffer.row_mut(y)[x as usize] = color;
+    }
+}
+
+/// Axis aligned ellipse, one span per row covering the pixel centers inside it
+pub fn draw_ellipse(buffer: &mut FrameBuffer, center_x: f32, center_y: f32, radius_x: f32, radius_y: f32, color: Color) {
+    let color = color.to_pixel();
+    let (radius_x, radius_y) = (radius_x.abs(), radius_y.abs());
+    if radius_x == 0.0 || radius_y == 0.0 {
+        return;
+    }
+    let min_y = ((center_y - radius_y).floor() as i32).max(0);
+    let max_y = ((center_y + radius_y).ceil() as i32).min(buffer.height());
+    for y in min_y..max_y {
+        let offset_y = (y as f32 + 0.5 - center_y) / radius_y;
+        if offset_y.abs() > 1.0 {
+            continue;
+        }
+        let half_width = radius_x * (1.0 - offset_y * offset_y).sqrt();
+        fill_rect(buffer, center_x - half_width, y as f32, center_x + half_width, (y + 1) as f32, color);
+    }
+}
+
+/// One pixel wide ellipse outline (midpoint algorithm), pixels outside the buffer are dropped
+pub fn draw_ellipse_outline(buffer: &mut FrameBuffer, center_x: f32, center_y: f32, radius_x: f32, radius_y: f32, color: Color) {
+    if buffer.is_empty() {
+        return;
+    }
+    let color = color.to_pixel();
+    let (center_x, center_y) = (center_x.round() as i32, center_y.round() as i32);
+    let (radius_x, radius_y) = (radius_x.abs().round() as i64, radius_y.abs().round() as i64);
+    // skip the whole thing when it can't touch the buffer, huge zoomed in outlines would otherwise walk millions of pixels
+    if center_x as i64 + radius_x < 0
+        || center_y as i64 + radius_y < 0
+        || center_x as i64 - radius_x >= buffer.width() as i64
+        || center_y as i64 - radius_y >= buffer.height() as i64
+    {
+        return;
+    }
+
+    let mut plot_quadrants = |x: i64, y: i64| {
+        for (x, y) in [(x, y), (-x, y), (x, -y), (-x, -y)] {
+            plot_clipped(buffer, center_x + x as i32, center_y + y as i32, color);
+        }
+    };
+
+    // everything below is scaled by 4 to keep the half pixel midpoint tests in integers
+    let (rx2, ry2) = (radius_x * radius_x, radius_y * radius_y);
+    let (mut x, mut y) = (0, radius_y);
+    let (mut step_x, mut step_y) = (0, 2 * rx2 * y);
+    // region 1, slope shallower than -1: always step x
+    let mut decision = 4 * ry2 - 4 * rx2 * radius_y + rx2;
+    while step_x < step_y {
+        plot_quadrants(x, y);
+        x += 1;
+        step_x += 2 * ry2;
+        if decision < 0 {
+            decision += 4 * (step_x + ry2);
+        } else {
+            y -= 1;
+            step_y -= 2 * rx2;
+            decision += 4 * (step_x - step_y + ry2);
+        }
+    }
+    // region 2, steeper: always step y
+    decision = ry2 * (2 * x + 1) * (2 * x + 1) + 4 * rx2 * (y - 1) * (y - 1) - 4 * rx2 * ry2;
+    while y >= 0 {
+        plot_quadrants(x, y);
+        y -= 1;
+        step_y -= 2 * rx2;
+        if decision > 0 {
+            decision += 4 * (rx2 - step_y);
+        } else {
+            x += 1;
+            step_x += 2 * ry2;
+            decision += 4 * (step_x - step_y + rx2);
+        }
+    }
+}
+
+pub fn draw_circle(buffer: &mut FrameBuffer, center_x: f32, center_y: f32, radius: f32, color: Color) {
+    draw_ellipse(buffer, center_x, center_y, radius, radius, color);
+}
+
+pub fn draw_circle_outline(buffer: &mut FrameBuffer, center_x: f32, center_y: f32, radius: f32, color: Color) {
+    draw_ellipse_outline(buffer, center_x, center_y, radius, radius, color);
+}
+
+/// Composites the bitmap with its top left corner at x, y using its alpha channel, clipped to the buffer.
+/// Whole pixel positions copy it straight, anything in between is resampled so slow movement doesn't step.
+pub fn draw_bitmap(buffer: &mut FrameBuffer, bitmap: &Bitmap, x: f32, y: f32) {
+    if is_whole_pixel(x) && is_whole_pixel(y) {
+        draw_bitmap_region(buffer, bitmap, 0, 0, bitmap.width, bitmap.height, x, y);
+    } else {
+        draw_bitmap_subpixel(buffer, bitmap, x, y);
+    }
+}
+
+// within 1/256th, closer than an 8 bit weight can tell apart
+fn is_whole_pixel(value: f32) -> bool {
+    (value - value.round()).abs() < 1.0 / 256.0
+}
+
+/// Every pixel the bitmap touches takes the bilinear mix of the texels over it, the edges blend with what's behind
+fn draw_bitmap_subpixel(buffer: &mut FrameBuffer, bitmap: &Bitmap, x: f32, y: f32) {
+    let min_x = (x.floor() as i32).max(0);
+    let min_y = (y.floor() as i32).max(0);
+    let max_x = ((x + bitmap.width as f32).ceil() as i32).min(buffer.width());
+    let max_y = ((y + bitmap.height as f32).ceil() as i32).min(buffer.height());
+    if min_x >= max_x || min_y >= max_y || buffer.is_empty() {
+        return;
+    }
+
+    let mut row = vec![0; (max_x - min_x) as usize];
+    for dest_y in min_y..max_y {
+        let v = dest_y as f32 + 0.5 - y;
+        for (dest_x, pixel) in (min_x..max_x).zip(row.iter_mut()) {
+            *pixel = sample_bilinear_with(dest_x as f32 + 0.5 - x, v, |x, y| texel_or_transparent(bitmap, x, y));
+        }
+        render_simd::blend(buffer.span_mut(min_x, dest_y, row.len()), &row);
+    }
+}
+
+/// One instance for draw_particles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Particle {
+    pub position: V2,
+    /// Width and height in pixels, the particle is centered on position
+    pub size: f32,
+    pub color: Color,
+}
+
+/// Lots of small squares in one call, each either filled with its color or, with a bitmap, showing the bitmap
+/// shrunk (nearest texel) to its size and tinted by its color. Positions round to whole pixels, at the sizes
+/// particles are drawn at nobody sees the difference and it keeps the inner loop to integer stepping.
+/// Positions are moved by -shift first, so a tile of the frame can draw the frame's particles without copying them.
+pub fn draw_particles(buffer: &mut FrameBuffer, particles: &[Particle], shift: V2, bitmap: Option<&Bitmap>) {
+    if buffer.is_empty() || bitmap.is_some_and(|bitmap| bitmap.width <= 0 || bitmap.height <= 0) {
+        return;
+    }
+    let mut row = Vec::new();
+    for particle in particles {
+        let size = particle.size.round() as i32;
+        let x = (particle.position.x - shift.x - particle.size / 2.0).round() as i32;
+        let y = (particle.position.y - shift.y - particle.size / 2.0).round() as i32;
+        let (min_x, min_y) = (x.max(0), y.max(0));
+        let (max_x, max_y) = ((x + size).min(buffer.width()), (y + size).min(buffer.height()));
+        if min_x >= max_x || min_y >= max_y || particle.color.a <= 0.0 {
+            continue;
+        }
+        let tint = particle.color.premultiplied().to_pixel();
+        let width = (max_x - min_x) as usize;
+        match bitmap {
+            None => {
+                row.clear();
+                row.resize(width, tint);
+                for dest_y in min_y..max_y {
+                    render_simd::blend(buffer.span_mut(min_x, dest_y, width), &row);
+                }
+            }
+            Some(bitmap) => {
+                for dest_y in min_y..max_y {
+                    let texel_y = (dest_y - y) * bitmap.height / size;
+                    row.clear();
+                    row.extend((min_x..max_x).map(|dest_x| modulate(texel_clamped(bitmap, (dest_x - x) * bitmap.width / size, texel_y), tint)));
+                    render_simd::blend(buffer.span_mut(min_x, dest_y, width), &row);
+                }
+            }
+        }
+    }
+}
+
+/// Channel by channel product of two premultiplied pixels, which is still premultiplied
+fn modulate(a: Pixel, b: Pixel) -> Pixel {
+    let channel = |shift: u32| ((((a >> shift) & 0xFF) * ((b >> shift) & 0xFF) + 127) / 255) << shift;
+    channel(24) | channel(16) | channel(8) | channel(0)
+}
+
+/// Light for normal mapped bitmaps, hanging height pixels in front of the screen over position.
+/// Falls off to nothing at radius
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight {
+    pub position: V2,
+    pub height: f32,
+    pub radius: f32,
+    pub color: Color,
+}
+
+/// draw_bitmap (rounded to whole pixels) with every pixel lit: the normal map is the same size as the bitmap and holds
+/// a direction per pixel, x in red, y in green (up is positive), z towards the viewer in blue. Each light adds
+/// its color times N.L times its falloff on top of ambient, then the bitmap's color is multiplied by the total
+#[allow(clippy::too_many_arguments)]
+pub fn draw_bitmap_lit(buffer: &mut FrameBuffer, bitmap: &Bitmap, normal_map: &Bitmap, x: f32, y: f32, lights: &[PointLight], ambient: Color) {
+    if normal_map.width != bitmap.width || normal_map.height != bitmap.height {
+        return;
+    }
+    let (x, y) = (x.round() as i32, y.round() as i32);
+    let min_x = x.max(0);
+    let min_y = y.max(0);
+    let max_x = (x + bitmap.width).min(buffer.width());
+    let max_y = (y + bitmap.height).min(buffer.height());
+    if min_x >= max_x || min_y >= max_y || buffer.is_empty() {
+        return;
+    }
+
+    let mut row = vec![0; (max_x - min_x) as usize];
+    for dest_y in min_y..max_y {
+        for (dest_x, pixel) in (min_x..max_x).zip(row.iter_mut()) {
+            let index = ((dest_y - y) * bitmap.width + dest_x - x) as usize;
+            let albedo = bitmap.pixels[index];
+            if albedo >> 24 == 0 {
+                *pixel = 0;
+                continue;
+            }
+            let [normal_z, normal_y, normal_x, _] = normal_map.pixels[index].to_le_bytes().map(|channel| channel as f32 / 127.5 - 1.0);
+            // the map's y points up, the screen's down
+            let normal = (normal_x, -normal_y, normal_z);
+
+            let (mut red, mut green, mut blue) = (ambient.r, ambient.g, ambient.b);
+            let position = V2::new(dest_x as f32 + 0.5, dest_y as f32 + 0.5);
+            for light in lights {
+                let offset = light.position - position;
+                let distance = (offset.dot(offset) + light.height * light.height).sqrt();
+                let falloff = (1.0 - distance / light.radius).max(0.0);
+                let facing = (normal.0 * offset.x + normal.1 * offset.y + normal.2 * light.height) / distance.max(1e-6);
+                let amount = facing.max(0.0) * falloff * falloff;
+                red += light.color.r * amount;
+                green += light.color.g * amount;
+                blue += light.color.b * amount;
+            }
+
+            // premultiplied, so the channels can't go over alpha however bright it gets
+            let [albedo_blue, albedo_green, albedo_red, alpha] = albedo.to_le_bytes();
+            let lit = |channel: u8, light: f32| (channel as f32 * light).min(alpha as f32) as u8;
+            *pixel = pack_rgba8(lit(albedo_red, red), lit(albedo_green, green), lit(albedo_blue, blue), alpha);
+        }
+        render_simd::blend(buffer.span_mut(min_x, dest_y, row.len()), &row);
+    }
+}
+
+/// How draw_bitmap_nine_slice cuts up its bitmap: the margins are in texels, and make the corners,
+/// which keep their size, and the edges, which only stretch along their length
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NineSlice {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub fill: SliceFill,
+}
+
+/// What the edges and center do to cover more room than they have texels for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceFill {
+    Stretch,
+    Tile,
+}
+
+/// Draws the bitmap as a width x height box at x, y for UI panels and buttons, corners unscaled.
+/// A box smaller than the two margins shrinks the corners rather than overlapping them. Sizes round to whole pixels
+/// and stretching is nearest texel, so borders stay crisp and slices never bleed into each other.
+pub fn draw_bitmap_nine_slice(buffer: &mut FrameBuffer, bitmap: &Bitmap, slice: NineSlice, x: f32, y: f32, width: f32, height: f32) {
+    let (x, y, width, height) = (x.round() as i32, y.round() as i32, width.round() as i32, height.round() as i32);
+    if width <= 0 || height <= 0 || bitmap.width <= 0 || bitmap.height <= 0 {
+        return;
+    }
+    let (source_xs, dest_xs) = nine_slice_cuts(slice.left, slice.right, bitmap.width, width);
+    let (source_ys, dest_ys) = nine_slice_cuts(slice.top, slice.bottom, bitmap.height, height);
+    for row in 0..3 {
+        for column in 0..3 {
+            let (source_x, source_width) = (source_xs[column], source_xs[column + 1] - source_xs[column]);
+            let (source_y, source_height) = (source_ys[row], source_ys[row + 1] - source_ys[row]);
+            let (dest_x, dest_width) = (x + dest_xs[column], dest_xs[column + 1] - dest_xs[column]);
+            let (dest_y, dest_height) = (y + dest_ys[row], dest_ys[row + 1] - dest_ys[row]);
+            if source_width <= 0 || source_height <= 0 || dest_width <= 0 || dest_height <= 0 {
+                continue;
+            }
+            let corner = row != 1 && column != 1;
+            if dest_width == source_width && dest_height == source_height {
+                draw_bitmap_region(buffer, bitmap, source_x, source_y, source_width, source_height, dest_x as f32, dest_y as f32);
+            } else if slice.fill == SliceFill::Tile && !corner {
+                // whole copies, the last one in each direction cut short
+                for tile_y in (0..dest_height).step_by(source_height as usize) {
+                    for tile_x in (0..dest_width).step_by(source_width as usize) {
+                        let (cut_width, cut_height) = (source_width.min(dest_width - tile_x), source_height.min(dest_height - tile_y));
+                        let (tile_x, tile_y) = ((dest_x + tile_x) as f32, (dest_y + tile_y) as f32);
+                        draw_bitmap_region(buffer, bitmap, source_x, source_y, cut_width, cut_height, tile_x, tile_y);
+                    }
+                }
+            } else {
+                let source = (source_x, source_y, source_width, source_height);
+                draw_region_stretched(buffer, bitmap, source, (dest_x, dest_y, dest_width, dest_height));
+            }
+        }
+    }
+}
+
+// where one axis of a nine slice is cut, in the bitmap and in the box
+fn nine_slice_cuts(first: i32, second: i32, source: i32, dest: i32) -> ([i32; 4], [i32; 4]) {
+    let first = first.clamp(0, source);
+    let second = second.clamp(0, source - first);
+    let (dest_first, dest_second) = if first + second > dest {
+        let shrunk = first * dest / (first + second);
+        (shrunk, dest - shrunk)
+    } else {
+        (first, second)
+    };
+    ([0, first, source - second, source], [0, dest_first, dest - dest_second, dest])
+}
+
+/// The source rect of the bitmap stretched over the dest rect, nearest texel. Rects are x, y, width, height
+fn draw_region_stretched(buffer: &mut FrameBuffer, bitmap: &Bitmap, source: (i32, i32, i32, i32), dest: (i32, i32, i32, i32)) {
+    let (source_x, source_y, source_width, source_height) = source;
+    let (x, y, width, height) = dest;
+    let (min_x, min_y) = (x.max(0), y.max(0));
+    let (max_x, max_y) = ((x + width).min(buffer.width()), (y + height).min(buffer.height()));
+    if min_x >= max_x || min_y >= max_y || buffer.is_empty() {
+        return;
+    }
+    let mut row = vec![0; (max_x - min_x) as usize];
+    for dest_y in min_y..max_y {
+        let texel_y = source_y + (dest_y - y) * source_height / height;
+        for (dest_x, pixel) in (min_x..max_x).zip(row.iter_mut()) {
+            *pixel = texel_clamped(bitmap, source_x + (dest_x - x) * source_width / width, texel_y);
+        }
+        render_simd::blend(buffer.span_mut(min_x, dest_y, row.len()), &row);
+    }
+}
+
+/// Like draw_bitmap but only the source_width x source_height rect at source_x, source_y of the bitmap,
+/// for sprite sheets and atlases. The source rect is clamped to the bitmap.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_bitmap_region(
+    buffer: &mut FrameBuffer,
+    bitmap: &Bitmap,
+    source_x: i32,
+    source_y: i32,
+    source_width: i32,
+    source_height: i32,
+    x: f32,
+    y: f32,
+) {
+    // clamp the source rect first, shifting the destination along with whatever got cut off the top left
+    let (x, y) = (x.round() as i32 - source_x.min(0), y.round() as i32 - source_y.min(0));
+    let (source_min_x, source_min_y) = (source_x.max(0), source_y.max(0));
+    let source_max_x = (source_x + source_width).min(bitmap.width);
+    let source_max_y = (source_y + source_height).min(bitmap.height);
+
+    let min_x = x.max(0);
+    let min_y = y.max(0);
+    let max_x = (x + source_max_x - source_min_x).min(buffer.width());
+    let max_y = (y + source_max_y - source_min_y).min(buffer.height());
+    if min_x >= max_x || min_y >= max_y || buffer.is_empty() {
+        return;
+    }
+
+    let span = (max_x - min_x) as usize;
+    for dest_y in min_y..max_y {
+        let source_row = source_min_y + dest_y - y;
+        let source_start = (source_row * bitmap.width + source_min_x + min_x - x) as usize;
+        let source = &bitmap.pixels[source_start..source_start + span];
+        render_simd::blend(buffer.span_mut(min_x, dest_y, span), source);
+    }
+}
+
+/// How a scaled blit picks its source pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitmapFilter {
+    /// closest texel, blocky when magnified but keeps pixel art crisp
+    Nearest,
+    /// weighted mix of the four closest texels, smooth at any scale
+    Bilinear,
+}
+
+/// Draws the bitmap stretched to width x height with its top left corner at x, y. Every covered pixel maps its
+/// center back into the bitmap and samples it with the given filter, edges clamp. Nothing is drawn for a zero or negative size.
+pub fn draw_bitmap_scaled(buffer: &mut FrameBuffer, bitmap: &Bitmap, x: f32, y: f32, width: f32, height: f32, filter: BitmapFilter) {
+    if width <= 0.0 || height <= 0.0 || bitmap.width <= 0 || bitmap.height <= 0 || buffer.is_empty() {
+        return;
+    }
+    // same rounding as draw_rect, a pixel is covered when its center is inside
+    let min_x = (x.round() as i32).max(0);
+    let min_y = (y.round() as i32).max(0);
+    let max_x = ((x + width).round() as i32).min(buffer.width());
+    let max_y = ((y + height).round() as i32).min(buffer.height());
+    if min_x >= max_x || min_y >= max_y {
+        return;
+    }
+
+    let (scale_x, scale_y) = (bitmap.width as f32 / width, bitmap.height as f32 / height);
+    let span = (max_x - min_x) as usize;
+    // one row is sampled into here and then blended with the same path as unscaled blits
+    let mut row = vec![0; span];
+    for dest_y in min_y..max_y {
+        let v = (dest_y as f32 + 0.5 - y) * scale_y;
+        for (index, pixel) in row.iter_mut().enumerate() {
+            let u = ((min_x + index as i32) as f32 + 0.5 - x) * scale_x;
+            *pixel = match filter {
+                BitmapFilter::Nearest => texel_clamped(bitmap, u.floor() as i32, v.floor() as i32),
+                BitmapFilter::Bilinear => sample_bilinear(bitmap, u, v),
+            };
+        }
+        render_simd::blend(buffer.span_mut(min_x, dest_y, span), &row);
+    }
+}
+
+/// Fills the parallelogram origin, origin + x_axis, origin + y_axis, origin + x_axis + y_axis with the bitmap, its left edge
+/// along y_axis and top edge along x_axis, so rotation, scale, skew and flips all come from the axes.
+/// Each pixel center inside is mapped back through the inverse of the axes and sampled with the filter.
+pub fn draw_bitmap_transformed(buffer: &mut FrameBuffer, bitmap: &Bitmap, origin: V2, x_axis: V2, y_axis: V2, filter: BitmapFilter) {
+    if bitmap.width <= 0 || bitmap.height <= 0 {
+        return;
+    }
+    let Some(shape) = Parallelogram::new(buffer, origin, x_axis, y_axis) else {
+        return;
+    };
+
+    let (texels_x, texels_y) = (bitmap.width as f32, bitmap.height as f32);
+    draw_parallelogram_texels(buffer, &shape, |_, _, u, v| match filter {
+        BitmapFilter::Nearest => texel_clamped(bitmap, (u * texels_x) as i32, (v * texels_y) as i32),
+        BitmapFilter::Bilinear => sample_bilinear(bitmap, u * texels_x, v * texels_y),
+    });
+}
+
+/// Pictures of the surroundings for shiny bitmaps to reflect, each stretched over the whole frame:
+/// what's above the screen, what's behind it and what's below
+#[derive(Clone)]
+pub struct EnvironmentMaps {
+    pub top: Arc<Bitmap>,
+    pub middle: Arc<Bitmap>,
+    pub bottom: Arc<Bitmap>,
+}
+
+/// How a reflective bitmap picks up the environment maps
+#[derive(Clone, Copy)]
+pub struct Reflection<'a> {
+    pub environment: &'a EnvironmentMaps,
+    /// 0 is the plain bitmap, 1 a mirror
+    pub shininess: f32,
+    /// Where the buffer's top left corner sits in the frame, and the frame's size, the maps are laid over the frame
+    pub frame_origin: V2,
+    pub frame_size: V2,
+}
+
+// how far across the frame a surface turned fully sideways looks for its reflection
+const REFLECTION_REACH: f32 = 0.25;
+
+/// draw_bitmap_transformed (bilinear) with a normal map of the same size making the surface shiny. Each pixel
+/// bounces the view direction off its normal: the bounce's z (how far the surface is turned away from the viewer)
+/// sets how far from the pixel the middle map is sampled, and bounces that point up or down mix in the top or bottom map.
+/// The normal map is x in red, y in green (up is positive), z towards the viewer in blue, in the bitmap's own axes.
+pub fn draw_bitmap_reflective(buffer: &mut FrameBuffer, bitmap: &Bitmap, normal_map: &Bitmap, origin: V2, x_axis: V2, y_axis: V2, reflection: Reflection) {
+    if bitmap.width <= 0 || bitmap.height <= 0 || normal_map.width != bitmap.width || normal_map.height != bitmap.height {
+        return;
+    }
+    let Some(shape) = Parallelogram::new(buffer, origin, x_axis, y_axis) else {
+        return;
+    };
+
+    let (texels_x, texels_y) = (bitmap.width as f32, bitmap.height as f32);
+    // the normals turn with the bitmap
+    let x_direction = x_axis * (1.0 / x_axis.dot(x_axis).sqrt());
+    let y_direction = y_axis * (1.0 / y_axis.dot(y_axis).sqrt());
+    let Reflection { environment, shininess, frame_origin, frame_size } = reflection;
+    let shine = (shininess.clamp(0.0, 1.0) * 256.0) as u32;
+    draw_parallelogram_texels(buffer, &shape, |x, y, u, v| {
+        let albedo = sample_bilinear(bitmap, u * texels_x, v * texels_y);
+        let alpha = albedo >> 24;
+        if alpha == 0 {
+            return 0;
+        }
+        let normal_texel = texel_clamped(normal_map, (u * texels_x) as i32, (v * texels_y) as i32);
+        let [normal_z, normal_y, normal_x, _] = normal_texel.to_le_bytes().map(|channel| channel as f32 / 127.5 - 1.0);
+        // bitmap axes to screen, still with y down
+        let screen = x_direction * normal_x - y_direction * normal_y;
+        let length = (screen.dot(screen) + normal_z * normal_z).sqrt().max(1e-6);
+        let normal = (screen.x / length, screen.y / length, normal_z / length);
+
+        // the viewer looks straight down -z, so the bounce is 2 * n.z * n - (0, 0, 1)
+        let bounce = (2.0 * normal.2 * normal.0, 2.0 * normal.2 * normal.1, 2.0 * normal.2 * normal.2 - 1.0);
+        let reach = (1.0 - bounce.2) * REFLECTION_REACH;
+        let map_u = (frame_origin.x + x as f32 + 0.5) / frame_size.x + bounce.0 * reach;
+        let map_v = (frame_origin.y + y as f32 + 0.5) / frame_size.y + bounce.1 * reach;
+        let sample = |map: &Bitmap| sample_bilinear(map, map_u * map.width as f32, map_v * map.height as f32);
+        // y is down on screen, so a bounce with negative y heads for the top
+        let mut seen = sample(&environment.middle);
+        if bounce.1 < -0.5 {
+            seen = lerp_pixel(seen, sample(&environment.top), ((-bounce.1 - 0.5) * 512.0).min(256.0) as u32);
+        } else if bounce.1 > 0.5 {
+            seen = lerp_pixel(seen, sample(&environment.bottom), ((bounce.1 - 0.5) * 512.0).min(256.0) as u32);
+        }
+
+        // the reflection is only as solid as the bitmap it's on
+        lerp_pixel(albedo, lerp_pixel(0, seen, alpha + (alpha >> 7)), shine)
+    });
+}
+
+/// Blends texel(x, y, u, v) over each pixel of the shape, u and v run 0..1 across it
+fn draw_parallelogram_texels(buffer: &mut FrameBuffer, shape: &Parallelogram, mut texel: impl FnMut(i32, i32, f32, f32) -> Pixel) {
+    let mut row = Vec::with_capacity((shape.max_x - shape.min_x) as usize);
+    for y in shape.min_y..shape.max_y {
+        // the shape is convex, so the covered pixels of a row are one run
+        row.clear();
+        let mut start = None;
+        for x in shape.min_x..shape.max_x {
+            if let Some((u, v)) = shape.uv(x, y) {
+                start.get_or_insert(x);
+                row.push(texel(x, y, u, v));
+            } else if start.is_some() {
+                break;
+            }
+        }
+        if let Some(start) = start {
+            render_simd::blend(buffer.span_mut(start, y, row.len()), &row);
+        }
+    }
+}
+
+/// Fills the parallelogram origin, origin + x_axis, origin + y_axis, origin + x_axis + y_axis, what a rect turns into when it's rotated
+pub fn draw_parallelogram(buffer: &mut FrameBuffer, origin: V2, x_axis: V2, y_axis: V2, color: Color) {
+    let Some(shape) = Parallelogram::new(buffer, origin, x_axis, y_axis) else {
+        return;
+    };
+    let color = color.to_pixel();
+    for y in shape.min_y..shape.max_y {
+        let mut run = (shape.min_x..shape.max_x).skip_while(|&x| shape.uv(x, y).is_none());
+        let Some(start) = run.next() else {
+            continue;
+        };
+        let end = run.find(|&x| shape.uv(x, y).is_none()).unwrap_or(shape.max_x);
+        render_simd::fill(buffer.span_mut(start, y, (end - start) as usize), color);
+    }
+}
+
+/// Filled triangle with each vertex's color blended smoothly across it. Edge functions decide coverage with the
+/// top-left fill rule, so triangles sharing an edge (a quad split in two, a fan) touch every pixel along it exactly once.
+pub fn draw_triangle(buffer: &mut FrameBuffer, vertices: [V2; 3], colors: [Color; 3]) {
+    // twice the signed area, vertices get swapped round to make it positive (clockwise on screen)
+    let area = edge_function(vertices[0], vertices[1], vertices[2]);
+    if area.abs() < 1e-6 || buffer.is_empty() {
+        return;
+    }
+    let (vertices, colors) = if area < 0.0 {
+        ([vertices[0], vertices[2], vertices[1]], [colors[0], colors[2], colors[1]])
+    } else {
+        (vertices, colors)
+    };
+    let area = area.abs();
+
+    let min_x = (vertices.iter().map(|vertex| vertex.x).fold(f32::MAX, f32::min).floor() as i32).max(0);
+    let min_y = (vertices.iter().map(|vertex| vertex.y).fold(f32::MAX, f32::min).floor() as i32).max(0);
+    let max_x = (vertices.iter().map(|vertex| vertex.x).fold(f32::MIN, f32::max).ceil() as i32).min(buffer.width());
+    let max_y = (vertices.iter().map(|vertex| vertex.y).fold(f32::MIN, f32::max).ceil() as i32).min(buffer.height());
+    if min_x >= max_x || min_y >= max_y {
+        return;
+    }
+
+    // edge i is the one across from vertex i, its edge function is vertex i's weight
+    let edges = [(vertices[1], vertices[2]), (vertices[2], vertices[0]), (vertices[0], vertices[1])];
+    // clockwise on a y down screen, a top edge runs exactly rightwards and a left edge runs upwards
+    let top_left = edges.map(|(from, to)| (from.y == to.y && to.x > from.x) || to.y < from.y);
+    let mut row = Vec::with_capacity((max_x - min_x) as usize);
+    for y in min_y..max_y {
+        // the inside of a triangle is convex, so a row is one run
+        row.clear();
+        let mut start = None;
+        for x in min_x..max_x {
+            let center = V2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let weights = edges.map(|(from, to)| edge_function(from, to, center));
+            let inside = (0..3).all(|index| weights[index] > 0.0 || (weights[index] == 0.0 && top_left[index]));
+            if inside {
+                start.get_or_insert(x);
+                let (w0, w1, w2) = (weights[0] / area, weights[1] / area, weights[2] / area);
+                let color = Color::from_rgba_f32(
+                    colors[0].r * w0 + colors[1].r * w1 + colors[2].r * w2,
+                    colors[0].g * w0 + colors[1].g * w1 + colors[2].g * w2,
+                    colors[0].b * w0 + colors[1].b * w1 + colors[2].b * w2,
+                    colors[0].a * w0 + colors[1].a * w1 + colors[2].a * w2,
+                );
+                row.push(color.premultiplied().to_pixel());
+            } else if start.is_some() {
+                break;
+            }
+        }
+        if let Some(start) = start {
+            render_simd::blend(buffer.span_mut(start, y, row.len()), &row);
+        }
+    }
+}
+
+/// Positive when point is to the right of from -> to on a y down screen, twice the area of the triangle the three make
+fn edge_function(from: V2, to: V2, point: V2) -> f32 {
+    (to.x - from.x) * (point.y - from.y) - (to.y - from.y) * (point.x - from.x)
+}
+
+/// The inverse of the map (u, v) -> origin + u * x_axis + v * y_axis, plus the part of the buffer the shape can touch
+struct Parallelogram {
+    origin: V2,
+    x_normal: V2,
+    y_normal: V2,
+    min_x: i32,
+    min_y: i32,
+    max_x: i32,
+    max_y: i32,
+}
+
+impl Parallelogram {
+    /// None when the axes are parallel (no area) or the shape misses the buffer
+    fn new(buffer: &FrameBuffer, origin: V2, x_axis: V2, y_axis: V2) -> Option<Self> {
+        // with the axes as columns, u = dot(d, perp(y)) / dot(x, perp(y)) and v the same way round is the 2x2 inverse
+        let (x_normal, y_normal) = (y_axis.perp(), x_axis.perp());
+        let (x_denominator, y_denominator) = (x_axis.dot(x_normal), y_axis.dot(y_normal));
+        if x_denominator.abs() < 1e-6 || buffer.is_empty() {
+            return None;
+        }
+
+        let corners = [origin, origin + x_axis, origin + y_axis, origin + x_axis + y_axis];
+        let min_x = (corners.iter().map(|corner| corner.x).fold(f32::MAX, f32::min).floor() as i32).max(0);
+        let min_y = (corners.iter().map(|corner| corner.y).fold(f32::MAX, f32::min).floor() as i32).max(0);
+        let max_x = (corners.iter().map(|corner| corner.x).fold(f32::MIN, f32::max).ceil() as i32).min(buffer.width());
+        let max_y = (corners.iter().map(|corner| corner.y).fold(f32::MIN, f32::max).ceil() as i32).min(buffer.height());
+        if min_x >= max_x || min_y >= max_y {
+            return None;
+        }
+        Some(Parallelogram {
+            origin,
+            x_normal: x_normal * (1.0 / x_denominator),
+            y_normal: y_normal * (1.0 / y_denominator),
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        })
+    }
+
+    /// Where the center of pixel x, y lands across the shape, None outside it
+    fn uv(&self, x: i32, y: i32) -> Option<(f32, f32)> {
+        let offset = V2::new(x as f32 + 0.5, y as f32 + 0.5) - self.origin;
+        let (u, v) = (offset.dot(self.x_normal), offset.dot(self.y_normal));
+        ((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v)).then_some((u, v))
+    }
+}
+
+fn texel_clamped(bitmap: &Bitmap, x: i32, y: i32) -> Pixel {
+    let x = x.clamp(0, bitmap.width - 1);
+    let y = y.clamp(0, bitmap.height - 1);
+    bitmap.pixels[(y * bitmap.width + x) as usize]
+}
+
+// outside the bitmap is transparent, so its edges fade out when it sits between pixels
+fn texel_or_transparent(bitmap: &Bitmap, x: i32, y: i32) -> Pixel {
+    if (0..bitmap.width).contains(&x) && (0..bitmap.height).contains(&y) { bitmap.pixels[(y * bitmap.width + x) as usize] } else { 0 }
+}
+
+/// u, v in texel units, texel centers sit on .5
+fn sample_bilinear(bitmap: &Bitmap, u: f32, v: f32) -> Pixel {
+    sample_bilinear_with(u, v, |x, y| texel_clamped(bitmap, x, y))
+}
+
+fn sample_bilinear_with(u: f32, v: f32, texel: impl Fn(i32, i32) -> Pixel) -> Pixel {
+    let (u, v) = (u - 0.5, v - 0.5);
+    let (left, top) = (u.floor(), v.floor());
+    // 8 bit weights towards the right and bottom texels
+    let (weight_x, weight_y) = (((u - left) * 256.0) as u32, ((v - top) * 256.0) as u32);
+    let (left, top) = (left as i32, top as i32);
+    let upper = lerp_pixel(texel(left, top), texel(left + 1, top), weight_x);
+    let lower = lerp_pixel(texel(left, top + 1), texel(left + 1, top + 1), weight_x);
+    lerp_pixel(upper, lower, weight_y)
+}
+
+/// Per channel a + (b - a) * t / 256, two channels per multiply with a byte of headroom between them
+fn lerp_pixel(a: Pixel, b: Pixel, t: u32) -> Pixel {
+    let red_blue = (((a & 0x00FF00FF) * (256 - t) + (b & 0x00FF00FF) * t) >> 8) & 0x00FF00FF;
+    let alpha_green = (((a >> 8) & 0x00FF00FF) * (256 - t) + ((b >> 8) & 0x00FF00FF) * t) & 0xFF00FF00;
+    red_blue | alpha_green
+}
+
+/// Blends a coverage bitmap (baked glyphs) tinted with a premultiplied color, only its alpha is read
+fn draw_coverage(buffer: &mut FrameBuffer, coverage: &Bitmap, x: i32, y: i32, color: Pixel) {
+    let min_x = x.max(0);
+    let min_y = y.max(0);
+    let max_x = (x + coverage.width).min(buffer.width());
+    let max_y = (y + coverage.height).min(buffer.height());
+    if min_x >= max_x || min_y >= max_y || buffer.is_empty() {
+        return;
+    }
+
+    let span = (max_x - min_x) as usize;
+    let [blue, green, red, alpha] = color.to_le_bytes();
+    // tinted a row at a time so it goes through the same blend as bitmaps
+    let mut row = vec![0; span];
+    for dest_y in min_y..max_y {
+        let source_start = ((dest_y - y) * coverage.width + min_x - x) as usize;
+        let source = &coverage.pixels[source_start..source_start + span];
+        for (tinted, source) in row.iter_mut().zip(source) {
+            // the color is premultiplied already, so coverage scales all four channels alike
+            let coverage = source >> 24;
+            let scale = |channel: u8| ((channel as u32 * coverage + 127) / 255) as u8;
+            *tinted = pack_rgba8(scale(red), scale(green), scale(blue), scale(alpha));
+        }
+        render_simd::blend(buffer.span_mut(min_x, dest_y, span), &row);
+    }
+}
+
+/// Draws laid out text with its block's top left corner at x, y
+pub fn draw_glyphs(buffer: &mut FrameBuffer, font: &Font, glyphs: &[GlyphPlacement], x: f32, y: f32, color: Color) {
+    let color = color.premultiplied().to_pixel();
+    for placement in glyphs {
+        let glyph = font.glyph(placement.character);
+        let (pen_x, baseline) = ((x + placement.x).round() as i32, (y + placement.y).round() as i32);
+        draw_coverage(buffer, &glyph.bitmap, pen_x + glyph.offset_x, baseline + glyph.offset_y, color);
+    }
+}
+
+/// One pixel wide outline, both corners included. Edges outside the buffer are skipped rather than clamped onto its border.
+pub fn draw_rectangle_outline(buffer: &mut FrameBuffer, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+    let color = color.to_pixel();
+    let (left, right) = (x0.min(x1) as f32, (x0.max(x1) + 1) as f32);
+    let (top, bottom) = (y0.min(y1) as f32, (y0.max(y1) + 1) as f32);
+    fill_rect(buffer, left, top, right, top + 1.0, color);
+    fill_rect(buffer, left, bottom - 1.0, right, bottom, color);
+    fill_rect(buffer, left, top, left + 1.0, bottom, color);
+    fill_rect(buffer, right - 1.0, top, right, bottom, color);
+}
+
+pub fn render_gradient(buffer: &mut FrameBuffer, x_offset: i32, y_offset: i32) {
+    if buffer.is_empty() {
+        return;
+    }
+    // Fill with gradient, a row at a time
+    for (y, row) in buffer.rows_mut().enumerate() {
+        /*
+            offset          : +0 +1 +2 +3
+            Pixel in memory : 00 00 00 00
+            Channel         : BB GG RR xx (reversed little endian because windows reverses it to look like 0x xxRRGGBB)
+
+            in 32bit Register     : xx RR GG BB
+            this is why void pointer is cast to u32 to fill it and move to next pixel
+        */
+        // blue follows x, green follows y, red stays 0 and alpha 255
+        render_simd::gradient(row, x_offset, (y as i32 + y_offset) as u8);
+    }
+}
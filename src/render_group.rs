@@ -0,0 +1,775 @@
+use std::{ops::Range, sync::Arc};
+
+use crate::{
+    bitmap::Bitmap,
+    color::Color,
+    font::Font,
+    frame_buffer::FrameBuffer,
+    math::V2,
+    post_process::PostPass,
+    render::{
+        clear, draw_bitmap, draw_bitmap_scaled, draw_bitmap_transformed, draw_circle, draw_circle_outline, draw_line, draw_parallelogram,
+        draw_bitmap_lit, draw_bitmap_nine_slice, draw_bitmap_reflective, draw_glyphs, draw_gradient_rect, draw_particles, draw_rect, draw_rectangle_outline, draw_triangle, render_gradient, BitmapFilter,
+        EnvironmentMaps, Gradient, NineSlice, Particle, PointLight, Reflection,
+    },
+    test_pattern::{draw_test_pattern, TestPattern},
+    text_layout::{GlyphPlacement, TextBlock},
+    work_queue::WorkQueue,
+};
+
+// 4x4 tiles, a 1280x720 frame gives 320x180 tiles, enough jobs to keep 8-16 threads busy when some tiles are cheaper than others
+const TILE_COUNT_X: i32 = 4;
+const TILE_COUNT_Y: i32 = 4;
+// fewer bitmaps in a row than this aren't worth the overlay render_split needs to keep them in order
+const MIN_SPRITE_RUN: usize = 16;
+
+/// A plain bitmap draw handed to a GPU backend as a quad, from render_split. The corners are
+/// origin, origin + x_axis, origin + y_axis and origin + x_axis + y_axis, in pixels of the frame
+#[derive(Clone, Debug)]
+pub struct SpriteQuad {
+    pub bitmap: Arc<Bitmap>,
+    pub origin: V2,
+    pub x_axis: V2,
+    pub y_axis: V2,
+    pub filter: BitmapFilter,
+    pub clip: Option<DirtyRect>,
+}
+
+impl SpriteQuad {
+    /// The quad for a plain bitmap draw, None for every other command
+    fn from_command(pushed: &PushedCommand) -> Option<SpriteQuad> {
+        let (bitmap, origin, x_axis, y_axis, filter) = match &pushed.command {
+            // bilinear samples texel centers exactly at whole pixels and blends like the subpixel blit off them
+            RenderCommand::Bitmap { bitmap, x, y } => {
+                (bitmap, V2::new(*x, *y), V2::new(bitmap.width as f32, 0.0), V2::new(0.0, bitmap.height as f32), BitmapFilter::Bilinear)
+            }
+            RenderCommand::BitmapScaled { bitmap, x, y, width, height, filter } => {
+                (bitmap, V2::new(*x, *y), V2::new(*width, 0.0), V2::new(0.0, *height), *filter)
+            }
+            RenderCommand::BitmapTransformed { bitmap, origin, x_axis, y_axis, filter } => (bitmap, *origin, *x_axis, *y_axis, *filter),
+            _ => return None,
+        };
+        Some(SpriteQuad { bitmap: bitmap.clone(), origin, x_axis, y_axis, filter, clip: pushed.clip })
+    }
+}
+
+/// What render_split took out of the frame for the GPU
+#[derive(Default)]
+pub struct SpriteRun {
+    /// In draw order, over the buffer and under the overlay
+    pub quads: Vec<SpriteQuad>,
+    /// Whether anything was drawn into the overlay, it's left alone when the run was too short or came last
+    pub overlay: bool,
+    /// What went into the buffer and into the overlay, as describe_commands puts it, to label them in GPU captures
+    pub before: String,
+    pub after: String,
+}
+
+/// Region of the frame in pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl DirtyRect {
+    /// The overlap, empty (0 wide or high) when there isn't one
+    fn intersect(&self, other: &DirtyRect) -> DirtyRect {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let width = ((self.x + self.width).min(other.x + other.width) - x).max(0);
+        let height = ((self.y + self.height).min(other.y + other.height) - y).max(0);
+        DirtyRect { x, y, width, height }
+    }
+}
+
+/// An offscreen buffer in the group, returned by push_target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetId(usize);
+
+/// Commands drawn into a buffer of their own instead of the frame, rendered before the frame is
+struct RenderTarget {
+    width: i32,
+    height: i32,
+    commands: Vec<PushedCommand>,
+    clip_stack: Vec<DirtyRect>,
+}
+
+/// A command as it sits in the push buffer, with what push worked out for it
+struct PushedCommand {
+    order: Order,
+    // in pixels of the frame or target the command draws into
+    clip: Option<DirtyRect>,
+    command: RenderCommand,
+}
+
+/// One set of commands rasterized into one buffer: the frame or a target
+struct Pass<'a> {
+    commands: &'a [PushedCommand],
+    // every target finished before this pass, the rest are still empty
+    targets: &'a [Bitmap],
+    width: i32,
+    height: i32,
+}
+
+/// Hash of every tile from the last frame, what render_tiled compares against to tell which tiles changed
+#[derive(Default)]
+pub struct TileHashes {
+    width: i32,
+    height: i32,
+    hashes: Vec<u64>,
+}
+
+/// One draw call recorded by the game, rasterized later by RenderGroup::render
+#[derive(Clone, Debug)]
+pub enum RenderCommand {
+    Clear { color: Color },
+    Gradient { x_offset: i32, y_offset: i32 },
+    TestPattern { pattern: TestPattern },
+    Rect { min_x: f32, min_y: f32, max_x: f32, max_y: f32, color: Color },
+    GradientRect { min_x: f32, min_y: f32, max_x: f32, max_y: f32, gradient: Gradient },
+    RectOutline { x0: i32, y0: i32, x1: i32, y1: i32, color: Color },
+    Line { x0: f32, y0: f32, x1: f32, y1: f32, color: Color },
+    Circle { center_x: f32, center_y: f32, radius: f32, color: Color },
+    CircleOutline { center_x: f32, center_y: f32, radius: f32, color: Color },
+    Bitmap { bitmap: Arc<Bitmap>, x: f32, y: f32 },
+    BitmapScaled { bitmap: Arc<Bitmap>, x: f32, y: f32, width: f32, height: f32, filter: BitmapFilter },
+    BitmapTransformed { bitmap: Arc<Bitmap>, origin: V2, x_axis: V2, y_axis: V2, filter: BitmapFilter },
+    // lit by every light in the group
+    BitmapLit { bitmap: Arc<Bitmap>, normal_map: Arc<Bitmap>, x: f32, y: f32, ambient: Color },
+    // reflects the group's environment maps, drawn plain without them
+    BitmapReflective { bitmap: Arc<Bitmap>, normal_map: Arc<Bitmap>, origin: V2, x_axis: V2, y_axis: V2, shininess: f32 },
+    NineSlice { bitmap: Arc<Bitmap>, slice: NineSlice, x: f32, y: f32, width: f32, height: f32 },
+    // a run of the group's particle arena
+    Particles { particles: Range<usize>, bitmap: Option<Arc<Bitmap>> },
+    // a finished render target drawn like a bitmap
+    Target { target: usize, x: f32, y: f32, width: f32, height: f32 },
+    Parallelogram { origin: V2, x_axis: V2, y_axis: V2, color: Color },
+    Triangle { vertices: [V2; 3], colors: [Color; 3] },
+    // the placements live in the group's glyph arena
+    TextBlock { font: Arc<Font>, glyphs: Range<usize>, x: f32, y: f32, color: Color },
+}
+
+/// Where commands land in the draw order, see RenderGroup::sort_key
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SortKey {
+    pub layer: i32,
+    pub depth: Depth,
+}
+
+/// Orders commands within a layer, lowest first
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Depth {
+    /// Everything at 0, so push order decides
+    #[default]
+    PushOrder,
+    /// The command's bottom edge on screen, things lower down the screen are nearer and cover what's above them
+    Y,
+    Z(f32),
+}
+
+// a SortKey worked out for one command
+type Order = (i32, f32);
+
+impl RenderCommand {
+    /// The variant's name, for debug markers
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RenderCommand::Clear { .. } => "Clear",
+            RenderCommand::Gradient { .. } => "Gradient",
+            RenderCommand::TestPattern { .. } => "TestPattern",
+            RenderCommand::Rect { .. } => "Rect",
+            RenderCommand::GradientRect { .. } => "GradientRect",
+            RenderCommand::RectOutline { .. } => "RectOutline",
+            RenderCommand::Line { .. } => "Line",
+            RenderCommand::Circle { .. } => "Circle",
+            RenderCommand::CircleOutline { .. } => "CircleOutline",
+            RenderCommand::Bitmap { .. } => "Bitmap",
+            RenderCommand::BitmapScaled { .. } => "BitmapScaled",
+            RenderCommand::BitmapTransformed { .. } => "BitmapTransformed",
+            RenderCommand::BitmapLit { .. } => "BitmapLit",
+            RenderCommand::BitmapReflective { .. } => "BitmapReflective",
+            RenderCommand::NineSlice { .. } => "NineSlice",
+            RenderCommand::Particles { .. } => "Particles",
+            RenderCommand::Target { .. } => "Target",
+            RenderCommand::Parallelogram { .. } => "Parallelogram",
+            RenderCommand::Triangle { .. } => "Triangle",
+            RenderCommand::TextBlock { .. } => "TextBlock",
+        }
+    }
+
+    /// Lowest point on screen of what the command draws, for Depth::Y. Whole frame commands sit at the top
+    fn bottom(&self) -> f32 {
+        match self {
+            RenderCommand::Clear { .. } | RenderCommand::Gradient { .. } | RenderCommand::TestPattern { .. } => 0.0,
+            RenderCommand::Rect { min_y, max_y, .. } | RenderCommand::GradientRect { min_y, max_y, .. } => min_y.max(*max_y),
+            RenderCommand::RectOutline { y0, y1, .. } => (y0.max(y1) + 1) as f32,
+            RenderCommand::Line { y0, y1, .. } => y0.max(*y1),
+            RenderCommand::Circle { center_y, radius, .. } | RenderCommand::CircleOutline { center_y, radius, .. } => center_y + radius,
+            RenderCommand::Bitmap { bitmap, y, .. } | RenderCommand::BitmapLit { bitmap, y, .. } => y + bitmap.height as f32,
+            RenderCommand::BitmapScaled { y, height, .. } | RenderCommand::NineSlice { y, height, .. } | RenderCommand::Target { y, height, .. } => {
+                y + height
+            }
+            RenderCommand::BitmapTransformed { origin, x_axis, y_axis, .. }
+            | RenderCommand::BitmapReflective { origin, x_axis, y_axis, .. }
+            | RenderCommand::Parallelogram { origin, x_axis, y_axis, .. } => origin.y + x_axis.y.max(0.0) + y_axis.y.max(0.0),
+            RenderCommand::Triangle { vertices, .. } => vertices.iter().map(|vertex| vertex.y).fold(f32::MIN, f32::max),
+            RenderCommand::TextBlock { y, .. } => *y,
+            // spread all over, it goes in with the whole frame commands
+            RenderCommand::Particles { .. } => 0.0,
+        }
+    }
+}
+
+/// Maps world units to the screen: position is the world point that lands in the middle of the frame,
+/// zoom is pixels per world unit and rotation turns the world clockwise around that point, in radians
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: V2,
+    pub zoom: f32,
+    pub rotation: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera { position: V2::default(), zoom: 1.0, rotation: 0.0 }
+    }
+}
+
+impl Camera {
+    pub fn world_to_screen(&self, point: V2, screen_width: i32, screen_height: i32) -> V2 {
+        let center = V2::new(screen_width as f32 / 2.0, screen_height as f32 / 2.0);
+        center + (point - self.position).rotate(self.rotation) * self.zoom
+    }
+
+    pub fn screen_to_world(&self, point: V2, screen_width: i32, screen_height: i32) -> V2 {
+        let center = V2::new(screen_width as f32 / 2.0, screen_height as f32 / 2.0);
+        self.position + ((point - center) * (1.0 / self.zoom)).rotate(-self.rotation)
+    }
+
+    /// A world space direction or extent on screen, no translation
+    fn scale_axis(&self, axis: V2) -> V2 {
+        axis.rotate(self.rotation) * self.zoom
+    }
+}
+
+/// Push buffer of draw commands for one frame. Game code only records what it wants drawn,
+/// the platform decides when and how it gets rasterized into the backbuffer.
+/// Kept across frames and reset, so the command, glyph and particle storage is only allocated while it grows.
+#[derive(Default)]
+pub struct RenderGroup {
+    pub width: i32,
+    pub height: i32,
+    /// While set, the push_* positions and sizes are in world units and get mapped to the screen as they're pushed.
+    /// Clear and the gradient always cover the whole frame, and text keeps its pixel size.
+    pub camera: Option<Camera>,
+    /// What reflective bitmaps reflect, for the whole frame whenever it's set
+    pub environment: Option<EnvironmentMaps>,
+    /// Given to everything pushed while it's set. Commands are drawn sorted by layer, then by depth,
+    /// and keep their push order when both are equal, so the default key draws in push order.
+    pub sort_key: SortKey,
+    /// Color the finished frame is pulled towards by its alpha, after color grading. Transparent is no flash
+    pub flash: Color,
+    /// Run in order over the finished frame by GPU backends that can, see Renderer::runs_post_passes. The others
+    /// leave them out
+    pub post_passes: Vec<PostPass>,
+    commands: Vec<PushedCommand>,
+    // clip rects pushed while drawing into the frame, each already cut down to the ones under it
+    clip_stack: Vec<DirtyRect>,
+    glyphs: Vec<GlyphPlacement>,
+    // every particle pushed this frame, already on screen, the commands hold ranges of it
+    particles: Vec<Particle>,
+    lights: Vec<PointLight>,
+    targets: Vec<RenderTarget>,
+    // targets being drawn into, pushes go to the last one
+    target_stack: Vec<usize>,
+    // targets in the order they were popped, inner ones come out before the ones they're drawn into
+    target_order: Vec<usize>,
+}
+
+impl RenderGroup {
+    /// Empties the group for a new frame drawn at the given size, back in screen space
+    pub fn reset(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        self.camera = None;
+        self.environment = None;
+        self.sort_key = SortKey::default();
+        self.flash = Color::TRANSPARENT;
+        self.post_passes.clear();
+        self.commands.clear();
+        self.clip_stack.clear();
+        self.glyphs.clear();
+        self.particles.clear();
+        self.lights.clear();
+        self.targets.clear();
+        self.target_stack.clear();
+        self.target_order.clear();
+    }
+
+    /// Screen position back to world units with the current camera, for mouse and touch positions
+    pub fn unproject(&self, point: V2) -> V2 {
+        match &self.camera {
+            Some(camera) => camera.screen_to_world(point, self.width, self.height),
+            None => point,
+        }
+    }
+
+    fn to_screen(&self, point: V2) -> V2 {
+        match &self.camera {
+            Some(camera) => camera.world_to_screen(point, self.width, self.height),
+            None => point,
+        }
+    }
+
+    // a rotated camera turns rects into parallelograms and keeps bitmaps off the fast unrotated paths
+    fn rotated(&self) -> bool {
+        self.camera.is_some_and(|camera| camera.rotation != 0.0)
+    }
+
+    fn zoom(&self) -> f32 {
+        self.camera.map_or(1.0, |camera| camera.zoom)
+    }
+
+    fn scale_axis(&self, axis: V2) -> V2 {
+        match &self.camera {
+            Some(camera) => camera.scale_axis(axis),
+            None => axis,
+        }
+    }
+
+    pub fn push(&mut self, command: RenderCommand) {
+        let depth = match self.sort_key.depth {
+            Depth::PushOrder => 0.0,
+            Depth::Y => command.bottom(),
+            Depth::Z(z) => z,
+        };
+        let order = (self.sort_key.layer, depth);
+        let clip = self.clip_stack_mut().last().copied();
+        let pushed = PushedCommand { order, clip, command };
+        match self.target_stack.last() {
+            Some(&target) => self.targets[target].commands.push(pushed),
+            None => self.commands.push(pushed),
+        }
+    }
+
+    /// Keeps everything pushed until the matching pop_clip_rect inside the rect, on top of any clip rect already pushed.
+    /// In pixels of whatever is being drawn into, the camera doesn't apply. Each target has its own clip rects.
+    pub fn push_clip_rect(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        let rect = DirtyRect { x, y, width: width.max(0), height: height.max(0) };
+        let stack = self.clip_stack_mut();
+        let clip = stack.last().map_or(rect, |outer| outer.intersect(&rect));
+        stack.push(clip);
+    }
+
+    pub fn pop_clip_rect(&mut self) {
+        self.clip_stack_mut().pop().expect("pop_clip_rect without a push_clip_rect");
+    }
+
+    fn clip_stack_mut(&mut self) -> &mut Vec<DirtyRect> {
+        match self.target_stack.last() {
+            Some(&target) => &mut self.targets[target].clip_stack,
+            None => &mut self.clip_stack,
+        }
+    }
+
+    /// Sends everything pushed until the matching pop_target into a new transparent width x height buffer.
+    /// The camera still applies, set it to None to draw in the target's own pixels. Targets nest.
+    pub fn push_target(&mut self, width: i32, height: i32) -> TargetId {
+        self.targets.push(RenderTarget { width: width.max(0), height: height.max(0), commands: Vec::new(), clip_stack: Vec::new() });
+        self.target_stack.push(self.targets.len() - 1);
+        TargetId(self.targets.len() - 1)
+    }
+
+    /// Goes back to drawing into whatever was being drawn into before the last push_target
+    pub fn pop_target(&mut self) {
+        let target = self.target_stack.pop().expect("pop_target without a push_target");
+        self.target_order.push(target);
+    }
+
+    /// Draws a popped target's contents, stretched to width x height at x, y. Drawing a target into itself,
+    /// or one still being drawn into, draws nothing.
+    pub fn push_target_output(&mut self, target: TargetId, x: f32, y: f32, width: f32, height: f32) {
+        let position = self.to_screen(V2::new(x, y));
+        let (width, height) = (width * self.zoom(), height * self.zoom());
+        self.push(RenderCommand::Target { target: target.0, x: position.x, y: position.y, width, height });
+    }
+
+    pub fn push_clear(&mut self, color: Color) {
+        self.push(RenderCommand::Clear { color });
+    }
+
+    pub fn push_gradient(&mut self, x_offset: i32, y_offset: i32) {
+        self.push(RenderCommand::Gradient { x_offset, y_offset });
+    }
+
+    /// Covers the whole frame (or target) like a clear, whatever the camera
+    pub fn push_test_pattern(&mut self, pattern: TestPattern) {
+        self.push(RenderCommand::TestPattern { pattern });
+    }
+
+    pub fn push_rect(&mut self, min_x: f32, min_y: f32, max_x: f32, max_y: f32, color: Color) {
+        let origin = self.to_screen(V2::new(min_x, min_y));
+        let x_axis = self.scale_axis(V2::new(max_x - min_x, 0.0));
+        let y_axis = self.scale_axis(V2::new(0.0, max_y - min_y));
+        if self.rotated() {
+            self.push(RenderCommand::Parallelogram { origin, x_axis, y_axis, color });
+        } else {
+            let corner = origin + x_axis + y_axis;
+            self.push(RenderCommand::Rect { min_x: origin.x, min_y: origin.y, max_x: corner.x, max_y: corner.y, color });
+        }
+    }
+
+    /// Axis aligned on screen even under a rotated camera, its corners are mapped and the bounds of those are filled
+    pub fn push_gradient_rect(&mut self, min_x: f32, min_y: f32, max_x: f32, max_y: f32, gradient: Gradient) {
+        let corners = [(min_x, min_y), (max_x, min_y), (min_x, max_y), (max_x, max_y)].map(|(x, y)| self.to_screen(V2::new(x, y)));
+        let (min_x, min_y) = corners.iter().fold((f32::MAX, f32::MAX), |(x, y), corner| (x.min(corner.x), y.min(corner.y)));
+        let (max_x, max_y) = corners.iter().fold((f32::MIN, f32::MIN), |(x, y), corner| (x.max(corner.x), y.max(corner.y)));
+        self.push(RenderCommand::GradientRect { min_x, min_y, max_x, max_y, gradient });
+    }
+
+    /// Darkens the edges of the whole frame, strength is how black the corners get (0..1). Always screen space
+    pub fn push_vignette(&mut self, strength: f32) {
+        let outer = Color::from_rgba_f32(0.0, 0.0, 0.0, strength);
+        let gradient = Gradient::Radial { inner: Color::TRANSPARENT, outer, start: 0.5 };
+        let (width, height) = (self.width as f32, self.height as f32);
+        // radial reaches outer at the edge midpoints, the corners are a further sqrt(2) out and clamp to it
+        self.push(RenderCommand::GradientRect { min_x: 0.0, min_y: 0.0, max_x: width, max_y: height, gradient });
+    }
+
+    pub fn push_rect_outline(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        if self.camera.is_none() {
+            return self.push(RenderCommand::RectOutline { x0, y0, x1, y1, color });
+        }
+        let corners = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)].map(|(x, y)| self.to_screen(V2::new(x as f32, y as f32)));
+        if self.rotated() {
+            for (from, to) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+                self.push(RenderCommand::Line { x0: from.x, y0: from.y, x1: to.x, y1: to.y, color });
+            }
+        } else {
+            let (from, to) = (corners[0], corners[2]);
+            self.push(RenderCommand::RectOutline {
+                x0: from.x.round() as i32,
+                y0: from.y.round() as i32,
+                x1: to.x.round() as i32,
+                y1: to.y.round() as i32,
+                color,
+            });
+        }
+    }
+
+    pub fn push_line(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, color: Color) {
+        let (from, to) = (self.to_screen(V2::new(x0, y0)), self.to_screen(V2::new(x1, y1)));
+        self.push(RenderCommand::Line { x0: from.x, y0: from.y, x1: to.x, y1: to.y, color });
+    }
+
+    pub fn push_circle(&mut self, center_x: f32, center_y: f32, radius: f32, color: Color) {
+        let center = self.to_screen(V2::new(center_x, center_y));
+        let radius = radius * self.zoom();
+        self.push(RenderCommand::Circle { center_x: center.x, center_y: center.y, radius, color });
+    }
+
+    pub fn push_circle_outline(&mut self, center_x: f32, center_y: f32, radius: f32, color: Color) {
+        let center = self.to_screen(V2::new(center_x, center_y));
+        let radius = radius * self.zoom();
+        self.push(RenderCommand::CircleOutline { center_x: center.x, center_y: center.y, radius, color });
+    }
+
+    /// Each vertex has its own color, blended across the inside
+    pub fn push_triangle(&mut self, vertices: [V2; 3], colors: [Color; 3]) {
+        let vertices = vertices.map(|vertex| self.to_screen(vertex));
+        self.push(RenderCommand::Triangle { vertices, colors });
+    }
+
+    pub fn push_bitmap(&mut self, bitmap: &Arc<Bitmap>, x: f32, y: f32) {
+        if self.zoom() == 1.0 && !self.rotated() {
+            let position = self.to_screen(V2::new(x, y));
+            self.push(RenderCommand::Bitmap { bitmap: bitmap.clone(), x: position.x, y: position.y });
+        } else {
+            self.push_bitmap_scaled(bitmap, x, y, bitmap.width as f32, bitmap.height as f32, BitmapFilter::Bilinear);
+        }
+    }
+
+    /// Lights every lit bitmap in the frame, including ones pushed before it
+    pub fn push_light(&mut self, light: PointLight) {
+        let position = self.to_screen(light.position);
+        let radius = light.radius * self.zoom();
+        self.lights.push(PointLight { position, radius, ..light });
+    }
+
+    /// Drawn unscaled at its mapped position whatever the camera's zoom and rotation, the lighting is what's interesting
+    pub fn push_bitmap_lit(&mut self, bitmap: &Arc<Bitmap>, normal_map: &Arc<Bitmap>, x: f32, y: f32, ambient: Color) {
+        let position = self.to_screen(V2::new(x, y));
+        self.push(RenderCommand::BitmapLit { bitmap: bitmap.clone(), normal_map: normal_map.clone(), x: position.x, y: position.y, ambient });
+    }
+
+    pub fn push_bitmap_scaled(&mut self, bitmap: &Arc<Bitmap>, x: f32, y: f32, width: f32, height: f32, filter: BitmapFilter) {
+        if self.rotated() {
+            return self.push_bitmap_transformed(bitmap, V2::new(x, y), V2::new(width, 0.0), V2::new(0.0, height), filter);
+        }
+        let position = self.to_screen(V2::new(x, y));
+        let (width, height) = (width * self.zoom(), height * self.zoom());
+        self.push(RenderCommand::BitmapScaled { bitmap: bitmap.clone(), x: position.x, y: position.y, width, height, filter });
+    }
+
+    pub fn push_bitmap_transformed(&mut self, bitmap: &Arc<Bitmap>, origin: V2, x_axis: V2, y_axis: V2, filter: BitmapFilter) {
+        let (origin, x_axis, y_axis) = (self.to_screen(origin), self.scale_axis(x_axis), self.scale_axis(y_axis));
+        self.push(RenderCommand::BitmapTransformed { bitmap: bitmap.clone(), origin, x_axis, y_axis, filter });
+    }
+
+    pub fn push_bitmap_reflective(&mut self, bitmap: &Arc<Bitmap>, normal_map: &Arc<Bitmap>, origin: V2, x_axis: V2, y_axis: V2, shininess: f32) {
+        let (origin, x_axis, y_axis) = (self.to_screen(origin), self.scale_axis(x_axis), self.scale_axis(y_axis));
+        self.push(RenderCommand::BitmapReflective { bitmap: bitmap.clone(), normal_map: normal_map.clone(), origin, x_axis, y_axis, shininess });
+    }
+
+    /// A UI box made from the bitmap, see draw_bitmap_nine_slice. The camera's rotation doesn't apply,
+    /// the box stays upright with its top left corner where x, y maps to
+    pub fn push_bitmap_nine_slice(&mut self, bitmap: &Arc<Bitmap>, slice: NineSlice, x: f32, y: f32, width: f32, height: f32) {
+        let position = self.to_screen(V2::new(x, y));
+        let (width, height) = (width * self.zoom(), height * self.zoom());
+        self.push(RenderCommand::NineSlice { bitmap: bitmap.clone(), slice, x: position.x, y: position.y, width, height });
+    }
+
+    /// One command for all of them, however many there are. Positions and sizes go through the camera,
+    /// a bitmap gets shrunk to each particle's size and tinted with its color
+    pub fn push_particles(&mut self, particles: &[Particle], bitmap: Option<&Arc<Bitmap>>) {
+        let start = self.particles.len();
+        let zoom = self.zoom();
+        for particle in particles {
+            let position = self.to_screen(particle.position);
+            self.particles.push(Particle { position, size: particle.size * zoom, ..*particle });
+        }
+        self.push(RenderCommand::Particles { particles: start..self.particles.len(), bitmap: bitmap.cloned() });
+    }
+
+    /// Text laid out by TextLayout, with the block's top left corner at x, y. Only the position goes through the camera,
+    /// the glyphs keep their pixel size
+    pub fn push_text_block(&mut self, font: &Arc<Font>, block: &TextBlock, x: f32, y: f32, color: Color) {
+        let position = self.to_screen(V2::new(x, y));
+        let start = self.glyphs.len();
+        self.glyphs.extend_from_slice(&block.glyphs);
+        self.push(RenderCommand::TextBlock { font: font.clone(), glyphs: start..self.glyphs.len(), x: position.x, y: position.y, color });
+    }
+
+    /// Sorts every command list and rasterizes the targets, what render_tiled and render_split both start with.
+    /// The targets are only needed until the frame is drawn
+    fn sort_and_render_targets(&mut self, queue: &WorkQueue) -> Vec<Bitmap> {
+        // stable, so equal keys stay in push order
+        let by_order = |a: &PushedCommand, b: &PushedCommand| a.order.0.cmp(&b.order.0).then(a.order.1.total_cmp(&b.order.1));
+        self.commands.sort_by(by_order);
+        for target in self.targets.iter_mut() {
+            target.commands.sort_by(by_order);
+        }
+
+        let mut targets = vec![Bitmap { width: 0, height: 0, pixels: Vec::new() }; self.targets.len()];
+        for &index in self.target_order.iter() {
+            let RenderTarget { width, height, ref commands, .. } = self.targets[index];
+            let mut bitmap = Bitmap { width, height, pixels: vec![0; (width * height) as usize] };
+            let pass = Pass { commands, targets: &targets, width, height };
+            self.render_pass(&pass, &mut FrameBuffer::new(&mut bitmap.pixels, width, height), queue);
+            targets[index] = bitmap;
+        }
+        targets
+    }
+
+    /// Every command of the pass into the whole buffer, a tile per job
+    fn render_pass(&self, pass: &Pass, buffer: &mut FrameBuffer, queue: &WorkQueue) {
+        let tiles = tile_grid(buffer.width(), buffer.height());
+        let views = buffer.split_mut(&tiles);
+        queue.complete_all(tiles.iter().zip(views).map(|(rect, view)| self.tile_job(pass, view, *rect, None)));
+    }
+
+    /// Sorts the commands, then rasterizes them with the software renderer. The buffer is cut into a grid of tiles
+    /// and each tile runs every command (clipped to itself) as a job on the work queue. Returns once all tiles are done,
+    /// with the parts of the frame that differ from the previous one, neighbouring changed tiles in a row merged.
+    /// Render targets are drawn first, the same way, each into a buffer that only lives for this call.
+    pub fn render_tiled(&mut self, buffer: &mut FrameBuffer, queue: &WorkQueue, previous: &mut TileHashes) -> Vec<DirtyRect> {
+        let targets = self.sort_and_render_targets(queue);
+        let tiles = tile_grid(buffer.width(), buffer.height());
+        let pass = Pass { commands: &self.commands, targets: &targets, width: self.width, height: self.height };
+        // every tile hashes itself once it's drawn, so the comparison costs no extra pass on one thread
+        let mut hashes = vec![0; tiles.len()];
+        let views = buffer.split_mut(&tiles);
+        let jobs: Vec<_> = tiles.iter().zip(views).zip(hashes.iter_mut()).map(|((rect, view), hash)| self.tile_job(&pass, view, *rect, Some(hash))).collect();
+        queue.complete_all(jobs);
+
+        // a different size means nothing from last frame lines up, all of it goes out
+        let resized = previous.width != buffer.width() || previous.height != buffer.height() || previous.hashes.len() != hashes.len();
+        let mut dirty: Vec<DirtyRect> = Vec::new();
+        for (index, rect) in tiles.iter().enumerate() {
+            if !resized && previous.hashes[index] == hashes[index] {
+                continue;
+            }
+            match dirty.last_mut() {
+                Some(last) if last.y == rect.y && last.x + last.width == rect.x => last.width += rect.width,
+                _ => dirty.push(*rect),
+            }
+        }
+        *previous = TileHashes { width: buffer.width(), height: buffer.height(), hashes };
+        dirty
+    }
+
+    /// render_tiled for GPU backends that draw bitmaps themselves. The longest unbroken run of plain bitmap draws in the
+    /// sorted frame commands comes back as quads, whatever's before it is rasterized into buffer and whatever's after
+    /// into overlay, cleared to transparent first, to go over the quads. Blending over is associative, so the three
+    /// layers stacked up come out the same as drawing it all in order. Only bitmaps fits says yes to go in the run
+    pub fn render_split(&mut self, buffer: &mut FrameBuffer, overlay: &mut FrameBuffer, queue: &WorkQueue, mut fits: impl FnMut(&Arc<Bitmap>) -> bool) -> SpriteRun {
+        let targets = self.sort_and_render_targets(queue);
+        let quads: Vec<Option<SpriteQuad>> = self.commands.iter().map(|pushed| SpriteQuad::from_command(pushed).filter(|quad| fits(&quad.bitmap))).collect();
+        let mut run = 0..0;
+        let mut start = 0;
+        for (index, quad) in quads.iter().enumerate() {
+            if quad.is_none() {
+                start = index + 1;
+            } else if index + 1 - start > run.len() {
+                run = start..index + 1;
+            }
+        }
+        if run.len() < MIN_SPRITE_RUN {
+            run = self.commands.len()..self.commands.len();
+        }
+
+        let (width, height) = (self.width, self.height);
+        self.render_pass(&Pass { commands: &self.commands[..run.start], targets: &targets, width, height }, buffer, queue);
+        let after = &self.commands[run.end..];
+        if !after.is_empty() {
+            clear(overlay, Color::TRANSPARENT);
+            self.render_pass(&Pass { commands: after, targets: &targets, width, height }, overlay, queue);
+        }
+        SpriteRun {
+            overlay: !after.is_empty(),
+            before: describe_commands(&self.commands[..run.start]),
+            after: describe_commands(after),
+            quads: quads.into_iter().take(run.end).skip(run.start).flatten().collect(),
+        }
+    }
+
+    /// Renders the view of rect as a job, hashing it afterwards when there's somewhere to put the hash
+    fn tile_job<'a>(
+        &'a self,
+        pass: &'a Pass,
+        mut tile: FrameBuffer<'a>,
+        rect: DirtyRect,
+        hash: Option<&'a mut u64>,
+    ) -> Box<dyn FnOnce() + Send + 'a> {
+        Box::new(move || {
+            self.render_tile(pass, &mut tile, rect.x, rect.y);
+            if let Some(hash) = hash {
+                *hash = tile.content_hash();
+            }
+        })
+    }
+
+    /// Draws into a view whose top left corner is at origin_x, origin_y of the full frame (or target)
+    fn render_tile(&self, pass: &Pass, target: &mut FrameBuffer, origin_x: i32, origin_y: i32) {
+        let tile = DirtyRect { x: origin_x, y: origin_y, width: target.width(), height: target.height() };
+        for PushedCommand { clip, command, .. } in pass.commands.iter() {
+            match clip {
+                None => self.render_command(pass, command, target, origin_x, origin_y),
+                // drawn into a smaller view of the tile, which the draw calls clip to like any other buffer
+                Some(clip) => {
+                    let visible = tile.intersect(clip);
+                    if visible.width > 0 && visible.height > 0 {
+                        let mut view = target.view_mut(visible.x - origin_x, visible.y - origin_y, visible.width, visible.height);
+                        self.render_command(pass, command, &mut view, visible.x, visible.y);
+                    }
+                }
+            }
+        }
+    }
+
+    /// One command into a view whose top left corner is at origin_x, origin_y of the full frame (or target)
+    fn render_command(&self, pass: &Pass, command: &RenderCommand, target: &mut FrameBuffer, origin_x: i32, origin_y: i32) {
+        let (x_shift, y_shift) = (origin_x as f32, origin_y as f32);
+        match command {
+            RenderCommand::Clear { color } => clear(target, *color),
+            RenderCommand::Gradient { x_offset, y_offset } => render_gradient(target, x_offset + origin_x, y_offset + origin_y),
+            RenderCommand::TestPattern { pattern } => draw_test_pattern(target, *pattern, origin_x, origin_y, pass.width, pass.height),
+            RenderCommand::Rect { min_x, min_y, max_x, max_y, color } => {
+                draw_rect(target, min_x - x_shift, min_y - y_shift, max_x - x_shift, max_y - y_shift, *color)
+            }
+            RenderCommand::GradientRect { min_x, min_y, max_x, max_y, gradient } => {
+                draw_gradient_rect(target, min_x - x_shift, min_y - y_shift, max_x - x_shift, max_y - y_shift, *gradient)
+            }
+            RenderCommand::RectOutline { x0, y0, x1, y1, color } => {
+                draw_rectangle_outline(target, x0 - origin_x, y0 - origin_y, x1 - origin_x, y1 - origin_y, *color)
+            }
+            RenderCommand::Line { x0, y0, x1, y1, color } => draw_line(target, x0 - x_shift, y0 - y_shift, x1 - x_shift, y1 - y_shift, *color),
+            RenderCommand::Circle { center_x, center_y, radius, color } => {
+                draw_circle(target, center_x - x_shift, center_y - y_shift, *radius, *color)
+            }
+            RenderCommand::CircleOutline { center_x, center_y, radius, color } => {
+                draw_circle_outline(target, center_x - x_shift, center_y - y_shift, *radius, *color)
+            }
+            RenderCommand::Bitmap { bitmap, x, y } => draw_bitmap(target, bitmap, x - x_shift, y - y_shift),
+            RenderCommand::BitmapScaled { bitmap, x, y, width, height, filter } => {
+                draw_bitmap_scaled(target, bitmap, x - x_shift, y - y_shift, *width, *height, *filter)
+            }
+            RenderCommand::BitmapTransformed { bitmap, origin, x_axis, y_axis, filter } => {
+                draw_bitmap_transformed(target, bitmap, *origin - V2::new(x_shift, y_shift), *x_axis, *y_axis, *filter)
+            }
+            RenderCommand::BitmapLit { bitmap, normal_map, x, y, ambient } => {
+                let shift = V2::new(x_shift, y_shift);
+                let lights: Vec<PointLight> = self.lights.iter().map(|light| PointLight { position: light.position - shift, ..*light }).collect();
+                draw_bitmap_lit(target, bitmap, normal_map, x - x_shift, y - y_shift, &lights, *ambient)
+            }
+            RenderCommand::BitmapReflective { bitmap, normal_map, origin, x_axis, y_axis, shininess } => {
+                let origin = *origin - V2::new(x_shift, y_shift);
+                match &self.environment {
+                    Some(environment) => {
+                        let frame_size = V2::new(pass.width as f32, pass.height as f32);
+                        let reflection = Reflection { environment, shininess: *shininess, frame_origin: V2::new(x_shift, y_shift), frame_size };
+                        draw_bitmap_reflective(target, bitmap, normal_map, origin, *x_axis, *y_axis, reflection)
+                    }
+                    None => draw_bitmap_transformed(target, bitmap, origin, *x_axis, *y_axis, BitmapFilter::Bilinear),
+                }
+            }
+            RenderCommand::NineSlice { bitmap, slice, x, y, width, height } => {
+                draw_bitmap_nine_slice(target, bitmap, *slice, x - x_shift, y - y_shift, *width, *height)
+            }
+            RenderCommand::Particles { particles, bitmap } => {
+                draw_particles(target, &self.particles[particles.clone()], V2::new(x_shift, y_shift), bitmap.as_deref())
+            }
+            RenderCommand::Target { target: index, x, y, width, height } => {
+                if let Some(bitmap) = pass.targets.get(*index) {
+                    draw_bitmap_scaled(target, bitmap, x - x_shift, y - y_shift, *width, *height, BitmapFilter::Bilinear)
+                }
+            }
+            RenderCommand::Parallelogram { origin, x_axis, y_axis, color } => {
+                draw_parallelogram(target, *origin - V2::new(x_shift, y_shift), *x_axis, *y_axis, *color)
+            }
+            RenderCommand::Triangle { vertices, colors } => {
+                draw_triangle(target, vertices.map(|vertex| vertex - V2::new(x_shift, y_shift)), *colors)
+            }
+            RenderCommand::TextBlock { font, glyphs, x, y, color } => {
+                draw_glyphs(target, font, &self.glyphs[glyphs.clone()], x - x_shift, y - y_shift, *color)
+            }
+        }
+    }
+}
+
+/// The kinds of command in order with repeats counted, "Clear, Rect x12, TextBlock"
+fn describe_commands(commands: &[PushedCommand]) -> String {
+    let kinds: Vec<String> = commands
+        .chunk_by(|a, b| a.command.kind() == b.command.kind())
+        .map(|same| match same.len() {
+            1 => same[0].command.kind().to_string(),
+            count => format!("{} x{count}", same[0].command.kind()),
+        })
+        .collect();
+    kinds.join(", ")
+}
+
+/// Cuts a width x height buffer into the TILE_COUNT_X x TILE_COUNT_Y grid, row by row
+fn tile_grid(width: i32, height: i32) -> Vec<DirtyRect> {
+    let tile_width = ((width + TILE_COUNT_X - 1) / TILE_COUNT_X).max(1);
+    let tile_height = ((height + TILE_COUNT_Y - 1) / TILE_COUNT_Y).max(1);
+    let mut tiles = Vec::new();
+    for tile_y in (0..height).step_by(tile_height as usize) {
+        for tile_x in (0..width).step_by(tile_width as usize) {
+            tiles.push(DirtyRect { x: tile_x, y: tile_y, width: tile_width.min(width - tile_x), height: tile_height.min(height - tile_y) });
+        }
+    }
+    tiles
+}
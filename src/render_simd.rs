@@ -0,0 +1,222 @@
+//! Wide versions of the inner pixel loops. AVX2 is picked at runtime when the cpu has it,
+//! SSE2 is always there on x86_64, and the scalar loops stay as the fallback for everything else.
+//! Gamma correct blending goes through lookup tables, which don't vectorize, so it only has the scalar loop.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::color::{gamma_tables, pack_rgba8, GammaTables, Pixel};
+
+static GAMMA_CORRECT: AtomicBool = AtomicBool::new(false);
+
+/// Switches blends between plain sRGB math (fast, dark fringes on soft edges) and blending in linear light
+pub fn set_gamma_correct(enabled: bool) {
+    GAMMA_CORRECT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn gamma_correct() -> bool {
+    GAMMA_CORRECT.load(Ordering::Relaxed)
+}
+
+/// x / 255 rounded to nearest, exact for every x a blend of two bytes can produce
+#[inline]
+fn div_255(x: u32) -> u32 {
+    let x = x + 128;
+    (x + (x >> 8)) >> 8
+}
+
+/// Source-over blend of one premultiplied 0xAARRGGBB pixel onto another: every lane, alpha included,
+/// is source + dest * (1 - source alpha), which is what lets the wide paths treat all four lanes alike.
+/// Saturating so a pixel with a channel above its alpha can't wrap around.
+#[inline]
+fn blend_pixel(source: Pixel, dest: Pixel) -> Pixel {
+    let alpha = source >> 24;
+    match alpha {
+        0 => dest,
+        255 => source,
+        _ => {
+            let inverse = 255 - alpha;
+            let channel = |shift: u32| (((source >> shift) & 0xFF) + div_255(((dest >> shift) & 0xFF) * inverse)).min(255) << shift;
+            channel(24) | channel(16) | channel(8) | channel(0)
+        }
+    }
+}
+
+/// Same as blend_pixel but the color channels are converted to linear light, mixed, and converted back.
+/// The premultiply happened on sRGB values, so the source is un-premultiplied before it is linearized.
+/// Alpha is coverage rather than a color so it mixes the same as before.
+#[inline]
+fn blend_pixel_linear(source: Pixel, dest: Pixel, tables: &GammaTables) -> Pixel {
+    let alpha = source >> 24;
+    match alpha {
+        0 => dest,
+        255 => source,
+        _ => {
+            let inverse = 255 - alpha;
+            let channel = |shift: u32| {
+                let straight = ((((source >> shift) & 0xFF) * 255 + alpha / 2) / alpha).min(255);
+                let source = tables.to_linear[straight as usize] as u32;
+                let dest = tables.to_linear[((dest >> shift) & 0xFF) as usize] as u32;
+                let mixed = (source * alpha + dest * inverse) / 255;
+                (tables.to_srgb[((mixed + 8) >> 4).min(4095) as usize] as u32) << shift
+            };
+            div_255(255 * alpha + (dest >> 24) * inverse) << 24 | channel(16) | channel(8) | channel(0)
+        }
+    }
+}
+
+pub fn fill(span: &mut [Pixel], color: Pixel) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { fill_avx2(span, color) };
+        }
+        unsafe { fill_sse2(span, color) }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    span.fill(color);
+}
+
+/// Blends source onto dest pixel by pixel, both spans are the same length
+pub fn blend(dest: &mut [Pixel], source: &[Pixel]) {
+    debug_assert_eq!(dest.len(), source.len());
+    if gamma_correct() {
+        return blend_linear_scalar(dest, source, gamma_tables());
+    }
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { blend_avx2(dest, source) };
+        }
+        unsafe { blend_sse2(dest, source) }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    blend_scalar(dest, source);
+}
+
+/// One row of the test gradient: blue counts up from blue_start along the row, green is fixed
+pub fn gradient(span: &mut [Pixel], blue_start: i32, green: u8) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        gradient_sse2(span, blue_start, green)
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    gradient_scalar(span, blue_start, green);
+}
+
+fn blend_scalar(dest: &mut [Pixel], source: &[Pixel]) {
+    for (dest, source) in dest.iter_mut().zip(source) {
+        *dest = blend_pixel(*source, *dest);
+    }
+}
+
+fn blend_linear_scalar(dest: &mut [Pixel], source: &[Pixel], tables: &GammaTables) {
+    for (dest, source) in dest.iter_mut().zip(source) {
+        *dest = blend_pixel_linear(*source, *dest, tables);
+    }
+}
+
+fn gradient_scalar(span: &mut [Pixel], blue_start: i32, green: u8) {
+    for (x, pixel) in span.iter_mut().enumerate() {
+        *pixel = pack_rgba8(0, green, (blue_start + x as i32) as u8, 255);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn fill_avx2(span: &mut [Pixel], color: Pixel) {
+    let wide = _mm256_set1_epi32(color as i32);
+    let mut chunks = span.chunks_exact_mut(8);
+    for chunk in chunks.by_ref() {
+        unsafe { _mm256_storeu_si256(chunk.as_mut_ptr() as *mut __m256i, wide) };
+    }
+    chunks.into_remainder().fill(color);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn fill_sse2(span: &mut [Pixel], color: Pixel) {
+    let wide = _mm_set1_epi32(color as i32);
+    let mut chunks = span.chunks_exact_mut(4);
+    for chunk in chunks.by_ref() {
+        unsafe { _mm_storeu_si128(chunk.as_mut_ptr() as *mut __m128i, wide) };
+    }
+    chunks.into_remainder().fill(color);
+}
+
+// dest is unpacked to 16 bits per channel so channel * (255 - alpha) fits, two pixels per 128 bit half
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+fn scale_half_sse2(dest: __m128i, inverse: __m128i) -> __m128i {
+    let rounded = _mm_add_epi16(_mm_mullo_epi16(dest, inverse), _mm_set1_epi16(128));
+    _mm_srli_epi16(_mm_add_epi16(rounded, _mm_srli_epi16(rounded, 8)), 8)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn blend_sse2(dest: &mut [Pixel], source: &[Pixel]) {
+    let zero = _mm_setzero_si128();
+    let mut dest_chunks = dest.chunks_exact_mut(4);
+    let mut source_chunks = source.chunks_exact(4);
+    for (dest, source) in dest_chunks.by_ref().zip(source_chunks.by_ref()) {
+        let source_pixels = unsafe { _mm_loadu_si128(source.as_ptr() as *const __m128i) };
+        let dest_pixels = unsafe { _mm_loadu_si128(dest.as_ptr() as *const __m128i) };
+
+        // 255 - every byte, then each pixel's inverted alpha copied into all four of its 16 bit channels
+        let inverse = _mm_xor_si128(source_pixels, _mm_set1_epi32(-1));
+        let inverse_low = _mm_shufflehi_epi16(_mm_shufflelo_epi16(_mm_unpacklo_epi8(inverse, zero), 0xFF), 0xFF);
+        let inverse_high = _mm_shufflehi_epi16(_mm_shufflelo_epi16(_mm_unpackhi_epi8(inverse, zero), 0xFF), 0xFF);
+        let low = scale_half_sse2(_mm_unpacklo_epi8(dest_pixels, zero), inverse_low);
+        let high = scale_half_sse2(_mm_unpackhi_epi8(dest_pixels, zero), inverse_high);
+        let blended = _mm_adds_epu8(source_pixels, _mm_packus_epi16(low, high));
+        unsafe { _mm_storeu_si128(dest.as_mut_ptr() as *mut __m128i, blended) };
+    }
+    blend_scalar(dest_chunks.into_remainder(), source_chunks.remainder());
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+fn scale_half_avx2(dest: __m256i, inverse: __m256i) -> __m256i {
+    let rounded = _mm256_add_epi16(_mm256_mullo_epi16(dest, inverse), _mm256_set1_epi16(128));
+    _mm256_srli_epi16(_mm256_add_epi16(rounded, _mm256_srli_epi16(rounded, 8)), 8)
+}
+
+// same as the sse2 version, unpack and pack both work inside 128 bit lanes so the pixel order comes back out unchanged
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn blend_avx2(dest: &mut [Pixel], source: &[Pixel]) {
+    let zero = _mm256_setzero_si256();
+    let mut dest_chunks = dest.chunks_exact_mut(8);
+    let mut source_chunks = source.chunks_exact(8);
+    for (dest, source) in dest_chunks.by_ref().zip(source_chunks.by_ref()) {
+        let source_pixels = unsafe { _mm256_loadu_si256(source.as_ptr() as *const __m256i) };
+        let dest_pixels = unsafe { _mm256_loadu_si256(dest.as_ptr() as *const __m256i) };
+
+        let inverse = _mm256_xor_si256(source_pixels, _mm256_set1_epi32(-1));
+        let inverse_low = _mm256_shufflehi_epi16(_mm256_shufflelo_epi16(_mm256_unpacklo_epi8(inverse, zero), 0xFF), 0xFF);
+        let inverse_high = _mm256_shufflehi_epi16(_mm256_shufflelo_epi16(_mm256_unpackhi_epi8(inverse, zero), 0xFF), 0xFF);
+        let low = scale_half_avx2(_mm256_unpacklo_epi8(dest_pixels, zero), inverse_low);
+        let high = scale_half_avx2(_mm256_unpackhi_epi8(dest_pixels, zero), inverse_high);
+        let blended = _mm256_adds_epu8(source_pixels, _mm256_packus_epi16(low, high));
+        unsafe { _mm256_storeu_si256(dest.as_mut_ptr() as *mut __m256i, blended) };
+    }
+    unsafe { blend_sse2(dest_chunks.into_remainder(), source_chunks.remainder()) };
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn gradient_sse2(span: &mut [Pixel], blue_start: i32, green: u8) {
+    let row = _mm_set1_epi32(pack_rgba8(0, green, 0, 255) as i32);
+    let blue_mask = _mm_set1_epi32(0xFF);
+    let step = _mm_set1_epi32(4);
+    let mut blue = _mm_add_epi32(_mm_set1_epi32(blue_start), _mm_setr_epi32(0, 1, 2, 3));
+    let done = (span.len() / 4 * 4) as i32;
+    let mut chunks = span.chunks_exact_mut(4);
+    for chunk in chunks.by_ref() {
+        let pixels = _mm_or_si128(row, _mm_and_si128(blue, blue_mask));
+        unsafe { _mm_storeu_si128(chunk.as_mut_ptr() as *mut __m128i, pixels) };
+        blue = _mm_add_epi32(blue, step);
+    }
+    gradient_scalar(chunks.into_remainder(), blue_start + done, green);
+}
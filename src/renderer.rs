@@ -0,0 +1,114 @@
+//! The seam between the render commands the game pushes and whatever turns them into a picture in the window.
+//! Game code only ever sees a RenderGroup, main picks a Renderer at startup and calls it around the game's update.
+
+use crate::{
+    frame_buffer::FrameBuffer,
+    render_group::{RenderGroup, TileHashes},
+    work_queue::WorkQueue,
+    Win32OffscreenBuffer,
+};
+
+/// When a GPU backend waits for the vertical blank before showing a frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VSync {
+    /// Frames go out as soon as they're done and can tear
+    Off,
+    /// Every frame waits, the frame rate is capped at the refresh rate
+    #[default]
+    On,
+    /// Frames that are on time wait, late ones go straight out and tear instead of waiting a whole extra refresh
+    Adaptive,
+}
+
+impl VSync {
+    /// `off`, `on` or `adaptive`, what `--vsync` takes
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(VSync::Off),
+            "on" => Some(VSync::On),
+            "adaptive" => Some(VSync::Adaptive),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            VSync::Off => VSync::On,
+            VSync::On => VSync::Adaptive,
+            VSync::Adaptive => VSync::Off,
+        }
+    }
+}
+
+/// What the window's swap chain sends the display
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// 8 bits a channel, sRGB, what every display takes
+    #[default]
+    Sdr,
+    /// 16 bit float, linear, extended range. Windows maps it onto whatever the display is
+    ScRgb,
+    /// 10 bits a channel, PQ encoded rec.2020, what HDR displays take directly
+    Hdr10,
+}
+
+impl OutputMode {
+    /// `sdr`, `scrgb` or `hdr10`, what `--hdr` takes
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sdr" => Some(OutputMode::Sdr),
+            "scrgb" => Some(OutputMode::ScRgb),
+            "hdr10" => Some(OutputMode::Hdr10),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            OutputMode::Sdr => OutputMode::ScRgb,
+            OutputMode::ScRgb => OutputMode::Hdr10,
+            OutputMode::Hdr10 => OutputMode::Sdr,
+        }
+    }
+}
+
+pub trait Renderer {
+    fn name(&self) -> &'static str;
+
+    /// Gets the render group ready for a width by height frame, before the game pushes this frame's commands
+    fn begin_frame(&mut self, render_group: &mut RenderGroup, width: i32, height: i32) {
+        render_group.reset(width, height);
+    }
+
+    /// Turns the render group's commands into frame's pixels. Rasterizes them in software unless the backend can do
+    /// better. software_only is set when everything has to end up in frame, because the CPU post passes run over it
+    /// or the blending has to be the software path's
+    fn execute(&mut self, render_group: &mut RenderGroup, frame: &mut FrameBuffer, queue: &WorkQueue, _software_only: bool) {
+        render_group.render_tiled(frame, queue, &mut TileHashes::default());
+    }
+
+    /// Copies the last presented picture into frame when the backend drew some of it itself, so frame looks like what's
+    /// on screen. False when the front buffer already has all of it
+    fn read_back(&mut self, _frame: &mut FrameBuffer) -> bool {
+        false
+    }
+
+    /// Changes how present waits for the vertical blank. False when the backend can't choose, GDI just blits
+    fn set_vsync(&mut self, _vsync: VSync) -> bool {
+        false
+    }
+
+    /// Whether the render group's post_passes get run. The CPU ones in post_process are the only kind otherwise
+    fn runs_post_passes(&self) -> bool {
+        false
+    }
+
+    /// Switches what goes out to the display. False when the backend or the display can't do it, the mode stays as it was
+    fn set_output_mode(&mut self, mode: OutputMode) -> bool {
+        mode == OutputMode::Sdr
+    }
+
+    /// Shows the swap chain's front buffer in the window. everything_changed is set when something after execute,
+    /// like the post passes, changed pixels the commands didn't draw
+    fn present(&mut self, buffer: &Win32OffscreenBuffer, window_width: i32, window_height: i32, everything_changed: bool);
+}
@@ -0,0 +1,87 @@
+//! A fixed size queue threads can push to and pop from without taking a lock, for talking to a thread that can't be
+//! kept waiting, like the audio thread. The bounded queue from Dmitry Vyukov: every slot carries a sequence number
+//! that says whether it's waiting to be written or to be read on the current trip round the ring.
+
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+struct Slot<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+pub struct RingQueue<T> {
+    slots: Box<[Slot<T>]>,
+    // capacity - 1, the capacity being a power of two
+    mask: usize,
+    // where the next push and pop go, counting up forever, the slot is this masked
+    enqueue: AtomicUsize,
+    dequeue: AtomicUsize,
+}
+
+// values only ever go through one thread at a time, handed over by the sequence numbers
+unsafe impl<T: Send> Send for RingQueue<T> {}
+unsafe impl<T: Send> Sync for RingQueue<T> {}
+
+impl<T> RingQueue<T> {
+    /// Holds capacity rounded up to a power of two
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        let slots = (0..capacity).map(|index| Slot { sequence: AtomicUsize::new(index), value: UnsafeCell::new(MaybeUninit::uninit()) }).collect();
+        RingQueue { slots, mask: capacity - 1, enqueue: AtomicUsize::new(0), dequeue: AtomicUsize::new(0) }
+    }
+
+    /// Hands the value back when the queue is full
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut position = self.enqueue.load(Ordering::Relaxed);
+        let slot = loop {
+            let slot = &self.slots[position & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            match (sequence as isize).wrapping_sub(position as isize) {
+                // free on this trip, take it unless another push got there first
+                0 => match self.enqueue.compare_exchange_weak(position, position + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => break slot,
+                    Err(current) => position = current,
+                },
+                // still holds last trip's value, full
+                difference if difference < 0 => return Err(value),
+                // another push took it, try again from where they got to
+                _ => position = self.enqueue.load(Ordering::Relaxed),
+            }
+        };
+        unsafe { (*slot.value.get()).write(value) };
+        slot.sequence.store(position + 1, Ordering::Release);
+        Ok(())
+    }
+
+    /// None when the queue is empty
+    pub fn pop(&self) -> Option<T> {
+        let mut position = self.dequeue.load(Ordering::Relaxed);
+        let slot = loop {
+            let slot = &self.slots[position & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            match (sequence as isize).wrapping_sub(position as isize + 1) {
+                0 => match self.dequeue.compare_exchange_weak(position, position + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => break slot,
+                    Err(current) => position = current,
+                },
+                // not written yet, empty
+                difference if difference < 0 => return None,
+                _ => position = self.dequeue.load(Ordering::Relaxed),
+            }
+        };
+        let value = unsafe { (*slot.value.get()).assume_init_read() };
+        // free for the push on the next trip round
+        slot.sequence.store(position + self.mask + 1, Ordering::Release);
+        Some(value)
+    }
+}
+
+impl<T> Drop for RingQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
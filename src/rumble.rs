@@ -0,0 +1,109 @@
+use crate::game_input::{GamepadState, GamepadVibration};
+
+/// Attack/sustain/decay shape for one burst of rumble, times in seconds, motor strengths in 0.0..1.0
+#[derive(Clone, Copy, Debug)]
+pub struct RumbleEnvelope {
+    pub attack: f32,
+    pub sustain: f32,
+    pub decay: f32,
+    pub low_frequency: f32,
+    pub high_frequency: f32,
+}
+
+impl RumbleEnvelope {
+    fn duration(&self) -> f32 {
+        self.attack + self.sustain + self.decay
+    }
+
+    /// 0.0..1.0 ramp up during attack, flat during sustain, ramp down during decay
+    fn level_at(&self, time: f32) -> f32 {
+        if time < 0.0 || time >= self.duration() {
+            0.0
+        } else if time < self.attack {
+            time / self.attack
+        } else if time < self.attack + self.sustain {
+            1.0
+        } else {
+            1.0 - (time - self.attack - self.sustain) / self.decay
+        }
+    }
+}
+
+/// A sequence of envelopes, each starting at its own offset into the effect
+#[derive(Clone, Debug)]
+pub struct RumbleEffect {
+    envelopes: Vec<(f32, RumbleEnvelope)>,
+}
+
+impl RumbleEffect {
+    pub fn envelope(envelope: RumbleEnvelope) -> Self {
+        RumbleEffect {
+            envelopes: vec![(0.0, envelope)],
+        }
+    }
+
+    /// Hard hit: instant heavy kick that dies off quickly, a touch of the light motor for the crack
+    pub fn impact() -> Self {
+        Self::envelope(RumbleEnvelope {
+            attack: 0.0,
+            sustain: 0.05,
+            decay: 0.25,
+            low_frequency: 1.0,
+            high_frequency: 0.4,
+        })
+    }
+
+    /// Evenly spaced short buzzes, heartbeat / warning style
+    pub fn pulse(count: u32, interval: f32) -> Self {
+        let buzz = RumbleEnvelope {
+            attack: 0.02,
+            sustain: 0.06,
+            decay: 0.04,
+            low_frequency: 0.3,
+            high_frequency: 0.8,
+        };
+        RumbleEffect {
+            envelopes: (0..count).map(|index| (index as f32 * interval, buzz)).collect(),
+        }
+    }
+
+    fn duration(&self) -> f32 {
+        self.envelopes.iter().map(|(start, envelope)| start + envelope.duration()).fold(0.0, f32::max)
+    }
+
+    fn sample(&self, time: f32) -> GamepadVibration {
+        let mut vibration = GamepadVibration::default();
+        for (start, envelope) in self.envelopes.iter() {
+            let level = envelope.level_at(time - start);
+            vibration.low_frequency += envelope.low_frequency * level;
+            vibration.high_frequency += envelope.high_frequency * level;
+        }
+        vibration
+    }
+}
+
+/// Effects currently playing on each pad, mixed into the vibration the platform sends every frame
+#[derive(Default)]
+pub struct RumbleMixer {
+    playing: Vec<(usize, f32, RumbleEffect)>,
+}
+
+impl RumbleMixer {
+    pub fn play(&mut self, gamepad_index: usize, effect: RumbleEffect) {
+        self.playing.push((gamepad_index, 0.0, effect));
+    }
+
+    /// Advances all effects and adds them on top of the vibration already set on the pads (clamped to full power)
+    pub fn tick(&mut self, dt: f32, gamepads: &mut [GamepadState]) {
+        for (gamepad_index, time, effect) in self.playing.iter_mut() {
+            *time += dt;
+            let Some(gamepad) = gamepads.get_mut(*gamepad_index).filter(|gamepad| gamepad.connected) else {
+                continue;
+            };
+            let sample = effect.sample(*time);
+            gamepad.vibration.low_frequency = (gamepad.vibration.low_frequency + sample.low_frequency).min(1.0);
+            gamepad.vibration.high_frequency = (gamepad.vibration.high_frequency + sample.high_frequency).min(1.0);
+        }
+        self.playing.retain(|(_, time, effect)| *time < effect.duration());
+    }
+}
@@ -0,0 +1,55 @@
+//! Shader source for the GPU backends. Every shader is built into the executable from shaders/, and while that
+//! directory is still where the build found it the files there are read instead and watched, so a shader can be
+//! edited with the game running and picked up as soon as it's saved.
+
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+// how often the files are looked at, there's no need to touch the file system every frame
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct ShaderFile {
+    /// The file name, what compile errors are reported against
+    pub name: &'static str,
+    embedded: &'static str,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    polled: Instant,
+}
+
+impl ShaderFile {
+    /// name in shaders/, with what it held at build time for when the file isn't there anymore
+    pub fn new(name: &'static str, embedded: &'static str) -> Self {
+        let path = [env!("CARGO_MANIFEST_DIR"), "shaders", name].iter().collect::<PathBuf>();
+        let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        ShaderFile { name, embedded, path, modified, polled: Instant::now() }
+    }
+
+    /// The file as it is on disk, or as it was built in if it can't be read
+    pub fn source(&self) -> String {
+        fs::read_to_string(&self.path).unwrap_or_else(|_| self.embedded.to_string())
+    }
+
+    /// As it was built in
+    pub fn embedded(&self) -> &'static str {
+        self.embedded
+    }
+
+    /// The new source when the file was saved since the last look, None the rest of the time
+    pub fn poll(&mut self) -> Option<String> {
+        if self.polled.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.polled = Instant::now();
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        // editors can save in two goes, a half written file fails to compile and the rest of the save reloads it again
+        fs::read_to_string(&self.path).ok()
+    }
+}
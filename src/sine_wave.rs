@@ -0,0 +1,30 @@
+use std::f32::consts::TAU;
+
+/// A sine tone that keeps its phase from one fill to the next, so a change of pitch between fills carries on from
+/// where the wave was instead of jumping and clicking
+#[derive(Clone, Copy, Debug)]
+pub struct SineWave {
+    pub tone_hz: f32,
+    /// Out of i16::MAX
+    pub volume: f32,
+    // 0..TAU, wrapped every sample so it never grows big enough to lose precision
+    t_sine: f32,
+}
+
+impl SineWave {
+    pub fn new(tone_hz: f32, volume: f32) -> Self {
+        SineWave { tone_hz, volume, t_sine: 0.0 }
+    }
+
+    /// Writes the tone into both channels of interleaved left, right samples, carrying on from the last fill
+    pub fn fill(&mut self, samples: &mut [i16], samples_per_second: u32) {
+        let step = TAU * self.tone_hz / samples_per_second as f32;
+        for frame in samples.chunks_exact_mut(2) {
+            frame.fill((self.t_sine.sin() * self.volume) as i16);
+            self.t_sine += step;
+            if self.t_sine >= TAU {
+                self.t_sine -= TAU;
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Packs the bitmaps a GPU backend draws as sprites into one big texture, so a whole run of them can go out in a
+//! single draw call. Bitmaps are packed onto shelves as they first show up and stay until the atlas fills up, then
+//! it starts over empty. The backend uploads what's new each frame.
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{bitmap::Bitmap, color::Pixel};
+
+/// Where a bitmap sits in the atlas, in texels, not counting its border
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// A bitmap with its border that still has to go up to the texture, at x, y
+pub struct AtlasUpload {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<Pixel>,
+}
+
+// a row of bitmaps no taller than height, filled in left to right
+struct Shelf {
+    y: i32,
+    height: i32,
+    used: i32,
+}
+
+pub struct SpriteAtlas {
+    size: i32,
+    // keyed by where the bitmap lives, which can't be reused while the atlas holds on to the Arc
+    entries: HashMap<usize, (Arc<Bitmap>, AtlasRect)>,
+    shelves: Vec<Shelf>,
+    pending: Vec<Arc<Bitmap>>,
+    // set when a bitmap didn't fit, the next frame starts the atlas over
+    full: bool,
+}
+
+impl SpriteAtlas {
+    /// An empty size by size atlas
+    pub fn new(size: i32) -> Self {
+        SpriteAtlas { size, entries: HashMap::new(), shelves: Vec::new(), pending: Vec::new(), full: false }
+    }
+
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    /// Starts over empty if the last frame ran out of room, call it before placing any of a frame's bitmaps
+    pub fn begin_frame(&mut self) {
+        if self.full {
+            self.entries.clear();
+            self.shelves.clear();
+            self.pending.clear();
+            self.full = false;
+        }
+    }
+
+    /// Where the bitmap is, packing it in first if it's new. None when it's empty or there's no room left for it
+    pub fn place(&mut self, bitmap: &Arc<Bitmap>) -> Option<AtlasRect> {
+        let key = Arc::as_ptr(bitmap) as usize;
+        if let Some((_, rect)) = self.entries.get(&key) {
+            return Some(*rect);
+        }
+        if bitmap.width <= 0 || bitmap.height <= 0 {
+            return None;
+        }
+        // a texel of border all round, a copy of the edge, so bilinear filtering at the edges clamps like the software
+        // path does instead of bleeding into the neighbours
+        let (width, height) = (bitmap.width + 2, bitmap.height + 2);
+        let Some((x, y)) = self.allocate(width, height) else {
+            self.full = true;
+            return None;
+        };
+        let rect = AtlasRect { x: x + 1, y: y + 1, width: bitmap.width, height: bitmap.height };
+        self.entries.insert(key, (bitmap.clone(), rect));
+        self.pending.push(bitmap.clone());
+        Some(rect)
+    }
+
+    /// Everything placed since the last call, bordered and ready to copy in
+    pub fn take_uploads(&mut self) -> Vec<AtlasUpload> {
+        let pending = std::mem::take(&mut self.pending);
+        pending
+            .iter()
+            .filter_map(|bitmap| self.entries.get(&(Arc::as_ptr(bitmap) as usize)))
+            .map(|(bitmap, rect)| {
+                let (width, height) = (bitmap.width + 2, bitmap.height + 2);
+                let mut pixels = Vec::with_capacity((width * height) as usize);
+                for y in -1..=bitmap.height {
+                    let row = &bitmap.pixels[(y.clamp(0, bitmap.height - 1) * bitmap.width) as usize..][..bitmap.width as usize];
+                    pixels.push(row[0]);
+                    pixels.extend_from_slice(row);
+                    pixels.push(row[row.len() - 1]);
+                }
+                AtlasUpload { x: rect.x - 1, y: rect.y - 1, width, height, pixels }
+            })
+            .collect()
+    }
+
+    /// Top left corner of a free width by height spot, on the first shelf it fits on or a new one below the rest
+    fn allocate(&mut self, width: i32, height: i32) -> Option<(i32, i32)> {
+        if width > self.size || height > self.size {
+            return None;
+        }
+        if let Some(shelf) = self.shelves.iter_mut().find(|shelf| height <= shelf.height && shelf.used + width <= self.size) {
+            shelf.used += width;
+            return Some((shelf.used - width, shelf.y));
+        }
+        let y = self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+        if y + height > self.size {
+            return None;
+        }
+        self.shelves.push(Shelf { y, height, used: width });
+        Some((0, y))
+    }
+}
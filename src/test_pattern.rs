@@ -0,0 +1,106 @@
+//! Fixed images for checking the output path rather than the renderer: whether the channels come out in the right
+//! order, rows line up (a wrong pitch shears them), how the window scaling filters, and what the display does to gamma.
+//! They always cover the whole frame and ignore the camera.
+
+use crate::{
+    color::{pack_rgba8, Pixel},
+    frame_buffer::FrameBuffer,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestPattern {
+    /// SMPTE style color bars, the seven 75% bars over the castellations and the pluge strip
+    ColorBars,
+    /// Black and white squares cell pixels on a side. At 1 it shows every bit of filtering the scaling does, and from
+    /// far enough away it should look as bright as the grey ramp about three quarters along, or the gamma is off
+    Checkerboard { cell: i32 },
+    /// Grey, red, green and blue ramps from 0 to 255 left to right, one band each down the frame
+    HorizontalRamps,
+    /// The same ramps top to bottom
+    VerticalRamps,
+}
+
+impl TestPattern {
+    pub const ALL: [TestPattern; 6] = [
+        TestPattern::ColorBars,
+        TestPattern::Checkerboard { cell: 1 },
+        TestPattern::Checkerboard { cell: 8 },
+        TestPattern::Checkerboard { cell: 32 },
+        TestPattern::HorizontalRamps,
+        TestPattern::VerticalRamps,
+    ];
+
+    /// Color of pixel x, y of a width by height frame
+    fn pixel(self, x: i32, y: i32, width: i32, height: i32) -> Pixel {
+        match self {
+            TestPattern::ColorBars => color_bars(x, y, width, height),
+            TestPattern::Checkerboard { cell } => {
+                let cell = cell.max(1);
+                if (x / cell + y / cell) % 2 == 0 { pack_rgba8(0xFF, 0xFF, 0xFF, 0xFF) } else { pack_rgba8(0, 0, 0, 0xFF) }
+            }
+            TestPattern::HorizontalRamps => ramp(x, width, y * 4 / height.max(1)),
+            TestPattern::VerticalRamps => ramp(y, height, x * 4 / width.max(1)),
+        }
+    }
+}
+
+/// Grey for band 0, then red, green and blue
+fn ramp(position: i32, length: i32, band: i32) -> Pixel {
+    let level = (position * 255 / (length - 1).max(1)) as u8;
+    match band {
+        0 => pack_rgba8(level, level, level, 0xFF),
+        1 => pack_rgba8(level, 0, 0, 0xFF),
+        2 => pack_rgba8(0, level, 0, 0xFF),
+        _ => pack_rgba8(0, 0, level, 0xFF),
+    }
+}
+
+fn color_bars(x: i32, y: i32, width: i32, height: i32) -> Pixel {
+    // 75% of full scale, what the broadcast bars use so nothing clips
+    const HIGH: u8 = 191;
+    let seventh = x * 7 / width.max(1);
+    if y < height * 2 / 3 {
+        // white yellow cyan green magenta red blue
+        return [
+            pack_rgba8(HIGH, HIGH, HIGH, 0xFF),
+            pack_rgba8(HIGH, HIGH, 0, 0xFF),
+            pack_rgba8(0, HIGH, HIGH, 0xFF),
+            pack_rgba8(0, HIGH, 0, 0xFF),
+            pack_rgba8(HIGH, 0, HIGH, 0xFF),
+            pack_rgba8(HIGH, 0, 0, 0xFF),
+            pack_rgba8(0, 0, HIGH, 0xFF),
+        ][seventh as usize];
+    }
+    let black = pack_rgba8(0x13, 0x13, 0x13, 0xFF);
+    if y < height * 3 / 4 {
+        // the castellations: the bars' complements under every other bar, black between
+        return match seventh {
+            0 => pack_rgba8(0, 0, HIGH, 0xFF),
+            2 => pack_rgba8(HIGH, 0, HIGH, 0xFF),
+            4 => pack_rgba8(0, HIGH, HIGH, 0xFF),
+            6 => pack_rgba8(HIGH, HIGH, HIGH, 0xFF),
+            _ => black,
+        };
+    }
+    // -I, full white and +Q a bar and a quarter wide each, then black, with the pluge in thirds under the red bar:
+    // darker than black, black and lighter than black. Only the last should stand out on a display set up right
+    let (quarter_bar, third_bar) = (x * 28 / width.max(1), x * 21 / width.max(1));
+    match (quarter_bar, third_bar) {
+        (0..=4, _) => pack_rgba8(0x00, 0x21, 0x4C, 0xFF),
+        (5..=9, _) => pack_rgba8(0xFF, 0xFF, 0xFF, 0xFF),
+        (10..=14, _) => pack_rgba8(0x32, 0x00, 0x6A, 0xFF),
+        (_, 15) => pack_rgba8(0x09, 0x09, 0x09, 0xFF),
+        (_, 17) => pack_rgba8(0x1D, 0x1D, 0x1D, 0xFF),
+        _ => black,
+    }
+}
+
+/// The part of the pattern that falls in buffer, a view whose top left corner is at origin_x, origin_y of a
+/// frame_width by frame_height frame
+pub fn draw_test_pattern(buffer: &mut FrameBuffer, pattern: TestPattern, origin_x: i32, origin_y: i32, frame_width: i32, frame_height: i32) {
+    for (y, row) in buffer.rows_mut().enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = pattern.pixel(origin_x + x as i32, origin_y + y as i32, frame_width, frame_height);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::font::Font;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Where one glyph goes: the pen position on its baseline, relative to the top left of the block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlyphPlacement {
+    pub character: char,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Laid out text, ready to draw anywhere
+#[derive(Clone, Debug, Default)]
+pub struct TextBlock {
+    pub glyphs: Vec<GlyphPlacement>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// How text gets broken into lines and lined up. Lines break at '\n', and with a max width also between words
+/// (inside a word only when it's wider than a whole line). Aligned within max width, or the widest line without one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextLayout {
+    pub max_width: Option<f32>,
+    pub align: Align,
+    /// Multiple of the font's line height from one baseline to the next
+    pub line_spacing: f32,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        TextLayout { max_width: None, align: Align::Left, line_spacing: 1.0 }
+    }
+}
+
+/// Width of text as one line, from the first pen position to the last glyph's advance
+pub fn measure(font: &Font, text: &str) -> f32 {
+    text.chars().map(|character| font.glyph(character).advance).sum()
+}
+
+impl TextLayout {
+    pub fn layout(&self, font: &Font, text: &str) -> TextBlock {
+        let lines: Vec<String> = text.split('\n').flat_map(|paragraph| self.wrap(font, paragraph)).collect();
+        let widths: Vec<f32> = lines.iter().map(|line| measure(font, line)).collect();
+        let widest = widths.iter().copied().fold(0.0, f32::max);
+        let block_width = self.max_width.unwrap_or(widest);
+
+        let mut glyphs = Vec::new();
+        let line_advance = font.line_height * self.line_spacing;
+        for (index, (line, width)) in lines.iter().zip(widths).enumerate() {
+            let mut pen_x = match self.align {
+                Align::Left => 0.0,
+                Align::Center => (block_width - width) / 2.0,
+                Align::Right => block_width - width,
+            };
+            let baseline = font.ascent + index as f32 * line_advance;
+            for character in line.chars() {
+                glyphs.push(GlyphPlacement { character, x: pen_x, y: baseline });
+                pen_x += font.glyph(character).advance;
+            }
+        }
+        // the last line is a full line height tall whatever the spacing, it has nothing after it to space out from
+        let height = (lines.len() as f32 - 1.0) * line_advance + font.line_height;
+        TextBlock { glyphs, width: block_width.max(widest), height }
+    }
+
+    /// One paragraph cut into lines that fit, greedily. The space a line breaks at goes with the break.
+    fn wrap(&self, font: &Font, paragraph: &str) -> Vec<String> {
+        let Some(max_width) = self.max_width else {
+            return vec![paragraph.to_string()];
+        };
+        let space = font.glyph(' ').advance;
+        let mut lines = Vec::new();
+        // None until the line has its first word, so leading spaces (empty words) are kept
+        let mut line: Option<String> = None;
+        let mut line_width = 0.0;
+        for word in paragraph.split(' ') {
+            let word_width = measure(font, word);
+            if let Some(current) = line.take_if(|_| line_width + space + word_width > max_width) {
+                lines.push(current);
+            }
+            match &mut line {
+                Some(current) => {
+                    current.push(' ');
+                    current.push_str(word);
+                    line_width += space + word_width;
+                }
+                None => {
+                    // a word too long for any line gets cut wherever it runs out of room
+                    let mut rest = String::new();
+                    let mut rest_width = 0.0;
+                    for character in word.chars() {
+                        let advance = font.glyph(character).advance;
+                        if rest_width + advance > max_width && !rest.is_empty() {
+                            lines.push(std::mem::take(&mut rest));
+                            rest_width = 0.0;
+                        }
+                        rest.push(character);
+                        rest_width += advance;
+                    }
+                    line = Some(rest);
+                    line_width = rest_width;
+                }
+            }
+        }
+        lines.extend(line);
+        lines
+    }
+}
@@ -0,0 +1,1035 @@
+//! Vorbis I audio decoding, from the packets of an ogg stream to floating point samples. Covers everything current
+//! encoders write: floor 1, residue 0, 1 and 2, channel coupling and both block sizes. Floor 0 is long obsolete and
+//! turned away. Huffman codes are walked a bit at a time down a tree, and the inverse MDCT goes through an FFT.
+
+use std::{
+    f32::consts::PI,
+    io::{self, Read},
+};
+
+use crate::ogg::OggReader;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Bad vorbis stream: {message}"))
+}
+
+// what floor 1 y values map to, 10^(7 * (y - 255) / 256), a little under 140dB from 0 to 255
+fn floor1_inverse_db(y: i32) -> f32 {
+    10f32.powf(7.0 * (y.clamp(0, 255) - 255) as f32 / 256.0)
+}
+
+/// Bits least significant first. Running off the end of a packet isn't always an error in audio packets, so that
+/// comes back as its own error kind the audio decode can tell apart
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    bit: u32,
+}
+
+fn end_of_packet() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "Bad vorbis stream: ran out of packet")
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, position: 0, bit: 0 }
+    }
+
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        let mut value = 0u32;
+        let mut read = 0;
+        while read < count {
+            let byte = *self.bytes.get(self.position).ok_or_else(end_of_packet)?;
+            let take = (8 - self.bit).min(count - read);
+            let chunk = (byte as u32 >> self.bit) & ((1 << take) - 1);
+            value |= chunk << read;
+            read += take;
+            self.bit += take;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn flag(&mut self) -> io::Result<bool> {
+        Ok(self.bits(1)? == 1)
+    }
+}
+
+/// How many bits it takes to hold value, 0 for 0
+fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+fn float32_unpack(value: u32) -> f32 {
+    let mantissa = (value & 0x1FFFFF) as f32;
+    let exponent = ((value & 0x7FE00000) >> 21) as i32;
+    let mantissa = if value & 0x80000000 != 0 { -mantissa } else { mantissa };
+    mantissa * 2f32.powi(exponent - 788)
+}
+
+/// The largest r where r^dimensions is no more than entries
+fn lookup1_values(entries: u32, dimensions: u32) -> u32 {
+    let mut values = (entries as f64).powf(1.0 / dimensions as f64).floor() as u32;
+    while (values + 1).checked_pow(dimensions).is_some_and(|power| power <= entries) {
+        values += 1;
+    }
+    while values > 0 && values.checked_pow(dimensions).is_none_or(|power| power > entries) {
+        values -= 1;
+    }
+    values
+}
+
+struct Codebook {
+    dimensions: usize,
+    // a binary tree, each node's children for a 0 and a 1 bit, negative is the leaf for entry !child
+    nodes: Vec<[i32; 2]>,
+    // dimensions values per entry when the book has vectors
+    vectors: Vec<f32>,
+}
+
+impl Codebook {
+    fn read(reader: &mut BitReader) -> io::Result<Self> {
+        if reader.bits(24)? != 0x564342 {
+            return Err(invalid("missing codebook sync pattern"));
+        }
+        let dimensions = reader.bits(16)?;
+        let entries = reader.bits(24)?;
+        let mut lengths = vec![0u8; entries as usize];
+        if reader.flag()? {
+            // ordered, runs of entries with lengths going up one at a time
+            let mut entry = 0;
+            let mut length = reader.bits(5)? + 1;
+            while entry < entries {
+                let count = reader.bits(ilog(entries - entry))?;
+                if entry + count > entries || length > 32 {
+                    return Err(invalid("ordered codebook lengths run past the end"));
+                }
+                lengths[entry as usize..(entry + count) as usize].fill(length as u8);
+                entry += count;
+                length += 1;
+            }
+        } else {
+            let sparse = reader.flag()?;
+            for length in &mut lengths {
+                if !sparse || reader.flag()? {
+                    *length = reader.bits(5)? as u8 + 1;
+                }
+            }
+        }
+
+        let lookup_type = reader.bits(4)?;
+        let vectors = match lookup_type {
+            0 => Vec::new(),
+            1 | 2 => {
+                // no values per vector would leave lookup1_values nothing to take a root of
+                if dimensions == 0 {
+                    return Err(invalid("vector codebook with no dimensions"));
+                }
+                let minimum = float32_unpack(reader.bits(32)?);
+                let delta = float32_unpack(reader.bits(32)?);
+                let value_bits = reader.bits(4)? + 1;
+                let sequence = reader.flag()?;
+                let lookup_values = if lookup_type == 1 { lookup1_values(entries, dimensions) } else { entries * dimensions };
+                let multiplicands = (0..lookup_values).map(|_| reader.bits(value_bits)).collect::<io::Result<Vec<_>>>()?;
+                let mut vectors = Vec::with_capacity((entries * dimensions) as usize);
+                for entry in 0..entries {
+                    let mut last = 0.0;
+                    let mut divisor = 1u32;
+                    for dimension in 0..dimensions {
+                        let offset = if lookup_type == 1 { (entry / divisor) % lookup_values } else { entry * dimensions + dimension };
+                        let value = multiplicands[offset as usize] as f32 * delta + minimum + last;
+                        if sequence {
+                            last = value;
+                        }
+                        vectors.push(value);
+                        divisor = divisor.wrapping_mul(lookup_values);
+                    }
+                }
+                vectors
+            }
+            _ => return Err(invalid("unknown codebook lookup type")),
+        };
+        Ok(Codebook { dimensions: dimensions as usize, nodes: build_tree(&lengths)?, vectors })
+    }
+
+    /// The next entry's number
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u32> {
+        let mut node = 0usize;
+        loop {
+            let child = self.nodes.get(node).ok_or_else(|| invalid("decode with an empty codebook"))?[reader.bits(1)? as usize];
+            if child < 0 {
+                return Ok(!child as u32);
+            }
+            if child == 0 {
+                return Err(invalid("codeword isn't in the codebook"));
+            }
+            node = child as usize;
+        }
+    }
+
+    /// The next entry's vector, for the books residues read through
+    fn decode_vector(&self, reader: &mut BitReader) -> io::Result<&[f32]> {
+        let entry = self.decode(reader)? as usize;
+        self.vectors.get(entry * self.dimensions..(entry + 1) * self.dimensions).ok_or_else(|| invalid("vector decode from a scalar codebook"))
+    }
+}
+
+/// Gives the entries their codewords the way the spec does, each the lowest one still free at its length in entry
+/// order, and puts them in a tree. Codewords are read from their top bit down
+fn build_tree(lengths: &[u8]) -> io::Result<Vec<[i32; 2]>> {
+    // 0 as a child is no child, the root can't be anyone's child
+    let mut nodes = vec![[0i32; 2]];
+    // available[length] is the next free codeword of that length, top aligned in 32 bits
+    let mut available = [0u32; 33];
+    let mut first = true;
+    for (entry, &length) in lengths.iter().enumerate() {
+        if length == 0 {
+            continue;
+        }
+        let length = length as usize;
+        let code = if first {
+            first = false;
+            for (index, slot) in available.iter_mut().enumerate().take(length + 1).skip(1) {
+                *slot = 1u32.wrapping_shl(32 - index as u32);
+            }
+            0
+        } else {
+            let mut free = length;
+            while free > 0 && available[free] == 0 {
+                free -= 1;
+            }
+            if free == 0 {
+                return Err(invalid("codebook lengths are overspecified"));
+            }
+            let code = available[free];
+            available[free] = 0;
+            for (index, slot) in available.iter_mut().enumerate().take(length + 1).skip(free + 1) {
+                *slot = code.wrapping_add(1u32.wrapping_shl(32 - index as u32));
+            }
+            code
+        };
+        // down the tree a bit at a time from the top, making nodes on the way
+        let mut node = 0usize;
+        for depth in 0..length {
+            let bit = ((code >> (31 - depth)) & 1) as usize;
+            if depth == length - 1 {
+                nodes[node][bit] = !(entry as i32);
+            } else {
+                if nodes[node][bit] <= 0 {
+                    nodes.push([0, 0]);
+                    nodes[node][bit] = (nodes.len() - 1) as i32;
+                }
+                node = nodes[node][bit] as usize;
+            }
+        }
+    }
+    // a book with a single entry is legal, its codeword is read but any bits decode to it
+    if lengths.iter().filter(|length| **length != 0).count() == 1 {
+        for node in &mut nodes {
+            node[1] = node[0];
+        }
+    }
+    Ok(nodes)
+}
+
+struct FloorClass {
+    dimensions: usize,
+    subclass_bits: u32,
+    masterbook: usize,
+    // -1 for no book, those values are 0
+    subclass_books: Vec<i32>,
+}
+
+struct Floor1 {
+    partition_classes: Vec<usize>,
+    classes: Vec<FloorClass>,
+    multiplier: i32,
+    x_list: Vec<i32>,
+    // the x list's indices in order of x
+    sorted: Vec<usize>,
+    // the nearest x before each point, below and above it in value
+    low_neighbors: Vec<usize>,
+    high_neighbors: Vec<usize>,
+}
+
+impl Floor1 {
+    fn read(reader: &mut BitReader, codebook_count: usize) -> io::Result<Self> {
+        let partitions = reader.bits(5)?;
+        let partition_classes = (0..partitions).map(|_| reader.bits(4).map(|class| class as usize)).collect::<io::Result<Vec<_>>>()?;
+        let class_count = partition_classes.iter().max().map_or(0, |max| max + 1);
+        let mut classes = Vec::with_capacity(class_count);
+        for _ in 0..class_count {
+            let dimensions = reader.bits(3)? as usize + 1;
+            let subclass_bits = reader.bits(2)?;
+            let masterbook = if subclass_bits > 0 { reader.bits(8)? as usize } else { 0 };
+            let subclass_books = (0..1 << subclass_bits).map(|_| reader.bits(8).map(|book| book as i32 - 1)).collect::<io::Result<Vec<_>>>()?;
+            if (subclass_bits > 0 && masterbook >= codebook_count) || subclass_books.iter().any(|book| *book >= codebook_count as i32) {
+                return Err(invalid("floor uses a codebook that isn't there"));
+            }
+            classes.push(FloorClass { dimensions, subclass_bits, masterbook, subclass_books });
+        }
+        let multiplier = reader.bits(2)? as i32 + 1;
+        let range_bits = reader.bits(4)?;
+        let mut x_list = vec![0, 1 << range_bits];
+        for class in &partition_classes {
+            for _ in 0..classes[*class].dimensions {
+                x_list.push(reader.bits(range_bits)? as i32);
+            }
+        }
+        let mut sorted: Vec<usize> = (0..x_list.len()).collect();
+        sorted.sort_by_key(|index| x_list[*index]);
+        let (mut low_neighbors, mut high_neighbors) = (vec![0; x_list.len()], vec![0; x_list.len()]);
+        for index in 2..x_list.len() {
+            let x = x_list[index];
+            let before = &x_list[..index];
+            low_neighbors[index] = (0..index).filter(|other| before[*other] < x).max_by_key(|other| before[*other]).unwrap_or(0);
+            high_neighbors[index] = (0..index).filter(|other| before[*other] > x).min_by_key(|other| before[*other]).unwrap_or(1);
+        }
+        Ok(Floor1 { partition_classes, classes, multiplier, x_list, sorted, low_neighbors, high_neighbors })
+    }
+
+    /// The packet's y values for this floor, None when the channel is unused this packet
+    fn decode(&self, reader: &mut BitReader, codebooks: &[Codebook]) -> io::Result<Option<Vec<i32>>> {
+        if !reader.flag()? {
+            return Ok(None);
+        }
+        let range = [256, 128, 86, 64][self.multiplier as usize - 1];
+        let bits = ilog(range - 1);
+        let mut y = vec![reader.bits(bits)? as i32, reader.bits(bits)? as i32];
+        for class in self.partition_classes.iter().map(|class| &self.classes[*class]) {
+            let mask = (1 << class.subclass_bits) - 1;
+            let mut value = if class.subclass_bits > 0 { codebooks[class.masterbook].decode(reader)? } else { 0 };
+            for _ in 0..class.dimensions {
+                let book = class.subclass_books[(value & mask) as usize];
+                value >>= class.subclass_bits;
+                y.push(if book >= 0 { codebooks[book as usize].decode(reader)? as i32 } else { 0 });
+            }
+        }
+        Ok(Some(y))
+    }
+
+    /// Turns decoded y values into the curve the residue is multiplied by, n values of it
+    fn render(&self, y: &[i32], n: usize) -> Vec<f32> {
+        let range = [256, 128, 86, 64][self.multiplier as usize - 1];
+        let count = self.x_list.len();
+        let mut used = vec![false; count];
+        let mut final_y = vec![0i32; count];
+        used[0] = true;
+        used[1] = true;
+        final_y[0] = y[0];
+        final_y[1] = y[1];
+        for index in 2..count {
+            let (low, high) = (self.low_neighbors[index], self.high_neighbors[index]);
+            let predicted = render_point(self.x_list[low], final_y[low], self.x_list[high], final_y[high], self.x_list[index]);
+            let value = y[index];
+            let high_room = range - predicted;
+            let low_room = predicted;
+            let room = if high_room < low_room { high_room * 2 } else { low_room * 2 };
+            if value == 0 {
+                final_y[index] = predicted;
+                continue;
+            }
+            used[low] = true;
+            used[high] = true;
+            used[index] = true;
+            final_y[index] = if value >= room {
+                if high_room > low_room { value - low_room + predicted } else { predicted - value + high_room - 1 }
+            } else if value % 2 == 1 {
+                predicted - (value + 1) / 2
+            } else {
+                predicted + value / 2
+            };
+        }
+
+        let mut curve = vec![0i32; n];
+        let (mut low_x, mut low_y) = (0, final_y[0] * self.multiplier);
+        let (mut high_x, mut high_y) = (0, 0);
+        for &index in &self.sorted[1..] {
+            if used[index] {
+                high_x = self.x_list[index];
+                high_y = final_y[index] * self.multiplier;
+                render_line(low_x, low_y, high_x, high_y, &mut curve);
+                low_x = high_x;
+                low_y = high_y;
+            }
+        }
+        if (high_x as usize) < n {
+            render_line(high_x, high_y, n as i32, high_y, &mut curve);
+        }
+        curve.into_iter().map(floor1_inverse_db).collect()
+    }
+}
+
+fn render_point(x0: i32, y0: i32, x1: i32, y1: i32, x: i32) -> i32 {
+    let dy = y1 - y0;
+    let adx = x1 - x0;
+    let offset = dy.abs() * (x - x0) / adx.max(1);
+    if dy < 0 { y0 - offset } else { y0 + offset }
+}
+
+// the spec's integer line, the floor curve has to come out the same as the encoder's to the bit
+fn render_line(x0: i32, y0: i32, x1: i32, y1: i32, curve: &mut [i32]) {
+    let dy = y1 - y0;
+    let adx = x1 - x0;
+    if adx <= 0 {
+        return;
+    }
+    let base = dy / adx;
+    let step = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let (mut y, mut error) = (y0, 0);
+    if let Some(value) = curve.get_mut(x0 as usize) {
+        *value = y;
+    }
+    for x in x0 + 1..x1 {
+        error += ady;
+        if error >= adx {
+            error -= adx;
+            y += step;
+        } else {
+            y += base;
+        }
+        if let Some(value) = curve.get_mut(x as usize) {
+            *value = y;
+        }
+    }
+}
+
+struct Residue {
+    kind: u32,
+    begin: usize,
+    end: usize,
+    partition_size: usize,
+    classifications: usize,
+    classbook: usize,
+    // per classification, the book for each of the 8 passes or -1
+    books: Vec<[i32; 8]>,
+}
+
+impl Residue {
+    fn read(reader: &mut BitReader, kind: u32, codebooks: &[Codebook]) -> io::Result<Self> {
+        let begin = reader.bits(24)? as usize;
+        let end = reader.bits(24)? as usize;
+        let partition_size = reader.bits(24)? as usize + 1;
+        let classifications = reader.bits(6)? as usize + 1;
+        let classbook = reader.bits(8)? as usize;
+        let mut cascades = Vec::with_capacity(classifications);
+        for _ in 0..classifications {
+            let low = reader.bits(3)?;
+            let high = if reader.flag()? { reader.bits(5)? } else { 0 };
+            cascades.push(high * 8 + low);
+        }
+        let mut books = Vec::with_capacity(classifications);
+        for cascade in cascades {
+            let mut passes = [-1i32; 8];
+            for (pass, book) in passes.iter_mut().enumerate() {
+                if cascade & (1 << pass) != 0 {
+                    *book = reader.bits(8)? as i32;
+                    if codebooks.get(*book as usize).is_none_or(|book| book.vectors.is_empty()) {
+                        return Err(invalid("residue uses a codebook without vectors"));
+                    }
+                }
+            }
+            books.push(passes);
+        }
+        if codebooks.get(classbook).is_none() {
+            return Err(invalid("residue classbook isn't there"));
+        }
+        Ok(Residue { kind, begin, end, partition_size, classifications, classbook, books })
+    }
+
+    /// Adds the residue onto each vector not marked skip, each n / 2 long. Running out of packet in here is how a
+    /// packet with less to say ends, what was decoded up to there stands
+    fn decode(&self, reader: &mut BitReader, codebooks: &[Codebook], vectors: &mut [Vec<f32>], skip: &[bool], half: usize) {
+        if self.kind == 2 {
+            // all the channels interleaved into one vector, and decoded as a single channel
+            if skip.iter().all(|skip| *skip) {
+                return;
+            }
+            let channels = vectors.len();
+            let mut interleaved = vec![vec![0.0; half * channels]];
+            let _ = self.decode_channels(reader, codebooks, &mut interleaved, &[false], half * channels);
+            for (index, value) in interleaved[0].iter().enumerate() {
+                vectors[index % channels][index / channels] += value;
+            }
+        } else {
+            let _ = self.decode_channels(reader, codebooks, vectors, skip, half);
+        }
+    }
+
+    fn decode_channels(&self, reader: &mut BitReader, codebooks: &[Codebook], vectors: &mut [Vec<f32>], skip: &[bool], size: usize) -> io::Result<()> {
+        let classbook = &codebooks[self.classbook];
+        let per_codeword = classbook.dimensions.max(1);
+        let begin = self.begin.min(size);
+        let partitions = (self.end.min(size).saturating_sub(begin)) / self.partition_size;
+        let mut classes = vec![vec![0usize; partitions + per_codeword]; vectors.len()];
+        for pass in 0..8 {
+            let mut partition = 0;
+            while partition < partitions {
+                if pass == 0 {
+                    for (channel, classes) in classes.iter_mut().enumerate() {
+                        if skip[channel] {
+                            continue;
+                        }
+                        let mut temp = classbook.decode(reader)? as usize;
+                        for index in (0..per_codeword).rev() {
+                            classes[partition + index] = temp % self.classifications;
+                            temp /= self.classifications;
+                        }
+                    }
+                }
+                for _ in 0..per_codeword {
+                    if partition >= partitions {
+                        break;
+                    }
+                    for (channel, vector) in vectors.iter_mut().enumerate() {
+                        if skip[channel] {
+                            continue;
+                        }
+                        let book = self.books[classes[channel][partition]][pass];
+                        if book < 0 {
+                            continue;
+                        }
+                        let book = &codebooks[book as usize];
+                        let offset = begin + partition * self.partition_size;
+                        let partition_vector = &mut vector[offset..offset + self.partition_size];
+                        if self.kind == 0 {
+                            // interleaved by step, each vector's values spread through the partition
+                            let step = self.partition_size / book.dimensions;
+                            for start in 0..step {
+                                for (index, value) in book.decode_vector(reader)?.iter().enumerate() {
+                                    partition_vector[start + index * step] += value;
+                                }
+                            }
+                        } else {
+                            let mut index = 0;
+                            while index < self.partition_size {
+                                for value in book.decode_vector(reader)? {
+                                    if let Some(out) = partition_vector.get_mut(index) {
+                                        *out += value;
+                                    }
+                                    index += 1;
+                                }
+                            }
+                        }
+                    }
+                    partition += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Mapping {
+    // magnitude and angle channel pairs
+    coupling: Vec<(usize, usize)>,
+    // which submap each channel is in
+    mux: Vec<usize>,
+    // per submap, its floor and residue
+    submaps: Vec<(usize, usize)>,
+}
+
+struct Mode {
+    long_block: bool,
+    mapping: usize,
+}
+
+/// The stream's headers and the state carried from one audio packet to the next
+pub struct VorbisDecoder<R: Read> {
+    ogg: OggReader<R>,
+    pub channels: usize,
+    pub samples_per_second: u32,
+    block_sizes: [usize; 2],
+    codebooks: Vec<Codebook>,
+    floors: Vec<Floor1>,
+    residues: Vec<Residue>,
+    mappings: Vec<Mapping>,
+    modes: Vec<Mode>,
+    // the right half of the last block, windowed, to overlap with the next, none before the first packet
+    previous: Option<Vec<Vec<f32>>>,
+    // samples handed out so far, to trim the last packet to the stream's length
+    position: u64,
+    finished: bool,
+}
+
+impl<R: Read> VorbisDecoder<R> {
+    /// Reads the three header packets, leaving the stream at the first audio packet
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut ogg = OggReader::new(reader);
+        let mut header = |kind: u8| -> io::Result<Vec<u8>> {
+            let packet = ogg.next_packet()?.ok_or_else(|| invalid("stream ends in the headers"))?;
+            if packet.data.len() < 7 || packet.data[0] != kind || &packet.data[1..7] != b"vorbis" {
+                return Err(invalid("missing header"));
+            }
+            Ok(packet.data)
+        };
+        let identification = header(1)?;
+        let _comments = header(3)?;
+        let setup = header(5)?;
+
+        let mut reader = BitReader::new(&identification[7..]);
+        if reader.bits(32)? != 0 {
+            return Err(invalid("unknown version"));
+        }
+        let channels = reader.bits(8)? as usize;
+        let samples_per_second = reader.bits(32)?;
+        reader.bits(32)?;
+        reader.bits(32)?;
+        reader.bits(32)?;
+        let block_sizes = [1usize << reader.bits(4)?, 1usize << reader.bits(4)?];
+        if channels == 0 || samples_per_second == 0 || block_sizes[0] < 64 || block_sizes[0] > block_sizes[1] || block_sizes[1] > 8192 {
+            return Err(invalid("identification header is out of range"));
+        }
+
+        let mut reader = BitReader::new(&setup[7..]);
+        let codebook_count = reader.bits(8)? as usize + 1;
+        let codebooks = (0..codebook_count).map(|_| Codebook::read(&mut reader)).collect::<io::Result<Vec<_>>>()?;
+        for _ in 0..reader.bits(6)? + 1 {
+            if reader.bits(16)? != 0 {
+                return Err(invalid("unknown time domain transform"));
+            }
+        }
+        let mut floors = Vec::new();
+        for _ in 0..reader.bits(6)? + 1 {
+            match reader.bits(16)? {
+                1 => floors.push(Floor1::read(&mut reader, codebook_count)?),
+                0 => return Err(invalid("floor 0 isn't supported")),
+                _ => return Err(invalid("unknown floor type")),
+            }
+        }
+        let mut residues = Vec::new();
+        for _ in 0..reader.bits(6)? + 1 {
+            let kind = reader.bits(16)?;
+            if kind > 2 {
+                return Err(invalid("unknown residue type"));
+            }
+            residues.push(Residue::read(&mut reader, kind, &codebooks)?);
+        }
+        let mut mappings = Vec::new();
+        for _ in 0..reader.bits(6)? + 1 {
+            if reader.bits(16)? != 0 {
+                return Err(invalid("unknown mapping type"));
+            }
+            let submap_count = if reader.flag()? { reader.bits(4)? as usize + 1 } else { 1 };
+            let mut coupling = Vec::new();
+            if reader.flag()? {
+                let bits = ilog(channels as u32 - 1);
+                for _ in 0..reader.bits(8)? + 1 {
+                    let (magnitude, angle) = (reader.bits(bits)? as usize, reader.bits(bits)? as usize);
+                    if magnitude == angle || magnitude >= channels || angle >= channels {
+                        return Err(invalid("bad channel coupling"));
+                    }
+                    coupling.push((magnitude, angle));
+                }
+            }
+            if reader.bits(2)? != 0 {
+                return Err(invalid("reserved mapping bits are set"));
+            }
+            let mux = if submap_count > 1 { (0..channels).map(|_| reader.bits(4).map(|submap| submap as usize)).collect::<io::Result<Vec<_>>>()? } else { vec![0; channels] };
+            let mut submaps = Vec::with_capacity(submap_count);
+            for _ in 0..submap_count {
+                reader.bits(8)?;
+                let (floor, residue) = (reader.bits(8)? as usize, reader.bits(8)? as usize);
+                if floor >= floors.len() || residue >= residues.len() {
+                    return Err(invalid("mapping uses a floor or residue that isn't there"));
+                }
+                submaps.push((floor, residue));
+            }
+            if mux.iter().any(|submap| *submap >= submap_count) {
+                return Err(invalid("channel is in a submap that isn't there"));
+            }
+            mappings.push(Mapping { coupling, mux, submaps });
+        }
+        let mut modes = Vec::new();
+        for _ in 0..reader.bits(6)? + 1 {
+            let long_block = reader.flag()?;
+            reader.bits(16)?;
+            reader.bits(16)?;
+            let mapping = reader.bits(8)? as usize;
+            if mapping >= mappings.len() {
+                return Err(invalid("mode uses a mapping that isn't there"));
+            }
+            modes.push(Mode { long_block, mapping });
+        }
+        if !reader.flag()? {
+            return Err(invalid("setup header framing bit isn't set"));
+        }
+
+        Ok(VorbisDecoder {
+            ogg,
+            channels,
+            samples_per_second,
+            block_sizes,
+            codebooks,
+            floors,
+            residues,
+            mappings,
+            modes,
+            previous: None,
+            position: 0,
+            finished: false,
+        })
+    }
+
+    /// The next packet's samples, a vector per channel, which can be empty. None at the end of the stream
+    pub fn next_samples(&mut self) -> io::Result<Option<Vec<Vec<f32>>>> {
+        loop {
+            if self.finished {
+                return Ok(None);
+            }
+            let Some(packet) = self.ogg.next_packet()? else {
+                self.finished = true;
+                return Ok(None);
+            };
+            self.finished = packet.last;
+            match self.decode_packet(&packet.data) {
+                Ok(Some(mut samples)) => {
+                    let count = samples[0].len() as u64;
+                    // the last page's granule position is where the stream really ends, inside its last packet
+                    if packet.last
+                        && let Some(granule) = packet.granule
+                        && granule < self.position + count
+                    {
+                        let keep = granule.saturating_sub(self.position) as usize;
+                        for channel in &mut samples {
+                            channel.truncate(keep);
+                        }
+                    }
+                    self.position += samples[0].len() as u64;
+                    return Ok(Some(samples));
+                }
+                Ok(None) => continue,
+                // a broken packet is dropped, the overlap starts over from the next one
+                Err(_) => self.previous = None,
+            }
+        }
+    }
+
+    fn decode_packet(&mut self, data: &[u8]) -> io::Result<Option<Vec<Vec<f32>>>> {
+        let mut reader = BitReader::new(data);
+        if reader.flag()? {
+            // a header packet somewhere it shouldn't be
+            return Ok(None);
+        }
+        let mode = self.modes.get(reader.bits(ilog(self.modes.len() as u32 - 1))? as usize).ok_or_else(|| invalid("bad mode number"))?;
+        let n = self.block_sizes[mode.long_block as usize];
+        let half = n / 2;
+        let (previous_long, next_long) = if mode.long_block { (reader.flag()?, reader.flag()?) } else { (false, false) };
+        let mapping = &self.mappings[mode.mapping];
+
+        // floors first, for every channel
+        let mut floors = Vec::with_capacity(self.channels);
+        for channel in 0..self.channels {
+            let floor = &self.floors[mapping.submaps[mapping.mux[channel]].0];
+            floors.push(match floor.decode(&mut reader, &self.codebooks) {
+                Ok(y) => y,
+                // out of packet here means the rest of the channels are unused too
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => None,
+                Err(error) => return Err(error),
+            });
+        }
+        // a coupled pair is decoded if either of them is used
+        let mut skip: Vec<bool> = floors.iter().map(Option::is_none).collect();
+        for &(magnitude, angle) in &mapping.coupling {
+            if !skip[magnitude] || !skip[angle] {
+                skip[magnitude] = false;
+                skip[angle] = false;
+            }
+        }
+
+        let mut vectors = vec![vec![0.0f32; half]; self.channels];
+        for (submap, &(_, residue)) in mapping.submaps.iter().enumerate() {
+            let channels: Vec<usize> = (0..self.channels).filter(|channel| mapping.mux[*channel] == submap).collect();
+            let mut submap_vectors: Vec<Vec<f32>> = channels.iter().map(|_| vec![0.0; half]).collect();
+            let submap_skip: Vec<bool> = channels.iter().map(|channel| skip[*channel]).collect();
+            self.residues[residue].decode(&mut reader, &self.codebooks, &mut submap_vectors, &submap_skip, half);
+            for (channel, vector) in channels.into_iter().zip(submap_vectors) {
+                vectors[channel] = vector;
+            }
+        }
+
+        for &(magnitude, angle) in mapping.coupling.iter().rev() {
+            // the setup header made sure the two are different channels
+            let mut angles = std::mem::take(&mut vectors[angle]);
+            for (m, a) in vectors[magnitude].iter_mut().zip(&mut angles).take(half) {
+                (*m, *a) = match (*m > 0.0, *a > 0.0) {
+                    (true, true) => (*m, *m - *a),
+                    (true, false) => (*m + *a, *m),
+                    (false, true) => (*m, *m + *a),
+                    (false, false) => (*m - *a, *m),
+                };
+            }
+            vectors[angle] = angles;
+        }
+
+        let window = self.window(n, mode.long_block, previous_long, next_long);
+        let mut blocks = Vec::with_capacity(self.channels);
+        for (channel, vector) in vectors.iter_mut().enumerate() {
+            match &floors[channel] {
+                Some(y) => {
+                    let floor = &self.floors[mapping.submaps[mapping.mux[channel]].0];
+                    for (value, floor) in vector.iter_mut().zip(floor.render(y, half)) {
+                        *value *= floor;
+                    }
+                }
+                None => vector.fill(0.0),
+            }
+            let mut block = inverse_mdct(vector);
+            for (value, window) in block.iter_mut().zip(&window) {
+                *value *= window;
+            }
+            blocks.push(block);
+        }
+
+        // what comes out runs from the middle of the last block to the middle of this one, overlapped where they meet
+        let output = self.previous.as_ref().map(|previous| {
+            let previous_n = previous[0].len() * 2;
+            let length = previous_n / 4 + n / 4;
+            let shift = n as isize / 4 - previous_n as isize / 4;
+            previous
+                .iter()
+                .zip(&blocks)
+                .map(|(previous, block)| {
+                    (0..length)
+                        .map(|index| {
+                            let current = index as isize + shift;
+                            previous.get(index).copied().unwrap_or(0.0) + if current >= 0 { block.get(current as usize).copied().unwrap_or(0.0) } else { 0.0 }
+                        })
+                        .collect()
+                })
+                .collect()
+        });
+        self.previous = Some(blocks.into_iter().map(|block| block[half..].to_vec()).collect());
+        Ok(Some(output.unwrap_or_else(|| vec![Vec::new(); self.channels])))
+    }
+
+    /// The window for a block of n, a long block's slopes are short ones on the sides next to short blocks
+    fn window(&self, n: usize, long_block: bool, previous_long: bool, next_long: bool) -> Vec<f32> {
+        let short = self.block_sizes[0];
+        let (left_start, left_end) = if long_block && !previous_long { (n / 4 - short / 4, n / 4 + short / 4) } else { (0, n / 2) };
+        let (right_start, right_end) = if long_block && !next_long { (n * 3 / 4 - short / 4, n * 3 / 4 + short / 4) } else { (n / 2, n) };
+        let slope = |index: usize, length: usize| {
+            let x = (index as f32 + 0.5) / length as f32 * PI / 2.0;
+            (PI / 2.0 * x.sin() * x.sin()).sin()
+        };
+        (0..n)
+            .map(|index| {
+                if index < left_start || index >= right_end {
+                    0.0
+                } else if index < left_end {
+                    slope(index - left_start, left_end - left_start)
+                } else if index < right_start {
+                    1.0
+                } else {
+                    slope(right_end - 1 - index, right_end - right_start)
+                }
+            })
+            .collect()
+    }
+}
+
+/// n / 2 coefficients to n samples, unscaled like the spec's. Goes through a DCT-IV of n / 2, which is done with
+/// an FFT of n / 4
+fn inverse_mdct(coefficients: &[f32]) -> Vec<f32> {
+    let m = coefficients.len();
+    let quarter = m / 2;
+    // the DCT-IV's input paired up into complex values, turned, transformed and turned back
+    let mut real = Vec::with_capacity(quarter);
+    let mut imaginary = Vec::with_capacity(quarter);
+    for k in 0..quarter {
+        let (a, b) = (coefficients[2 * k], coefficients[m - 1 - 2 * k]);
+        let angle = -PI * (4 * k + 1) as f32 / (4 * m) as f32;
+        let (sin, cos) = angle.sin_cos();
+        real.push(a * cos - b * sin);
+        imaginary.push(a * sin + b * cos);
+    }
+    fft(&mut real, &mut imaginary);
+    let mut dct = vec![0.0; m];
+    for n in 0..quarter {
+        let angle = -PI * n as f32 / m as f32;
+        let (sin, cos) = angle.sin_cos();
+        dct[2 * n] = real[n] * cos - imaginary[n] * sin;
+        dct[m - 1 - 2 * n] = -(real[n] * sin + imaginary[n] * cos);
+    }
+    // the IMDCT is the DCT-IV shifted a quarter along and unfolded by its symmetries
+    let half = m / 2;
+    (0..2 * m)
+        .map(|index| {
+            if index < half {
+                dct[index + half]
+            } else if index < 3 * half {
+                -dct[3 * half - 1 - index]
+            } else {
+                -dct[index - 3 * half]
+            }
+        })
+        .collect()
+}
+
+/// In place radix 2 FFT, the length has to be a power of two
+fn fft(real: &mut [f32], imaginary: &mut [f32]) {
+    let n = real.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * PI / length as f32;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + length / 2);
+                let (tr, ti) = (real[b] * cos - imaginary[b] * sin, real[b] * sin + imaginary[b] * cos);
+                real[b] = real[a] - tr;
+                imaginary[b] = imaginary[a] - ti;
+                real[a] += tr;
+                imaginary[a] += ti;
+            }
+        }
+        length *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the other way round from BitReader, for building headers and packets
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        bit: u32,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: u32) -> &mut Self {
+            for index in 0..count {
+                if self.bit == 0 {
+                    self.bytes.push(0);
+                }
+                *self.bytes.last_mut().unwrap() |= (((value >> index) & 1) as u8) << self.bit;
+                self.bit = (self.bit + 1) % 8;
+            }
+            self
+        }
+    }
+
+    // a codebook header with every entry the same length, leaving the lookup to be written after
+    fn codebook_header(dimensions: u32, entries: u32, length: u32) -> BitWriter {
+        let mut writer = BitWriter::default();
+        writer.bits(0x564342, 24).bits(dimensions, 16).bits(entries, 24).bits(0, 1).bits(0, 1);
+        for _ in 0..entries {
+            writer.bits(length - 1, 5);
+        }
+        writer
+    }
+
+    #[test]
+    fn inverse_mdct_matches_its_definition() {
+        for m in [32usize, 128, 1024] {
+            let coefficients: Vec<f32> = (0..m).map(|index| ((index * 7919 % 13) as f32 - 6.0) / 3.0).collect();
+            let fast = inverse_mdct(&coefficients);
+            let n = 2 * m;
+            assert_eq!(fast.len(), n);
+            for (index, sample) in fast.iter().enumerate() {
+                let expected: f64 = coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, coefficient)| {
+                        let angle = std::f64::consts::PI / 2.0 / n as f64 * (2.0 * index as f64 + 1.0 + n as f64 / 2.0) * (2.0 * k as f64 + 1.0);
+                        *coefficient as f64 * angle.cos()
+                    })
+                    .sum();
+                assert!((expected - *sample as f64).abs() < 1e-2, "m {m} sample {index}: {sample} isn't {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn codewords_are_assigned_as_in_the_spec() {
+        // the example from section 3.2.1
+        let book = Codebook { dimensions: 1, nodes: build_tree(&[2, 4, 4, 4, 4, 2, 3, 3]).unwrap(), vectors: Vec::new() };
+        for (entry, code) in ["00", "0100", "0101", "0110", "0111", "10", "110", "111"].iter().enumerate() {
+            let mut writer = BitWriter::default();
+            for bit in code.chars() {
+                writer.bits((bit == '1') as u32, 1);
+            }
+            assert_eq!(book.decode(&mut BitReader::new(&writer.bytes)).unwrap(), entry as u32, "code {code}");
+        }
+    }
+
+    #[test]
+    fn overspecified_lengths_are_rejected() {
+        assert!(build_tree(&[1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn unpacks_floats_and_counts_lookup_values() {
+        assert_eq!(float32_unpack(788 << 21 | 3), 3.0);
+        assert_eq!(float32_unpack(0x80000000 | 787 << 21 | 1), -0.5);
+        assert_eq!(lookup1_values(81, 4), 3);
+        assert_eq!(lookup1_values(80, 4), 2);
+        assert_eq!(lookup1_values(1_000_000, 2), 1000);
+        assert_eq!(lookup1_values(5, 1), 5);
+    }
+
+    #[test]
+    fn reads_lookup1_vectors() {
+        // two values a dimension, 0 and 1, so the four entries are every pair, first dimension changing fastest
+        let mut writer = codebook_header(2, 4, 2);
+        writer.bits(1, 4).bits(0, 32).bits(788 << 21 | 1, 32).bits(0, 4).bits(0, 1).bits(0, 1).bits(1, 1);
+        let book = Codebook::read(&mut BitReader::new(&writer.bytes)).unwrap();
+        assert_eq!(book.vectors, [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn vector_codebooks_need_dimensions() {
+        let mut writer = codebook_header(0, 4, 2);
+        writer.bits(1, 4).bits(0, 32).bits(788 << 21 | 1, 32).bits(0, 4).bits(0, 1);
+        let error = Codebook::read(&mut BitReader::new(&writer.bytes)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bad_mode_numbers_are_an_error() {
+        let mut decoder = VorbisDecoder {
+            ogg: OggReader::new(&[][..]),
+            channels: 1,
+            samples_per_second: 44100,
+            block_sizes: [256, 2048],
+            codebooks: Vec::new(),
+            floors: Vec::new(),
+            residues: Vec::new(),
+            mappings: Vec::new(),
+            modes: (0..3).map(|_| Mode { long_block: false, mapping: 0 }).collect(),
+            previous: None,
+            position: 0,
+            finished: false,
+        };
+        // an audio packet asking for mode 3 of 0 to 2
+        let mut writer = BitWriter::default();
+        writer.bits(0, 1).bits(3, 2);
+        let error = decoder.decode_packet(&writer.bytes).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn streams_without_headers_are_an_error() {
+        assert!(VorbisDecoder::new(&b""[..]).is_err());
+        assert!(VorbisDecoder::new(&b"OggS"[..]).is_err());
+    }
+}
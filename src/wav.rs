@@ -0,0 +1,89 @@
+//! WAV decoding into Sounds. PCM at 8, 16, 24 or 32 bits and 32 bit float, any number of channels, of which the
+//! first two are kept (mono plays in both). The first loop in a sampler chunk becomes the sound's loop points.
+
+use std::io;
+
+use crate::mixer::Sound;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Bad WAV: {message}"))
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+fn u16_at(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    let field = bytes.get(offset..offset + 2).ok_or_else(|| invalid("chunk is cut short"))?;
+    Ok(u16::from_le_bytes([field[0], field[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    let field = bytes.get(offset..offset + 4).ok_or_else(|| invalid("chunk is cut short"))?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+// one sample, -1..1 as i16 is, from its little endian bytes
+fn decode_sample(bytes: &[u8], float: bool) -> i16 {
+    match (bytes.len(), float) {
+        (4, true) => (f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).clamp(-1.0, 1.0) * i16::MAX as f32) as i16,
+        // 8 bit is the one unsigned width
+        (1, _) => ((bytes[0] as i16) - 128) << 8,
+        // anything wider keeps its top 16 bits
+        (width, _) => i16::from_le_bytes([bytes[width - 2], bytes[width - 1]]),
+    }
+}
+
+impl Sound {
+    pub fn parse_wav(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("missing RIFF WAVE header"));
+        }
+        let mut format = None;
+        let mut data = None;
+        let mut loop_points = None;
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size = u32_at(bytes, offset + 4)? as usize;
+            // a truncated last chunk is common enough in the wild, take what's there
+            let chunk = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+            match id {
+                b"fmt " => {
+                    let mut tag = u16_at(chunk, 0)?;
+                    if tag == WAVE_FORMAT_EXTENSIBLE {
+                        // the sub format GUID starts with the plain tag it stands for
+                        tag = u16_at(chunk, 24)?;
+                    }
+                    format = Some((tag, u16_at(chunk, 2)? as usize, u32_at(chunk, 4)?, u16_at(chunk, 14)? as usize));
+                }
+                b"data" => data = Some(chunk),
+                b"smpl" if u32_at(chunk, 28)? > 0 => {
+                    // the end is the last sample played, inclusive
+                    loop_points = Some(u32_at(chunk, 36 + 8)? as usize..u32_at(chunk, 36 + 12)? as usize + 1);
+                }
+                _ => {}
+            }
+            // chunks are padded to an even size
+            offset += 8 + size + size % 2;
+        }
+
+        let (tag, channels, samples_per_second, bits) = format.ok_or_else(|| invalid("no fmt chunk"))?;
+        let data = data.ok_or_else(|| invalid("no data chunk"))?;
+        let float = match (tag, bits) {
+            (WAVE_FORMAT_PCM, 8 | 16 | 24 | 32) => false,
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => true,
+            _ => return Err(invalid(&format!("{bits} bit samples of format {tag} aren't supported"))),
+        };
+        if channels == 0 || samples_per_second == 0 {
+            return Err(invalid("no channels or no rate"));
+        }
+        let width = bits / 8;
+        let right = if channels == 1 { 0 } else { 1 };
+        let samples = data
+            .chunks_exact(width * channels)
+            .flat_map(|frame| [0, right].map(|channel| decode_sample(&frame[channel * width..(channel + 1) * width], float)))
+            .collect();
+        Ok(Sound { samples, samples_per_second, loop_points })
+    }
+}
@@ -0,0 +1,991 @@
+//! Direct3D 11 presentation: a flip model swap chain on the window, with the software rendered frame copied into a
+//! texture every frame and drawn over the letterboxed rect by one triangle. Same job as the OpenGL path, on the API
+//! the rest of the GPU work is built on.
+//!
+//! Plain bitmap draws don't have to go through the rasterizer: the longest run of them in the frame's commands is
+//! drawn here as instanced quads out of a sprite atlas, straight into the frame texture between what the software
+//! path drew before and after the run.
+//!
+//! Each step of a frame is wrapped in a named event, with the kinds of render command it covers, so a frame caught in
+//! PIX or RenderDoc reads like the render group that made it.
+//!
+//! The render group's post passes run between the frame texture and the present draw, each a fullscreen draw into
+//! one of a few frame sized textures, so the frame texture itself stays as rendered.
+//!
+//! The swap chain can also send scRGB or HDR10 to a display with HDR on, the present shader tone maps the SDR frame
+//! for it.
+//!
+//! The shaders are in shaders/, and reloaded whenever they're saved while the game runs. One that doesn't compile
+//! is reported on screen and the one before it is kept.
+
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::*,
+    Win32::{
+        Foundation::*,
+        Graphics::{
+            Direct3D::{Fxc::*, *},
+            Direct3D11::*,
+            Dxgi::{Common::*, *},
+            Gdi::*,
+        },
+    },
+};
+
+use crate::{
+    color::{Color, Pixel},
+    debug_draw::debug_draw_message,
+    frame_buffer::FrameBuffer,
+    post_process::{PostPass, CHROMA_OFFSET, CURVATURE, SCANLINE_BRIGHTNESS},
+    render::BitmapFilter,
+    render_group::{RenderGroup, SpriteQuad, TileHashes},
+    renderer::{OutputMode, Renderer, VSync},
+    shader_files::ShaderFile,
+    sprite_atlas::SpriteAtlas,
+    win32_presentation_rect,
+    work_queue::WorkQueue,
+    Win32OffscreenBuffer,
+    GLOBAL_SMOOTH_PRESENT,
+};
+
+// how bright white in the frame is on an HDR display, about where SDR content sits in Windows' own HDR mode
+const PAPER_WHITE_NITS: f32 = 200.0;
+// for displays that don't say how bright they go
+const DEFAULT_MAX_NITS: f32 = 1000.0;
+
+// well under what every feature level we ask for supports
+const ATLAS_SIZE: i32 = 2048;
+
+/// present.hlsl's Output constant buffer
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OutputConstants {
+    mode: u32,
+    paper_white: f32,
+    max_nits: f32,
+    _padding: f32,
+}
+
+/// post.hlsl's Post constant buffer
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct PostConstants {
+    direction: [i32; 2],
+    radius: f32,
+    threshold: f32,
+    intensity: f32,
+    curvature: f32,
+    chroma_offset: f32,
+    scanline_brightness: f32,
+}
+
+/// One sprite as the vertex shader reads it
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SpriteInstance {
+    origin: [f32; 2],
+    x_axis: [f32; 2],
+    y_axis: [f32; 2],
+    source: [f32; 4],
+}
+
+/// Everything built from present.hlsl
+struct PresentShaders {
+    vertex: ID3D11VertexShader,
+    pixel: ID3D11PixelShader,
+    overlay: ID3D11PixelShader,
+}
+
+/// Everything built from post.hlsl, drawn with present.hlsl's fullscreen triangle
+struct PostShaders {
+    blur: ID3D11PixelShader,
+    threshold: ID3D11PixelShader,
+    composite: ID3D11PixelShader,
+    crt: ID3D11PixelShader,
+}
+
+/// Everything built from sprite.hlsl, the input layout has to match its vertex shader
+struct SpriteShaders {
+    vertex: ID3D11VertexShader,
+    pixel: ID3D11PixelShader,
+    layout: ID3D11InputLayout,
+}
+
+/// A texture with the views it's used through
+struct Texture {
+    texture: ID3D11Texture2D,
+    view: ID3D11ShaderResourceView,
+    target: Option<ID3D11RenderTargetView>,
+}
+
+pub struct Win32D3D11 {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    swap_chain: IDXGISwapChain,
+    // the swap chain's buffer as a render target, let go of and made again whenever the window changes size
+    target: Option<ID3D11RenderTargetView>,
+    target_size: (i32, i32),
+    // what the swap chain sends, a change remakes the target in the new format
+    output_mode: OutputMode,
+    output_constants: ID3D11Buffer,
+    // the display's peak brightness in nits, from when HDR was last switched on
+    max_nits: f32,
+    // the frame is copied into this and the sprites drawn over it at the frame's size, remade when that changes
+    frame: Option<Texture>,
+    frame_size: (i32, i32),
+    // what the software path drew after the sprite run, same size as the frame
+    overlay: Option<Texture>,
+    present_file: ShaderFile,
+    present_shaders: PresentShaders,
+    // point then linear, picked by the smooth present toggle or a sprite's filter
+    samplers: [Option<ID3D11SamplerState>; 2],
+
+    atlas: SpriteAtlas,
+    atlas_texture: Texture,
+    sprite_file: ShaderFile,
+    sprite_shaders: SpriteShaders,
+    sizes: ID3D11Buffer,
+    // dynamic, grown to the next power of two sprites whenever a frame has more than fit
+    instances: Option<ID3D11Buffer>,
+    instance_capacity: usize,
+    premultiplied_blend: ID3D11BlendState,
+    scissored: ID3D11RasterizerState,
+
+    post_file: ShaderFile,
+    post_shaders: PostShaders,
+    post_constants: ID3D11Buffer,
+    // what the passes draw into, frame sized, made with the first pass and dropped with the frame texture
+    post_targets: Vec<Texture>,
+
+    // what execute left for present
+    sprites: Vec<SpriteQuad>,
+    overlay_pixels: Vec<Pixel>,
+    overlay_drawn: bool,
+    post_passes: Vec<PostPass>,
+    // whether the frame texture has more in it than the front buffer, read_back copies it down then
+    composited: bool,
+    // what the software path drew before and after the sprite run, the names of the events they're uploaded under
+    software_before: String,
+    software_after: String,
+    // debug events go to this while a capture tool is attached, checked once a frame so begins and ends pair up
+    annotation: Option<ID3DUserDefinedAnnotation>,
+    annotating: bool,
+
+    vsync: VSync,
+    // whether presents that don't wait can tear, without it they're still uncapped but wait for the compositor
+    tearing: bool,
+    // how long a refresh of the window's monitor takes, adaptive vsync stops waiting for frames slower than this
+    refresh_period: Duration,
+    last_present: Instant,
+}
+
+/// Compiles one entry point of source, the error has what the compiler had to say when it fails. name is only for
+/// the messages
+fn compile_shader(source: &str, name: &str, entry_point: PCSTR, target: PCSTR) -> Result<ID3DBlob> {
+    let name = format!("{name}\0");
+    let mut code = None;
+    let mut errors = None;
+    let result = unsafe {
+        D3DCompile(
+            source.as_ptr().cast(),
+            source.len(),
+            PCSTR(name.as_ptr()),
+            None,
+            None::<&ID3DInclude>,
+            entry_point,
+            target,
+            D3DCOMPILE_OPTIMIZATION_LEVEL3,
+            0,
+            &mut code,
+            Some(&mut errors),
+        )
+    };
+    if let Err(error) = result {
+        return Err(match &errors {
+            Some(errors) => Error::new(error.code(), String::from_utf8_lossy(blob_bytes(errors)).trim_end()),
+            None => error,
+        });
+    }
+    code.ok_or_else(|| Error::from(E_FAIL))
+}
+
+fn blob_bytes(blob: &ID3DBlob) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize()) }
+}
+
+impl PresentShaders {
+    fn build(device: &ID3D11Device, name: &str, source: &str) -> Result<Self> {
+        let vertex_code = compile_shader(source, name, s!("vertex_main"), s!("vs_4_0"))?;
+        let pixel_code = compile_shader(source, name, s!("pixel_main"), s!("ps_4_0"))?;
+        let overlay_code = compile_shader(source, name, s!("overlay_main"), s!("ps_4_0"))?;
+        let (mut vertex, mut pixel, mut overlay) = (None, None, None);
+        unsafe {
+            device.CreateVertexShader(blob_bytes(&vertex_code), None::<&ID3D11ClassLinkage>, Some(&mut vertex))?;
+            device.CreatePixelShader(blob_bytes(&pixel_code), None::<&ID3D11ClassLinkage>, Some(&mut pixel))?;
+            device.CreatePixelShader(blob_bytes(&overlay_code), None::<&ID3D11ClassLinkage>, Some(&mut overlay))?;
+        }
+        match (vertex, pixel, overlay) {
+            (Some(vertex), Some(pixel), Some(overlay)) => Ok(PresentShaders { vertex, pixel, overlay }),
+            _ => Err(Error::from(E_FAIL)),
+        }
+    }
+}
+
+impl PostShaders {
+    fn build(device: &ID3D11Device, name: &str, source: &str) -> Result<Self> {
+        let pixel = |entry_point: PCSTR| -> Result<ID3D11PixelShader> {
+            let code = compile_shader(source, name, entry_point, s!("ps_4_0"))?;
+            let mut shader = None;
+            unsafe { device.CreatePixelShader(blob_bytes(&code), None::<&ID3D11ClassLinkage>, Some(&mut shader))? };
+            shader.ok_or_else(|| Error::from(E_FAIL))
+        };
+        Ok(PostShaders {
+            blur: pixel(s!("blur_main"))?,
+            threshold: pixel(s!("threshold_main"))?,
+            composite: pixel(s!("composite_main"))?,
+            crt: pixel(s!("crt_main"))?,
+        })
+    }
+}
+
+impl SpriteShaders {
+    fn build(device: &ID3D11Device, name: &str, source: &str) -> Result<Self> {
+        let vertex_code = compile_shader(source, name, s!("vertex_main"), s!("vs_4_0"))?;
+        let pixel_code = compile_shader(source, name, s!("pixel_main"), s!("ps_4_0"))?;
+        // every element steps once per sprite, the vertices only differ by SV_VertexID
+        let element = |name: PCSTR, format: DXGI_FORMAT, offset: u32| D3D11_INPUT_ELEMENT_DESC {
+            SemanticName: name,
+            SemanticIndex: 0,
+            Format: format,
+            InputSlot: 0,
+            AlignedByteOffset: offset,
+            InputSlotClass: D3D11_INPUT_PER_INSTANCE_DATA,
+            InstanceDataStepRate: 1,
+        };
+        let elements = [
+            element(s!("ORIGIN"), DXGI_FORMAT_R32G32_FLOAT, 0),
+            element(s!("XAXIS"), DXGI_FORMAT_R32G32_FLOAT, 8),
+            element(s!("YAXIS"), DXGI_FORMAT_R32G32_FLOAT, 16),
+            element(s!("SOURCE"), DXGI_FORMAT_R32G32B32A32_FLOAT, 24),
+        ];
+        let (mut vertex, mut pixel, mut layout) = (None, None, None);
+        unsafe {
+            device.CreateVertexShader(blob_bytes(&vertex_code), None::<&ID3D11ClassLinkage>, Some(&mut vertex))?;
+            device.CreatePixelShader(blob_bytes(&pixel_code), None::<&ID3D11ClassLinkage>, Some(&mut pixel))?;
+            device.CreateInputLayout(&elements, blob_bytes(&vertex_code), Some(&mut layout))?;
+        }
+        match (vertex, pixel, layout) {
+            (Some(vertex), Some(pixel), Some(layout)) => Ok(SpriteShaders { vertex, pixel, layout }),
+            _ => Err(Error::from(E_FAIL)),
+        }
+    }
+}
+
+/// Builds from the file on disk, or from what was built in when that doesn't compile so a broken file at startup
+/// doesn't stop the backend starting
+fn build_from_file<T>(file: &ShaderFile, build: impl Fn(&str) -> Result<T>) -> Result<T> {
+    build(&file.source()).or_else(|error| {
+        report_shader_error(file.name, &error);
+        build(file.embedded())
+    })
+}
+
+fn report_shader_error(name: &str, error: &Error) {
+    println!("{name}: {error}");
+    debug_draw_message(&format!("{name}: {error}"), Color::rgb8(0xFF, 0x60, 0x60), Duration::from_secs(10));
+}
+
+/// Whether the system can show frames as soon as they're presented, tearing, instead of at the next refresh. Needs
+/// DXGI 1.5 and a driver that does it
+fn tearing_supported() -> bool {
+    let mut allowed = BOOL(0);
+    unsafe {
+        CreateDXGIFactory1::<IDXGIFactory5>()
+            .and_then(|factory| factory.CheckFeatureSupport(DXGI_FEATURE_PRESENT_ALLOW_TEARING, (&mut allowed as *mut BOOL).cast(), size_of::<BOOL>() as u32))
+            .is_ok()
+            && allowed.as_bool()
+    }
+}
+
+// the swap chain has to be created and resized with the same flags
+fn swap_chain_flags(tearing: bool) -> DXGI_SWAP_CHAIN_FLAG {
+    if tearing { DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING } else { DXGI_SWAP_CHAIN_FLAG(0) }
+}
+
+/// An event name for pixels the software path drew, with the commands that drew them when they're known
+fn software_event(name: &str, commands: &str) -> String {
+    if commands.is_empty() { name.to_string() } else { format!("{name}: {commands}") }
+}
+
+fn swap_chain_format(mode: OutputMode) -> DXGI_FORMAT {
+    match mode {
+        OutputMode::Sdr => DXGI_FORMAT_B8G8R8A8_UNORM,
+        OutputMode::ScRgb => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        OutputMode::Hdr10 => DXGI_FORMAT_R10G10B10A2_UNORM,
+    }
+}
+
+fn color_space(mode: OutputMode) -> DXGI_COLOR_SPACE_TYPE {
+    match mode {
+        OutputMode::Sdr => DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709,
+        OutputMode::ScRgb => DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709,
+        OutputMode::Hdr10 => DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020,
+    }
+}
+
+/// One refresh of the monitor the window's on, a 60Hz one when the driver won't say
+fn refresh_period(window: HWND) -> Duration {
+    let rate = unsafe {
+        let dc = GetDC(Some(window));
+        let rate = GetDeviceCaps(Some(dc), VREFRESH);
+        ReleaseDC(Some(window), dc);
+        rate
+    };
+    // 0 and 1 mean the hardware's default
+    Duration::from_secs_f64(1.0 / if rate > 1 { rate as f64 } else { 60.0 })
+}
+
+/// A width by height BGRA texture the CPU copies into with UpdateSubresource, with a render target view if asked for
+fn create_texture(device: &ID3D11Device, width: i32, height: i32, render_target: bool) -> Result<Texture> {
+    let bind = if render_target { D3D11_BIND_SHADER_RESOURCE.0 | D3D11_BIND_RENDER_TARGET.0 } else { D3D11_BIND_SHADER_RESOURCE.0 };
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: width as u32,
+        Height: height as u32,
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+        SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: bind as u32,
+        CPUAccessFlags: 0,
+        MiscFlags: 0,
+    };
+    let (mut texture, mut view, mut target) = (None, None, None);
+    unsafe {
+        device.CreateTexture2D(&desc, None, Some(&mut texture))?;
+        let texture = texture.ok_or_else(|| Error::from(E_FAIL))?;
+        device.CreateShaderResourceView(&texture, None, Some(&mut view))?;
+        if render_target {
+            device.CreateRenderTargetView(&texture, None, Some(&mut target))?;
+        }
+        let view = view.ok_or_else(|| Error::from(E_FAIL))?;
+        Ok(Texture { texture, view, target })
+    }
+}
+
+impl Win32D3D11 {
+    pub fn new(window: HWND) -> Result<Self> {
+        let tearing = tearing_supported();
+        let desc = DXGI_SWAP_CHAIN_DESC {
+            // zero size takes the window's, the first present sizes it properly anyway
+            BufferDesc: DXGI_MODE_DESC { Format: DXGI_FORMAT_B8G8R8A8_UNORM, ..Default::default() },
+            SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+            BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+            BufferCount: 2,
+            OutputWindow: window,
+            Windowed: true.into(),
+            SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
+            Flags: swap_chain_flags(tearing).0 as u32,
+        };
+        let (mut swap_chain, mut device, mut context) = (None, None, None);
+        unsafe {
+            D3D11CreateDeviceAndSwapChain(
+                None::<&IDXGIAdapter>,
+                D3D_DRIVER_TYPE_HARDWARE,
+                HMODULE::default(),
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                Some(&[D3D_FEATURE_LEVEL_11_0, D3D_FEATURE_LEVEL_10_1, D3D_FEATURE_LEVEL_10_0]),
+                D3D11_SDK_VERSION,
+                Some(&desc),
+                Some(&mut swap_chain),
+                Some(&mut device),
+                None,
+                Some(&mut context),
+            )?;
+        }
+        let (Some(swap_chain), Some(device), Some(context)) = (swap_chain, device, context) else {
+            return Err(Error::from(E_FAIL));
+        };
+
+        let present_file = ShaderFile::new("present.hlsl", include_str!("../shaders/present.hlsl"));
+        let sprite_file = ShaderFile::new("sprite.hlsl", include_str!("../shaders/sprite.hlsl"));
+        let present_shaders = build_from_file(&present_file, |source| PresentShaders::build(&device, present_file.name, source))?;
+        let sprite_shaders = build_from_file(&sprite_file, |source| SpriteShaders::build(&device, sprite_file.name, source))?;
+        let post_file = ShaderFile::new("post.hlsl", include_str!("../shaders/post.hlsl"));
+        let post_shaders = build_from_file(&post_file, |source| PostShaders::build(&device, post_file.name, source))?;
+        let (mut sizes, mut output_constants, mut post_constants, mut premultiplied_blend, mut scissored) = (None, None, None, None, None);
+        let mut samplers = [None, None];
+        unsafe {
+            for (sampler, filter) in samplers.iter_mut().zip([D3D11_FILTER_MIN_MAG_MIP_POINT, D3D11_FILTER_MIN_MAG_MIP_LINEAR]) {
+                let desc = D3D11_SAMPLER_DESC {
+                    Filter: filter,
+                    AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    MaxLOD: f32::MAX,
+                    ..Default::default()
+                };
+                device.CreateSamplerState(&desc, Some(sampler))?;
+            }
+
+            let desc = D3D11_BUFFER_DESC { ByteWidth: 16, Usage: D3D11_USAGE_DEFAULT, BindFlags: D3D11_BIND_CONSTANT_BUFFER.0 as u32, ..Default::default() };
+            device.CreateBuffer(&desc, None, Some(&mut sizes))?;
+            device.CreateBuffer(&desc, None, Some(&mut output_constants))?;
+            let desc = D3D11_BUFFER_DESC { ByteWidth: size_of::<PostConstants>() as u32, ..desc };
+            device.CreateBuffer(&desc, None, Some(&mut post_constants))?;
+
+            // everything the software path draws is premultiplied, so the source is added as it is
+            let mut desc = D3D11_BLEND_DESC::default();
+            desc.RenderTarget[0] = D3D11_RENDER_TARGET_BLEND_DESC {
+                BlendEnable: true.into(),
+                SrcBlend: D3D11_BLEND_ONE,
+                DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
+                BlendOp: D3D11_BLEND_OP_ADD,
+                SrcBlendAlpha: D3D11_BLEND_ONE,
+                DestBlendAlpha: D3D11_BLEND_INV_SRC_ALPHA,
+                BlendOpAlpha: D3D11_BLEND_OP_ADD,
+                RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8,
+            };
+            device.CreateBlendState(&desc, Some(&mut premultiplied_blend))?;
+
+            // transformed sprites can be flipped, so neither winding is culled
+            let desc = D3D11_RASTERIZER_DESC {
+                FillMode: D3D11_FILL_SOLID,
+                CullMode: D3D11_CULL_NONE,
+                DepthClipEnable: true.into(),
+                ScissorEnable: true.into(),
+                ..Default::default()
+            };
+            device.CreateRasterizerState(&desc, Some(&mut scissored))?;
+        }
+        let (Some(sizes), Some(output_constants), Some(post_constants), Some(premultiplied_blend), Some(scissored)) =
+            (sizes, output_constants, post_constants, premultiplied_blend, scissored)
+        else {
+            return Err(Error::from(E_FAIL));
+        };
+        let atlas_texture = create_texture(&device, ATLAS_SIZE, ATLAS_SIZE, false)?;
+        // missing before Windows 8, there are just no events then
+        let annotation = context.cast().ok();
+        Ok(Win32D3D11 {
+            device,
+            context,
+            swap_chain,
+            target: None,
+            target_size: (0, 0),
+            output_mode: OutputMode::Sdr,
+            output_constants,
+            max_nits: DEFAULT_MAX_NITS,
+            frame: None,
+            frame_size: (0, 0),
+            overlay: None,
+            present_file,
+            present_shaders,
+            samplers,
+            atlas: SpriteAtlas::new(ATLAS_SIZE),
+            atlas_texture,
+            sprite_file,
+            sprite_shaders,
+            sizes,
+            instances: None,
+            instance_capacity: 0,
+            premultiplied_blend,
+            scissored,
+            post_file,
+            post_shaders,
+            post_constants,
+            post_targets: Vec::new(),
+            sprites: Vec::new(),
+            overlay_pixels: Vec::new(),
+            overlay_drawn: false,
+            post_passes: Vec::new(),
+            composited: false,
+            software_before: String::new(),
+            software_after: String::new(),
+            annotation,
+            annotating: false,
+            vsync: VSync::default(),
+            tearing,
+            refresh_period: refresh_period(window),
+            last_present: Instant::now(),
+        })
+    }
+
+    /// Rebuilds whichever shaders were saved since the last look, keeping the old ones if the new ones don't compile
+    fn reload_shaders(&mut self) {
+        if let Some(source) = self.present_file.poll() {
+            match PresentShaders::build(&self.device, self.present_file.name, &source) {
+                Ok(shaders) => {
+                    self.present_shaders = shaders;
+                    println!("Reloaded {}", self.present_file.name);
+                }
+                Err(error) => report_shader_error(self.present_file.name, &error),
+            }
+        }
+        if let Some(source) = self.sprite_file.poll() {
+            match SpriteShaders::build(&self.device, self.sprite_file.name, &source) {
+                Ok(shaders) => {
+                    self.sprite_shaders = shaders;
+                    println!("Reloaded {}", self.sprite_file.name);
+                }
+                Err(error) => report_shader_error(self.sprite_file.name, &error),
+            }
+        }
+        if let Some(source) = self.post_file.poll() {
+            match PostShaders::build(&self.device, self.post_file.name, &source) {
+                Ok(shaders) => {
+                    self.post_shaders = shaders;
+                    println!("Reloaded {}", self.post_file.name);
+                }
+                Err(error) => report_shader_error(self.post_file.name, &error),
+            }
+        }
+    }
+
+    /// The sprites from execute into the frame texture in order, a draw call per stretch with the same filter and clip
+    fn draw_sprites(&mut self, frame_target: &ID3D11RenderTargetView) -> Result<()> {
+        let (width, height) = self.frame_size;
+        let mut instances = Vec::with_capacity(self.sprites.len());
+        for sprite in &self.sprites {
+            // execute only kept sprites that made it into the atlas, and nothing was placed since
+            let Some(source) = self.atlas.place(&sprite.bitmap) else {
+                return Err(Error::from(E_FAIL));
+            };
+            instances.push(SpriteInstance {
+                origin: [sprite.origin.x, sprite.origin.y],
+                x_axis: [sprite.x_axis.x, sprite.x_axis.y],
+                y_axis: [sprite.y_axis.x, sprite.y_axis.y],
+                source: [source.x as f32, source.y as f32, source.width as f32, source.height as f32],
+            });
+        }
+        unsafe {
+            let uploads = self.atlas.take_uploads();
+            if !uploads.is_empty() {
+                self.begin_event(&format!("Atlas uploads x{}", uploads.len()));
+            }
+            for upload in &uploads {
+                let area = D3D11_BOX {
+                    left: upload.x as u32,
+                    top: upload.y as u32,
+                    front: 0,
+                    right: (upload.x + upload.width) as u32,
+                    bottom: (upload.y + upload.height) as u32,
+                    back: 1,
+                };
+                let pitch = upload.width as u32 * size_of::<Pixel>() as u32;
+                self.context.UpdateSubresource(&self.atlas_texture.texture, 0, Some(&area), upload.pixels.as_ptr().cast(), pitch, 0);
+            }
+            if !uploads.is_empty() {
+                self.end_event();
+            }
+
+            if self.instances.is_none() || self.instance_capacity < instances.len() {
+                let capacity = instances.len().next_power_of_two();
+                let desc = D3D11_BUFFER_DESC {
+                    ByteWidth: (capacity * size_of::<SpriteInstance>()) as u32,
+                    Usage: D3D11_USAGE_DYNAMIC,
+                    BindFlags: D3D11_BIND_VERTEX_BUFFER.0 as u32,
+                    CPUAccessFlags: D3D11_CPU_ACCESS_WRITE.0 as u32,
+                    ..Default::default()
+                };
+                self.instances = None;
+                self.device.CreateBuffer(&desc, None, Some(&mut self.instances))?;
+                self.instance_capacity = capacity;
+            }
+            let Some(buffer) = &self.instances else {
+                return Err(Error::from(E_FAIL));
+            };
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            self.context.Map(buffer, 0, D3D11_MAP_WRITE_DISCARD, 0, Some(&mut mapped))?;
+            std::ptr::copy_nonoverlapping(instances.as_ptr(), mapped.pData as *mut SpriteInstance, instances.len());
+            self.context.Unmap(buffer, 0);
+
+            let atlas_size = self.atlas.size() as f32;
+            let sizes = [width as f32, height as f32, atlas_size, atlas_size];
+            self.context.UpdateSubresource(&self.sizes, 0, None, sizes.as_ptr().cast(), 0, 0);
+
+            let viewport = D3D11_VIEWPORT { TopLeftX: 0.0, TopLeftY: 0.0, Width: width as f32, Height: height as f32, MinDepth: 0.0, MaxDepth: 1.0 };
+            let stride = size_of::<SpriteInstance>() as u32;
+            self.context.RSSetViewports(Some(&[viewport]));
+            self.context.RSSetState(&self.scissored);
+            self.context.OMSetRenderTargets(Some(&[Some(frame_target.clone())]), None::<&ID3D11DepthStencilView>);
+            self.context.OMSetBlendState(&self.premultiplied_blend, None, u32::MAX);
+            self.context.IASetInputLayout(&self.sprite_shaders.layout);
+            self.context.IASetVertexBuffers(0, 1, Some(&self.instances), Some(&stride), Some(&0));
+            self.context.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP);
+            self.context.VSSetShader(&self.sprite_shaders.vertex, None);
+            self.context.VSSetConstantBuffers(0, Some(&[Some(self.sizes.clone())]));
+            self.context.PSSetShader(&self.sprite_shaders.pixel, None);
+            self.context.PSSetShaderResources(0, Some(&[Some(self.atlas_texture.view.clone())]));
+
+            let mut start = 0;
+            for batch in self.sprites.chunk_by(|a, b| a.filter == b.filter && a.clip == b.clip) {
+                let sampler = &self.samplers[(batch[0].filter == BitmapFilter::Bilinear) as usize..][..1];
+                let scissor = match batch[0].clip {
+                    Some(clip) => RECT { left: clip.x, top: clip.y, right: clip.x + clip.width, bottom: clip.y + clip.height },
+                    None => RECT { left: 0, top: 0, right: width, bottom: height },
+                };
+                self.begin_event(&format!("Sprites x{}, {:?}{}", batch.len(), batch[0].filter, if batch[0].clip.is_some() { ", clipped" } else { "" }));
+                self.context.PSSetSamplers(0, Some(sampler));
+                self.context.RSSetScissorRects(Some(&[scissor]));
+                self.context.DrawInstanced(4, batch.len() as u32, 0, start);
+                self.end_event();
+                start += batch.len() as u32;
+            }
+
+            self.context.RSSetState(None::<&ID3D11RasterizerState>);
+            self.context.IASetInputLayout(None::<&ID3D11InputLayout>);
+        }
+        Ok(())
+    }
+
+    /// The software path's pixels from after the sprite run, over everything else in the frame texture
+    fn draw_overlay(&mut self, frame_target: &ID3D11RenderTargetView) -> Result<()> {
+        let (width, height) = self.frame_size;
+        if self.overlay.is_none() {
+            self.overlay = Some(create_texture(&self.device, width, height, false)?);
+        }
+        let Some(overlay) = &self.overlay else {
+            return Err(Error::from(E_FAIL));
+        };
+        unsafe {
+            let pitch = width as u32 * size_of::<Pixel>() as u32;
+            self.context.UpdateSubresource(&overlay.texture, 0, None, self.overlay_pixels.as_ptr().cast(), pitch, 0);
+            let viewport = D3D11_VIEWPORT { TopLeftX: 0.0, TopLeftY: 0.0, Width: width as f32, Height: height as f32, MinDepth: 0.0, MaxDepth: 1.0 };
+            self.context.RSSetViewports(Some(&[viewport]));
+            self.context.OMSetRenderTargets(Some(&[Some(frame_target.clone())]), None::<&ID3D11DepthStencilView>);
+            self.context.OMSetBlendState(&self.premultiplied_blend, None, u32::MAX);
+            self.context.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            self.context.VSSetShader(&self.present_shaders.vertex, None);
+            self.context.PSSetShader(&self.present_shaders.overlay, None);
+            self.context.PSSetShaderResources(0, Some(&[Some(overlay.view.clone())]));
+            self.context.PSSetSamplers(0, Some(&[self.samplers[0].clone()]));
+            self.context.Draw(3, 0);
+        }
+        Ok(())
+    }
+
+    /// The post passes from execute over the frame texture, in order. What the last one drew is what gets presented
+    fn run_post_passes(&mut self, frame_view: ID3D11ShaderResourceView) -> Result<ID3D11ShaderResourceView> {
+        let (width, height) = self.frame_size;
+        if self.post_targets.is_empty() {
+            // a bloom needs its source and two more to blur in, and the source can be what the pass before drew
+            for _ in 0..3 {
+                self.post_targets.push(create_texture(&self.device, width, height, true)?);
+            }
+        }
+        unsafe {
+            let viewport = D3D11_VIEWPORT { TopLeftX: 0.0, TopLeftY: 0.0, Width: width as f32, Height: height as f32, MinDepth: 0.0, MaxDepth: 1.0 };
+            self.context.RSSetViewports(Some(&[viewport]));
+            self.context.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            self.context.VSSetShader(&self.present_shaders.vertex, None);
+            self.context.PSSetConstantBuffers(0, Some(&[Some(self.post_constants.clone())]));
+        }
+        // which post target holds the picture so far, none while it's still the frame texture
+        let mut current: Option<usize> = None;
+        for pass in std::mem::take(&mut self.post_passes) {
+            let source = match current {
+                Some(index) => self.post_targets[index].view.clone(),
+                None => frame_view.clone(),
+            };
+            let mut free = (0..self.post_targets.len()).filter(|&index| Some(index) != current);
+            let (Some(first), Some(second)) = (free.next(), free.next()) else {
+                return Err(Error::from(E_FAIL));
+            };
+            let name = match pass {
+                PostPass::Blur { .. } => "Post Blur",
+                PostPass::Bloom { .. } => "Post Bloom",
+                PostPass::Crt => "Post Crt",
+            };
+            self.begin_event(name);
+            let across = PostConstants { direction: [1, 0], ..PostConstants::default() };
+            let down = PostConstants { direction: [0, 1], ..PostConstants::default() };
+            current = Some(match pass {
+                PostPass::Blur { radius } => {
+                    self.post_draw(&self.post_shaders.blur, PostConstants { radius, ..across }, &[&source], first);
+                    self.post_draw(&self.post_shaders.blur, PostConstants { radius, ..down }, &[&self.post_targets[first].view], second);
+                    second
+                }
+                PostPass::Bloom { threshold, radius, intensity } => {
+                    let [first_view, second_view] = [first, second].map(|index| &self.post_targets[index].view);
+                    self.post_draw(&self.post_shaders.threshold, PostConstants { threshold, ..across }, &[&source], first);
+                    self.post_draw(&self.post_shaders.blur, PostConstants { radius, ..across }, &[first_view], second);
+                    self.post_draw(&self.post_shaders.blur, PostConstants { radius, ..down }, &[second_view], first);
+                    // the first is the blurred bright parts now, added over the source into the second
+                    self.post_draw(&self.post_shaders.composite, PostConstants { intensity, ..across }, &[&source, first_view], second);
+                    second
+                }
+                PostPass::Crt => {
+                    let constants = PostConstants {
+                        curvature: CURVATURE,
+                        chroma_offset: CHROMA_OFFSET,
+                        scanline_brightness: SCANLINE_BRIGHTNESS as f32 / 256.0,
+                        ..across
+                    };
+                    self.post_draw(&self.post_shaders.crt, constants, &[&source], first);
+                    first
+                }
+            });
+            self.end_event();
+        }
+        unsafe { self.context.PSSetShaderResources(0, Some(&[None::<ID3D11ShaderResourceView>, None])) };
+        Ok(current.map_or(frame_view, |index| self.post_targets[index].view.clone()))
+    }
+
+    /// One fullscreen draw of a post shader reading sources from t0 on, into post target index
+    fn post_draw(&self, shader: &ID3D11PixelShader, constants: PostConstants, sources: &[&ID3D11ShaderResourceView], index: usize) {
+        let sources: Vec<_> = sources.iter().map(|&source| Some(source.clone())).collect();
+        unsafe {
+            // a texture can't be read and drawn into at once, and the one drawn into now was likely read from before
+            self.context.PSSetShaderResources(0, Some(&[None::<ID3D11ShaderResourceView>, None]));
+            self.context.OMSetRenderTargets(Some(&[self.post_targets[index].target.clone()]), None::<&ID3D11DepthStencilView>);
+            self.context.UpdateSubresource(&self.post_constants, 0, None, (&constants as *const PostConstants).cast(), 0, 0);
+            self.context.PSSetShader(shader, None);
+            self.context.PSSetShaderResources(0, Some(&sources));
+            self.context.Draw(3, 0);
+        }
+    }
+
+    fn draw_frame(&mut self, buffer: &Win32OffscreenBuffer, window_width: i32, window_height: i32) -> Result<()> {
+        unsafe {
+            if self.target.is_none() || self.target_size != (window_width, window_height) {
+                // nothing can be holding on to the old buffers while they're resized
+                self.target = None;
+                self.context.OMSetRenderTargets(None, None::<&ID3D11DepthStencilView>);
+                let format = swap_chain_format(self.output_mode);
+                self.swap_chain.ResizeBuffers(0, window_width as u32, window_height as u32, format, swap_chain_flags(self.tearing))?;
+                self.swap_chain.cast::<IDXGISwapChain3>()?.SetColorSpace1(color_space(self.output_mode))?;
+                let back_buffer: ID3D11Texture2D = self.swap_chain.GetBuffer(0)?;
+                self.device.CreateRenderTargetView(&back_buffer, None, Some(&mut self.target))?;
+                self.target_size = (window_width, window_height);
+            }
+            let target = self.target.clone();
+            self.context.ClearRenderTargetView(target.as_ref(), &[0.0, 0.0, 0.0, 1.0]);
+
+            let pixels = buffer.pixels();
+            self.composited = false;
+            self.annotating = self.annotation.as_ref().is_some_and(|annotation| annotation.GetStatus().as_bool());
+            if !pixels.is_empty() {
+                if self.frame.is_none() || self.frame_size != (buffer.width, buffer.height) {
+                    self.frame = None;
+                    self.overlay = None;
+                    self.post_targets.clear();
+                    self.frame = Some(create_texture(&self.device, buffer.width, buffer.height, true)?);
+                    self.frame_size = (buffer.width, buffer.height);
+                }
+                let Some(Texture { texture, view, target: Some(frame_target) }) = &self.frame else {
+                    return Err(Error::from(E_FAIL));
+                };
+                let (texture, view, frame_target) = (texture.clone(), view.clone(), frame_target.clone());
+                // frames are always drawn at 32 bits, a 16 bit backbuffer's staging pixels come up the same way
+                let pitch = buffer.width as u32 * size_of::<Pixel>() as u32;
+                self.begin_event(&software_event("Software frame", &self.software_before));
+                self.context.UpdateSubresource(&texture, 0, None, pixels.as_ptr().cast(), pitch, 0);
+                self.end_event();
+
+                // a frame that came out a different size from the one execute split up gets no sprites
+                let split = self.overlay_pixels.len() == pixels.len();
+                if split && !self.sprites.is_empty() {
+                    self.begin_event("Sprite run");
+                    let drawn = self.draw_sprites(&frame_target);
+                    self.end_event();
+                    drawn?;
+                    self.composited = true;
+                }
+                if split && self.overlay_drawn {
+                    self.begin_event(&software_event("Software overlay", &self.software_after));
+                    let drawn = self.draw_overlay(&frame_target);
+                    self.end_event();
+                    drawn?;
+                    self.composited = true;
+                }
+                self.context.OMSetBlendState(None::<&ID3D11BlendState>, None, u32::MAX);
+                let view = if !self.post_passes.is_empty() { self.run_post_passes(view)? } else { view };
+
+                let rect = win32_presentation_rect(buffer.width, buffer.height, window_width, window_height);
+                let viewport = D3D11_VIEWPORT {
+                    TopLeftX: rect.left as f32,
+                    TopLeftY: rect.top as f32,
+                    Width: (rect.right - rect.left) as f32,
+                    Height: (rect.bottom - rect.top) as f32,
+                    MinDepth: 0.0,
+                    MaxDepth: 1.0,
+                };
+                let smooth = GLOBAL_SMOOTH_PRESENT.load(Ordering::Relaxed);
+                let constants = OutputConstants { mode: self.output_mode as u32, paper_white: PAPER_WHITE_NITS, max_nits: self.max_nits, _padding: 0.0 };
+                self.context.UpdateSubresource(&self.output_constants, 0, None, (&constants as *const OutputConstants).cast(), 0, 0);
+                self.context.RSSetViewports(Some(&[viewport]));
+                self.context.OMSetRenderTargets(Some(&[target]), None::<&ID3D11DepthStencilView>);
+                self.context.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+                self.context.VSSetShader(&self.present_shaders.vertex, None);
+                self.context.PSSetShader(&self.present_shaders.pixel, None);
+                self.context.PSSetConstantBuffers(0, Some(&[Some(self.output_constants.clone())]));
+                self.context.PSSetShaderResources(0, Some(&[Some(view)]));
+                self.context.PSSetSamplers(0, Some(&[self.samplers[smooth as usize].clone()]));
+                self.begin_event(&format!("Present {:?}", self.output_mode));
+                self.context.Draw(3, 0);
+                self.end_event();
+            }
+            let wait = match self.vsync {
+                VSync::Off => false,
+                VSync::On => true,
+                // a frame that took longer than a refresh has already missed the one it was going for
+                VSync::Adaptive => self.last_present.elapsed() <= self.refresh_period,
+            };
+            let flags = if !wait && self.tearing { DXGI_PRESENT_ALLOW_TEARING } else { DXGI_PRESENT(0) };
+            let result = self.swap_chain.Present(wait as u32, flags).ok();
+            self.last_present = Instant::now();
+            result
+        }
+    }
+
+    /// Opens a named group of calls in a GPU capture, up to the end_event that closes it
+    fn begin_event(&self, name: &str) {
+        if let Some(annotation) = self.annotation.as_ref().filter(|_| self.annotating) {
+            unsafe { annotation.BeginEvent(&HSTRING::from(name)) };
+        }
+    }
+
+    fn end_event(&self) {
+        if let Some(annotation) = self.annotation.as_ref().filter(|_| self.annotating) {
+            unsafe { annotation.EndEvent() };
+        }
+    }
+
+    /// The display's peak brightness if the swap chain can send it mode where the window is now
+    fn check_output_mode(&self, mode: OutputMode) -> Result<f32> {
+        unsafe {
+            let swap_chain: IDXGISwapChain3 = self.swap_chain.cast()?;
+            if swap_chain.CheckColorSpaceSupport(color_space(mode))? & DXGI_SWAP_CHAIN_COLOR_SPACE_SUPPORT_FLAG_PRESENT.0 as u32 == 0 {
+                return Err(Error::new(DXGI_ERROR_UNSUPPORTED, "the swap chain can't present in that color space"));
+            }
+            if mode == OutputMode::Sdr {
+                return Ok(self.max_nits);
+            }
+            // windows only sends HDR to displays that have it switched on in the display settings
+            let output: IDXGIOutput6 = swap_chain.GetContainingOutput()?.cast()?;
+            let desc = output.GetDesc1()?;
+            if desc.ColorSpace != DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020 {
+                return Err(Error::new(DXGI_ERROR_UNSUPPORTED, "HDR is off for this display"));
+            }
+            Ok(if desc.MaxLuminance > 0.0 { desc.MaxLuminance } else { DEFAULT_MAX_NITS })
+        }
+    }
+
+    /// Copies the frame texture down through a staging texture, only done when the game pauses on a frame
+    fn copy_frame_down(&self, frame: &mut FrameBuffer) -> Result<()> {
+        let Some(Texture { texture, .. }) = &self.frame else {
+            return Err(Error::from(E_FAIL));
+        };
+        let (width, height) = self.frame_size;
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: width as u32,
+            Height: height as u32,
+            MipLevels: 1,
+            ArraySize: 1,
+            Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+            SampleDesc: DXGI_SAMPLE_DESC { Count: 1, Quality: 0 },
+            Usage: D3D11_USAGE_STAGING,
+            BindFlags: 0,
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+            MiscFlags: 0,
+        };
+        unsafe {
+            let mut staging = None;
+            self.device.CreateTexture2D(&desc, None, Some(&mut staging))?;
+            let staging = staging.ok_or_else(|| Error::from(E_FAIL))?;
+            self.context.CopyResource(&staging, texture);
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            self.context.Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+            for (y, row) in frame.rows_mut().enumerate() {
+                let source = (mapped.pData as *const u8).add(y * mapped.RowPitch as usize) as *const Pixel;
+                std::ptr::copy_nonoverlapping(source, row.as_mut_ptr(), row.len());
+            }
+            self.context.Unmap(&staging, 0);
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for Win32D3D11 {
+    fn name(&self) -> &'static str {
+        "Direct3D 11"
+    }
+
+    /// Rasterizes everything but the longest run of plain bitmaps, which present draws as sprites
+    fn execute(&mut self, render_group: &mut RenderGroup, frame: &mut FrameBuffer, queue: &WorkQueue, software_only: bool) {
+        self.sprites.clear();
+        self.overlay_drawn = false;
+        self.software_before.clear();
+        self.software_after.clear();
+        self.post_passes = render_group.post_passes.clone();
+        if software_only {
+            render_group.render_tiled(frame, queue, &mut TileHashes::default());
+            return;
+        }
+        self.atlas.begin_frame();
+        let (width, height) = (frame.width(), frame.height());
+        self.overlay_pixels.resize((width * height) as usize, 0);
+        let mut overlay = FrameBuffer::new(&mut self.overlay_pixels, width, height);
+        let atlas = &mut self.atlas;
+        let run = render_group.render_split(frame, &mut overlay, queue, |bitmap| atlas.place(bitmap).is_some());
+        self.sprites = run.quads;
+        self.overlay_drawn = run.overlay;
+        self.software_before = run.before;
+        self.software_after = run.after;
+    }
+
+    fn runs_post_passes(&self) -> bool {
+        true
+    }
+
+    fn set_vsync(&mut self, vsync: VSync) -> bool {
+        self.vsync = vsync;
+        true
+    }
+
+    /// Takes effect on the next present, which remakes the swap chain's buffers in the mode's format
+    fn set_output_mode(&mut self, mode: OutputMode) -> bool {
+        match self.check_output_mode(mode) {
+            Ok(max_nits) => {
+                self.output_mode = mode;
+                self.max_nits = max_nits;
+                self.target = None;
+                true
+            }
+            Err(error) => {
+                println!("Can't output {mode:?}: {}", error.message());
+                false
+            }
+        }
+    }
+
+    fn read_back(&mut self, frame: &mut FrameBuffer) -> bool {
+        if !self.composited || self.frame_size != (frame.width(), frame.height()) {
+            return false;
+        }
+        match self.copy_frame_down(frame) {
+            Ok(()) => true,
+            Err(error) => {
+                println!("D3D11 read back failed: {error}");
+                false
+            }
+        }
+    }
+
+    /// Copies the buffer up, draws the sprites and overlay over it and draws that where the GDI path would put it,
+    /// then presents, on the next vertical blank unless vsync says otherwise
+    fn present(&mut self, buffer: &Win32OffscreenBuffer, window_width: i32, window_height: i32, _everything_changed: bool) {
+        // a minimized window has nothing to resize the swap chain to
+        if window_width <= 0 || window_height <= 0 {
+            return;
+        }
+        self.reload_shaders();
+        if let Err(error) = self.draw_frame(buffer, window_width, window_height) {
+            println!("D3D11 present failed: {error}");
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! DirectSound output: one looping secondary buffer the card plays round and round, which the game keeps filled ahead
+//! of the play cursor. The primary buffer is only created to set the format the card mixes at.
+
+use std::{ffi::c_void, ptr::null_mut};
+
+use windows::{
+    core::*,
+    Win32::{
+        Foundation::*,
+        Media::Audio::{DirectSound::*, *},
+    },
+};
+
+use crate::audio_output::{AudioOutput, FrameTiming};
+
+// 16 bit stereo, left then right
+const CHANNELS: u32 = 2;
+const BYTES_PER_SAMPLE: u32 = CHANNELS * size_of::<i16>() as u32;
+
+pub struct Win32DirectSound {
+    // the buffers stop playing once it's released
+    _direct_sound: IDirectSound,
+    buffer: IDirectSoundBuffer,
+    pub samples_per_second: u32,
+    /// In bytes, a whole number of stereo samples
+    pub buffer_size: u32,
+    // stereo samples written since the start, where the next write goes is this round the buffer
+    running_sample_index: u64,
+}
+
+/// The part of the buffer a lock handed out, in two pieces when it wrapped round the end
+pub struct SoundLock {
+    region1: *mut c_void,
+    region1_size: u32,
+    region2: *mut c_void,
+    region2_size: u32,
+}
+
+/// Creates the DirectSound object on the window and a secondary buffer buffer_size bytes long at samples_per_second,
+/// 16 bit stereo, cleared to silence and not playing yet
+pub fn win32_init_dsound(window: HWND, samples_per_second: u32, buffer_size: u32) -> Result<Win32DirectSound> {
+    let buffer_size = buffer_size / BYTES_PER_SAMPLE * BYTES_PER_SAMPLE;
+    let format = WAVEFORMATEX {
+        wFormatTag: WAVE_FORMAT_PCM as u16,
+        nChannels: CHANNELS as u16,
+        nSamplesPerSec: samples_per_second,
+        nAvgBytesPerSec: samples_per_second * BYTES_PER_SAMPLE,
+        nBlockAlign: BYTES_PER_SAMPLE as u16,
+        wBitsPerSample: 16,
+        cbSize: 0,
+    };
+    unsafe {
+        let mut direct_sound = None;
+        DirectSoundCreate(None, &mut direct_sound, None)?;
+        let direct_sound = direct_sound.ok_or_else(|| Error::from(E_FAIL))?;
+        // priority is what lets the primary buffer's format be set
+        direct_sound.SetCooperativeLevel(window, DSSCL_PRIORITY)?;
+
+        let desc = DSBUFFERDESC { dwSize: size_of::<DSBUFFERDESC>() as u32, dwFlags: DSBCAPS_PRIMARYBUFFER, ..Default::default() };
+        let mut primary = None;
+        direct_sound.CreateSoundBuffer(&desc, &mut primary, None)?;
+        // the card mixing at some other rate only costs a resample, not worth failing over
+        if let Some(Err(error)) = primary.map(|primary| primary.SetFormat(&format)) {
+            println!("Couldn't set the primary sound buffer format: {error}");
+        }
+
+        // GETCURRENTPOSITION2 for accurate play cursors, GLOBALFOCUS so it keeps playing behind other windows
+        let desc = DSBUFFERDESC {
+            dwSize: size_of::<DSBUFFERDESC>() as u32,
+            dwFlags: DSBCAPS_GETCURRENTPOSITION2 | DSBCAPS_GLOBALFOCUS,
+            dwBufferBytes: buffer_size,
+            lpwfxFormat: &format as *const WAVEFORMATEX as *mut WAVEFORMATEX,
+            ..Default::default()
+        };
+        let mut buffer = None;
+        direct_sound.CreateSoundBuffer(&desc, &mut buffer, None)?;
+        let buffer = buffer.ok_or_else(|| Error::from(E_FAIL))?;
+        let sound = Win32DirectSound { _direct_sound: direct_sound, buffer, samples_per_second, buffer_size, running_sample_index: 0 };
+        sound.clear()?;
+        Ok(sound)
+    }
+}
+
+impl Win32DirectSound {
+    /// Bytes from byte_to_lock on, which can wrap round the end of the buffer. Has to be unlocked before it plays
+    pub fn lock(&self, byte_to_lock: u32, bytes_to_write: u32) -> Result<SoundLock> {
+        let mut lock = SoundLock { region1: null_mut(), region1_size: 0, region2: null_mut(), region2_size: 0 };
+        unsafe {
+            self.buffer.Lock(
+                byte_to_lock % self.buffer_size,
+                bytes_to_write,
+                &mut lock.region1,
+                &mut lock.region1_size,
+                Some(&mut lock.region2),
+                Some(&mut lock.region2_size),
+                0,
+            )?;
+        }
+        Ok(lock)
+    }
+
+    pub fn unlock(&self, lock: SoundLock) -> Result<()> {
+        unsafe { self.buffer.Unlock(lock.region1, lock.region1_size, Some(lock.region2), lock.region2_size) }
+    }
+
+    /// Copies interleaved left, right samples in from byte_to_lock on, wrapping round the end
+    pub fn write_at(&self, byte_to_lock: u32, samples: &[i16]) -> Result<()> {
+        let bytes = size_of_val(samples) as u32 / BYTES_PER_SAMPLE * BYTES_PER_SAMPLE;
+        if bytes == 0 {
+            return Ok(());
+        }
+        let lock = self.lock(byte_to_lock, bytes)?;
+        let first = lock.region1_size as usize / size_of::<i16>();
+        unsafe {
+            std::ptr::copy_nonoverlapping(samples.as_ptr(), lock.region1 as *mut i16, first);
+            if !lock.region2.is_null() {
+                std::ptr::copy_nonoverlapping(samples[first..].as_ptr(), lock.region2 as *mut i16, lock.region2_size as usize / size_of::<i16>());
+            }
+        }
+        self.unlock(lock)
+    }
+
+    /// Silence all the way round
+    pub fn clear(&self) -> Result<()> {
+        self.write_at(0, &vec![0; self.buffer_size as usize / size_of::<i16>()])
+    }
+
+    /// Starts the buffer looping
+    pub fn play(&self) -> Result<()> {
+        unsafe { self.buffer.Play(0, 0, DSBPLAY_LOOPING) }
+    }
+
+    /// The play and write cursors in bytes. Everything from the play cursor to the write cursor is already committed
+    /// to the card, writes have to go after it
+    pub fn cursors(&self) -> Result<(u32, u32)> {
+        let (mut play_cursor, mut write_cursor) = (0, 0);
+        unsafe { self.buffer.GetCurrentPosition(Some(&mut play_cursor), Some(&mut write_cursor))? };
+        Ok((play_cursor, write_cursor))
+    }
+
+    fn byte_to_lock(&self) -> u32 {
+        ((self.running_sample_index * BYTES_PER_SAMPLE as u64) % self.buffer_size as u64) as u32
+    }
+}
+
+impl AudioOutput for Win32DirectSound {
+    fn name(&self) -> &'static str {
+        "DirectSound"
+    }
+
+    fn samples_per_second(&self) -> u32 {
+        self.samples_per_second
+    }
+
+    fn samples_to_write(&mut self, timing: &FrameTiming) -> usize {
+        let Ok((play_cursor, write_cursor)) = self.cursors() else {
+            return 0;
+        };
+        let buffer_size = self.buffer_size;
+        // bytes going forward from one place in the buffer to another, round the end if need be
+        let ahead = |from: u32, to: u32| (to + buffer_size - from) % buffer_size;
+        let to_bytes = |seconds: f64| (seconds * self.samples_per_second as f64) as u32 * BYTES_PER_SAMPLE;
+
+        // never more than half the buffer is queued, so further ahead than that means play went past the end of what
+        // was written. Then, and before the first write, carry on from the write cursor, a skip but back in step
+        let queued = ahead(play_cursor, self.byte_to_lock());
+        let card_latency = ahead(play_cursor, write_cursor);
+        if queued < card_latency || queued > buffer_size / 2 {
+            self.running_sample_index += (ahead(self.byte_to_lock(), write_cursor) / BYTES_PER_SAMPLE) as u64;
+        }
+        let queued = ahead(play_cursor, self.byte_to_lock());
+
+        // what's written now has to last until the audio thread's next write, plus some in case that wake's late. A card
+        // whose write cursor is that close behind play can have it all lined up from the play cursor, otherwise the
+        // card's own latency comes on top
+        let (frame_bytes, safety_bytes) = (to_bytes(timing.frame_seconds()), to_bytes(timing.safety_seconds()));
+        let low_latency = card_latency + safety_bytes < frame_bytes;
+        let target = if low_latency { frame_bytes + safety_bytes } else { card_latency + frame_bytes + safety_bytes };
+        (target.min(buffer_size / 2).saturating_sub(queued) / BYTES_PER_SAMPLE) as usize
+    }
+
+    fn write(&mut self, samples: &[i16]) {
+        match self.write_at(self.byte_to_lock(), samples) {
+            Ok(()) => self.running_sample_index += (samples.len() / CHANNELS as usize) as u64,
+            Err(error) => println!("DirectSound write failed: {error}"),
+        }
+    }
+}
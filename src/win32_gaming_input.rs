@@ -0,0 +1,218 @@
+use windows::{
+    core::*,
+    Gaming::Input::{GameControllerSwitchPosition, Gamepad, RawGameController},
+};
+
+use crate::{
+    game_input::{normalize_stick, normalize_trigger, process_polled_button, GamepadButtons, GamepadState, GamepadSticks, GamepadTriggers},
+    GamepadBackend, GamepadEvent, GamepadSettings,
+};
+
+const MICROSOFT_VENDOR_ID: u16 = 0x045E;
+const SONY_VENDOR_ID: u16 = 0x054C;
+
+/// Where the xinput style buttons live in a raw controller's button/axis arrays
+#[derive(Clone, Copy)]
+enum RawLayout {
+    // DualShock 4 / DualSense HID report order
+    Sony,
+    // best guess for everything else, most generic HID pads follow the xbox order
+    Generic,
+}
+
+impl RawLayout {
+    fn for_vendor(vendor_id: u16) -> Self {
+        match vendor_id {
+            SONY_VENDOR_ID => RawLayout::Sony,
+            _ => RawLayout::Generic,
+        }
+    }
+}
+
+struct RawSlot {
+    controller: RawGameController,
+    id: HSTRING,
+    layout: RawLayout,
+    buttons: Vec<bool>,
+    switches: Vec<GameControllerSwitchPosition>,
+    axes: Vec<f64>,
+}
+
+impl RawSlot {
+    fn new(controller: RawGameController, id: HSTRING) -> Result<Self> {
+        let layout = RawLayout::for_vendor(controller.HardwareVendorId()?);
+        Ok(RawSlot {
+            buttons: vec![false; controller.ButtonCount()?.max(0) as usize],
+            switches: vec![GameControllerSwitchPosition::Center; controller.SwitchCount()?.max(0) as usize],
+            axes: vec![0.5; controller.AxisCount()?.max(0) as usize],
+            controller,
+            id,
+            layout,
+        })
+    }
+
+    fn button(&self, index: usize) -> bool {
+        self.buttons.get(index).copied().unwrap_or(false)
+    }
+
+    // raw axes are 0.0..1.0 with 0.5 at rest
+    fn axis(&self, index: usize) -> f64 {
+        self.axes.get(index).copied().unwrap_or(0.5)
+    }
+
+    fn stick_axis(&self, index: usize) -> i16 {
+        ((self.axis(index) * 2.0 - 1.0) * i16::MAX as f64) as i16
+    }
+
+    fn trigger_axis(&self, index: usize) -> u8 {
+        // triggers rest at 0.0 on pads that report them as axes
+        (self.axis(index).clamp(0.0, 1.0) * u8::MAX as f64) as u8
+    }
+}
+
+/// Pads XInput can't see (DualShock, Switch Pro, generic HID), read through Windows.Gaming.Input's
+/// RawGameController and mapped onto the same GamepadState the xinput pads fill in
+pub struct GamingInputBackend {
+    slots: Vec<Option<RawSlot>>,
+}
+
+impl GamingInputBackend {
+    pub fn new(slot_count: usize) -> Self {
+        GamingInputBackend {
+            slots: (0..slot_count).map(|_| None).collect(),
+        }
+    }
+
+    fn refresh_controllers(&mut self, first_slot: usize, gamepads: &mut [GamepadState], events: &mut Vec<GamepadEvent>) -> Result<()> {
+        let controllers = RawGameController::RawGameControllers()?;
+        let mut present = Vec::new();
+        for index in 0..controllers.Size()? {
+            let controller = controllers.GetAt(index)?;
+            // xbox pads show up here too, leave them to xinput
+            if controller.HardwareVendorId()? == MICROSOFT_VENDOR_ID || Gamepad::FromGameController(&controller).is_ok() {
+                continue;
+            }
+            present.push((controller.NonRoamableId()?, controller));
+        }
+
+        for (slot_index, slot) in self.slots.iter_mut().enumerate() {
+            let gone = slot.as_ref().is_some_and(|slot| !present.iter().any(|(id, _)| *id == slot.id));
+            if gone {
+                *slot = None;
+                gamepads[slot_index] = GamepadState::default();
+                events.push(GamepadEvent::Disconnected(first_slot + slot_index));
+            }
+        }
+
+        for (id, controller) in present {
+            if self.slots.iter().flatten().any(|slot| slot.id == id) {
+                continue;
+            }
+            let Some(slot_index) = self.slots.iter().position(Option::is_none) else {
+                break;
+            };
+            self.slots[slot_index] = Some(RawSlot::new(controller, id)?);
+            events.push(GamepadEvent::Connected(first_slot + slot_index));
+        }
+        Ok(())
+    }
+}
+
+impl GamepadBackend for GamingInputBackend {
+    fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn poll(&mut self, settings: &GamepadSettings, recheck: bool, first_slot: usize, gamepads: &mut [GamepadState], events: &mut Vec<GamepadEvent>) {
+        // winrt isn't available before windows 10, in that case this backend just stays empty
+        if recheck && self.refresh_controllers(first_slot, gamepads, events).is_err() {
+            return;
+        }
+
+        for (slot, gamepad) in self.slots.iter_mut().zip(gamepads.iter_mut()) {
+            let Some(slot) = slot else {
+                continue;
+            };
+            if slot.controller.GetCurrentReading(&mut slot.buttons, &mut slot.switches, &mut slot.axes).is_err() {
+                continue;
+            }
+
+            // first switch is the dpad on every pad seen so far
+            let dpad = slot.switches.first().copied().unwrap_or(GameControllerSwitchPosition::Center);
+            let dpad_up = matches!(
+                dpad,
+                GameControllerSwitchPosition::Up | GameControllerSwitchPosition::UpLeft | GameControllerSwitchPosition::UpRight
+            );
+            let dpad_down = matches!(
+                dpad,
+                GameControllerSwitchPosition::Down | GameControllerSwitchPosition::DownLeft | GameControllerSwitchPosition::DownRight
+            );
+            let dpad_left = matches!(
+                dpad,
+                GameControllerSwitchPosition::Left | GameControllerSwitchPosition::UpLeft | GameControllerSwitchPosition::DownLeft
+            );
+            let dpad_right = matches!(
+                dpad,
+                GameControllerSwitchPosition::Right | GameControllerSwitchPosition::UpRight | GameControllerSwitchPosition::DownRight
+            );
+
+            // (a, b, x, y, lb, rb, back, start, ls, rs) button indices and the trigger values
+            let (face, (left_trigger, right_trigger), right_stick_axes) = match slot.layout {
+                RawLayout::Sony => ([1, 2, 0, 3, 4, 5, 8, 9, 10, 11], (slot.trigger_axis(3), slot.trigger_axis(4)), (2, 5)),
+                RawLayout::Generic => {
+                    let digital = |index| if slot.button(index) { u8::MAX } else { 0 };
+                    ([0, 1, 2, 3, 4, 5, 6, 7, 8, 9], (digital(10), digital(11)), (2, 3))
+                }
+            };
+
+            let old_buttons = gamepad.buttons;
+            gamepad.connected = true;
+            gamepad.buttons = GamepadButtons {
+                dpad_up: process_polled_button(old_buttons.dpad_up, dpad_up),
+                dpad_down: process_polled_button(old_buttons.dpad_down, dpad_down),
+                dpad_left: process_polled_button(old_buttons.dpad_left, dpad_left),
+                dpad_right: process_polled_button(old_buttons.dpad_right, dpad_right),
+                a: process_polled_button(old_buttons.a, slot.button(face[0])),
+                b: process_polled_button(old_buttons.b, slot.button(face[1])),
+                x: process_polled_button(old_buttons.x, slot.button(face[2])),
+                y: process_polled_button(old_buttons.y, slot.button(face[3])),
+                left_shoulder: process_polled_button(old_buttons.left_shoulder, slot.button(face[4])),
+                right_shoulder: process_polled_button(old_buttons.right_shoulder, slot.button(face[5])),
+                back: process_polled_button(old_buttons.back, slot.button(face[6])),
+                start: process_polled_button(old_buttons.start, slot.button(face[7])),
+                left_thumb: process_polled_button(old_buttons.left_thumb, slot.button(face[8])),
+                right_thumb: process_polled_button(old_buttons.right_thumb, slot.button(face[9])),
+                left_trigger: process_polled_button(old_buttons.left_trigger, left_trigger > settings.trigger_threshold),
+                right_trigger: process_polled_button(old_buttons.right_trigger, right_trigger > settings.trigger_threshold),
+            };
+            gamepad.triggers = GamepadTriggers {
+                left: normalize_trigger(left_trigger, settings.trigger_threshold),
+                right: normalize_trigger(right_trigger, settings.trigger_threshold),
+            };
+            // raw y axes grow downwards, xinput's grow upwards
+            let (left_x, left_y) = normalize_stick(
+                slot.stick_axis(0),
+                slot.stick_axis(1).saturating_neg(),
+                settings.left_stick_deadzone,
+                settings.left_stick_curve,
+            );
+            let (right_x, right_y) = normalize_stick(
+                slot.stick_axis(right_stick_axes.0),
+                slot.stick_axis(right_stick_axes.1).saturating_neg(),
+                settings.right_stick_deadzone,
+                settings.right_stick_curve,
+            );
+            gamepad.sticks = GamepadSticks {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+            };
+        }
+    }
+
+    fn set_vibration(&mut self, _slot: usize, _low_frequency: f32, _high_frequency: f32) -> bool {
+        // raw controllers only rumble through ForceFeedbackMotors, not wired up yet
+        false
+    }
+}
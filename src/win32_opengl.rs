@@ -0,0 +1,197 @@
+//! OpenGL presentation: the software rendered frame goes up as a texture every frame and is drawn as one quad. The
+//! card does the scaling, which costs next to nothing at any window size where StretchDIBits gets slow, and
+//! SwapBuffers waits for the vertical blank on most drivers. Nothing past GL 1.1 is used, that's what opengl32.dll
+//! exports without loading extensions, apart from WGL_EXT_swap_control for vsync and KHR_debug groups to label the
+//! frame in captures, when the driver has them.
+
+use std::{ffi::CStr, sync::atomic::Ordering};
+
+use windows::{
+    core::*,
+    Win32::{Foundation::*, Graphics::{Gdi::*, OpenGL::*}},
+};
+
+use crate::{
+    renderer::{Renderer, VSync},
+    win32_presentation_rect, Win32OffscreenBuffer, GLOBAL_SMOOTH_PRESENT,
+};
+
+pub struct Win32OpenGl {
+    window: HWND,
+    dc: HDC,
+    context: HGLRC,
+    texture: u32,
+    // what the texture was last allocated at, a frame of another size reallocates it
+    texture_size: (i32, i32),
+    // wglSwapIntervalEXT, and whether it takes -1 for adaptive (WGL_EXT_swap_control_tear)
+    swap_interval: Option<SwapIntervalExt>,
+    adaptive: bool,
+    // glPushDebugGroup and glPopDebugGroup
+    debug_groups: Option<(PushDebugGroup, PopDebugGroup)>,
+}
+
+type SwapIntervalExt = unsafe extern "system" fn(interval: i32) -> BOOL;
+type GetExtensionsStringExt = unsafe extern "system" fn() -> *const i8;
+type PushDebugGroup = unsafe extern "system" fn(source: u32, id: u32, length: i32, message: *const u8);
+type PopDebugGroup = unsafe extern "system" fn();
+
+// GL_DEBUG_SOURCE_APPLICATION, the groups come from the program rather than a library
+const DEBUG_SOURCE_APPLICATION: u32 = 0x824A;
+
+impl Win32OpenGl {
+    /// Sets up a context on the window and makes it current on this thread, which presents from then on
+    pub fn new(window: HWND) -> Result<Self> {
+        unsafe {
+            let dc = GetDC(Some(window));
+            let desired = PIXELFORMATDESCRIPTOR {
+                nSize: size_of::<PIXELFORMATDESCRIPTOR>() as u16,
+                nVersion: 1,
+                dwFlags: PFD_DRAW_TO_WINDOW | PFD_SUPPORT_OPENGL | PFD_DOUBLEBUFFER,
+                iPixelType: PFD_TYPE_RGBA,
+                cColorBits: 32,
+                cAlphaBits: 8,
+                ..Default::default()
+            };
+            // the closest one the driver has, anything rgb and double buffered does for one textured quad
+            let format = ChoosePixelFormat(dc, &desired);
+            if format == 0 {
+                ReleaseDC(Some(window), dc);
+                return Err(Error::from_win32());
+            }
+            let mut suggested = PIXELFORMATDESCRIPTOR::default();
+            DescribePixelFormat(dc, format, size_of::<PIXELFORMATDESCRIPTOR>() as u32, Some(&mut suggested));
+            let context = SetPixelFormat(dc, format, &suggested).and_then(|_| wglCreateContext(dc));
+            let context = match context {
+                Ok(context) => context,
+                Err(error) => {
+                    ReleaseDC(Some(window), dc);
+                    return Err(error);
+                }
+            };
+            if let Err(error) = wglMakeCurrent(dc, context) {
+                let _ = wglDeleteContext(context);
+                ReleaseDC(Some(window), dc);
+                return Err(error);
+            }
+            let mut texture = 0;
+            glGenTextures(1, &mut texture);
+
+            // extension functions only exist once there's a current context
+            let swap_interval = wglGetProcAddress(s!("wglSwapIntervalEXT")).map(|function| std::mem::transmute::<unsafe extern "system" fn() -> isize, SwapIntervalExt>(function));
+            let adaptive = wglGetProcAddress(s!("wglGetExtensionsStringEXT")).is_some_and(|function| {
+                let extensions = std::mem::transmute::<unsafe extern "system" fn() -> isize, GetExtensionsStringExt>(function)();
+                !extensions.is_null() && CStr::from_ptr(extensions).to_string_lossy().split(' ').any(|name| name == "WGL_EXT_swap_control_tear")
+            });
+            // core in 4.3, an extension before that
+            let push = wglGetProcAddress(s!("glPushDebugGroup")).or_else(|| wglGetProcAddress(s!("glPushDebugGroupKHR")));
+            let pop = wglGetProcAddress(s!("glPopDebugGroup")).or_else(|| wglGetProcAddress(s!("glPopDebugGroupKHR")));
+            let debug_groups = push.zip(pop).map(|(push, pop)| {
+                (
+                    std::mem::transmute::<unsafe extern "system" fn() -> isize, PushDebugGroup>(push),
+                    std::mem::transmute::<unsafe extern "system" fn() -> isize, PopDebugGroup>(pop),
+                )
+            });
+            let mut opengl = Win32OpenGl { window, dc, context, texture, texture_size: (0, 0), swap_interval, adaptive, debug_groups };
+            opengl.set_vsync(VSync::default());
+            Ok(opengl)
+        }
+    }
+
+    /// Opens a named group of calls in a GPU capture, up to the pop_group that closes it
+    fn push_group(&self, name: &str) {
+        if let Some((push, _)) = self.debug_groups {
+            unsafe { push(DEBUG_SOURCE_APPLICATION, 0, name.len() as i32, name.as_ptr()) };
+        }
+    }
+
+    fn pop_group(&self) {
+        if let Some((_, pop)) = self.debug_groups {
+            unsafe { pop() };
+        }
+    }
+}
+
+impl Renderer for Win32OpenGl {
+    fn name(&self) -> &'static str {
+        "OpenGL"
+    }
+
+    /// Off and on are swap intervals 0 and 1, adaptive is -1 where the driver has it and on where it doesn't
+    fn set_vsync(&mut self, vsync: VSync) -> bool {
+        let Some(swap_interval) = self.swap_interval else {
+            return false;
+        };
+        let interval = match vsync {
+            VSync::Off => 0,
+            VSync::On => 1,
+            VSync::Adaptive if self.adaptive => -1,
+            VSync::Adaptive => {
+                println!("The driver doesn't do adaptive vsync, using on");
+                1
+            }
+        };
+        unsafe { swap_interval(interval).as_bool() }
+    }
+
+    /// Uploads the buffer and draws it where the GDI path would put it, bars and all, then swaps.
+    /// The whole frame goes up every time, so there's nothing to gain from knowing what changed
+    fn present(&mut self, buffer: &Win32OffscreenBuffer, window_width: i32, window_height: i32, _everything_changed: bool) {
+        let rect = win32_presentation_rect(buffer.width, buffer.height, window_width, window_height);
+        let pixels = buffer.pixels();
+        unsafe {
+            glViewport(0, 0, window_width, window_height);
+            glClearColor(0.0, 0.0, 0.0, 1.0);
+            glClear(GL_COLOR_BUFFER_BIT);
+
+            if !pixels.is_empty() {
+                self.push_group("Software frame");
+                glBindTexture(GL_TEXTURE_2D, self.texture);
+                // frames are always drawn at 32 bits, a 16 bit backbuffer's staging pixels come up the same way
+                if self.texture_size != (buffer.width, buffer.height) {
+                    glTexImage2D(GL_TEXTURE_2D, 0, GL_RGBA8 as i32, buffer.width, buffer.height, 0, GL_BGRA_EXT, GL_UNSIGNED_BYTE, pixels.as_ptr().cast());
+                    self.texture_size = (buffer.width, buffer.height);
+                } else {
+                    glTexSubImage2D(GL_TEXTURE_2D, 0, 0, 0, buffer.width, buffer.height, GL_BGRA_EXT, GL_UNSIGNED_BYTE, pixels.as_ptr().cast());
+                }
+                let filter = if GLOBAL_SMOOTH_PRESENT.load(Ordering::Relaxed) { GL_LINEAR } else { GL_NEAREST };
+                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MIN_FILTER, filter as i32);
+                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MAG_FILTER, filter as i32);
+                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_S, GL_CLAMP as i32);
+                glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_T, GL_CLAMP as i32);
+                self.pop_group();
+
+                // gl counts y up from the bottom of the window, and the quad fills whatever the viewport is
+                glViewport(rect.left, window_height - rect.bottom, rect.right - rect.left, rect.bottom - rect.top);
+                self.push_group("Present");
+                glEnable(GL_TEXTURE_2D);
+                glBegin(GL_QUADS);
+                // the first row of the texture is the top of the frame
+                glTexCoord2f(0.0, 1.0);
+                glVertex2f(-1.0, -1.0);
+                glTexCoord2f(1.0, 1.0);
+                glVertex2f(1.0, -1.0);
+                glTexCoord2f(1.0, 0.0);
+                glVertex2f(1.0, 1.0);
+                glTexCoord2f(0.0, 0.0);
+                glVertex2f(-1.0, 1.0);
+                glEnd();
+                self.pop_group();
+            }
+
+            if let Err(error) = SwapBuffers(self.dc) {
+                println!("SwapBuffers failed: {error}");
+            }
+        }
+    }
+}
+
+impl Drop for Win32OpenGl {
+    fn drop(&mut self) {
+        unsafe {
+            glDeleteTextures(1, &self.texture);
+            let _ = wglMakeCurrent(HDC::default(), HGLRC::default());
+            let _ = wglDeleteContext(self.context);
+            ReleaseDC(Some(self.window), self.dc);
+        }
+    }
+}
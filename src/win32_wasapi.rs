@@ -0,0 +1,266 @@
+//! WASAPI shared mode output on the default render device. The device's mix rate is used so Windows doesn't have to
+//! resample, and the game's 16 bit stereo goes out as it is when the device takes it, or converted to the mix format
+//! (float, more channels) when it doesn't. When the default device changes (headphones plugged in, say) or the one
+//! in use goes away, the stream is opened again on whatever's the default now.
+
+use std::{
+    ptr::null_mut,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::*,
+    Win32::{
+        Foundation::*,
+        Media::Audio::*,
+        System::Com::*,
+    },
+};
+
+use crate::audio_output::{AudioOutput, FrameTiming};
+
+// from mmreg.h, the tags aren't worth pulling in the multimedia bindings for
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+// how big a buffer to ask for, in 100ns units, comfortably more than the latency it's kept filled to
+const BUFFER_DURATION: i64 = 2_000_000;
+// with no device to play on, how often to look again in case the notification of a new one never came
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Flags a change of default render device. Windows calls it on a thread of its own, so that's all it does
+#[implement(IMMNotificationClient)]
+struct DefaultDeviceWatcher {
+    changed: Arc<AtomicBool>,
+}
+
+impl IMMNotificationClient_Impl for DefaultDeviceWatcher_Impl {
+    fn OnDefaultDeviceChanged(&self, flow: EDataFlow, role: ERole, _device_id: &PCWSTR) -> Result<()> {
+        if flow == eRender && role == eConsole {
+            self.changed.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    fn OnDeviceStateChanged(&self, _device_id: &PCWSTR, _new_state: DEVICE_STATE) -> Result<()> {
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, _device_id: &PCWSTR) -> Result<()> {
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, _device_id: &PCWSTR) -> Result<()> {
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(&self, _device_id: &PCWSTR, _key: &PROPERTYKEY) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct Win32Wasapi {
+    enumerator: IMMDeviceEnumerator,
+    watcher: IMMNotificationClient,
+    device_changed: Arc<AtomicBool>,
+    // None while there's no device to play on
+    stream: Option<WasapiStream>,
+    // the last stream's, kept for while there's none
+    samples_per_second: u32,
+    last_opened: Instant,
+}
+
+// the stream on one device, thrown away and opened again on the next when the device changes
+struct WasapiStream {
+    client: IAudioClient,
+    render_client: IAudioRenderClient,
+    samples_per_second: u32,
+    // what the device takes: how many channels, the first two get left and right, and float or 16 bit samples
+    channels: usize,
+    float: bool,
+    // in frames, a sample for every channel
+    buffer_frames: u32,
+    // how often the audio engine takes a period's worth from the buffer, which has to be there on top of what
+    // lasts until the next write
+    period_seconds: f64,
+}
+
+impl Win32Wasapi {
+    /// Opens the default render device in shared mode and starts it playing silence, then keeps following the default
+    pub fn new() -> Result<Self> {
+        unsafe {
+            // the thread may already be in an apartment from something else, any kind does
+            let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+            let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+            let stream = WasapiStream::open(&enumerator)?;
+            let device_changed = Arc::new(AtomicBool::new(false));
+            let watcher: IMMNotificationClient = DefaultDeviceWatcher { changed: device_changed.clone() }.into();
+            // without it a new default is only noticed once the old device goes away
+            if let Err(error) = enumerator.RegisterEndpointNotificationCallback(&watcher) {
+                println!("WASAPI won't follow the default device: {error}");
+            }
+            Ok(Win32Wasapi {
+                enumerator,
+                watcher,
+                device_changed,
+                samples_per_second: stream.samples_per_second,
+                stream: Some(stream),
+                last_opened: Instant::now(),
+            })
+        }
+    }
+
+    // lets go of the old device before opening the default, which can be the same one again
+    fn reopen(&mut self) {
+        self.stream = None;
+        self.last_opened = Instant::now();
+        match unsafe { WasapiStream::open(&self.enumerator) } {
+            Ok(stream) => {
+                println!("WASAPI now playing on the default device at {}Hz", stream.samples_per_second);
+                self.samples_per_second = stream.samples_per_second;
+                self.stream = Some(stream);
+            }
+            Err(error) => println!("WASAPI has no device to play on: {error}"),
+        }
+    }
+
+    // a failed call on the stream: the device going away is the one worth doing something about
+    fn failed(&mut self, call: &str, error: Error) {
+        if error.code() == AUDCLNT_E_DEVICE_INVALIDATED {
+            println!("WASAPI device went away, reopening on the default");
+            self.reopen();
+        } else {
+            println!("WASAPI {call} failed: {error}");
+        }
+    }
+}
+
+impl Drop for Win32Wasapi {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.enumerator.UnregisterEndpointNotificationCallback(&self.watcher);
+        }
+    }
+}
+
+impl WasapiStream {
+    unsafe fn open(enumerator: &IMMDeviceEnumerator) -> Result<Self> {
+        unsafe {
+            let device = enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?;
+            let client: IAudioClient = device.Activate(CLSCTX_ALL, None)?;
+
+            let mix = client.GetMixFormat()?;
+            let samples_per_second = (*mix).nSamplesPerSec;
+            let wanted = WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_PCM as u16,
+                nChannels: 2,
+                nSamplesPerSec: samples_per_second,
+                nAvgBytesPerSec: samples_per_second * 4,
+                nBlockAlign: 4,
+                wBitsPerSample: 16,
+                cbSize: 0,
+            };
+            // shared mode hands back a closest match instead of failing outright, it's always the mix format anyway
+            let mut closest = null_mut();
+            let supported = client.IsFormatSupported(AUDCLNT_SHAREMODE_SHARED, &wanted, Some(&mut closest)) == S_OK;
+            CoTaskMemFree(Some(closest as *const _));
+            let format: *const WAVEFORMATEX = if supported { &wanted } else { mix };
+            let tag = if (*format).wFormatTag == WAVE_FORMAT_EXTENSIBLE && (*format).cbSize >= 22 {
+                // the extensible sub format GUIDs start with the plain tag they stand for
+                (*(format as *const WAVEFORMATEXTENSIBLE)).SubFormat.data1 as u16
+            } else {
+                (*format).wFormatTag
+            };
+            let (channels, bits) = ((*format).nChannels as usize, (*format).wBitsPerSample);
+            let float = match (tag, bits) {
+                (WAVE_FORMAT_IEEE_FLOAT, 32) => true,
+                (tag, 16) if tag == WAVE_FORMAT_PCM as u16 => false,
+                _ => {
+                    CoTaskMemFree(Some(mix as *const _));
+                    return Err(Error::new(AUDCLNT_E_UNSUPPORTED_FORMAT, format!("the device mixes {bits} bit samples of format {tag}")));
+                }
+            };
+            let initialized = client.Initialize(AUDCLNT_SHAREMODE_SHARED, 0, BUFFER_DURATION, 0, format, None);
+            CoTaskMemFree(Some(mix as *const _));
+            initialized?;
+
+            let render_client: IAudioRenderClient = client.GetService()?;
+            let buffer_frames = client.GetBufferSize()?;
+            let mut period = 0;
+            client.GetDevicePeriod(Some(&mut period), None)?;
+            client.Start()?;
+            Ok(WasapiStream {
+                client,
+                render_client,
+                samples_per_second,
+                channels: channels.max(1),
+                float,
+                buffer_frames,
+                period_seconds: period as f64 / 10_000_000.0,
+            })
+        }
+    }
+
+    fn write(&self, samples: &[i16]) -> Result<()> {
+        let frames = (samples.len() / 2) as u32;
+        unsafe {
+            let data = self.render_client.GetBuffer(frames)?;
+            // channels past the first two stay silent
+            let values = samples.chunks_exact(2).flat_map(|frame| (0..self.channels).map(move |channel| frame.get(channel).copied().unwrap_or(0)));
+            if self.float {
+                let out = std::slice::from_raw_parts_mut(data as *mut f32, frames as usize * self.channels);
+                for (out, value) in out.iter_mut().zip(values) {
+                    *out = value as f32 / 32768.0;
+                }
+            } else {
+                let out = std::slice::from_raw_parts_mut(data as *mut i16, frames as usize * self.channels);
+                for (out, value) in out.iter_mut().zip(values) {
+                    *out = value;
+                }
+            }
+            self.render_client.ReleaseBuffer(frames, 0)
+        }
+    }
+}
+
+impl AudioOutput for Win32Wasapi {
+    fn name(&self) -> &'static str {
+        "WASAPI"
+    }
+
+    fn samples_per_second(&self) -> u32 {
+        self.samples_per_second
+    }
+
+    fn samples_to_write(&mut self, timing: &FrameTiming) -> usize {
+        if self.device_changed.swap(false, Ordering::Acquire) || (self.stream.is_none() && self.last_opened.elapsed() >= REOPEN_INTERVAL) {
+            self.reopen();
+        }
+        let Some(stream) = &self.stream else {
+            return 0;
+        };
+        // padding is what's queued and not played yet, there's no cursor to line up with
+        let padding = match unsafe { stream.client.GetCurrentPadding() } {
+            Ok(padding) => padding,
+            Err(error) => {
+                self.failed("GetCurrentPadding", error);
+                return 0;
+            }
+        };
+        let latency_seconds = stream.period_seconds + timing.frame_seconds() + timing.safety_seconds();
+        let latency_frames = ((latency_seconds * stream.samples_per_second as f64) as u32).min(stream.buffer_frames);
+        latency_frames.saturating_sub(padding) as usize
+    }
+
+    fn write(&mut self, samples: &[i16]) {
+        if samples.len() < 2 {
+            return;
+        }
+        if let Some(Err(error)) = self.stream.as_ref().map(|stream| stream.write(samples)) {
+            self.failed("write", error);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use windows::{
+    core::*,
+    Win32::{Foundation::*, System::LibraryLoader::*, UI::Input::XboxController::*},
+};
+
+use crate::{
+    game_input::{
+        normalize_stick, normalize_trigger, process_polled_button, BatteryLevel, BatteryType, ButtonState, GamepadBattery, GamepadButtons, GamepadState,
+        GamepadSticks, GamepadTriggers,
+    },
+    GamepadBackend, GamepadEvent, GamepadSettings,
+};
+
+type XInputGetStateFn = unsafe extern "system" fn(u32, *mut XINPUT_STATE) -> u32;
+type XInputSetStateFn = unsafe extern "system" fn(u32, *const XINPUT_VIBRATION) -> u32;
+type XInputGetBatteryInformationFn = unsafe extern "system" fn(u32, BATTERY_DEVTYPE, *mut XINPUT_BATTERY_INFORMATION) -> u32;
+
+/// XInput entry points resolved at runtime, stubs when no xinput dll could be loaded
+struct Win32XInput {
+    get_state: XInputGetStateFn,
+    set_state: XInputSetStateFn,
+    // only xinput1_4 has this one
+    get_battery_information: XInputGetBatteryInformationFn,
+}
+
+unsafe extern "system" fn xinput_get_state_stub(_user_index: u32, _state: *mut XINPUT_STATE) -> u32 {
+    ERROR_DEVICE_NOT_CONNECTED.0
+}
+
+unsafe extern "system" fn xinput_set_state_stub(_user_index: u32, _vibration: *const XINPUT_VIBRATION) -> u32 {
+    ERROR_DEVICE_NOT_CONNECTED.0
+}
+
+unsafe extern "system" fn xinput_get_battery_information_stub(_user_index: u32, _device_type: BATTERY_DEVTYPE, _information: *mut XINPUT_BATTERY_INFORMATION) -> u32 {
+    ERROR_DEVICE_NOT_CONNECTED.0
+}
+
+fn win32_load_xinput() -> Win32XInput {
+    // xinput1_4 ships with windows 8+, 1_3 comes with the directx runtime and 9_1_0 is on every vista/7 box
+    let dll_names = [w!("xinput1_4.dll"), w!("xinput1_3.dll"), w!("xinput9_1_0.dll")];
+
+    for dll_name in dll_names {
+        let Ok(library) = (unsafe { LoadLibraryW(dll_name) }) else {
+            continue;
+        };
+
+        let get_state = unsafe { GetProcAddress(library, s!("XInputGetState")) };
+        let set_state = unsafe { GetProcAddress(library, s!("XInputSetState")) };
+        let get_battery_information = unsafe { GetProcAddress(library, s!("XInputGetBatteryInformation")) };
+        if let Some(get_state) = get_state {
+            println!("Loaded {}", unsafe { dll_name.display() });
+            return Win32XInput {
+                get_state: unsafe { std::mem::transmute::<unsafe extern "system" fn() -> isize, XInputGetStateFn>(get_state) },
+                set_state: match set_state {
+                    Some(set_state) => unsafe { std::mem::transmute::<unsafe extern "system" fn() -> isize, XInputSetStateFn>(set_state) },
+                    None => xinput_set_state_stub,
+                },
+                get_battery_information: match get_battery_information {
+                    Some(get_battery_information) => unsafe {
+                        std::mem::transmute::<unsafe extern "system" fn() -> isize, XInputGetBatteryInformationFn>(get_battery_information)
+                    },
+                    None => xinput_get_battery_information_stub,
+                },
+            };
+        }
+    }
+
+    println!("No XInput dll found, gamepads are disabled");
+    Win32XInput {
+        get_state: xinput_get_state_stub,
+        set_state: xinput_set_state_stub,
+        get_battery_information: xinput_get_battery_information_stub,
+    }
+}
+
+fn process_xinput_button(old_state: ButtonState, buttons: XINPUT_GAMEPAD_BUTTON_FLAGS, button_bit: XINPUT_GAMEPAD_BUTTON_FLAGS) -> ButtonState {
+    process_polled_button(old_state, buttons.contains(button_bit))
+}
+
+fn win32_read_battery(xinput: &Win32XInput, controller_index: usize) -> GamepadBattery {
+    let mut information = XINPUT_BATTERY_INFORMATION::default();
+    let result = unsafe { (xinput.get_battery_information)(controller_index as u32, BATTERY_DEVTYPE_GAMEPAD, &mut information) };
+    if result != ERROR_SUCCESS.0 {
+        return GamepadBattery::default();
+    }
+
+    let battery_type = match information.BatteryType {
+        BATTERY_TYPE_WIRED => BatteryType::Wired,
+        BATTERY_TYPE_ALKALINE => BatteryType::Alkaline,
+        BATTERY_TYPE_NIMH => BatteryType::NickelMetalHydride,
+        _ => BatteryType::Unknown,
+    };
+    let level = match information.BatteryLevel {
+        BATTERY_LEVEL_EMPTY => BatteryLevel::Empty,
+        BATTERY_LEVEL_LOW => BatteryLevel::Low,
+        BATTERY_LEVEL_MEDIUM => BatteryLevel::Medium,
+        _ => BatteryLevel::Full,
+    };
+    GamepadBattery { battery_type, level }
+}
+
+/// Xbox pads (and anything else speaking XInput), always the first XUSER_MAX_COUNT slots
+pub struct XInputBackend {
+    xinput: Win32XInput,
+    // dwPacketNumber from the last poll of each slot, it only moves when the pad state changes
+    last_packets: [Option<u32>; XUSER_MAX_COUNT as usize],
+    // the raw pad state that came with that packet
+    last_pads: [XINPUT_GAMEPAD; XUSER_MAX_COUNT as usize],
+    // polls where the packet number jumped by more than LARGE_PACKET_JUMP
+    large_packet_jumps: [u32; XUSER_MAX_COUNT as usize],
+}
+
+// a pad sends a packet per state change, noisy sticks easily make a few per frame,
+// this many between two polls means the loop stalled long enough to lose real input
+const LARGE_PACKET_JUMP: u32 = 64;
+
+impl XInputBackend {
+    pub fn new() -> Self {
+        XInputBackend {
+            xinput: win32_load_xinput(),
+            last_packets: [None; XUSER_MAX_COUNT as usize],
+            last_pads: [XINPUT_GAMEPAD::default(); XUSER_MAX_COUNT as usize],
+            large_packet_jumps: [0; XUSER_MAX_COUNT as usize],
+        }
+    }
+}
+
+impl GamepadBackend for XInputBackend {
+    fn slot_count(&self) -> usize {
+        XUSER_MAX_COUNT as usize
+    }
+
+    fn poll(&mut self, settings: &GamepadSettings, recheck: bool, first_slot: usize, gamepads: &mut [GamepadState], events: &mut Vec<GamepadEvent>) {
+        for (controller_index, gamepad) in gamepads.iter_mut().enumerate() {
+            if !gamepad.connected && !recheck {
+                continue;
+            }
+
+            let mut controller_state = XINPUT_STATE::default();
+            let result = unsafe { (self.xinput.get_state)(controller_index as u32, &mut controller_state) };
+
+            if result != ERROR_SUCCESS.0 {
+                if gamepad.connected {
+                    events.push(GamepadEvent::Disconnected(first_slot + controller_index));
+                }
+                // slot is empty, clear it so stale input from an unplugged pad doesn't stick around
+                *gamepad = GamepadState::default();
+                self.last_packets[controller_index] = None;
+                continue;
+            }
+
+            if !gamepad.connected {
+                events.push(GamepadEvent::Connected(first_slot + controller_index));
+            }
+
+            // battery level changes slowly, refreshing it with the empty slot recheck is plenty
+            if recheck {
+                let battery = win32_read_battery(&self.xinput, controller_index);
+                if battery.is_wireless() && battery.level <= BatteryLevel::Low && battery.level < gamepad.battery.level {
+                    events.push(GamepadEvent::BatteryLow(first_slot + controller_index, battery.level));
+                }
+                gamepad.battery = battery;
+            }
+
+            let packet = controller_state.dwPacketNumber;
+            let mut same_packet = false;
+            if let Some(last_packet) = self.last_packets[controller_index] {
+                let jump = packet.wrapping_sub(last_packet);
+                same_packet = jump == 0 && gamepad.connected;
+                if jump > LARGE_PACKET_JUMP {
+                    self.large_packet_jumps[controller_index] += 1;
+                    println!(
+                        "Gamepad {} skipped {jump} packets since the last poll ({} times so far), input may have been dropped",
+                        first_slot + controller_index,
+                        self.large_packet_jumps[controller_index]
+                    );
+                }
+            }
+            self.last_packets[controller_index] = Some(packet);
+            if !same_packet {
+                self.last_pads[controller_index] = controller_state.Gamepad;
+            }
+
+            let pad = &self.last_pads[controller_index];
+            gamepad.connected = true;
+            let old_buttons = gamepad.buttons;
+            if same_packet {
+                // nothing changed on the pad so there are no transitions this frame. The dpad still goes back to the
+                // pad's own for the stick to be ored onto again, the settings may have changed what the stick does
+                for button in gamepad.buttons.all_mut() {
+                    button.half_transition_count = 0;
+                }
+                gamepad.buttons.dpad_up.ended_down = pad.wButtons.contains(XINPUT_GAMEPAD_DPAD_UP);
+                gamepad.buttons.dpad_down.ended_down = pad.wButtons.contains(XINPUT_GAMEPAD_DPAD_DOWN);
+                gamepad.buttons.dpad_left.ended_down = pad.wButtons.contains(XINPUT_GAMEPAD_DPAD_LEFT);
+                gamepad.buttons.dpad_right.ended_down = pad.wButtons.contains(XINPUT_GAMEPAD_DPAD_RIGHT);
+            } else {
+                gamepad.buttons = GamepadButtons {
+                    dpad_up: process_xinput_button(old_buttons.dpad_up, pad.wButtons, XINPUT_GAMEPAD_DPAD_UP),
+                    dpad_down: process_xinput_button(old_buttons.dpad_down, pad.wButtons, XINPUT_GAMEPAD_DPAD_DOWN),
+                    dpad_left: process_xinput_button(old_buttons.dpad_left, pad.wButtons, XINPUT_GAMEPAD_DPAD_LEFT),
+                    dpad_right: process_xinput_button(old_buttons.dpad_right, pad.wButtons, XINPUT_GAMEPAD_DPAD_RIGHT),
+                    start: process_xinput_button(old_buttons.start, pad.wButtons, XINPUT_GAMEPAD_START),
+                    back: process_xinput_button(old_buttons.back, pad.wButtons, XINPUT_GAMEPAD_BACK),
+                    left_thumb: process_xinput_button(old_buttons.left_thumb, pad.wButtons, XINPUT_GAMEPAD_LEFT_THUMB),
+                    right_thumb: process_xinput_button(old_buttons.right_thumb, pad.wButtons, XINPUT_GAMEPAD_RIGHT_THUMB),
+                    left_shoulder: process_xinput_button(old_buttons.left_shoulder, pad.wButtons, XINPUT_GAMEPAD_LEFT_SHOULDER),
+                    right_shoulder: process_xinput_button(old_buttons.right_shoulder, pad.wButtons, XINPUT_GAMEPAD_RIGHT_SHOULDER),
+                    a: process_xinput_button(old_buttons.a, pad.wButtons, XINPUT_GAMEPAD_A),
+                    b: process_xinput_button(old_buttons.b, pad.wButtons, XINPUT_GAMEPAD_B),
+                    x: process_xinput_button(old_buttons.x, pad.wButtons, XINPUT_GAMEPAD_X),
+                    y: process_xinput_button(old_buttons.y, pad.wButtons, XINPUT_GAMEPAD_Y),
+                    left_trigger: old_buttons.left_trigger,
+                    right_trigger: old_buttons.right_trigger,
+                };
+            }
+            // the threshold is a setting, so the triggers are buttons again from the raw values either way
+            gamepad.buttons.left_trigger = process_polled_button(old_buttons.left_trigger, pad.bLeftTrigger > settings.trigger_threshold);
+            gamepad.buttons.right_trigger = process_polled_button(old_buttons.right_trigger, pad.bRightTrigger > settings.trigger_threshold);
+            gamepad.triggers = GamepadTriggers {
+                left: normalize_trigger(pad.bLeftTrigger, settings.trigger_threshold),
+                right: normalize_trigger(pad.bRightTrigger, settings.trigger_threshold),
+            };
+            let (left_x, left_y) = normalize_stick(pad.sThumbLX, pad.sThumbLY, settings.left_stick_deadzone, settings.left_stick_curve);
+            let (right_x, right_y) = normalize_stick(pad.sThumbRX, pad.sThumbRY, settings.right_stick_deadzone, settings.right_stick_curve);
+            gamepad.sticks = GamepadSticks {
+                left_x,
+                left_y,
+                right_x,
+                right_y,
+            };
+        }
+    }
+
+    fn set_vibration(&mut self, slot: usize, low_frequency: f32, high_frequency: f32) -> bool {
+        // left motor is the heavy low frequency one, right motor is the light high frequency one
+        let vibration = XINPUT_VIBRATION {
+            wLeftMotorSpeed: (low_frequency.clamp(0.0, 1.0) * u16::MAX as f32) as u16,
+            wRightMotorSpeed: (high_frequency.clamp(0.0, 1.0) * u16::MAX as f32) as u16,
+        };
+        let result = unsafe { (self.xinput.set_state)(slot as u32, &vibration) };
+        result == ERROR_SUCCESS.0
+    }
+}
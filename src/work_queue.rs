@@ -0,0 +1,124 @@
+use std::{
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Default)]
+struct Shared {
+    jobs: Mutex<VecDeque<Job>>,
+    // signalled when jobs are added, or on shutdown
+    job_added: Condvar,
+    unfinished: Mutex<usize>,
+    all_done: Condvar,
+    panicked: AtomicBool,
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    fn next_job(&self, wait: bool) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            if let Some(job) = jobs.pop_front() {
+                return Some(job);
+            }
+            if !wait || self.shutdown.load(Ordering::Acquire) {
+                return None;
+            }
+            jobs = self.job_added.wait(jobs).unwrap();
+        }
+    }
+
+    fn run(&self, job: Job) {
+        // a panicking job still has to count as finished or complete_all would wait forever
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            self.panicked.store(true, Ordering::Release);
+        }
+        let mut unfinished = self.unfinished.lock().unwrap();
+        *unfinished -= 1;
+        if *unfinished == 0 {
+            self.all_done.notify_all();
+        }
+    }
+}
+
+/// Handmade style work queue: a few long lived worker threads pulling jobs off a shared list.
+/// The calling thread works through the list too while it waits, so a queue with no workers still runs everything.
+pub struct WorkQueue {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkQueue {
+    pub fn new(worker_count: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+        let workers = (0..worker_count)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("worker {index}"))
+                    .spawn(move || {
+                        while let Some(job) = shared.next_job(true) {
+                            shared.run(job);
+                        }
+                    })
+                    .expect("Failed to spawn worker thread")
+            })
+            .collect();
+        WorkQueue { shared, workers }
+    }
+
+    /// One worker per core, leaving one for the thread that hands out the work
+    pub fn with_available_cores() -> Self {
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        Self::new(cores.saturating_sub(1))
+    }
+
+    /// Runs all the jobs and returns once every one of them has finished, which is what lets them borrow from the caller
+    pub fn complete_all<'a>(&self, jobs: impl IntoIterator<Item = Box<dyn FnOnce() + Send + 'a>>) {
+        {
+            let mut queue = self.shared.jobs.lock().unwrap();
+            let mut unfinished = self.shared.unfinished.lock().unwrap();
+            for job in jobs {
+                // SAFETY: the job can't outlive 'a, nothing returns from here until the unfinished count drops back to 0
+                let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Job>(job) };
+                queue.push_back(job);
+                *unfinished += 1;
+            }
+        }
+        self.shared.job_added.notify_all();
+
+        while let Some(job) = self.shared.next_job(false) {
+            self.shared.run(job);
+        }
+        let mut unfinished = self.shared.unfinished.lock().unwrap();
+        while *unfinished > 0 {
+            unfinished = self.shared.all_done.wait(unfinished).unwrap();
+        }
+        drop(unfinished);
+
+        if self.shared.panicked.swap(false, Ordering::AcqRel) {
+            panic!("A work queue job panicked");
+        }
+    }
+}
+
+impl Drop for WorkQueue {
+    fn drop(&mut self) {
+        {
+            // flip it under the jobs lock so no worker can miss the wakeup between checking and waiting
+            let _jobs = self.shared.jobs.lock().unwrap();
+            self.shared.shutdown.store(true, Ordering::Release);
+        }
+        self.shared.job_added.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}